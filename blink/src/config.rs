@@ -0,0 +1,174 @@
+use {
+	anyhow::{anyhow, Result},
+	serde::{Deserialize, Deserializer},
+	solana_sdk::pubkey::Pubkey,
+	std::{path::Path, str::FromStr},
+};
+
+/// Server options read from a TOML file passed with `--config`, every field mirrors the flag of
+/// the same name. Fields left out of the file fall back to the flag or its default
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+	/// Address of the token for sale
+	#[serde(deserialize_with = "pubkey_string")]
+	pub mint: Option<Pubkey>,
+	/// RPC url or one of t/testnet, d/devnet, m/mainnet, l/local
+	pub url: Option<String>,
+	/// Path to a solana config file
+	pub solana_config: Option<String>,
+	pub port: Option<u16>,
+	/// Path to the whitelist authority keypair
+	pub authority_keypair: Option<String>,
+	pub token_name: Option<String>,
+	pub icon_url: Option<String>,
+	/// Timeout in seconds for each RPC request
+	pub rpc_timeout: Option<u64>,
+	#[serde(deserialize_with = "pubkey_string")]
+	pub program_id: Option<Pubkey>,
+	/// Priority fee added to returned transactions, `"auto"` or micro-lamports per compute unit
+	pub priority_fee: Option<PriorityFee>,
+}
+
+/// Priority fee the blink prepends to the transactions it returns, so they are more likely to
+/// land on a congested network. The fee is advisory, the wallet may replace it before signing
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(try_from = "RawPriorityFee")]
+pub enum PriorityFee {
+	/// Fixed price in micro-lamports per compute unit
+	MicroLamports(u64),
+	/// Median of the fees recently paid to write the accounts a transaction touches
+	Auto,
+}
+
+impl FromStr for PriorityFee {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		if s.eq_ignore_ascii_case("auto") {
+			return Ok(Self::Auto);
+		}
+		s.parse().map(Self::MicroLamports).map_err(|_| {
+			format!(
+				"Invalid priority fee `{}`, expected `auto` or micro-lamports per compute unit",
+				s
+			)
+		})
+	}
+}
+
+/// A priority fee as written in the config file, either a number or `"auto"`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawPriorityFee {
+	MicroLamports(u64),
+	Mode(String),
+}
+
+impl TryFrom<RawPriorityFee> for PriorityFee {
+	type Error = String;
+
+	fn try_from(raw: RawPriorityFee) -> Result<Self, Self::Error> {
+		match raw {
+			RawPriorityFee::MicroLamports(fee) => Ok(Self::MicroLamports(fee)),
+			RawPriorityFee::Mode(mode) => mode.parse(),
+		}
+	}
+}
+
+impl ServerConfig {
+	pub fn load(path: &Path) -> Result<Self> {
+		let contents = std::fs::read_to_string(path)
+			.map_err(|err| anyhow!("Unable to read config file {}: {}", path.display(), err))?;
+		Self::parse(&contents)
+			.map_err(|err| anyhow!("Invalid config file {}: {}", path.display(), err))
+	}
+
+	pub fn parse(contents: &str) -> Result<Self> {
+		Ok(toml::from_str(contents)?)
+	}
+
+	/// Applies `overrides` on top of this config, any value set in `overrides` replaces the one
+	/// read from the file
+	pub fn merge(self, overrides: ServerConfig) -> Self {
+		Self {
+			mint: overrides.mint.or(self.mint),
+			url: overrides.url.or(self.url),
+			solana_config: overrides.solana_config.or(self.solana_config),
+			port: overrides.port.or(self.port),
+			authority_keypair: overrides.authority_keypair.or(self.authority_keypair),
+			token_name: overrides.token_name.or(self.token_name),
+			icon_url: overrides.icon_url.or(self.icon_url),
+			rpc_timeout: overrides.rpc_timeout.or(self.rpc_timeout),
+			program_id: overrides.program_id.or(self.program_id),
+			priority_fee: overrides.priority_fee.or(self.priority_fee),
+		}
+	}
+}
+
+/// Reads an optional public key written as a base58 string
+fn pubkey_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Pubkey>, D::Error> {
+	let s = String::deserialize(deserializer)?;
+	Pubkey::from_str(&s)
+		.map(Some)
+		.map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_config_with_override() {
+		let mint = Pubkey::new_unique();
+		let program_id = Pubkey::new_unique();
+		let contents = format!(
+			r#"
+mint = "{mint}"
+url = "devnet"
+port = 8080
+token_name = "FSP"
+icon_url = "https://example.com/icon.png"
+rpc_timeout = 5
+program_id = "{program_id}"
+priority_fee = 5000
+"#
+		);
+
+		let config = ServerConfig::parse(&contents).unwrap();
+		assert_eq!(
+			config,
+			ServerConfig {
+				mint: Some(mint),
+				url: Some("devnet".to_string()),
+				solana_config: None,
+				port: Some(8080),
+				authority_keypair: None,
+				token_name: Some("FSP".to_string()),
+				icon_url: Some("https://example.com/icon.png".to_string()),
+				rpc_timeout: Some(5),
+				program_id: Some(program_id),
+				priority_fee: Some(PriorityFee::MicroLamports(5000)),
+			}
+		);
+
+		// Flags win over the file, values not passed as flags are kept
+		let config = config.merge(ServerConfig {
+			port: Some(9000),
+			..ServerConfig::default()
+		});
+		assert_eq!(config.port, Some(9000));
+		assert_eq!(config.mint, Some(mint));
+		assert_eq!(config.url.as_deref(), Some("devnet"));
+
+		assert!(ServerConfig::parse("mint = \"not a key\"").is_err());
+		assert!(ServerConfig::parse("unknown = 1").is_err());
+		assert_eq!(
+			ServerConfig::parse("priority_fee = \"auto\"")
+				.unwrap()
+				.priority_fee,
+			Some(PriorityFee::Auto)
+		);
+		assert!(ServerConfig::parse("priority_fee = \"fast\"").is_err());
+	}
+}
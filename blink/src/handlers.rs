@@ -4,18 +4,45 @@ use {
 		http::StatusCode,
 		response::IntoResponse,
 	},
+	anyhow::Result as AnyResult,
 	base64::{engine::general_purpose::STANDARD, Engine},
 	bincode::serialize,
-    crate::{monitor::CounterMessage, server::AppState},
+    crate::{config::PriorityFee, monitor::CounterMessage, server::AppState},
 	serde::{Deserialize, Serialize},
 	serde_json::{json, Value},
-    solana_sdk::{pubkey::Pubkey, transaction::Transaction},
-    std::{str::FromStr, sync::Arc},
-    fsp_wl::instructions,
+	solana_client::{
+		client_error::{ClientError, Result as ClientResult},
+		rpc_config::RpcProgramAccountsConfig,
+		rpc_filter::{Memcmp, RpcFilterType},
+	},
+    solana_sdk::{
+		compute_budget::ComputeBudgetInstruction,
+		hash::Hash,
+		instruction::{AccountMeta, Instruction},
+		native_token::lamports_to_sol,
+		program_error::ProgramError,
+		pubkey::Pubkey,
+		signature::{Keypair, Signer},
+		transaction::Transaction,
+	},
+	spl_token_2022::{
+		extension::{BaseStateWithExtensions, StateWithExtensions},
+		state::{Account, Mint},
+	},
+	spl_token_metadata_interface::state::TokenMetadata,
+    std::{str::FromStr, sync::Arc, time::{Duration, Instant, SystemTime, UNIX_EPOCH}},
+    fsp_wl::{
+		error::WhitelistError,
+		instructions,
+		state::{Ticket, Whitelist},
+	},
 };
 
 #[derive(Serialize)]
 struct ActionGetResponse {
+	/// `action` or `completed`, only set on actions returned to chain transactions together
+	#[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+	kind: Option<&'static str>,
 	title: String,
 	icon: String,
 	description: String,
@@ -45,6 +72,15 @@ struct Parameter {
 #[derive(Deserialize)]
 pub(crate) struct QueryParams {
 	amount: f64,
+	/// Token price quoted to the buyer, the purchase fails if the price has since been raised
+	max_price: Option<u64>,
+	/// Campaign the action link was shared under, see [`parse_campaign`]
+	campaign: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CampaignParams {
+	campaign: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -56,6 +92,86 @@ pub(crate) struct PostRequest {
 struct PostResponse {
 	transaction: String,
 	message: String,
+	/// Action the client requests once the transaction is confirmed
+	#[serde(skip_serializing_if = "Option::is_none")]
+	links: Option<PostLinks>,
+}
+
+#[derive(Serialize)]
+struct PostLinks {
+	next: NextActionLink,
+}
+
+#[derive(Serialize)]
+struct NextActionLink {
+	#[serde(rename = "type")]
+	kind: &'static str,
+	href: String,
+}
+
+/// Longest campaign id accepted, the id is written to the transaction's memo
+const MAX_CAMPAIGN_LEN: usize = 32;
+
+/// Validates the `campaign` query parameter, ids are limited to ASCII letters, digits, `-` and
+/// `_` so they can be passed through action links and memos unescaped
+pub(crate) fn parse_campaign(
+	campaign: Option<String>,
+) -> Result<Option<String>, (StatusCode, Json<Value>)> {
+	match campaign {
+		Some(id)
+			if id.is_empty()
+				|| id.len() > MAX_CAMPAIGN_LEN
+				|| !id
+					.chars()
+					.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+		{
+			Err((
+				StatusCode::BAD_REQUEST,
+				Json(json!({"error": "Invalid 'campaign' provided"})),
+			))
+		}
+		campaign => Ok(campaign),
+	}
+}
+
+/// Memo recording the campaign a transaction was created through, so purchases can be
+/// attributed on-chain
+pub(crate) fn campaign_memo(campaign: &str) -> Instruction {
+	spl_memo::build_memo(format!("campaign:{}", campaign).as_bytes(), &[])
+}
+
+/// Serializes the transaction for the user to sign, echoing the campaign in the message and
+/// counting the request towards it
+fn post_response(
+	state: Arc<AppState>,
+	transaction: &Transaction,
+	message: String,
+	campaign: Option<String>,
+) -> Result<Json<PostResponse>, (StatusCode, Json<Value>)> {
+	let serialized_transaction = serialize(transaction).map_err(|_| {
+		(
+			StatusCode::INTERNAL_SERVER_ERROR,
+			Json(json!({"error": "Failed to serialize transaction"})),
+		)
+	})?;
+
+	let message = match campaign {
+		Some(ref campaign) => format!("{} (campaign: {})", message, campaign),
+		None => message,
+	};
+
+	tokio::spawn(async move {
+		let _ = state
+			.counter_tx
+			.send(CounterMessage::Post { campaign })
+			.await;
+	});
+
+	Ok(Json(PostResponse {
+		transaction: STANDARD.encode(serialized_transaction),
+		message,
+		links: None,
+	}))
 }
 
 pub(crate) async fn get_request_actions_json(State(state): State<Arc<AppState>>) -> impl IntoResponse {
@@ -76,16 +192,276 @@ pub(crate) async fn get_request_actions_json(State(state): State<Arc<AppState>>)
 	}))
 }
 
-pub(crate) async fn reg_get_request_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-	let base_href = "/api/actions/register";
+/// Live details of the sale used to describe the actions
+pub(crate) struct SaleSummary {
+	pub(crate) token_name: String,
+	pub(crate) price: f64,
+	/// The whitelist's `token_price`, quoted back to the program as the buyer's maximum price
+	pub(crate) token_price: u64,
+	pub(crate) remaining: f64,
+}
+
+/// Reads the whitelist, vault and mint to summarise the current state of the sale
+pub(crate) fn fetch_sale_summary(state: &AppState) -> AnyResult<SaleSummary> {
+	let (whitelist, _) =
+		fsp_wl::get_whitelist_address_with_program_id(&state.mint, &state.program_id);
+	let wl_data = Whitelist::unpack(&state.rpc_client.get_account_data(&whitelist)?)?;
+
+	let mint_data = state.rpc_client.get_account_data(&state.mint)?;
+	let decimals = StateWithExtensions::<Mint>::unpack(&mint_data)?
+		.base
+		.decimals;
+
+	let vault_data = state.rpc_client.get_account_data(&wl_data.vault)?;
+	let vault_balance = StateWithExtensions::<Account>::unpack(&vault_data)?
+		.base
+		.amount;
+
+	Ok(SaleSummary {
+		token_name: token_name(&mint_data, state.token_name.as_deref()),
+		price: price_per_token(wl_data.token_price),
+		token_price: wl_data.token_price,
+		remaining: spl_token_2022::amount_to_ui_amount(vault_balance, decimals),
+	})
+}
+
+/// Resolves the display name of the token, preferring the symbol or name from the mint's
+/// Token-2022 metadata, then the configured name
+pub(crate) fn token_name(mint_data: &[u8], configured: Option<&str>) -> String {
+	let metadata = StateWithExtensions::<Mint>::unpack(mint_data)
+		.ok()
+		.and_then(|mint| mint.get_variable_len_extension::<TokenMetadata>().ok());
+
+	match metadata {
+		Some(metadata) if !metadata.symbol.is_empty() => metadata.symbol,
+		Some(metadata) if !metadata.name.is_empty() => metadata.name,
+		_ => configured.unwrap_or("tokens").to_string(),
+	}
+}
+
+/// Price of a whole token in SOL, `token_price` is charged in lamports per whole token
+pub(crate) fn price_per_token(token_price: u64) -> f64 {
+	lamports_to_sol(token_price)
+}
+
+/// Base units of `amount` whole tokens, as the program converts a purchase
+fn base_units(amount: u64, decimals: u8) -> Result<u64, WhitelistError> {
+	10u64
+		.checked_pow(decimals as u32)
+		.and_then(|unit| amount.checked_mul(unit))
+		.ok_or(WhitelistError::Overflow)
+}
+
+/// Message shown to the buyer alongside the transaction, priced with the same `buy_cost` the
+/// program charges
+pub(crate) fn buy_message(amount: u64, token_price: u64, decimals: u8) -> AnyResult<String> {
+	let amount_base_units = base_units(amount, decimals)?;
+	let cost = fsp_wl::buy_cost(amount_base_units, token_price, decimals)?;
+	Ok(format!(
+		"Buying {} tokens for {} SOL",
+		amount,
+		lamports_to_sol(cost)
+	))
+}
+
+/// Receipt shown to the buyer before signing, the `buy_message` followed by what `ticket` can
+/// still buy once the purchase lands and the `reference` key added to the transaction so the
+/// purchase can be looked up once sent. Fails with `BuyLimitExceeded` when the purchase is more
+/// than the ticket has left
+pub(crate) fn buy_receipt(
+	amount: u64,
+	wl_data: &Whitelist,
+	ticket: &Ticket,
+	decimals: u8,
+	reference: &Pubkey,
+) -> AnyResult<String> {
+	let amount_base_units = base_units(amount, decimals)?;
+	let remaining = ticket
+		.allowance
+		.saturating_sub(ticket.amount_bought)
+		.checked_sub(amount_base_units)
+		.ok_or(WhitelistError::BuyLimitExceeded)?;
+	Ok(format!(
+		"{}, {} tokens of allowance left after this purchase. Reference: {}",
+		buy_message(amount, wl_data.token_price, decimals)?,
+		spl_token_2022::amount_to_ui_amount(remaining, decimals),
+		reference
+	))
+}
+
+/// How long a `GET /api/whitelist` response is served from the cache before the RPC is queried
+/// again
+const WHITELIST_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Whitelist state along with the derived addresses and live vault balance
+#[derive(Serialize)]
+pub(crate) struct WhitelistResponse {
+	address: String,
+	#[serde(flatten)]
+	whitelist: Whitelist,
+	mint_decimals: u8,
+	vault_balance: u64,
+}
+
+pub(crate) fn fetch_whitelist_response(state: &AppState) -> AnyResult<WhitelistResponse> {
+	let (whitelist, _) =
+		fsp_wl::get_whitelist_address_with_program_id(&state.mint, &state.program_id);
+	let wl_data = Whitelist::unpack(&state.rpc_client.get_account_data(&whitelist)?)?;
+
+	let mint_data = state.rpc_client.get_account_data(&state.mint)?;
+	let mint_decimals = StateWithExtensions::<Mint>::unpack(&mint_data)?
+		.base
+		.decimals;
+
+	// The vault may not exist yet if nothing has been deposited
+	let vault_balance = match state.rpc_client.get_account_data(&wl_data.vault) {
+		Ok(data) => StateWithExtensions::<Account>::unpack(&data)?.base.amount,
+		Err(_) => 0,
+	};
+
+	Ok(WhitelistResponse {
+		address: whitelist.to_string(),
+		whitelist: wl_data,
+		mint_decimals,
+		vault_balance,
+	})
+}
+
+pub(crate) async fn whitelist_get_request_handler(
+	State(state): State<Arc<AppState>>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+	if let Some((fetched_at, cached)) = state.whitelist_cache.lock().unwrap().as_ref() {
+		if fetched_at.elapsed() < WHITELIST_CACHE_TTL {
+			return Ok(Json(cached.clone()));
+		}
+	}
+
+	let response = fetch_whitelist_response(&state).map_err(|err| {
+		(
+			StatusCode::INTERNAL_SERVER_ERROR,
+			Json(json!({"error": format!("Failed to fetch whitelist: {}", err)})),
+		)
+	})?;
+	let value = serde_json::to_value(response).map_err(|_| {
+		(
+			StatusCode::INTERNAL_SERVER_ERROR,
+			Json(json!({"error": "Failed to serialize whitelist"})),
+		)
+	})?;
+
+	*state.whitelist_cache.lock().unwrap() = Some((Instant::now(), value.clone()));
+	Ok(Json(value))
+}
+
+/// How long a `GET /api/sale-info` response is served from the cache
+const SALE_INFO_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Live progress of the sale for front-ends to poll, token amounts are in whole tokens
+#[derive(Serialize)]
+pub(crate) struct SaleInfo {
+	/// SOL per whole token
+	price: f64,
+	sold: f64,
+	remaining: f64,
+	phase: &'static str,
+}
+
+/// Phase of the sale at `unix_timestamp`, mirroring the order of the checks made by `Buy`
+pub(crate) fn sale_phase(
+	wl_data: &Whitelist,
+	vault_balance: u64,
+	unix_timestamp: i64,
+) -> &'static str {
+	if wl_data.sale_paused {
+		"paused"
+	} else if wl_data.sale_timestamp > unix_timestamp {
+		if wl_data.registration_timestamp > 0 && wl_data.registration_timestamp <= unix_timestamp {
+			"registration"
+		} else {
+			"upcoming"
+		}
+	} else if wl_data.sale_ended(unix_timestamp) {
+		"ended"
+	} else if vault_balance == 0 {
+		"sold_out"
+	} else {
+		"sale"
+	}
+}
+
+pub(crate) fn sale_info(response: &WhitelistResponse, unix_timestamp: i64) -> SaleInfo {
+	let decimals = response.mint_decimals;
+	// Everything deposited that has left the vault has been bought
+	let sold = response
+		.whitelist
+		.deposited
+		.saturating_sub(response.vault_balance);
+
+	SaleInfo {
+		price: price_per_token(response.whitelist.token_price),
+		sold: spl_token_2022::amount_to_ui_amount(sold, decimals),
+		remaining: spl_token_2022::amount_to_ui_amount(response.vault_balance, decimals),
+		phase: sale_phase(&response.whitelist, response.vault_balance, unix_timestamp),
+	}
+}
+
+pub(crate) async fn sale_info_get_request_handler(
+	State(state): State<Arc<AppState>>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+	if let Some((fetched_at, cached)) = state.sale_info_cache.lock().unwrap().as_ref() {
+		if fetched_at.elapsed() < SALE_INFO_CACHE_TTL {
+			return Ok(Json(cached.clone()));
+		}
+	}
+
+	let response = fetch_whitelist_response(&state).map_err(|err| {
+		(
+			StatusCode::INTERNAL_SERVER_ERROR,
+			Json(json!({"error": format!("Failed to fetch sale info: {}", err)})),
+		)
+	})?;
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |elapsed| elapsed.as_secs() as i64);
+	let value = serde_json::to_value(sale_info(&response, now)).map_err(|_| {
+		(
+			StatusCode::INTERNAL_SERVER_ERROR,
+			Json(json!({"error": "Failed to serialize sale info"})),
+		)
+	})?;
+
+	*state.sale_info_cache.lock().unwrap() = Some((Instant::now(), value.clone()));
+	Ok(Json(value))
+}
+
+pub(crate) fn buy_description(summary: &SaleSummary) -> String {
+	format!(
+		"Buy {} at {} SOL - {} left",
+		summary.token_name, summary.price, summary.remaining
+	)
+}
+
+pub(crate) async fn reg_get_request_handler(
+	State(state): State<Arc<AppState>>,
+	Query(params): Query<CampaignParams>,
+) -> impl IntoResponse {
+	let href = match parse_campaign(params.campaign).ok().flatten() {
+		Some(campaign) => format!("/api/actions/register?campaign={}", campaign),
+		None => "/api/actions/register".to_string(),
+	};
+	let description = match fetch_sale_summary(&state) {
+		Ok(summary) => format!("Register for the {} presale", summary.token_name),
+		Err(_) => "Register for token presale".to_string(),
+	};
 	let response = ActionGetResponse {
+		kind: None,
 		title: "Whitelist Register".into(),
-		icon: "".into(),
-		description: "Register for token presale".into(),
+		icon: state.icon.clone(),
+		description,
 		links: Links {
 			actions: vec![ActionLink {
 				label: "Register".into(),
-				href: base_href.to_string(),
+				href,
 				parameters: None,
 			}],
 		},
@@ -97,27 +473,45 @@ pub(crate) async fn reg_get_request_handler(State(state): State<Arc<AppState>>)
 	(StatusCode::OK, Json(response))
 }
 
-pub(crate) async fn buy_get_request_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+pub(crate) async fn buy_get_request_handler(
+	State(state): State<Arc<AppState>>,
+	Query(params): Query<CampaignParams>,
+) -> impl IntoResponse {
 	let base_href = "/api/actions/buy-token?";
+	let (description, mut extra_params) = match fetch_sale_summary(&state) {
+		Ok(summary) => (
+			buy_description(&summary),
+			format!("&max_price={}", summary.token_price),
+		),
+		Err(_) => (
+			"Allow purchase of tokens if user is whitelisted".to_string(),
+			String::new(),
+		),
+	};
+	// An invalid campaign is dropped rather than failing the action, the links still work
+	if let Some(campaign) = parse_campaign(params.campaign).ok().flatten() {
+		extra_params.push_str(&format!("&campaign={}", campaign));
+	}
 	let response = ActionGetResponse {
+		kind: None,
 		title: "Whitelist - Buy token".into(),
-		icon: "".into(),
-		description: "Allow purchase of tokens if user is whitelisted".into(),
+		icon: state.icon.clone(),
+		description,
 		links: Links {
 			actions: vec![
 				ActionLink {
 					label: "Buy 1 Token".into(),
-					href: format!("{}amount=1", base_href),
+					href: format!("{}amount=1{}", base_href, extra_params),
 					parameters: None,
 				},
 				ActionLink {
 					label: "Buy 10 Tokens".into(),
-					href: format!("{}amount=10", base_href),
+					href: format!("{}amount=10{}", base_href, extra_params),
 					parameters: None,
 				},
 				ActionLink {
 					label: "Buy 100 Tokens".into(),
-					href: format!("{}amount=100", base_href),
+					href: format!("{}amount=100{}", base_href, extra_params),
 					parameters: None,
 				},
 			],
@@ -131,6 +525,70 @@ pub(crate) async fn buy_get_request_handler(State(state): State<Arc<AppState>>)
 	(StatusCode::OK, Json(response))
 }
 
+/// Number of attempts made for an RPC request before giving up
+const RPC_ATTEMPTS: u32 = 3;
+const RPC_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Retries an RPC request a bounded number of times, each attempt is bounded by the client's
+/// timeout
+pub(crate) fn with_retry<T>(request: impl Fn() -> ClientResult<T>) -> ClientResult<T> {
+	let mut attempt = 1;
+	loop {
+		match request() {
+			Ok(result) => return Ok(result),
+			Err(err) if attempt >= RPC_ATTEMPTS => return Err(err),
+			Err(_) => {
+				std::thread::sleep(RPC_RETRY_DELAY * attempt);
+				attempt += 1;
+			}
+		}
+	}
+}
+
+/// The RPC could not be reached, the request may succeed if retried later
+fn rpc_unavailable(context: &str, err: ClientError) -> (StatusCode, Json<Value>) {
+	(
+		StatusCode::SERVICE_UNAVAILABLE,
+		Json(json!({"error": format!("{}: {}", context, err)})),
+	)
+}
+
+/// Compute units requested by a `Register` or `AddUser` transaction
+pub(crate) const REGISTER_COMPUTE_UNITS: u32 = 50_000;
+/// Compute units requested per purchase, a purchase may also create the ticket's and the user's
+/// token accounts
+pub(crate) const BUY_COMPUTE_UNITS: u32 = 200_000;
+
+/// Compute budget instructions to prepend to a transaction that uses up to `units` and writes to
+/// `writable`, none when no priority fee is configured. In auto mode the price is the median of
+/// the fees recently paid to write those accounts. The budget is advisory, the wallet may replace
+/// it before signing
+pub(crate) fn compute_budget_instructions(
+	state: &AppState,
+	units: u32,
+	writable: &[Pubkey],
+) -> Result<Vec<Instruction>, (StatusCode, Json<Value>)> {
+	let micro_lamports = match state.priority_fee {
+		None => return Ok(vec![]),
+		Some(PriorityFee::MicroLamports(micro_lamports)) => micro_lamports,
+		Some(PriorityFee::Auto) => {
+			let fees = with_retry(|| state.rpc_client.get_recent_prioritization_fees(writable))
+				.map_err(|err| rpc_unavailable("Failed to get recent priority fees", err))?;
+			median_fee(fees.iter().map(|fee| fee.prioritization_fee).collect())
+		}
+	};
+	Ok(vec![
+		ComputeBudgetInstruction::set_compute_unit_limit(units),
+		ComputeBudgetInstruction::set_compute_unit_price(micro_lamports),
+	])
+}
+
+/// Median of the recently paid priority fees, `0` when there are none
+pub(crate) fn median_fee(mut fees: Vec<u64>) -> u64 {
+	fees.sort_unstable();
+	fees.get(fees.len() / 2).copied().unwrap_or(0)
+}
+
 pub(crate) async fn buy_post_request_handler(
 	State(state): State<Arc<AppState>>,
 	Query(params): Query<QueryParams>,
@@ -142,25 +600,73 @@ pub(crate) async fn buy_post_request_handler(
 			Json(json!({"error": "Invalid 'account' provided"})),
 		)
 	})?;
+	let campaign = parse_campaign(params.campaign)?;
 
-	let latest_blockhash = state.rpc_client.get_latest_blockhash().map_err(|err| {
-		(
-			StatusCode::INTERNAL_SERVER_ERROR,
-			Json(json!({"error": format!("Failed to get latest blockhash: {}", err)})),
-		)
-	})?;
+	// The amount is sent in whole tokens, anything below one would buy nothing
+	if params.amount as u64 == 0 {
+		return Err((
+			StatusCode::BAD_REQUEST,
+			Json(json!({"error": WhitelistError::ZeroAmount.to_string()})),
+		));
+	}
+
+	let latest_blockhash = with_retry(|| state.rpc_client.get_latest_blockhash())
+		.map_err(|err| rpc_unavailable("Failed to get latest blockhash", err))?;
 
-	let (whitelist, _) = fsp_wl::get_whitelist_address(&state.mint);
-	let (ticket, _) = fsp_wl::get_user_ticket_address(&account, &whitelist);
+	let (whitelist, _) =
+		fsp_wl::get_whitelist_address_with_program_id(&state.mint, &state.program_id);
+	let (ticket, _) =
+		fsp_wl::get_user_ticket_address_with_program_id(&account, &whitelist, &state.program_id);
 
-	let mint_account = state.rpc_client.get_account(&state.mint).map_err(|err| {
+	let mint_account = with_retry(|| state.rpc_client.get_account(&state.mint))
+		.map_err(|err| rpc_unavailable("Failed to get mint account", err))?;
+
+	let token_program = mint_account.owner;
+
+	let whitelist_data = with_retry(|| state.rpc_client.get_account_data(&whitelist))
+		.map_err(|err| rpc_unavailable("Failed to get whitelist", err))?;
+	let ticket_account = with_retry(|| {
+		state
+			.rpc_client
+			.get_account_with_commitment(&ticket, state.rpc_client.commitment())
+	})
+	.map_err(|err| rpc_unavailable("Failed to get ticket", err))?
+	.value
+	.ok_or_else(|| {
 		(
-			StatusCode::INTERNAL_SERVER_ERROR,
-			Json(json!({"error": format!("Failed to get mint account: {}", err)})),
+			StatusCode::BAD_REQUEST,
+			Json(json!({"error": "Account is not registered for the whitelist"})),
 		)
 	})?;
 
-	let token_program = mint_account.owner;
+	// Added to the purchase as a read-only account, so the transaction can be found by it once
+	// sent
+	let reference = Keypair::new().pubkey();
+	let message = Whitelist::unpack(&whitelist_data)
+		.map_err(anyhow::Error::from)
+		.and_then(|wl_data| {
+			let ticket_data = Ticket::unpack(&ticket_account.data)?;
+			let decimals = StateWithExtensions::<Mint>::unpack(&mint_account.data)?
+				.base
+				.decimals;
+			buy_receipt(
+				params.amount as u64,
+				&wl_data,
+				&ticket_data,
+				decimals,
+				&reference,
+			)
+		})
+		.map_err(|err| match err.downcast_ref::<WhitelistError>() {
+			Some(WhitelistError::BuyLimitExceeded) => (
+				StatusCode::BAD_REQUEST,
+				Json(json!({"error": WhitelistError::BuyLimitExceeded.to_string()})),
+			),
+			_ => (
+				StatusCode::INTERNAL_SERVER_ERROR,
+				Json(json!({"error": format!("Failed to price purchase: {}", err)})),
+			),
+		})?;
 
 	let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
 		&whitelist,
@@ -182,7 +688,7 @@ pub(crate) async fn buy_post_request_handler(
 			&token_program,
 		);
 
-	let instruction = instructions::buy_tokens(
+	let mut instruction = instructions::buy_tokens(
 		&whitelist,
 		&vault,
 		&state.mint,
@@ -191,6 +697,8 @@ pub(crate) async fn buy_post_request_handler(
 		&ticket_token_account,
 		&user_token_account,
 		params.amount as u64,
+		params.max_price,
+		false,
 		&token_program,
 	)
 	.map_err(|err| {
@@ -199,30 +707,26 @@ pub(crate) async fn buy_post_request_handler(
 			Json(json!({"error": format!("Could not create `BuyToken` instruction: {}", err)})),
 		)
 	})?;
+	instruction.program_id = state.program_id;
+	instruction
+		.accounts
+		.push(AccountMeta::new_readonly(reference, false));
 
-	let mut transaction = Transaction::new_with_payer(&[instruction], Some(&account));
+	let mut instructions =
+		compute_budget_instructions(&state, BUY_COMPUTE_UNITS, &[whitelist, vault])?;
+	instructions.push(instruction);
+	if let Some(ref campaign) = campaign {
+		instructions.push(campaign_memo(campaign));
+	}
+	let mut transaction = Transaction::new_with_payer(&instructions, Some(&account));
 	transaction.message.recent_blockhash = latest_blockhash;
 
-	let serialized_transaction = serialize(&transaction).map_err(|_| {
-		(
-			StatusCode::INTERNAL_SERVER_ERROR,
-			Json(json!({"error": "Failed to serialize transaction"})),
-		)
-	})?;
-
-	tokio::spawn(async move {
-		let _ = state.counter_tx.send(CounterMessage::Post).await;
-	});
-
-	Ok(Json(PostResponse {
-		transaction: STANDARD.encode(serialized_transaction),
-		message: format!("Buying {} tokens", params.amount),
-	}))
+	post_response(state, &transaction, message, campaign)
 }
 
 pub(crate) async fn reg_post_request_handler(
 	State(state): State<Arc<AppState>>,
-	Query(_params): Query<QueryParams>,
+	Query(params): Query<CampaignParams>,
 	Json(payload): Json<PostRequest>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
 	let account = Pubkey::from_str(&payload.account).map_err(|_| {
@@ -231,39 +735,942 @@ pub(crate) async fn reg_post_request_handler(
 			Json(json!({"error": "Invalid 'account' provided"})),
 		)
 	})?;
+	let campaign = parse_campaign(params.campaign)?;
 
-	let latest_blockhash = state.rpc_client.get_latest_blockhash().map_err(|err| {
+	let latest_blockhash = with_retry(|| state.rpc_client.get_latest_blockhash())
+		.map_err(|err| rpc_unavailable("Failed to get latest blockhash", err))?;
+
+	// Catch a full whitelist before handing the user a transaction that is bound to fail
+	let (whitelist, _) =
+		fsp_wl::get_whitelist_address_with_program_id(&state.mint, &state.program_id);
+	let whitelist_data = with_retry(|| state.rpc_client.get_account_data(&whitelist))
+		.map_err(|err| rpc_unavailable("Failed to get whitelist", err))?;
+	let wl_data = Whitelist::unpack(&whitelist_data).map_err(|err| {
 		(
 			StatusCode::INTERNAL_SERVER_ERROR,
-			Json(json!({"error": format!("Failed to get latest blockhash: {}", err)})),
+			Json(json!({"error": format!("Failed to read whitelist: {}", err)})),
 		)
 	})?;
+	if wl_data.whitelist_size > 0 && wl_data.registered >= wl_data.whitelist_size {
+		return Err((
+			StatusCode::CONFLICT,
+			Json(json!({"error": WhitelistError::WhitelistFull.to_string()})),
+		));
+	}
+
+	let compute_budget = compute_budget_instructions(&state, REGISTER_COMPUTE_UNITS, &[whitelist])?;
+	let transaction = match state.authority {
+		// The server holds the authority key so the user is added by the authority instead of
+		// registering themselves, this works even when `allow_registration` is false
+		Some(ref authority) => {
+			add_user_transaction(
+				&state.program_id,
+				&state.mint,
+				authority,
+				&account,
+				compute_budget,
+				campaign.as_deref(),
+				latest_blockhash,
+			)
+			.map_err(|err| {
+				(
+					StatusCode::INTERNAL_SERVER_ERROR,
+					Json(
+						json!({"error": format!("Could not create `AddUser` instruction: {}", err)}),
+					),
+				)
+			})?
+		}
+		None => {
+			let (ticket, _) = fsp_wl::get_user_ticket_address_with_program_id(
+				&account,
+				&whitelist,
+				&state.program_id,
+			);
+
+			// Always pass the allocation, the program applies it whenever the authority set one
+			let (allocation, _) = fsp_wl::get_allocation_address_with_program_id(
+				&account,
+				&whitelist,
+				&state.program_id,
+			);
+
+			let mut instruction =
+				instructions::register(&whitelist, &account, &ticket, &allocation).map_err(
+					|err| {
+						(
+							StatusCode::INTERNAL_SERVER_ERROR,
+							Json(
+								json!({"error": format!("Could not create `Register` instruction: {}", err)}),
+							),
+						)
+					},
+				)?;
+			instruction.program_id = state.program_id;
+			let mut instructions = compute_budget;
+			instructions.push(instruction);
+			if let Some(ref campaign) = campaign {
+				instructions.push(campaign_memo(campaign));
+			}
+			let mut transaction = Transaction::new_with_payer(&instructions, Some(&account));
+			transaction.message.recent_blockhash = latest_blockhash;
+			transaction
+		}
+	};
+
+	post_response(
+		state,
+		&transaction,
+		"Registered for whitelist".to_string(),
+		campaign,
+	)
+}
+
+/// Builds an `AddUser` transaction for `user` that is partially signed by the whitelist
+/// authority held by the server. The user is the fee payer and must add their own signature
+/// before the transaction can be sent. The compute budget and campaign memo are added before the
+/// authority signs.
+pub(crate) fn add_user_transaction(
+	program_id: &Pubkey,
+	mint: &Pubkey,
+	authority: &Keypair,
+	user: &Pubkey,
+	compute_budget: Vec<Instruction>,
+	campaign: Option<&str>,
+	recent_blockhash: Hash,
+) -> Result<Transaction, ProgramError> {
+	let (whitelist, _) = fsp_wl::get_whitelist_address_with_program_id(mint, program_id);
+	let (ticket, _) =
+		fsp_wl::get_user_ticket_address_with_program_id(user, &whitelist, program_id);
+
+	let mut instruction =
+		instructions::add_user(&whitelist, &authority.pubkey(), mint, user, &ticket)?;
+	instruction.program_id = *program_id;
+
+	let mut instructions = compute_budget;
+	instructions.push(instruction);
+	if let Some(campaign) = campaign {
+		instructions.push(campaign_memo(campaign));
+	}
+	let mut transaction = Transaction::new_with_payer(&instructions, Some(user));
+	transaction.partial_sign(&[authority], recent_blockhash);
+
+	Ok(transaction)
+}
+
+/// Most purchases batched into a single `buy-all` transaction. Each purchase adds six accounts
+/// and its instruction, a fourth can overrun the 1232 byte transaction limit once the mints span
+/// both token programs and a campaign memo and compute budget are added
+pub(crate) const MAX_BATCH_PURCHASES: usize = 3;
+
+/// Most accounts the RPC returns for a single `getMultipleAccounts` request
+const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+/// Purchase made from one of the user's tickets by the `buy-all` action
+pub(crate) struct BatchPurchase {
+	whitelist: Pubkey,
+	mint: Pubkey,
+	token_program: Pubkey,
+	ticket: Pubkey,
+	/// Whole tokens to buy
+	amount: u64,
+	/// The whitelist's `token_price`, quoted back to the program as the buyer's maximum price
+	token_price: u64,
+}
+
+/// Query for every ticket held by `owner`, leaving the RPC node to match the ticket size and the
+/// owner stored after the version, bump and whitelist
+pub(crate) fn owner_tickets_config(owner: &Pubkey) -> RpcProgramAccountsConfig {
+	RpcProgramAccountsConfig {
+		filters: Some(vec![
+			RpcFilterType::DataSize(Ticket::LEN as u64),
+			RpcFilterType::Memcmp(Memcmp::new_base58_encoded(2 + 32, owner.as_ref())),
+		]),
+		..RpcProgramAccountsConfig::default()
+	}
+}
+
+/// Whole tokens `ticket` can still buy at `unix_timestamp`, capped by the vault balance. `None`
+/// when a purchase would fail, so one ticket cannot fail the whole batch
+pub(crate) fn purchasable(
+	wl_data: &Whitelist,
+	ticket: &Ticket,
+	vault_balance: u64,
+	decimals: u8,
+	unix_timestamp: i64,
+) -> Option<u64> {
+	if sale_phase(wl_data, vault_balance, unix_timestamp) != "sale"
+		|| wl_data
+			.cooldown_until(ticket.last_purchase_ts, unix_timestamp)
+			.is_some()
+	{
+		return None;
+	}
+	let remaining = ticket
+		.allowance
+		.saturating_sub(ticket.amount_bought)
+		.min(vault_balance);
+	let amount = remaining / 10u64.checked_pow(decimals as u32)?;
+	(amount > 0).then_some(amount)
+}
+
+fn fetch_multiple_accounts(
+	state: &AppState,
+	addresses: &[Pubkey],
+) -> Result<Vec<Option<solana_sdk::account::Account>>, (StatusCode, Json<Value>)> {
+	let mut accounts = Vec::with_capacity(addresses.len());
+	for chunk in addresses.chunks(MAX_MULTIPLE_ACCOUNTS) {
+		accounts.extend(
+			with_retry(|| state.rpc_client.get_multiple_accounts(chunk))
+				.map_err(|err| rpc_unavailable("Failed to get accounts", err))?,
+		);
+	}
+	Ok(accounts)
+}
+
+/// Finds the purchases `owner` can make across all of their tickets, ordered by ticket address
+/// so the same batches are offered on every request
+pub(crate) fn fetch_batch_purchases(
+	state: &AppState,
+	owner: &Pubkey,
+	unix_timestamp: i64,
+) -> Result<Vec<BatchPurchase>, (StatusCode, Json<Value>)> {
+	let mut tickets = with_retry(|| {
+		state
+			.rpc_client
+			.get_program_accounts_with_config(&state.program_id, owner_tickets_config(owner))
+	})
+	.map_err(|err| rpc_unavailable("Failed to get tickets", err))?
+	.into_iter()
+	.filter_map(|(address, account)| Some((address, Ticket::unpack(&account.data).ok()?)))
+	.collect::<Vec<_>>();
+	tickets.sort_by_key(|(address, _)| *address);
+
+	let whitelists = fetch_multiple_accounts(
+		state,
+		&tickets
+			.iter()
+			.map(|(_, ticket)| ticket.whitelist)
+			.collect::<Vec<_>>(),
+	)?;
+	let tickets = tickets
+		.into_iter()
+		.zip(whitelists)
+		.filter_map(|((address, ticket), account)| {
+			let wl_data = Whitelist::unpack(&account?.data).ok()?;
+			Some((address, ticket, wl_data))
+		})
+		.collect::<Vec<_>>();
+
+	let accounts = fetch_multiple_accounts(
+		state,
+		&tickets
+			.iter()
+			.flat_map(|(_, _, wl_data)| [wl_data.mint, wl_data.vault])
+			.collect::<Vec<_>>(),
+	)?;
+
+	Ok(tickets
+		.into_iter()
+		.zip(accounts.chunks(2))
+		.filter_map(|((address, ticket, wl_data), accounts)| {
+			let mint_account = accounts[0].as_ref()?;
+			let decimals = StateWithExtensions::<Mint>::unpack(&mint_account.data)
+				.ok()?
+				.base
+				.decimals;
+			let vault_balance = StateWithExtensions::<Account>::unpack(&accounts[1].as_ref()?.data)
+				.ok()?
+				.base
+				.amount;
+			let amount = purchasable(&wl_data, &ticket, vault_balance, decimals, unix_timestamp)?;
+			Some(BatchPurchase {
+				whitelist: ticket.whitelist,
+				mint: wl_data.mint,
+				token_program: mint_account.owner,
+				ticket: address,
+				amount,
+				token_price: wl_data.token_price,
+			})
+		})
+		.collect())
+}
+
+/// Builds the `Buy` instructions for the first `MAX_BATCH_PURCHASES` of `purchases`, along with
+/// whether any purchases are left for a chained transaction
+pub(crate) fn buy_all_instructions(
+	program_id: &Pubkey,
+	owner: &Pubkey,
+	purchases: &[BatchPurchase],
+) -> Result<(Vec<Instruction>, bool), ProgramError> {
+	let batch = purchases
+		.iter()
+		.take(MAX_BATCH_PURCHASES)
+		.map(|purchase| {
+			let token_account = |wallet: &Pubkey| {
+				spl_associated_token_account::get_associated_token_address_with_program_id(
+					wallet,
+					&purchase.mint,
+					&purchase.token_program,
+				)
+			};
+			let mut instruction = instructions::buy_tokens(
+				&purchase.whitelist,
+				&token_account(&purchase.whitelist),
+				&purchase.mint,
+				owner,
+				&purchase.ticket,
+				&token_account(&purchase.ticket),
+				&token_account(owner),
+				purchase.amount,
+				Some(purchase.token_price),
+				false,
+				&purchase.token_program,
+			)?;
+			instruction.program_id = *program_id;
+			Ok(instruction)
+		})
+		.collect::<Result<Vec<_>, ProgramError>>()?;
+
+	Ok((batch, purchases.len() > MAX_BATCH_PURCHASES))
+}
+
+pub(crate) async fn buy_all_get_request_handler(
+	State(state): State<Arc<AppState>>,
+	Query(params): Query<CampaignParams>,
+) -> impl IntoResponse {
+	let href = match parse_campaign(params.campaign).ok().flatten() {
+		Some(campaign) => format!("/api/actions/buy-all?campaign={}", campaign),
+		None => "/api/actions/buy-all".to_string(),
+	};
+	let response = ActionGetResponse {
+		kind: None,
+		title: "Whitelist - Buy from all tickets".into(),
+		icon: state.icon.clone(),
+		description: format!(
+			"Buy the remaining allowance of every ticket you hold, {} tickets per transaction",
+			MAX_BATCH_PURCHASES
+		),
+		links: Links {
+			actions: vec![ActionLink {
+				label: "Buy All".into(),
+				href,
+				parameters: None,
+			}],
+		},
+	};
 
-	let (whitelist, _) = fsp_wl::get_whitelist_address(&state.mint);
-	let (ticket, _) = fsp_wl::get_user_ticket_address(&account, &whitelist);
+	tokio::spawn(async move {
+		let _ = state.counter_tx.send(CounterMessage::Get).await;
+	});
+	(StatusCode::OK, Json(response))
+}
 
-	let instruction = instructions::register(&whitelist, &account, &ticket).map_err(|err| {
+pub(crate) async fn buy_all_post_request_handler(
+	State(state): State<Arc<AppState>>,
+	Query(params): Query<CampaignParams>,
+	Json(payload): Json<PostRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+	let account = Pubkey::from_str(&payload.account).map_err(|_| {
 		(
-			StatusCode::INTERNAL_SERVER_ERROR,
-			Json(json!({"error": format!("Could not create `Register` instruction: {}", err)})),
+			StatusCode::BAD_REQUEST,
+			Json(json!({"error": "Invalid 'account' provided"})),
 		)
 	})?;
-	let mut transaction = Transaction::new_with_payer(&[instruction], Some(&account));
+	let campaign = parse_campaign(params.campaign)?;
+
+	let latest_blockhash = with_retry(|| state.rpc_client.get_latest_blockhash())
+		.map_err(|err| rpc_unavailable("Failed to get latest blockhash", err))?;
+
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |elapsed| elapsed.as_secs() as i64);
+	let purchases = fetch_batch_purchases(&state, &account, now)?;
+	if purchases.is_empty() {
+		return Err((
+			StatusCode::BAD_REQUEST,
+			Json(json!({"error": "No tickets with tokens left to buy"})),
+		));
+	}
+
+	let (batch, chained) =
+		buy_all_instructions(&state.program_id, &account, &purchases).map_err(|err| {
+			(
+				StatusCode::INTERNAL_SERVER_ERROR,
+				Json(json!({"error": format!("Could not create `BuyToken` instruction: {}", err)})),
+			)
+		})?;
+	let message = format!("Buying from {} of {} tickets", batch.len(), purchases.len());
+
+	let whitelists = purchases
+		.iter()
+		.take(batch.len())
+		.map(|purchase| purchase.whitelist)
+		.collect::<Vec<_>>();
+	let mut instructions =
+		compute_budget_instructions(&state, BUY_COMPUTE_UNITS * batch.len() as u32, &whitelists)?;
+	instructions.extend(batch);
+	if let Some(ref campaign) = campaign {
+		instructions.push(campaign_memo(campaign));
+	}
+	let mut transaction = Transaction::new_with_payer(&instructions, Some(&account));
 	transaction.message.recent_blockhash = latest_blockhash;
 
-	let serialized_transaction = serialize(&transaction).map_err(|_| {
+	// The remaining tickets are offered by the next action once this transaction is confirmed
+	let next_href = match campaign {
+		Some(ref campaign) => format!("/api/actions/buy-all/next?campaign={}", campaign),
+		None => "/api/actions/buy-all/next".to_string(),
+	};
+	let Json(mut response) = post_response(state, &transaction, message, campaign)?;
+	if chained {
+		response.links = Some(PostLinks {
+			next: NextActionLink {
+				kind: "post",
+				href: next_href,
+			},
+		});
+	}
+
+	Ok(Json(response))
+}
+
+/// Called by the client once a `buy-all` transaction is confirmed, offers the next batch or
+/// completes the chain when no tickets are left to buy from
+pub(crate) async fn buy_all_next_post_request_handler(
+	State(state): State<Arc<AppState>>,
+	Query(params): Query<CampaignParams>,
+	Json(payload): Json<PostRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+	let account = Pubkey::from_str(&payload.account).map_err(|_| {
 		(
-			StatusCode::INTERNAL_SERVER_ERROR,
-			Json(json!({"error": "Failed to serialize transaction"})),
+			StatusCode::BAD_REQUEST,
+			Json(json!({"error": "Invalid 'account' provided"})),
 		)
 	})?;
+	let href = match parse_campaign(params.campaign)? {
+		Some(campaign) => format!("/api/actions/buy-all?campaign={}", campaign),
+		None => "/api/actions/buy-all".to_string(),
+	};
 
-	tokio::spawn(async move {
-		let _ = state.counter_tx.send(CounterMessage::Post).await;
-	});
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |elapsed| elapsed.as_secs() as i64);
+	let remaining = fetch_batch_purchases(&state, &account, now)?.len();
 
-	Ok(Json(PostResponse {
-		transaction: STANDARD.encode(serialized_transaction),
-		message: format!("Registered for whitelist"),
-	}))
+	let response = if remaining == 0 {
+		ActionGetResponse {
+			kind: Some("completed"),
+			title: "Whitelist - Buy from all tickets".into(),
+			icon: state.icon.clone(),
+			description: "Bought from every ticket".into(),
+			links: Links { actions: vec![] },
+		}
+	} else {
+		ActionGetResponse {
+			kind: Some("action"),
+			title: "Whitelist - Buy from all tickets".into(),
+			icon: state.icon.clone(),
+			description: format!("{} tickets left to buy from", remaining),
+			links: Links {
+				actions: vec![ActionLink {
+					label: "Buy Next".into(),
+					href,
+					parameters: None,
+				}],
+			},
+		}
+	};
+
+	Ok((StatusCode::OK, Json(response)))
+}
+
+#[cfg(test)]
+mod tests {
+	use {
+		super::*,
+		crate::monitor::Monitor,
+		fsp_wl::account_order::buy,
+		solana_sdk::{packet::PACKET_DATA_SIZE, program_pack::Pack, signature::Signature},
+	};
+
+	#[test]
+	fn test_buy_description_uses_configured_name() {
+		let mut mint_data = vec![0u8; Mint::LEN];
+		Mint {
+			decimals: 9,
+			is_initialized: true,
+			..Mint::default()
+		}
+		.pack_into_slice(&mut mint_data);
+
+		let summary = SaleSummary {
+			token_name: token_name(&mint_data, Some("FSP")),
+			price: price_per_token(1_000_000_000),
+			token_price: 1,
+			remaining: 42.0,
+		};
+		let description = buy_description(&summary);
+
+		assert!(description.contains("FSP"));
+		assert!(description.contains("1 SOL"));
+		assert!(description.contains("42 left"));
+	}
+
+	#[test]
+	fn test_buy_message_uses_program_pricing() {
+		// 2 tokens at 0.5 SOL per whole token, independent of the mint's decimals
+		for decimals in [0, 6, 9] {
+			assert_eq!(
+				buy_message(2, 500_000_000, decimals).unwrap(),
+				"Buying 2 tokens for 1 SOL"
+			);
+		}
+		assert!(buy_message(u64::MAX, u64::MAX, 0).is_err());
+		assert!(buy_message(u64::MAX / 10, 1, 9).is_err());
+	}
+
+	#[test]
+	fn test_buy_receipt() {
+		let mut wl_data = sample_response().whitelist;
+		wl_data.token_price = 500_000_000;
+		let ticket = Ticket {
+			version: Ticket::VERSION,
+			bump: 255,
+			whitelist: Pubkey::new_unique(),
+			owner: Pubkey::new_unique(),
+			payer: Pubkey::new_unique(),
+			allowance: 10_000_000_000,
+			amount_bought: 3_000_000_000,
+			claimed: 0,
+			note: [0; Ticket::NOTE_LEN],
+			referrer: Pubkey::default(),
+			last_purchase_ts: 0,
+		};
+		let reference = Pubkey::new_unique();
+
+		// 7 of the ticket's 10 tokens are left before the purchase
+
+		assert_eq!(
+			buy_receipt(2, &wl_data, &ticket, 9, &reference).unwrap(),
+			format!(
+				"Buying 2 tokens for 1 SOL, 5 tokens of allowance left after this purchase. Reference: {}",
+				reference
+			)
+		);
+		assert!(buy_receipt(7, &wl_data, &ticket, 9, &reference)
+			.unwrap()
+			.contains("0 tokens of allowance left"));
+		let err = buy_receipt(8, &wl_data, &ticket, 9, &reference).unwrap_err();
+		assert_eq!(
+			err.downcast_ref::<WhitelistError>(),
+			Some(&WhitelistError::BuyLimitExceeded)
+		);
+	}
+
+	fn sample_response() -> WhitelistResponse {
+		WhitelistResponse {
+			address: Pubkey::new_unique().to_string(),
+			whitelist: Whitelist {
+				version: Whitelist::VERSION,
+				bump: 255,
+				authority: Pubkey::new_unique(),
+				vault: Pubkey::new_unique(),
+				treasury: Pubkey::new_unique(),
+				mint: Pubkey::new_unique(),
+				token_price: 1,
+				buy_limit: 10,
+				deposited: 0,
+				whitelist_size: 5,
+				allow_registration: true,
+				registration_timestamp: 0,
+				registration_duration: 0,
+				sale_timestamp: 0,
+				sale_duration: 0,
+				registered: 0,
+				registration_paused: false,
+				sale_paused: false,
+				referral_bonus: 0,
+				max_tickets: 0,
+				cooldown_secs: 0,
+				refund_window_secs: 0,
+				authority_pays_rent: false,
+				register_authority: Pubkey::default(),
+			},
+			mint_decimals: 9,
+			vault_balance: 50,
+		}
+	}
+
+	#[test]
+	fn test_whitelist_response_json() {
+		let response = sample_response();
+		let vault = response.whitelist.vault.to_string();
+
+		let value = serde_json::to_value(response).unwrap();
+		assert_eq!(value["token_price"], 1);
+		assert_eq!(value["buy_limit"], 10);
+		assert_eq!(value["vault_balance"], 50);
+		assert_eq!(value["vault"], vault);
+	}
+
+	#[test]
+	fn test_sale_info_json() {
+		let mut response = sample_response();
+		response.whitelist.deposited = 40_000_000_000;
+		response.vault_balance = 30_000_000_000;
+		response.whitelist.token_price = 500_000_000;
+
+		let value = serde_json::to_value(sale_info(&response, 100)).unwrap();
+		assert_eq!(value["price"].as_f64(), Some(0.5));
+		assert_eq!(value["sold"].as_f64(), Some(10.0));
+		assert_eq!(value["remaining"].as_f64(), Some(30.0));
+		assert_eq!(value["phase"], "sale");
+	}
+
+	#[test]
+	fn test_sale_phase() {
+		let mut wl_data = sample_response().whitelist;
+		wl_data.registration_timestamp = 100;
+		wl_data.sale_timestamp = 200;
+		wl_data.sale_duration = 100;
+
+		assert_eq!(sale_phase(&wl_data, 1, 50), "upcoming");
+		assert_eq!(sale_phase(&wl_data, 1, 150), "registration");
+		assert_eq!(sale_phase(&wl_data, 1, 200), "sale");
+		assert_eq!(sale_phase(&wl_data, 0, 250), "sold_out");
+		assert_eq!(sale_phase(&wl_data, 1, 300), "ended");
+
+		wl_data.sale_paused = true;
+		assert_eq!(sale_phase(&wl_data, 1, 250), "paused");
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_unresponsive_rpc_returns_service_unavailable() {
+		// Accepts connections but never responds, so every request runs into the timeout
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}", listener.local_addr().unwrap());
+		let (counter_tx, _counter_rx) = tokio::sync::mpsc::channel(1);
+		let state = Arc::new(AppState::new(
+			Pubkey::new_unique(),
+			fsp_wl::id(),
+			url,
+			Duration::from_secs(1),
+			counter_tx,
+			None,
+			None,
+			String::new(),
+			None,
+		));
+
+		let start = Instant::now();
+		let result = buy_post_request_handler(
+			State(state),
+			Query(QueryParams {
+				amount: 1.0,
+				max_price: None,
+				campaign: None,
+			}),
+			Json(PostRequest {
+				account: Pubkey::new_unique().to_string(),
+			}),
+		)
+		.await;
+
+		let (status, _) = result.err().unwrap();
+		assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+		assert!(start.elapsed() < Duration::from_secs(10));
+	}
+
+	#[tokio::test]
+	async fn test_zero_amount_buy_is_rejected() {
+		let (counter_tx, _counter_rx) = tokio::sync::mpsc::channel(1);
+		// Never contacted, the amount is rejected before any RPC request is made
+		let state = Arc::new(AppState::new(
+			Pubkey::new_unique(),
+			fsp_wl::id(),
+			"http://127.0.0.1:1".to_string(),
+			Duration::from_secs(1),
+			counter_tx,
+			None,
+			None,
+			String::new(),
+			None,
+		));
+
+		let result = buy_post_request_handler(
+			State(state),
+			Query(QueryParams {
+				amount: 0.5,
+				max_price: None,
+				campaign: None,
+			}),
+			Json(PostRequest {
+				account: Pubkey::new_unique().to_string(),
+			}),
+		)
+		.await;
+
+		let (status, _) = result.err().unwrap();
+		assert_eq!(status, StatusCode::BAD_REQUEST);
+	}
+
+	#[tokio::test]
+	async fn test_campaign_is_attributed() {
+		let (counter_tx, mut counter_rx) = tokio::sync::mpsc::channel(1);
+		let state = Arc::new(AppState::new(
+			Pubkey::new_unique(),
+			fsp_wl::id(),
+			"http://127.0.0.1:1".to_string(),
+			Duration::from_secs(1),
+			counter_tx,
+			None,
+			None,
+			String::new(),
+			None,
+		));
+
+		let uri = "/api/actions/buy-token?amount=10&campaign=spring-a"
+			.parse()
+			.unwrap();
+		let Query(params) = Query::<QueryParams>::try_from_uri(&uri).unwrap();
+		let campaign = parse_campaign(params.campaign).unwrap();
+
+		// The memo is part of the message the authority signs
+		let transaction = add_user_transaction(
+			&fsp_wl::id(),
+			&Pubkey::new_unique(),
+			&Keypair::new(),
+			&Pubkey::new_unique(),
+			vec![],
+			campaign.as_deref(),
+			Hash::new_unique(),
+		)
+		.unwrap();
+		let message = &transaction.message;
+		let memo = &message.instructions[1];
+		assert_eq!(memo.program_id(&message.account_keys), &spl_memo::id());
+		assert_eq!(memo.data, b"campaign:spring-a");
+
+		let Json(response) = post_response(
+			state,
+			&transaction,
+			"Registered for whitelist".to_string(),
+			campaign,
+		)
+		.unwrap();
+		assert_eq!(
+			response.message,
+			"Registered for whitelist (campaign: spring-a)"
+		);
+
+		let mut monitor = Monitor::new(tokio::sync::mpsc::channel(1).1);
+		monitor.record(counter_rx.recv().await.unwrap());
+		monitor.record(CounterMessage::Post { campaign: None });
+		assert_eq!(monitor.campaign_count("spring-a"), 1);
+		assert_eq!(monitor.campaign_count("spring-b"), 0);
+
+		for invalid in ["", "spring a", "<b>", &"a".repeat(MAX_CAMPAIGN_LEN + 1)] {
+			assert!(parse_campaign(Some(invalid.to_string())).is_err());
+		}
+	}
+
+	#[test]
+	fn test_buy_all_batches_tickets() {
+		let owner = Pubkey::new_unique();
+		let purchase = |token_program: Pubkey| {
+			let whitelist = Pubkey::new_unique();
+			let (ticket, _) =
+				fsp_wl::get_user_ticket_address_with_program_id(&owner, &whitelist, &fsp_wl::id());
+			BatchPurchase {
+				whitelist,
+				mint: Pubkey::new_unique(),
+				token_program,
+				ticket,
+				amount: 10,
+				token_price: 1,
+			}
+		};
+
+		let mut purchases = vec![
+			purchase(spl_token_2022::id()),
+			purchase(Pubkey::new_unique()),
+		];
+		let (batch, chained) = buy_all_instructions(&fsp_wl::id(), &owner, &purchases).unwrap();
+		assert_eq!(batch.len(), 2);
+		assert!(!chained);
+		for (instruction, purchase) in batch.iter().zip(&purchases) {
+			assert_eq!(instruction.program_id, fsp_wl::id());
+			assert_eq!(instruction.accounts[buy::USER].pubkey, owner);
+			assert_eq!(
+				instruction.accounts[buy::USER_TICKET].pubkey,
+				purchase.ticket
+			);
+			assert_eq!(instruction.accounts[buy::MINT].pubkey, purchase.mint);
+			assert_eq!(
+				instruction.accounts[buy::TOKEN_PROGRAM].pubkey,
+				purchase.token_program
+			);
+		}
+
+		// Tickets past the limit are left for the chained transaction, which must still fit with
+		// mints across both token programs, the longest campaign memo and a compute budget
+		while purchases.len() <= MAX_BATCH_PURCHASES {
+			purchases.push(purchase(spl_token_2022::id()));
+		}
+		let (batch, chained) = buy_all_instructions(&fsp_wl::id(), &owner, &purchases).unwrap();
+		assert_eq!(batch.len(), MAX_BATCH_PURCHASES);
+		assert!(chained);
+		let mut instructions = vec![
+			ComputeBudgetInstruction::set_compute_unit_limit(u32::MAX),
+			ComputeBudgetInstruction::set_compute_unit_price(u64::MAX),
+		];
+		instructions.extend(batch);
+		instructions.push(campaign_memo(&"a".repeat(MAX_CAMPAIGN_LEN)));
+		let transaction = Transaction::new_with_payer(&instructions, Some(&owner));
+		assert!(serialize(&transaction).unwrap().len() <= PACKET_DATA_SIZE);
+	}
+
+	#[test]
+	fn test_purchasable() {
+		let mut wl_data = sample_response().whitelist;
+		let ticket = Ticket {
+			version: Ticket::VERSION,
+			bump: 255,
+			whitelist: Pubkey::new_unique(),
+			owner: Pubkey::new_unique(),
+			payer: Pubkey::new_unique(),
+			allowance: 5_000_000_000,
+			amount_bought: 2_000_000_000,
+			claimed: 0,
+			note: [0; Ticket::NOTE_LEN],
+			referrer: Pubkey::default(),
+			last_purchase_ts: 0,
+		};
+
+		assert_eq!(purchasable(&wl_data, &ticket, u64::MAX, 9, 0), Some(3));
+		// Capped by the vault and rounded down to whole tokens
+		assert_eq!(purchasable(&wl_data, &ticket, 1_500_000_000, 9, 0), Some(1));
+		assert_eq!(purchasable(&wl_data, &ticket, 500_000_000, 9, 0), None);
+
+		wl_data.sale_paused = true;
+		assert_eq!(purchasable(&wl_data, &ticket, u64::MAX, 9, 0), None);
+	}
+
+	#[test]
+	fn test_add_user_transaction_is_partially_signed() {
+		let program_id = Pubkey::new_unique();
+		let mint = Pubkey::new_unique();
+		let authority = Keypair::new();
+		let user = Pubkey::new_unique();
+
+		let transaction = add_user_transaction(
+			&program_id,
+			&mint,
+			&authority,
+			&user,
+			vec![],
+			None,
+			Hash::new_unique(),
+		)
+		.unwrap();
+
+		assert_eq!(transaction.message.header.num_required_signatures, 2);
+		assert_eq!(transaction.message.account_keys[0], user);
+		assert_eq!(transaction.message.account_keys[1], authority.pubkey());
+		assert_eq!(transaction.signatures[0], Signature::default());
+		assert_ne!(transaction.signatures[1], Signature::default());
+
+		// The whitelist and instruction must follow the program the server was pointed at
+		let (whitelist, _) = fsp_wl::get_whitelist_address_with_program_id(&mint, &program_id);
+		let message = &transaction.message;
+		assert_eq!(
+			message.instructions[0].program_id(&message.account_keys),
+			&program_id
+		);
+		assert!(message.account_keys.contains(&whitelist));
+	}
+
+	#[tokio::test]
+	async fn test_priority_fee_is_prepended() {
+		let (counter_tx, _counter_rx) = tokio::sync::mpsc::channel(1);
+		// Never contacted, a fixed fee needs no recent fees from the RPC
+		let state = Arc::new(AppState::new(
+			Pubkey::new_unique(),
+			fsp_wl::id(),
+			"http://127.0.0.1:1".to_string(),
+			Duration::from_secs(1),
+			counter_tx,
+			None,
+			None,
+			String::new(),
+			Some(PriorityFee::MicroLamports(5000)),
+		));
+
+		let compute_budget =
+			compute_budget_instructions(&state, REGISTER_COMPUTE_UNITS, &[]).unwrap();
+		let authority = Keypair::new();
+		let transaction = add_user_transaction(
+			&fsp_wl::id(),
+			&Pubkey::new_unique(),
+			&authority,
+			&Pubkey::new_unique(),
+			compute_budget,
+			None,
+			Hash::new_unique(),
+		)
+		.unwrap();
+
+		// The budget is covered by the authority's signature
+		assert!(transaction.verify_with_results()[1]);
+
+		let Json(response) = post_response(
+			state,
+			&transaction,
+			"Registered for whitelist".to_string(),
+			None,
+		)
+		.unwrap();
+		let decoded: Transaction =
+			bincode::deserialize(&STANDARD.decode(response.transaction).unwrap()).unwrap();
+		let message = &decoded.message;
+		assert_eq!(message.instructions.len(), 3);
+		for instruction in &message.instructions[..2] {
+			assert_eq!(
+				instruction.program_id(&message.account_keys),
+				&solana_sdk::compute_budget::id()
+			);
+		}
+		assert_eq!(
+			message.instructions[0].data,
+			ComputeBudgetInstruction::set_compute_unit_limit(REGISTER_COMPUTE_UNITS).data
+		);
+		assert_eq!(
+			message.instructions[1].data,
+			ComputeBudgetInstruction::set_compute_unit_price(5000).data
+		);
+		assert_eq!(
+			message.instructions[2].program_id(&message.account_keys),
+			&fsp_wl::id()
+		);
+
+		// Nothing is prepended when no fee is configured
+		let (counter_tx, _counter_rx) = tokio::sync::mpsc::channel(1);
+		let state = AppState::new(
+			Pubkey::new_unique(),
+			fsp_wl::id(),
+			"http://127.0.0.1:1".to_string(),
+			Duration::from_secs(1),
+			counter_tx,
+			None,
+			None,
+			String::new(),
+			None,
+		);
+		assert!(compute_budget_instructions(&state, BUY_COMPUTE_UNITS, &[])
+			.unwrap()
+			.is_empty());
+
+		assert_eq!(median_fee(vec![]), 0);
+		assert_eq!(median_fee(vec![300, 0, 100]), 100);
+	}
 }
@@ -1,3 +1,4 @@
+pub mod config;
 pub mod server;
 pub mod monitor;
 pub mod handlers;
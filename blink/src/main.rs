@@ -1,8 +1,12 @@
 use {
-	anyhow:: Result,
-	blink::server::Server,
+	anyhow::{anyhow, Result},
+	blink::{
+		config::{PriorityFee, ServerConfig},
+		server::Server,
+	},
 	clap::{command, Parser},
-    solana_sdk::pubkey::Pubkey,
+    solana_sdk::{pubkey::Pubkey, signature::read_keypair_file},
+	std::{path::PathBuf, time::Duration},
 };
 
 #[derive(Parser, Debug)]
@@ -11,25 +15,82 @@ use {
 	about = "A solana action/blink for the Superteam UK Whitelist-Gated Token Sale"
 )]
 struct Cli {
-	/// Address of the token for sale
-	mint: Pubkey,
+	/// Address of the token for sale, required unless set in the config file
+	mint: Option<Pubkey>,
+	/// Path to a TOML file holding any of the options below, flags override its values
+	#[arg(long)]
+	config: Option<PathBuf>,
 	/// RPC url values: t/testnet, d/devnet, m/mainnet, l/local, or a custom RPC
 	#[arg(short, long)]
 	url: Option<String>,
 	/// Path to a solana config file - must be a full path
-	#[arg(short, long)]
-	config: Option<String>,
+	#[arg(long)]
+	solana_config: Option<String>,
 	/// The exposed port, default: :8080
 	#[arg(short, long)]
 	port: Option<u16>,
+	/// Path to the whitelist authority keypair
+	///
+	/// When provided the register action adds users with `AddUser`, returning a transaction
+	/// already signed by the authority for the user to co-sign. This allows registration for
+	/// sales where `allow_registration` is false, but the server can then add any account to
+	/// the whitelist at the authority's expense, only use this on a host you trust with the
+	/// authority key.
+	#[arg(long)]
+	authority_keypair: Option<String>,
+	/// Name of the token shown in the action description, used when the mint has no
+	/// Token-2022 metadata
+	#[arg(long)]
+	token_name: Option<String>,
+	/// URL of the icon shown with the actions
+	#[arg(long)]
+	icon_url: Option<String>,
+	/// Timeout in seconds for each RPC request, default: 10
+	#[arg(long)]
+	rpc_timeout: Option<u64>,
+	/// Address of the whitelist program to use instead of the declared program id, e.g. a
+	/// deployment on a local validator
+	#[arg(long, env = "WL_PROGRAM_ID")]
+	program_id: Option<Pubkey>,
+	/// Priority fee added to the returned transactions: `auto` for the median of recent fees, or
+	/// a price in micro-lamports per compute unit. No fee is added when left unset
+	///
+	/// The fee is advisory, wallets may replace the compute budget before signing.
+	#[arg(long)]
+	priority_fee: Option<PriorityFee>,
+}
+
+impl Cli {
+	/// The options passed as flags, applied over those read from `--config`
+	fn overrides(self) -> ServerConfig {
+		ServerConfig {
+			mint: self.mint,
+			url: self.url,
+			solana_config: self.solana_config,
+			port: self.port,
+			authority_keypair: self.authority_keypair,
+			token_name: self.token_name,
+			icon_url: self.icon_url,
+			rpc_timeout: self.rpc_timeout,
+			program_id: self.program_id,
+			priority_fee: self.priority_fee,
+		}
+	}
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
 	let args = Cli::parse();
 
-	let mint = args.mint;
-	let solana_config_file = match args.config {
+	let args = match args.config.clone() {
+		Some(path) => ServerConfig::load(&path)?.merge(args.overrides()),
+		None => args.overrides(),
+	};
+
+	let mint = args.mint.ok_or_else(|| {
+		anyhow!("No mint given, pass it as an argument or set it in the config file")
+	})?;
+	let solana_config_file = match args.solana_config {
 		Some(path) => solana_cli_config::Config::load(&path).unwrap_or_default(),
 		None => {
 			if let Some(ref config) = *solana_cli_config::CONFIG_FILE {
@@ -51,8 +112,29 @@ async fn main() -> Result<()> {
 		None => solana_config_file.json_rpc_url,
 	};
 
+	let authority = match args.authority_keypair {
+		Some(path) => Some(
+			read_keypair_file(&path)
+				.map_err(|err| anyhow!("Unable to read authority keypair file: {}", err))?,
+		),
+		None => None,
+	};
+
 	let port = args.port.unwrap_or(8080);
-	let server = Server::new(mint, url, port).await;
+	let rpc_timeout = Duration::from_secs(args.rpc_timeout.unwrap_or(10));
+	let program_id = args.program_id.unwrap_or_else(fsp_wl::id);
+	let server = Server::new(
+		mint,
+		program_id,
+		url,
+		rpc_timeout,
+		port,
+		authority,
+		args.token_name,
+		args.icon_url.unwrap_or_default(),
+		args.priority_fee,
+	)
+	.await;
 	server.run().await?;
 
     Ok(())
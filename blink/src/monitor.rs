@@ -1,6 +1,6 @@
 use {
 	indicatif::{ProgressBar, ProgressStyle},
-	std::time::Instant,
+	std::{collections::BTreeMap, time::Instant},
 	tokio::{
 		sync::mpsc,
 		time::{interval, Duration, Interval},
@@ -13,12 +13,14 @@ pub struct Monitor {
 	update_interval: Interval,
 	get_counter: u64,
 	post_counter: u64,
+	/// POST requests made through a campaign's action links, keyed by campaign id
+	campaign_counters: BTreeMap<String, u64>,
 	counter_rx: mpsc::Receiver<CounterMessage>,
 }
 
 pub enum CounterMessage {
 	Get,
-	Post,
+	Post { campaign: Option<String> },
 }
 
 impl Monitor {
@@ -38,10 +40,28 @@ impl Monitor {
 			update_interval: interval(Duration::from_millis(80)),
 			get_counter: 0,
 			post_counter: 0,
+			campaign_counters: BTreeMap::new(),
 			counter_rx,
 		}
 	}
 
+	pub fn record(&mut self, message: CounterMessage) {
+		match message {
+			CounterMessage::Get => self.get_counter += 1,
+			CounterMessage::Post { campaign } => {
+				self.post_counter += 1;
+				if let Some(campaign) = campaign {
+					*self.campaign_counters.entry(campaign).or_default() += 1;
+				}
+			}
+		}
+	}
+
+	/// Number of POST requests attributed to `campaign`
+	pub fn campaign_count(&self, campaign: &str) -> u64 {
+		self.campaign_counters.get(campaign).copied().unwrap_or(0)
+	}
+
 	fn update_spinner(&mut self) {
 		self.spinner.set_message(self.get_display_string());
 	}
@@ -57,13 +77,19 @@ impl Monitor {
 	fn get_display_string(&self) -> String {
 		let get_text = format!("\x1b[1m{}\x1b[0m requests:", "GET");
 		let post_text = format!("\x1b[1m{}\x1b[0m requests:", "POST");
+		let campaigns: String = self
+			.campaign_counters
+			.iter()
+			.map(|(campaign, count)| format!(" | {}: {}", campaign, count))
+			.collect();
 		format!(
-			"Server running... | {} | {} {} | {} {}",
+			"Server running... | {} | {} {} | {} {}{}",
 			self.get_elapsed_time(),
 			get_text,
 			self.get_counter,
 			post_text,
-			self.post_counter
+			self.post_counter,
+			campaigns
 		)
 	}
 
@@ -72,10 +98,7 @@ impl Monitor {
 		loop {
 			tokio::select! {
 				Some(message) = self.counter_rx.recv() => {
-						match message {
-							CounterMessage::Get => self.get_counter += 1,
-							CounterMessage::Post => self.post_counter += 1,
-							}
+					self.record(message);
 					self.update_spinner();
 				},
 				_ = self.update_interval.tick() => {
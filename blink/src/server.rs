@@ -1,5 +1,6 @@
 use {
 	crate::{
+		config::PriorityFee,
 		handlers::*,
 		monitor::{CounterMessage, Monitor},
 	},
@@ -13,25 +14,61 @@ use {
 		Router,
 	},
 	solana_client::rpc_client::RpcClient,
-	solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey},
-	std::sync::Arc,
+	serde_json::Value,
+	solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Keypair},
+	std::{
+		sync::{Arc, Mutex},
+		time::{Duration, Instant},
+	},
 	tokio::{net::TcpListener, sync::mpsc},
 	tower_http::cors::{Any, CorsLayer},
 };
 
 pub(crate) struct AppState {
 	pub(crate) mint: Pubkey,
+	/// Whitelist program the sale accounts belong to
+	pub(crate) program_id: Pubkey,
 	pub(crate) rpc_client: RpcClient,
 	pub(crate) counter_tx: mpsc::Sender<CounterMessage>,
+	pub(crate) authority: Option<Keypair>,
+	pub(crate) token_name: Option<String>,
+	pub(crate) icon: String,
+	/// Priority fee prepended to the returned transactions, none when unset
+	pub(crate) priority_fee: Option<PriorityFee>,
+	/// Last response of `GET /api/whitelist` and when it was fetched
+	pub(crate) whitelist_cache: Mutex<Option<(Instant, Value)>>,
+	/// Last response of `GET /api/sale-info` and when it was fetched
+	pub(crate) sale_info_cache: Mutex<Option<(Instant, Value)>>,
 }
 
 impl AppState {
-	pub fn new(mint: Pubkey, url: String, counter_tx: mpsc::Sender<CounterMessage>) -> Self {
-		let rpc_client = RpcClient::new_with_commitment(url, CommitmentConfig::confirmed());
+	pub fn new(
+		mint: Pubkey,
+		program_id: Pubkey,
+		url: String,
+		rpc_timeout: Duration,
+		counter_tx: mpsc::Sender<CounterMessage>,
+		authority: Option<Keypair>,
+		token_name: Option<String>,
+		icon: String,
+		priority_fee: Option<PriorityFee>,
+	) -> Self {
+		let rpc_client = RpcClient::new_with_timeout_and_commitment(
+			url,
+			rpc_timeout,
+			CommitmentConfig::confirmed(),
+		);
 		AppState {
 			mint,
+			program_id,
 			rpc_client,
 			counter_tx,
+			authority,
+			token_name,
+			icon,
+			priority_fee,
+			whitelist_cache: Mutex::new(None),
+			sale_info_cache: Mutex::new(None),
 		}
 	}
 }
@@ -43,7 +80,17 @@ pub struct Server {
 }
 
 impl Server {
-	pub async fn new(mint: Pubkey, url: String, port: u16) -> Self {
+	pub async fn new(
+		mint: Pubkey,
+		program_id: Pubkey,
+		url: String,
+		rpc_timeout: Duration,
+		port: u16,
+		authority: Option<Keypair>,
+		token_name: Option<String>,
+		icon: String,
+		priority_fee: Option<PriorityFee>,
+	) -> Self {
 		let (counter_tx, counter_rx) = mpsc::channel(1024);
 
 		let cors = CorsLayer::new()
@@ -56,7 +103,17 @@ impl Server {
 			])
 			.allow_origin(Any);
 
-		let state = Arc::new(AppState::new(mint, url, counter_tx));
+		let state = Arc::new(AppState::new(
+			mint,
+			program_id,
+			url,
+			rpc_timeout,
+			counter_tx,
+			authority,
+			token_name,
+			icon,
+			priority_fee,
+		));
 
 		let app = Router::new()
 			.route("/actions.json", get(get_request_actions_json))
@@ -65,6 +122,14 @@ impl Server {
 				"/api/actions/buy-token",
 				post(buy_post_request_handler),
 			)
+			.route("/api/actions/buy-all", get(buy_all_get_request_handler))
+			.route("/api/actions/buy-all", post(buy_all_post_request_handler))
+			.route(
+				"/api/actions/buy-all/next",
+				post(buy_all_next_post_request_handler),
+			)
+			.route("/api/whitelist", get(whitelist_get_request_handler))
+			.route("/api/sale-info", get(sale_info_get_request_handler))
 			.route("/api/actions/register", get(reg_get_request_handler))
 			.route(
 				"/api/actions/register",
@@ -1,21 +1,53 @@
 use {
 	anyhow::{anyhow, Result},
+	base64::{engine::general_purpose::STANDARD, Engine},
 	borsh::BorshDeserialize,
-	chrono::NaiveDateTime,
+	chrono::{NaiveDateTime, Utc},
 	clap::{command, Args, Parser, Subcommand},
+	serde_json::{json, Value},
+	solana_account_decoder::UiAccountEncoding,
 	solana_cli_config,
-	solana_client::rpc_client::RpcClient,
-	solana_program::{instruction::Instruction, pubkey::Pubkey},
+	solana_client::{
+		client_error::ClientError,
+		rpc_client::RpcClient,
+		rpc_config::{
+			RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcSimulateTransactionAccountsConfig,
+			RpcSimulateTransactionConfig,
+		},
+		rpc_filter::{Memcmp, RpcFilterType},
+	},
+	solana_program::{
+		instruction::{AccountMeta, Instruction},
+		message::Message,
+		pubkey::Pubkey,
+	},
 	solana_sdk::{
+		account::Account as SdkAccount,
 		commitment_config::CommitmentConfig,
-		signature::{read_keypair_file, Signer},
-		transaction::Transaction,
+		hash::Hash,
+		instruction::InstructionError,
+		native_token::lamports_to_sol,
+		packet::PACKET_DATA_SIZE,
+		signature::{read_keypair_file, Keypair, Signature, Signer},
+		transaction::{Transaction, TransactionError},
 	},
 	spl_token_2022::{
-		extension::StateWithExtensions,
+		extension::{ExtensionType, StateWithExtensions},
 		state::{Account, Mint},
 	},
-	stuk_wl::{get_user_ticket_address, get_whitelist_address, instructions},
+	std::{
+		collections::HashMap,
+		io::{BufRead, IsTerminal, Write},
+		path::{Path, PathBuf},
+	},
+	stuk_wl::{
+		error::WhitelistError,
+		get_allocation_address_with_program_id, get_depositor_address_with_program_id,
+		get_treasury_address_with_program_id, get_user_ticket_address_with_program_id,
+		get_whitelist_address_with_program_id,
+		instructions::{self, PauseTarget},
+		MAX_BATCH_USERS,
+	},
 };
 
 #[derive(Parser, Debug)]
@@ -26,6 +58,30 @@ struct Cli {
 	rpc: Option<String>,
 	#[arg(short, long)]
 	payer: Option<String>,
+	/// Skip the confirmation prompt for destructive commands (close, bulk burn, withdraw) and buy
+	#[arg(short, long, global = true)]
+	yes: bool,
+	/// Print the transaction signature as soon as it is sent instead of waiting for it to be
+	/// confirmed, confirming it is left to the caller
+	#[arg(long, global = true)]
+	no_wait: bool,
+	/// Address of the whitelist program to use instead of the declared program id, e.g. a
+	/// deployment on a local validator
+	#[arg(long, global = true)]
+	program_id: Option<Pubkey>,
+	/// Sign the transaction and write it base64 encoded to this file instead of sending it, for a
+	/// relayer to broadcast before its blockhash expires. Not supported by commands that send
+	/// more than one transaction
+	#[arg(long, global = true)]
+	out_signed: Option<PathBuf>,
+	/// Keypair that pays the transaction fees, signing alongside the wallet, e.g. a dedicated
+	/// relayer wallet so the authority key does not need to hold SOL for fees
+	#[arg(long, global = true)]
+	fee_payer: Option<String>,
+	/// Ticket addresses written by `precompute`, looked up instead of deriving them for the
+	/// users it lists
+	#[arg(long, global = true)]
+	ticket_cache: Option<PathBuf>,
 	#[command(subcommand)]
 	cmd: Commands,
 }
@@ -44,18 +100,87 @@ enum Commands {
 	User(UserManagement),
 
 	/// Buy tokens
-	Buy(TokenFields),
+	Buy(BuyFields),
 
 	/// Deposit tokens into the vault
-	Deposit(TokenFields),
+	Deposit(DepositFields),
 
 	/// Withdraw tokens from the vault - authority only
 	Withdraw(TokenFields),
 
+	/// Reclaim tokens deposited with `deposit --credit`, only before the sale starts
+	WithdrawDeposit {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// Amount of tokens you wish to reclaim
+		amount: u64,
+	},
+
+	/// Move the SOL paid for purchases from a user's ticket to the treasury - authority only
+	///
+	/// Without `--user` the SOL held by the whitelist account itself is withdrawn. The ticket or
+	/// whitelist keeps its rent
+	WithdrawSol {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// Owner of the ticket to withdraw from
+		#[arg(long)]
+		user: Option<Pubkey>,
+
+		/// Lamports to withdraw, defaults to everything above the rent-exempt minimum
+		#[arg(long)]
+		amount: Option<u64>,
+	},
+
+	/// Move SOL out of the sale's treasury PDA, for sales initialised with `--treasury-pda` -
+	/// authority only
+	///
+	/// The treasury keeps its rent
+	WithdrawTreasury {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// Address to send the SOL to, defaults to your wallet
+		#[arg(long)]
+		recipient: Option<Pubkey>,
+
+		/// Lamports to withdraw, defaults to everything above the rent-exempt minimum
+		#[arg(long)]
+		amount: Option<u64>,
+	},
+
+	/// Return tokens you bought for their price, only within the sale's refund window of your
+	/// latest purchase
+	Refund {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// Amount of tokens you wish to return
+		amount: u64,
+	},
+
 	/// Amend whitelist size or registration/token sale times/duration
 	#[command(subcommand)]
 	Amend(Detail),
 
+	/// Set the whole schedule at once, registration runs from <REGISTRATION_START> until the
+	/// sale opens at <SALE_START> and the sale runs until <SALE_END> - authority only
+	Schedule {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// When registration starts. Format: YYYY-MM-DD HH:MM:SS (UTC)
+		registration_start: String,
+
+		/// When registration ends and the token sale starts. Format: YYYY-MM-DD HH:MM:SS (UTC)
+		sale_start: String,
+
+		/// When the token sale stops. Format: YYYY-MM-DD HH:MM:SS (UTC)
+		sale_end: String,
+	},
+
 	/// Commence registration/token sale
 	#[command(subcommand)]
 	Start(Start),
@@ -68,10 +193,79 @@ enum Commands {
 		allow: String,
 	},
 
+	/// Pause or resume registration or buying without affecting the other - authority only
+	Pause {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// Pause registration
+		#[arg(long, required_unless_present = "sale", conflicts_with = "sale")]
+		registration: bool,
+
+		/// Pause buying
+		#[arg(long)]
+		sale: bool,
+
+		/// Resume the paused target instead of pausing it
+		#[arg(long)]
+		resume: bool,
+	},
+
 	/// Register to the whitelist
 	Register {
 		/// Mint of the token sale
 		mint: Pubkey,
+
+		/// Registered user who referred you, credited the whitelist's referral bonus
+		#[arg(long)]
+		referrer: Option<Pubkey>,
+	},
+
+	/// Set the extra allowance credited to a referrer for each referred registration, 0
+	/// disables the bonus - authority only
+	ReferralBonus {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// Bonus allowance in whole tokens
+		bonus: u64,
+	},
+
+	/// Reserve an allowance for a user ahead of registration, taken by their ticket instead of
+	/// the buy limit when they register - authority only
+	Allocate {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// User to reserve the allowance for
+		user: Pubkey,
+
+		/// Allowance in whole tokens
+		amount: u64,
+	},
+
+	/// Hand the whitelist to another authority
+	#[command(subcommand)]
+	Authority(Authority),
+
+	/// Gate registration behind vouchers signed by a register authority, omit the authority to
+	/// open registration again - authority only
+	RegisterAuthority {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// Key that signs registration vouchers
+		authority: Option<Pubkey>,
+	},
+
+	/// Set the seconds a ticket must wait between purchases, 0 disables the cooldown - authority
+	/// only
+	Cooldown {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// Cooldown in seconds
+		seconds: u64,
 	},
 
 	/// Unregister from the whitelist and claim rent
@@ -80,10 +274,52 @@ enum Commands {
 		mint: Pubkey,
 	},
 
+	/// Close your empty token account for the sale's mint, such as the one created when you
+	/// bought, and reclaim its rent
+	Cleanup {
+		/// Mint of the token sale
+		mint: Pubkey,
+	},
+
+	/// Pay the rent of a ticket the authority added you with, reimbursing whoever funded it so
+	/// the rent is returned to you when the ticket is closed
+	ClaimTicketRent {
+		/// Mint of the token sale
+		mint: Pubkey,
+	},
+
 	/// Burn ticket and reclaims tokens + lamports to treasury
 	#[command(subcommand)]
 	Burn(Method),
 
+	/// Close every remaining ticket of a sale and reclaim its rent, also after the whitelist has
+	/// been terminated
+	///
+	/// While the whitelist is live this is authority only and the rent goes to its treasury. Once
+	/// it is closed only the tickets paid for by your wallet can be reclaimed
+	ReclaimRent {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// Address to send the rent to once the whitelist is closed, defaults to your wallet
+		#[arg(long)]
+		treasury: Option<Pubkey>,
+	},
+
+	/// Close every ticket of an ended sale whose token account holds no tokens, packing as many
+	/// tickets into each `CloseTickets` instruction as fit in a transaction
+	///
+	/// While the whitelist is live this is authority only, the sale must have ended and the rent
+	/// goes to its treasury. Once it is closed only the tickets paid for by your wallet are closed
+	CloseTickets {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// Address to send the rent to once the whitelist is closed, defaults to your wallet
+		#[arg(long)]
+		treasury: Option<Pubkey>,
+	},
+
 	/// Terminate the whitelist and send tokens to the recipient
 	Close {
 		/// Mint of the token sale
@@ -93,18 +329,106 @@ enum Commands {
 		recipient: Option<Pubkey>,
 	},
 
+	/// Close the vault once the sale has ended, sending its tokens to the recipient and keeping
+	/// the whitelist - authority only
+	CloseVault {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// Address to send tokens to, if `None` then defaults to authority wallet
+		recipient: Option<Pubkey>,
+	},
+
 	/// Get info about the whitelist or a specific ticket
 	#[command(subcommand)]
 	Info(Info),
+
+	/// Migrate a whitelist created with an older account layout - authority only
+	Migrate {
+		/// Mint of the token sale
+		mint: Pubkey,
+	},
+
+	/// Migrate a ticket created with an older account layout, the payer funds the extra rent
+	MigrateTicket {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// Public key of the ticket's user
+		user: Pubkey,
+	},
+
+	/// Check that the whitelist and its tickets match the account layout this client expects
+	Doctor {
+		/// Mint of the token sale
+		mint: Pubkey,
+	},
+
+	/// Print the payer, RPC URL, commitment and program id commands will use, without sending
+	/// anything
+	Whoami,
+
+	/// Estimate the rent and tokens needed to run a sale before initialising it
+	Estimate {
+		/// The number of subscribers allowed in the whitelist
+		#[arg(long)]
+		whitelist_size: u64,
+
+		/// Number of tokens a whitelist member can purchase
+		#[arg(long)]
+		buy_limit: u64,
+
+		/// Decimals of the mint that is to be sold
+		#[arg(long)]
+		decimals: u8,
+	},
+
+	/// Derive the ticket address of every user in a file once and write them to a cache, to be
+	/// passed to later commands with `--ticket-cache`
+	Precompute {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// File listing one user public key per line, blank lines and lines starting with `#`
+		/// are skipped
+		#[arg(long)]
+		from_file: PathBuf,
+
+		/// File to write the cache to
+		#[arg(long)]
+		out: PathBuf,
+	},
 }
 
 #[derive(Subcommand, Debug)]
 enum UserManagement {
 	/// Add a user to the whitelist
-	Add(UserManagementCommonFields),
+	Add(AddUserFields),
+
+	/// Add several users to the whitelist in one transaction
+	AddBatch {
+		/// Public key of the mint of the token associated with the whitelist
+		mint: Pubkey,
+
+		/// Public keys of the users, at most 8
+		#[arg(required = true)]
+		users: Vec<Pubkey>,
+	},
 
 	/// Remove a user from the whitelist and claim rent
 	Remove(UserManagementCommonFields),
+
+	/// Set an informational note on a user's ticket - authority only
+	Note {
+		/// Public key of the mint of the token associated with the whitelist
+		mint: Pubkey,
+
+		/// Public key of the user
+		user: Pubkey,
+
+		/// Note to attach to the ticket, at most 32 bytes
+		note: String,
+	},
 }
 
 #[derive(Args, Debug)]
@@ -116,6 +440,16 @@ struct UserManagementCommonFields {
 	user: Pubkey,
 }
 
+#[derive(Args, Debug)]
+struct AddUserFields {
+	#[command(flatten)]
+	common: UserManagementCommonFields,
+
+	/// Also seed the new ticket's token account with the whitelist's buy limit from the vault
+	#[arg(long)]
+	seed: bool,
+}
+
 #[derive(Subcommand, Debug)]
 enum Method {
 	/// Withdraw from a single ticket instance
@@ -125,6 +459,10 @@ enum Method {
 	Bulk {
 		/// Mint of the token sale
 		mint: Pubkey,
+
+		/// Write a CSV of the tokens and lamports swept from each burned ticket to this path
+		#[arg(long)]
+		report: Option<PathBuf>,
 	},
 }
 
@@ -146,6 +484,10 @@ enum Detail {
 
 		/// When token sale stops. Format: YYYY-MM-DD HH:MM:SS (UTC)
 		sale_end_time: Option<String>,
+
+		/// Allow registration to stay open after the token sale starts
+		#[arg(long)]
+		allow_overlap: bool,
 	},
 
 	/// Amend whitelist size
@@ -156,6 +498,47 @@ enum Detail {
 		/// Desired whitelist size. `0` == no limit
 		size: u64,
 	},
+
+	/// Amend the token price, only before the sale starts
+	Price {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// Price of one whole token in lamports
+		price: u64,
+	},
+
+	/// Amend the buy limit of tickets registered from now on, existing tickets keep theirs
+	/// until synced with `amend sync-allowance`
+	BuyLimit {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// Number of tokens a whitelist member can purchase
+		buy_limit: u64,
+	},
+
+	/// Raise a user's ticket allowance to the current buy limit
+	SyncAllowance {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// Owner of the ticket
+		user: Pubkey,
+	},
+}
+
+#[derive(Subcommand, Debug)]
+enum Authority {
+	/// Transfer the whitelist to <NEW_AUTHORITY>, you can no longer manage it afterwards -
+	/// authority only
+	Set {
+		/// Mint of the token sale
+		mint: Pubkey,
+
+		/// Key that takes over the whitelist
+		new_authority: Pubkey,
+	},
 }
 
 #[derive(Subcommand, Debug)]
@@ -164,7 +547,13 @@ enum Start {
 	Registration { mint: Pubkey },
 
 	/// Commences the token sale
-	Sale { mint: Pubkey },
+	Sale {
+		mint: Pubkey,
+
+		/// Refuse to start unless the vault holds enough tokens to fill every ticket
+		#[arg(long)]
+		require_fully_funded: bool,
+	},
 }
 
 #[derive(Subcommand, Debug)]
@@ -179,6 +568,16 @@ enum Info {
 		/// User ticket address
 		user: Pubkey,
 	},
+
+	/// List every ticket of the whitelist with its token account balance, sorted by amount
+	/// bought
+	Tickets {
+		/// Mint of the token sale
+		mint: Pubkey,
+	},
+
+	/// List every whitelist created by the program with its mint
+	Whitelists,
 }
 
 #[derive(Args, Debug)]
@@ -193,6 +592,49 @@ struct TokenFields {
 	#[clap(long)]
 	recipient: Option<Pubkey>,
 }
+
+#[derive(Args, Debug)]
+struct BuyFields {
+	/// Mint of the token associated with the whitelist
+	mint: Pubkey,
+
+	/// Amount of tokens you wish to buy
+	amount: u64,
+
+	/// Split the purchase into transactions of at most this many tokens, stops once the
+	/// ticket's allowance is exhausted
+	#[clap(long)]
+	chunk: Option<u64>,
+
+	/// Simulate the purchase and print its outcome without sending a transaction
+	#[clap(long, conflicts_with = "chunk")]
+	quote: bool,
+
+	/// Buy what the vault holds when it cannot cover the full amount, rather than failing
+	#[clap(long)]
+	allow_partial: bool,
+}
+
+#[derive(Args, Debug)]
+struct DepositFields {
+	/// Mint of the token associated with the whitelist
+	mint: Pubkey,
+
+	/// Amount of tokens you wish to deposit
+	#[clap(required_unless_present = "fill")]
+	amount: Option<u64>,
+
+	/// Deposit exactly enough tokens to fill every ticket in the whitelist
+	/// (whitelist size * buy limit - current vault balance)
+	#[clap(long, conflicts_with = "amount")]
+	fill: bool,
+
+	/// Credit the deposit to your wallet so it can be reclaimed with `withdraw-deposit` before
+	/// the sale starts
+	#[clap(long)]
+	credit: bool,
+}
+
 #[derive(Args, Clone, Debug)]
 struct TicketFields {
 	/// Mint of the token
@@ -241,6 +683,34 @@ struct Init {
 	/// When token sale ends. Format: YYYY-MM-DD HH:MM:SS
 	#[clap(long)]
 	sale_end_time: Option<String>,
+
+	/// Most tickets that can be open at once, applied even when the whitelist size is 0.
+	/// 0 disables the ceiling
+	#[clap(long, default_value_t = 0)]
+	max_tickets: u64,
+
+	/// Seconds after a purchase during which the buyer may refund it, 0 disables refunds
+	#[clap(long, default_value_t = 0)]
+	refund_window_secs: u64,
+
+	/// Charge the rent of tickets opened with `register` to the authority, who must co-sign
+	/// each registration
+	#[clap(long)]
+	authority_pays_rent: bool,
+
+	/// Run a free sale where buyers pay no SOL, required for a price of 0
+	#[clap(long)]
+	free: bool,
+
+	/// Pay the proceeds into a treasury PDA held by the program instead of TREASURY, which is
+	/// then ignored. Only the authority can take them out, with `withdraw-treasury`
+	#[clap(long)]
+	treasury_pda: bool,
+
+	/// Directory to write `sale.json` to on success, recording every address and the
+	/// configuration of the sale
+	#[clap(long)]
+	out_dir: Option<PathBuf>,
 }
 
 fn main() -> Result<()> {
@@ -270,77 +740,124 @@ fn main() -> Result<()> {
 	};
 
 	let wallet_pubkey = wallet_keypair.pubkey();
+	let fee_payer = match args.fee_payer {
+		Some(ref path) => Some(
+			read_keypair_file(path)
+				.map_err(|err| anyhow!("Unable to read fee payer keypair file: {}", err))?,
+		),
+		None => None,
+	};
+	let skip_confirmation = args.yes;
+	let no_wait = args.no_wait;
+	let out_signed = args.out_signed;
+	let program = WhitelistProgram::new(args.program_id);
+	let program = match args.ticket_cache {
+		Some(ref path) => {
+			let cache = TicketCache::load(path, &program.id)?;
+			program.with_ticket_cache(cache)
+		}
+		None => program,
+	};
 
 	let client = RpcClient::new_with_commitment(
 		solana_config_file.json_rpc_url.to_string(),
 		CommitmentConfig::confirmed(),
 	);
 
+	// Written after the transaction succeeds, along with its TXID
+	let mut sale_record: Option<(PathBuf, Value)> = None;
+
 	let instruction: Instruction = match args.cmd {
 		Commands::Init(fields) => {
-			let whitelist = get_whitelist_address(&fields.mint).0;
+			let (whitelist, whitelist_bump) =
+				get_whitelist_address_with_program_id(&fields.mint, &program.id);
 
 			// Retrieve the correct token program from the mint's owner
 			let mint_account = client.get_account(&fields.mint)?;
 			let token_program = mint_account.owner;
 
-			let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
-				&whitelist,
-				&fields.mint,
-				&token_program,
-			);
+			let vault = program.vault_address(&fields.mint, &token_program);
+
+			if fields.price == 0 && !fields.free {
+				return Err(anyhow!(
+					"A price of 0 gives the tokens away, pass --free to run a free sale"
+				));
+			}
+
+			let now = Utc::now().timestamp();
 
 			let registration_start_timestamp = match fields.registration_start_time {
-				Some(ref time) => string_to_timestamp(time.to_string())?,
+				Some(ref time) => check_start_time(string_to_timestamp(time.to_string())?, now)?,
 				None => 0,
 			};
 
 			let registration_duration = match fields.registration_end_time {
-				Some(ref time) => {
-					let ts = string_to_timestamp(time.to_string()).expect("error parsing time");
-
-					if registration_start_timestamp > 0 && registration_start_timestamp < ts {
-						ts - registration_start_timestamp
-					} else {
-						return Err(anyhow!(
-							"Cannot compute duration, start time is after provided end time"
-						));
-					}
-				}
+				Some(ref time) => duration_between(
+					registration_start_timestamp,
+					string_to_timestamp(time.to_string())?,
+				)?,
 				None => 0,
 			};
 
 			let sale_start_timestamp = match fields.sale_start_time {
-				Some(ref time) => string_to_timestamp(time.to_string())?,
+				Some(ref time) => check_start_time(string_to_timestamp(time.to_string())?, now)?,
 				None => 0,
 			};
 
 			let sale_duration = match fields.sale_end_time {
 				Some(ref time) => {
-					let ts = string_to_timestamp(time.to_string()).expect("error parsing time");
-
-					if sale_start_timestamp > 0 && sale_start_timestamp < ts {
-						ts - sale_start_timestamp
-					} else {
-						return Err(anyhow!(
-							"Cannot compute duration, start time is after provided end time"
-						));
-					}
+					duration_between(sale_start_timestamp, string_to_timestamp(time.to_string())?)?
 				}
 				None => 0,
 			};
 
+			let treasury = if fields.treasury_pda {
+				program.treasury_address(&fields.mint)
+			} else {
+				fields.treasury
+			};
+
 			println!("Whitelist Account: {}", whitelist);
 			println!("Vault Account: {}", vault);
-			println!("Treasury: {}", wallet_pubkey);
+			println!("Treasury: {}", treasury);
 			println!("Mint: {}", fields.mint);
 
+			if let Some(ref out_dir) = fields.out_dir {
+				sale_record = Some((
+					out_dir.clone(),
+					json!({
+						"whitelist": whitelist.to_string(),
+						"whitelist_bump": whitelist_bump,
+						"vault": vault.to_string(),
+						"treasury": treasury.to_string(),
+						"mint": fields.mint.to_string(),
+						"authority": wallet_pubkey.to_string(),
+						"token_program": token_program.to_string(),
+						"config": {
+							"token_price": fields.price,
+							"buy_limit": fields.buy_limit,
+							"whitelist_size": fields.whitelist_size,
+							"allow_registration": fields.allow_registration,
+							"registration_timestamp": registration_start_timestamp,
+							"registration_duration": registration_duration,
+							"sale_timestamp": sale_start_timestamp,
+							"sale_duration": sale_duration,
+							"max_tickets": fields.max_tickets,
+							"refund_window_secs": fields.refund_window_secs,
+							"authority_pays_rent": fields.authority_pays_rent,
+							"allow_free": fields.free,
+							"treasury_is_pda": fields.treasury_pda,
+						},
+					}),
+				));
+			}
+
 			instructions::init_whitelist(
 				&whitelist,
 				&wallet_pubkey,
 				&vault,
 				&fields.mint,
-				&fields.treasury,
+				&treasury,
 				fields.price,
 				fields.buy_limit,
 				fields.whitelist_size,
@@ -349,6 +866,11 @@ fn main() -> Result<()> {
 				registration_duration,
 				sale_start_timestamp,
 				sale_duration,
+				fields.max_tickets,
+				fields.refund_window_secs,
+				fields.authority_pays_rent,
+				fields.free,
+				fields.treasury_pda,
 				&token_program,
 			)
 			.map_err(|err| {
@@ -359,41 +881,131 @@ fn main() -> Result<()> {
 			})?
 		}
 		Commands::User(subcommand) => match subcommand {
-			UserManagement::Add(fields) => {
-				let whitelist = get_whitelist_address(&fields.mint).0;
-				let user_ticket = get_user_ticket_address(&fields.user, &whitelist).0;
+			UserManagement::Add(AddUserFields {
+				common: fields,
+				seed,
+			}) => {
+				let whitelist = program.whitelist_address(&fields.mint);
+				let user_ticket = program.ticket_address(&fields.user, &whitelist);
 
 				println!("User Whitelist Account: {}", user_ticket);
 
-				instructions::add_user(
-					&whitelist,
-					&wallet_pubkey,
-					&fields.mint,
-					&fields.user,
-					&user_ticket,
-				)
-				.map_err(|err| anyhow!("Unable to create `AddUser` instruction: {}", err))?
-			}
-			UserManagement::Remove(fields) => {
-				let (whitelist, _) = get_whitelist_address(&fields.mint);
-				let (user_ticket, _) = get_user_ticket_address(&fields.user, &whitelist);
+				if seed {
+					let token_program = client.get_account(&fields.mint)?.owner;
+					let vault =
+						spl_associated_token_account::get_associated_token_address_with_program_id(
+							&whitelist,
+							&fields.mint,
+							&token_program,
+						);
+					let ticket_token_account =
+						spl_associated_token_account::get_associated_token_address_with_program_id(
+							&user_ticket,
+							&fields.mint,
+							&token_program,
+						);
 
-				println!("Removing user from whitelist: {}", fields.user);
-				println!("Whitelist Account: {}", user_ticket);
+					println!("Seeding ticket token account: {}", ticket_token_account);
 
-				instructions::remove_user(
-					&whitelist,
-					&wallet_pubkey,
-					&fields.mint,
-					&fields.user,
-					&user_ticket,
-				)
-				.map_err(|err| anyhow!("Unable to create `RemoveUser` instruction: {}", err))?
+					instructions::add_user_and_seed(
+						&whitelist,
+						&wallet_pubkey,
+						&fields.mint,
+						&fields.user,
+						&user_ticket,
+						&vault,
+						&ticket_token_account,
+						&token_program,
+					)
+					.map_err(|err| {
+						anyhow!("Unable to create `AddUserAndSeed` instruction: {}", err)
+					})?
+				} else {
+					instructions::add_user(
+						&whitelist,
+						&wallet_pubkey,
+						&fields.mint,
+						&fields.user,
+						&user_ticket,
+					)
+					.map_err(|err| anyhow!("Unable to create `AddUser` instruction: {}", err))?
+				}
 			}
-		},
-		Commands::Buy(fields) => {
-			let whitelist = get_whitelist_address(&fields.mint).0;
-			let user_ticket = get_user_ticket_address(&wallet_pubkey, &whitelist).0;
+			UserManagement::AddBatch { mint, users } => {
+				if users.len() > MAX_BATCH_USERS {
+					return Err(anyhow!(
+						"At most {} users can be added at once, got {}",
+						MAX_BATCH_USERS,
+						users.len()
+					));
+				}
+
+				let whitelist = program.whitelist_address(&mint);
+				let users: Vec<(Pubkey, Pubkey)> = users
+					.into_iter()
+					.map(|user| (user, program.ticket_address(&user, &whitelist)))
+					.collect();
+
+				for (user, user_ticket) in &users {
+					println!("User {} Whitelist Account: {}", user, user_ticket);
+				}
+
+				instructions::batch_add_user(&whitelist, &wallet_pubkey, &mint, &users).map_err(
+					|err| anyhow!("Unable to create `BatchAddUser` instruction: {}", err),
+				)?
+			}
+			UserManagement::Remove(fields) => {
+				let whitelist = program.whitelist_address(&fields.mint);
+				let user_ticket = program.ticket_address(&fields.user, &whitelist);
+
+				let token_program = client.get_account(&fields.mint)?.owner;
+				let vault =
+					spl_associated_token_account::get_associated_token_address_with_program_id(
+						&whitelist,
+						&fields.mint,
+						&token_program,
+					);
+				let ticket_token_account =
+					spl_associated_token_account::get_associated_token_address_with_program_id(
+						&user_ticket,
+						&fields.mint,
+						&token_program,
+					);
+
+				println!("Removing user from whitelist: {}", fields.user);
+				println!("Whitelist Account: {}", user_ticket);
+
+				instructions::remove_user(
+					&whitelist,
+					&wallet_pubkey,
+					&vault,
+					&fields.mint,
+					&fields.user,
+					&user_ticket,
+					&ticket_token_account,
+					&token_program,
+				)
+				.map_err(|err| anyhow!("Unable to create `RemoveUser` instruction: {}", err))?
+			}
+			UserManagement::Note { mint, user, note } => {
+				let whitelist = program.whitelist_address(&mint);
+				let user_ticket = program.ticket_address(&user, &whitelist);
+
+				println!("Setting note on ticket: {}", user_ticket);
+
+				instructions::set_ticket_note(&whitelist, &wallet_pubkey, &user_ticket, &note)
+					.map_err(|err| {
+						anyhow!("Unable to create `SetTicketNote` instruction: {}", err)
+					})?
+			}
+		},
+		Commands::Buy(fields) => {
+			if fields.amount == 0 {
+				return Err(anyhow!("Amount must be greater than 0"));
+			}
+
+			let whitelist = program.whitelist_address(&fields.mint);
+			let user_ticket = program.ticket_address(&wallet_pubkey, &whitelist);
 
 			let mint_account = client.get_account(&fields.mint)?;
 			let token_program = mint_account.owner;
@@ -418,6 +1030,149 @@ fn main() -> Result<()> {
 					&token_program,
 				);
 
+			// Quote the current price so the purchase fails, rather than paying more, if the
+			// price is raised before the transaction lands
+			let whitelist_data = client.get_account_data(&whitelist)?;
+			let wl_data = stuk_wl::state::Whitelist::unpack(&whitelist_data)?;
+			let max_price = wl_data.token_price;
+			let mint_decimals = StateWithExtensions::<Mint>::unpack(&mint_account.data)?
+				.base
+				.decimals;
+
+			if fields.quote {
+				let instruction = instructions::buy_tokens(
+					&whitelist,
+					&vault,
+					&fields.mint,
+					&wallet_pubkey,
+					&user_ticket,
+					&ticket_token_account,
+					&user_token_account,
+					fields.amount,
+					Some(max_price),
+					fields.allow_partial,
+					&token_program,
+				)
+				.map_err(|err| anyhow!("Unable to create `BuyTokens` instruction: {}", err))?;
+
+				let token_balance = match client
+					.get_account_with_commitment(&user_token_account, client.commitment())?
+					.value
+				{
+					Some(account) => {
+						StateWithExtensions::<Account>::unpack(&account.data)?
+							.base
+							.amount
+					}
+					None => 0,
+				};
+				let accounts = BuyQuoteAccounts {
+					token_account: user_token_account,
+					token_balance,
+					treasury: wl_data.treasury,
+					treasury_lamports: client.get_balance(&wl_data.treasury)?,
+					ticket: user_ticket,
+				};
+
+				let quote = quote_buy(
+					&client,
+					&program.target(instruction),
+					&wallet_pubkey,
+					&accounts,
+				)?;
+				println!(
+					"Tokens received: {}",
+					spl_token_2022::amount_to_ui_amount(quote.tokens_received, mint_decimals)
+				);
+				println!("SOL charged: {}", lamports_to_sol(quote.lamports_charged));
+				println!(
+					"Remaining allowance: {}",
+					spl_token_2022::amount_to_ui_amount(quote.remaining_allowance, mint_decimals)
+				);
+				return Ok(());
+			}
+
+			if let Some(chunk) = fields.chunk {
+				// Each chunk is only sent once the previous one has landed, and identical chunks
+				// sent back to back would share a signature and be dropped as duplicates
+				if no_wait {
+					return Err(anyhow!("--chunk cannot be combined with --no-wait"));
+				}
+				if out_signed.is_some() {
+					return Err(anyhow!("--chunk cannot be combined with --out-signed"));
+				}
+				let ticket_data = client.get_account_data(&user_ticket)?;
+				let ticket = stuk_wl::state::Ticket::unpack(&ticket_data)?;
+				let remaining_allowance = ticket.allowance.saturating_sub(ticket.amount_bought)
+					/ 10u64.pow(mint_decimals as u32);
+
+				let chunks = split_amount(fields.amount, chunk, remaining_allowance)?;
+				if chunks.is_empty() {
+					return Err(anyhow!("Ticket allowance is exhausted"));
+				}
+				if chunks.iter().sum::<u64>() < fields.amount {
+					println!(
+						"Requested amount exceeds remaining allowance, buying: {}",
+						remaining_allowance
+					);
+				}
+
+				let total = chunks.iter().sum::<u64>();
+				confirm(
+					&buy_summary(total, &fields.mint, max_price, mint_decimals)?,
+					skip_confirmation,
+				)?;
+
+				let mut total_bought = 0;
+				for amount in chunks {
+					let instruction = instructions::buy_tokens(
+						&whitelist,
+						&vault,
+						&fields.mint,
+						&wallet_pubkey,
+						&user_ticket,
+						&ticket_token_account,
+						&user_token_account,
+						amount,
+						Some(max_price),
+						fields.allow_partial,
+						&token_program,
+					)
+					.map_err(|err| anyhow!("Unable to create `BuyTokens` instruction: {}", err))?;
+
+					let latest_blockhash = client
+						.get_latest_blockhash()
+						.map_err(|err| anyhow!("Unable to get latest blockhash: {}", err))?;
+					let transaction = signed_transaction(
+						program.target(instruction),
+						&wallet_keypair,
+						fee_payer.as_ref(),
+						latest_blockhash,
+					);
+					match send_transaction(&client, &transaction, no_wait) {
+						Ok(txid) => {
+							total_bought += amount;
+							println!("Bought: {}", amount);
+							println!("TXID: {}", txid);
+						}
+						Err(err) => {
+							println!("Total bought: {}", total_bought);
+							return Err(anyhow!(
+								"Unable to send transaction: {}",
+								describe_client_error(&err)
+							));
+						}
+					}
+				}
+				println!("Total bought: {}", total_bought);
+				return Ok(());
+			}
+
+			confirm(
+				&buy_summary(fields.amount, &fields.mint, max_price, mint_decimals)?,
+				skip_confirmation,
+			)?;
+
 			instructions::buy_tokens(
 				&whitelist,
 				&vault,
@@ -427,12 +1182,14 @@ fn main() -> Result<()> {
 				&ticket_token_account,
 				&user_token_account,
 				fields.amount,
+				Some(max_price),
+				fields.allow_partial,
 				&token_program,
 			)
 			.map_err(|err| anyhow!("Unable to create `BuyTokens` instruction: {}", err))?
 		}
 		Commands::Deposit(fields) => {
-			let whitelist = get_whitelist_address(&fields.mint).0;
+			let whitelist = program.whitelist_address(&fields.mint);
 			let mint_account = client.get_account(&fields.mint)?;
 			let token_program = mint_account.owner;
 
@@ -447,19 +1204,196 @@ fn main() -> Result<()> {
 					&fields.mint,
 					&token_program,
 				);
-			instructions::deposit_tokens(
+
+			let amount = if fields.fill {
+				let whitelist_data = client.get_account_data(&whitelist)?;
+				let wl_data = stuk_wl::state::Whitelist::unpack(&whitelist_data)?;
+				let mint_decimals = StateWithExtensions::<Mint>::unpack(&mint_account.data)?
+					.base
+					.decimals;
+				let vault_balance = match client.get_account_data(&vault) {
+					Ok(data) => StateWithExtensions::<Account>::unpack(&data)?.base.amount,
+					Err(_) => 0,
+				};
+
+				let fill = fill_amount(wl_data.whitelist_size, wl_data.buy_limit, vault_balance)?;
+				if fill == 0 {
+					return Err(anyhow!(
+						"Vault already holds enough tokens to fill every ticket"
+					));
+				}
+
+				println!(
+					"Filling vault with: {}",
+					spl_token_2022::amount_to_ui_amount(fill, mint_decimals)
+				);
+
+				// The instruction takes whole tokens, round up and let the program cap the
+				// deposit to the exact amount required
+				let one_token = 10u64.pow(mint_decimals as u32);
+				fill / one_token + u64::from(fill % one_token != 0)
+			} else {
+				// Guaranteed by clap when `--fill` is not present
+				fields.amount.unwrap()
+			};
+
+			if fields.credit {
+				let ledger = program.depositor_address(&wallet_pubkey, &whitelist);
+				println!("Crediting deposit to ledger: {}", ledger);
+
+				instructions::deposit_for(
+					&whitelist,
+					&vault,
+					&wallet_pubkey,
+					&user_token_account,
+					&fields.mint,
+					&ledger,
+					amount,
+					&token_program,
+				)
+				.map_err(|err| anyhow!("Unable to create `DepositFor` instruction: {}", err))?
+			} else {
+				instructions::deposit_tokens(
+					&whitelist,
+					&vault,
+					&wallet_pubkey,
+					&user_token_account,
+					&fields.mint,
+					amount,
+					&token_program,
+				)
+				.map_err(|err| anyhow!("Unable to create `DepositTokens` instruction: {}", err))?
+			}
+		}
+		Commands::WithdrawDeposit { mint, amount } => {
+			let whitelist = program.whitelist_address(&mint);
+			let mint_account = client.get_account(&mint)?;
+			let token_program = mint_account.owner;
+
+			let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
+				&whitelist,
+				&mint,
+				&token_program,
+			);
+			let user_token_account =
+				spl_associated_token_account::get_associated_token_address_with_program_id(
+					&wallet_pubkey,
+					&mint,
+					&token_program,
+				);
+			let ledger = program.depositor_address(&wallet_pubkey, &whitelist);
+
+			instructions::withdraw_deposit(
 				&whitelist,
 				&vault,
 				&wallet_pubkey,
 				&user_token_account,
-				&fields.mint,
-				fields.amount,
+				&mint,
+				&ledger,
+				amount,
+				&token_program,
+			)
+			.map_err(|err| anyhow!("Unable to create `WithdrawDeposit` instruction: {}", err))?
+		}
+		Commands::WithdrawSol { mint, user, amount } => {
+			let whitelist = program.whitelist_address(&mint);
+			let whitelist_data = client.get_account_data(&whitelist)?;
+			let wl_data = stuk_wl::state::Whitelist::unpack(&whitelist_data)?;
+			let ticket = user.map(|user| program.ticket_address(&user, &whitelist));
+			let source = ticket.unwrap_or(whitelist);
+
+			let amount = match amount {
+				Some(amount) => amount,
+				None => {
+					let account = client.get_account(&source)?;
+					let rent = client.get_minimum_balance_for_rent_exemption(account.data.len())?;
+					account.lamports.saturating_sub(rent)
+				}
+			};
+			if amount == 0 {
+				return Err(anyhow!("{} holds no SOL above its rent", source));
+			}
+
+			println!("Withdrawing {} lamports from: {}", amount, source);
+			println!("Treasury: {}", wl_data.treasury);
+
+			instructions::withdraw_sol(
+				&whitelist,
+				&wallet_pubkey,
+				&wl_data.treasury,
+				ticket.as_ref(),
+				amount,
+			)
+			.map_err(|err| anyhow!("Unable to create `WithdrawSol` instruction: {}", err))?
+		}
+		Commands::WithdrawTreasury {
+			mint,
+			recipient,
+			amount,
+		} => {
+			let whitelist = program.whitelist_address(&mint);
+			let treasury = program.treasury_address(&mint);
+			let recipient = recipient.unwrap_or(wallet_pubkey);
+
+			let amount = match amount {
+				Some(amount) => amount,
+				None => {
+					let account = client.get_account(&treasury)?;
+					let rent = client.get_minimum_balance_for_rent_exemption(account.data.len())?;
+					account.lamports.saturating_sub(rent)
+				}
+			};
+			if amount == 0 {
+				return Err(anyhow!("{} holds no SOL above its rent", treasury));
+			}
+
+			println!(
+				"Withdrawing {} lamports from treasury: {}",
+				amount, treasury
+			);
+			println!("Recipient: {}", recipient);
+
+			instructions::withdraw_treasury(
+				&whitelist,
+				&wallet_pubkey,
+				&treasury,
+				&recipient,
+				amount,
+			)
+			.map_err(|err| anyhow!("Unable to create `WithdrawTreasury` instruction: {}", err))?
+		}
+		Commands::Refund { mint, amount } => {
+			let whitelist = program.whitelist_address(&mint);
+			let user_ticket = program.ticket_address(&wallet_pubkey, &whitelist);
+			let mint_account = client.get_account(&mint)?;
+			let token_program = mint_account.owner;
+
+			let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
+				&whitelist,
+				&mint,
+				&token_program,
+			);
+			let user_token_account =
+				spl_associated_token_account::get_associated_token_address_with_program_id(
+					&wallet_pubkey,
+					&mint,
+					&token_program,
+				);
+
+			instructions::refund(
+				&whitelist,
+				&vault,
+				&mint,
+				&wallet_pubkey,
+				&user_ticket,
+				&user_token_account,
+				amount,
 				&token_program,
 			)
-			.map_err(|err| anyhow!("Unable to create `DepositTokens` instruction: {}", err))?
+			.map_err(|err| anyhow!("Unable to create `Refund` instruction: {}", err))?
 		}
 		Commands::Withdraw(fields) => {
-			let whitelist = get_whitelist_address(&fields.mint).0;
+			let whitelist = program.whitelist_address(&fields.mint);
 			let mint_account = client.get_account(&fields.mint)?;
 			let token_program = mint_account.owner;
 
@@ -478,6 +1412,15 @@ fn main() -> Result<()> {
 					&fields.mint,
 					&token_program,
 				);
+
+			confirm(
+				&format!(
+					"Withdrawing {} tokens of mint: {}\nFrom vault: {}\nTo token account: {}",
+					fields.amount, fields.mint, vault, token_account
+				),
+				skip_confirmation,
+			)?;
+
 			instructions::withdraw_tokens(
 				&whitelist,
 				&wallet_pubkey,
@@ -491,8 +1434,22 @@ fn main() -> Result<()> {
 		}
 		Commands::Burn(method) => match method {
 			Method::Single(fields) => {
-				let whitelist = get_whitelist_address(&fields.mint).0;
-				let user_ticket = get_user_ticket_address(&fields.user, &whitelist).0;
+				let whitelist = program.whitelist_address(&fields.mint);
+				let user_ticket = program.ticket_address(&fields.user, &whitelist);
+
+				let token_program = client.get_account(&fields.mint)?.owner;
+				let vault =
+					spl_associated_token_account::get_associated_token_address_with_program_id(
+						&whitelist,
+						&fields.mint,
+						&token_program,
+					);
+				let ticket_token_account =
+					spl_associated_token_account::get_associated_token_address_with_program_id(
+						&user_ticket,
+						&fields.mint,
+						&token_program,
+					);
 
 				println!("Removing user from whitelist: {}", fields.user);
 				println!("Whitelist Account: {}", user_ticket);
@@ -500,29 +1457,30 @@ fn main() -> Result<()> {
 				instructions::remove_user(
 					&whitelist,
 					&wallet_pubkey,
+					&vault,
 					&fields.mint,
 					&fields.user,
 					&user_ticket,
+					&ticket_token_account,
+					&token_program,
 				)
 				.map_err(|err| anyhow!("Unable to create `RemoveUser` instruction: {}", err))?
 			}
-			Method::Bulk { mint } => {
-				let (whitelist, _) = get_whitelist_address(&mint);
+			Method::Bulk { mint, report } => {
+				// Burns are packed into as few transactions as fit
+				if out_signed.is_some() {
+					return Err(anyhow!("burn bulk cannot be combined with --out-signed"));
+				}
+				let whitelist = program.whitelist_address(&mint);
 				let whitelist_account_data = client.get_account_data(&whitelist)?;
-				let wl_data = stuk_wl::state::Whitelist::try_from_slice(&whitelist_account_data)?;
+				let wl_data = stuk_wl::state::Whitelist::unpack(&whitelist_account_data)?;
 				let mint_account = client.get_account(&mint)?;
 				let token_program = mint_account.owner;
+				let mint_decimals = StateWithExtensions::<Mint>::unpack(&mint_account.data)?
+					.base
+					.decimals;
 
-				let program_accounts = client.get_program_accounts(&stuk_wl::id())?;
-				let mut whitelist_accounts = Vec::new();
-				// May want to split the returned array into chunks for parallel
-				// processing and the reconstruct when done
-				for (pubkey, account) in program_accounts.iter() {
-					let data = stuk_wl::state::Ticket::try_from_slice(&account.data)?;
-					if data.whitelist == whitelist {
-						whitelist_accounts.push((pubkey, account, data));
-					}
-				}
+				let whitelist_accounts = program.tickets(&client, &whitelist)?;
 				let treasury_token_account =
 					spl_associated_token_account::get_associated_token_address_with_program_id(
 						&wl_data.treasury,
@@ -530,14 +1488,51 @@ fn main() -> Result<()> {
 						&token_program,
 					);
 
-				// Depending on the size of this array we may want to split into
-				// threads depending on number of cores on a machine to parallel
-				// execute the withdrawals to reduce execution time for now let's
-				// just do this single threadedly
-				let mut failures = 0;
+				confirm(
+					&format!(
+						"Burning {} tickets of whitelist: {}\nMint: {}\nReclaiming tokens to: {}",
+						whitelist_accounts.len(),
+						whitelist,
+						mint,
+						treasury_token_account
+					),
+					skip_confirmation,
+				)?;
+
+				// Balances are read before burning, a burn sweeps the ticket's tokens and the
+				// lamports of both the ticket and its token account to the treasury.
+				// `getMultipleAccounts` takes at most 100 addresses per request
+				let mut swept = HashMap::with_capacity(whitelist_accounts.len());
+				for page in whitelist_accounts.chunks(100) {
+					let tickets: Vec<Pubkey> = page.iter().map(|(ticket, _)| *ticket).collect();
+					let token_accounts: Vec<Pubkey> = tickets
+						.iter()
+						.map(|ticket| {
+							spl_associated_token_account::get_associated_token_address_with_program_id(
+								ticket,
+								&mint,
+								&token_program,
+							)
+						})
+						.collect();
+					let ticket_accounts = client.get_multiple_accounts(&tickets)?;
+					let token_accounts = client.get_multiple_accounts(&token_accounts)?;
+					for ((ticket, ticket_account), token_account) in
+						tickets.iter().zip(ticket_accounts).zip(token_accounts)
+					{
+						let ticket_lamports = ticket_account.map_or(0, |account| account.lamports);
+						swept.insert(
+							*ticket,
+							burn_row(*ticket, ticket_lamports, token_account.as_ref())?,
+						);
+					}
+				}
+
 				let mut failed_accounts: Vec<&Pubkey> =
 					Vec::with_capacity(whitelist_accounts.len());
-				for (ticket, _ticket_account, _data) in whitelist_accounts {
+				let mut burned = Vec::with_capacity(whitelist_accounts.len());
+				let mut burns = Vec::with_capacity(whitelist_accounts.len());
+				for (ticket, _data) in &whitelist_accounts {
 					// want this to continue on failure
 					let ticket_token_account =
 						spl_associated_token_account::get_associated_token_address_with_program_id(
@@ -545,7 +1540,7 @@ fn main() -> Result<()> {
 							&mint,
 							&token_program,
 						);
-					let instruction = match instructions::burn_ticket(
+					match instructions::burn_ticket(
 						&whitelist,
 						&wallet_pubkey,
 						&mint,
@@ -555,118 +1550,136 @@ fn main() -> Result<()> {
 						&ticket_token_account,
 						&token_program,
 					) {
-						Ok(ix) => ix,
+						Ok(ix) => burns.push((ticket, program.target(ix))),
 						Err(e) => {
 							println!(
 								"Unable to create `BurnTicket` instruction for: {}, reason: {}",
 								ticket, e
 							);
-							failures += 1;
 							failed_accounts.push(ticket);
-							continue;
 						}
 					};
-					let mut transaction =
-						Transaction::new_with_payer(&[instruction], Some(&wallet_pubkey));
-					let latest_blockhash = match client.get_latest_blockhash() {
-						Ok(bh) => bh,
-						Err(e) => {
-							println!(
-								"Unable to get latest blockhash for: {}, reason: {}",
-								ticket, e
-							);
-							failures += 1;
-							failed_accounts.push(ticket);
-							continue;
+				}
+
+				let signers = bulk_signers(&wallet_keypair, fee_payer.as_ref());
+				let (tickets, burns): (Vec<_>, Vec<_>) = burns.into_iter().unzip();
+				let mut tickets = tickets.into_iter();
+				for mut transaction in
+					chunk_instructions(burns, &signers, client.get_latest_blockhash()?)?
+				{
+					let batch: Vec<&Pubkey> = tickets
+						.by_ref()
+						.take(transaction.message.instructions.len())
+						.collect();
+					match send_chunk(&client, &mut transaction, &signers, no_wait) {
+						Ok(txid) => {
+							for ticket in &batch {
+								if no_wait {
+									println!("Ticket burn sent: {}", ticket);
+								} else {
+									println!("Ticket burned: {}", ticket);
+								}
+								burned.extend(swept.remove(*ticket));
+							}
+							println!("TXID: {}", txid);
 						}
-					};
-					transaction.sign(&[&wallet_keypair], latest_blockhash);
-					let txid = match client.send_and_confirm_transaction_with_spinner(&transaction)
-					{
-						Ok(tx) => tx,
 						Err(e) => {
-							println!("Unable to send transaction for: {}, reason: {}", ticket, e);
-							failures += 1;
-							failed_accounts.push(ticket);
-							continue;
+							println!("Unable to burn tickets: {:?}, reason: {}", batch, e);
+							failed_accounts.extend(batch);
 						}
-					};
-					println!("Ticket burned: {}", ticket);
-					println!("TXID: {}", txid);
+					}
 				}
 				println!("Complete");
-				println!("Number of failures: {}", failures);
-				println!("Failed accounts: {:?}", failed_accounts);
-				std::process::exit(1);
+				let (tokens, lamports) = burn_totals(&burned);
+				println!(
+					"Swept from {} tickets to treasury {}: {} tokens, {} SOL",
+					burned.len(),
+					wl_data.treasury,
+					spl_token_2022::amount_to_ui_amount(tokens, mint_decimals),
+					lamports_to_sol(lamports)
+				);
+				if let Some(path) = report {
+					std::fs::write(&path, format_burn_report(&burned))?;
+					println!("Burn report written to: {}", path.display());
+				}
+				if report_failures(&failed_accounts, &mut std::io::stderr())? > 0 {
+					std::process::exit(1);
+				}
+				return Ok(());
 			}
 		},
 		Commands::Amend(detail) => {
 			match detail {
 				Detail::Size { mint, size } => {
-					let whitelist = get_whitelist_address(&mint).0;
+					let whitelist = program.whitelist_address(&mint);
 					instructions::amend_whitelist_size(&whitelist, &wallet_pubkey, size).map_err(
 						|err| anyhow!("Unable to create `AmendWhitelistSize` instruction: {}", err),
 					)?
 				}
+				Detail::Price { mint, price } => {
+					let whitelist = program.whitelist_address(&mint);
+					instructions::update_price(&whitelist, &wallet_pubkey, price).map_err(
+						|err| anyhow!("Unable to create `UpdatePrice` instruction: {}", err),
+					)?
+				}
+				Detail::BuyLimit { mint, buy_limit } => {
+					let whitelist = program.whitelist_address(&mint);
+					instructions::update_buy_limit(&whitelist, &wallet_pubkey, &mint, buy_limit)
+						.map_err(|err| {
+							anyhow!("Unable to create `UpdateBuyLimit` instruction: {}", err)
+						})?
+				}
+				Detail::SyncAllowance { mint, user } => {
+					let whitelist = program.whitelist_address(&mint);
+					let user_ticket = program.ticket_address(&user, &whitelist);
+					instructions::sync_ticket_allowance(&whitelist, &wallet_pubkey, &user_ticket)
+						.map_err(|err| {
+							anyhow!(
+								"Unable to create `SyncTicketAllowance` instruction: {}",
+								err
+							)
+						})?
+				}
 				Detail::Times {
 					mint,
 					registration_start_time,
 					registration_end_time,
 					sale_start_time,
 					sale_end_time,
+					allow_overlap,
 				} => {
-					let whitelist = get_whitelist_address(&mint).0;
+					let whitelist = program.whitelist_address(&mint);
 
 					let whitelist_account = client.get_account_data(&whitelist)?;
-					let wl_data = stuk_wl::state::Whitelist::try_from_slice(&whitelist_account)?;
+					let wl_data = stuk_wl::state::Whitelist::unpack(&whitelist_account)?;
+
+					let now = Utc::now().timestamp();
 
 					let registration_start_timestamp = match registration_start_time {
-						Some(time) => Some(string_to_timestamp(time)?),
+						Some(time) => Some(check_start_time(string_to_timestamp(time)?, now)?),
 						None => None,
 					};
 
+					// A new end time is measured from the new start time when one is given,
+					// otherwise from the whitelist's current start time
 					let registration_duration = match registration_end_time {
-						Some(time) => {
-							let ts = string_to_timestamp(time).expect("error parsing time");
-
-							if registration_start_timestamp.is_some_and(|t| t < ts) {
-								Some(ts - registration_start_timestamp.unwrap())
-							} else {
-								return Err(anyhow!("Cannot compute duration, start time is after provided end time"));
-							};
-
-							if wl_data.registration_timestamp > 0
-								&& wl_data.registration_timestamp < ts
-							{
-								Some(ts - wl_data.registration_timestamp)
-							} else {
-								return Err(anyhow!("Cannot compute duration, start time is after provided end time"));
-							}
-						}
+						Some(time) => Some(duration_between(
+							registration_start_timestamp.unwrap_or(wl_data.registration_timestamp),
+							string_to_timestamp(time)?,
+						)?),
 						None => None,
 					};
 
 					let sale_start_timestamp = match sale_start_time {
-						Some(time) => Some(string_to_timestamp(time)?),
+						Some(time) => Some(check_start_time(string_to_timestamp(time)?, now)?),
 						None => None,
 					};
 
 					let sale_duration = match sale_end_time {
-						Some(time) => {
-							let ts = string_to_timestamp(time).expect("error parsing time");
-
-							if sale_start_timestamp.is_some_and(|t| t < ts) {
-								Some(ts - sale_start_timestamp.unwrap())
-							} else {
-								return Err(anyhow!("Cannot compute duration, start time is after provided end time"));
-							};
-
-							if wl_data.sale_timestamp > 0 && wl_data.sale_timestamp < ts {
-								Some(ts - wl_data.sale_timestamp)
-							} else {
-								return Err(anyhow!("Cannot compute duration, start time is after provided end time"));
-							}
-						}
+						Some(time) => Some(duration_between(
+							sale_start_timestamp.unwrap_or(wl_data.sale_timestamp),
+							string_to_timestamp(time)?,
+						)?),
 						None => None,
 					};
 
@@ -677,27 +1690,64 @@ fn main() -> Result<()> {
 						registration_duration,
 						sale_start_timestamp,
 						sale_duration,
+						allow_overlap,
 					)
 					.map_err(|err| anyhow!("Unable to create `AmendTimes` instruction: {}", err))?
 				}
 			}
 		}
+		Commands::Schedule {
+			mint,
+			registration_start,
+			sale_start,
+			sale_end,
+		} => {
+			let whitelist = program.whitelist_address(&mint);
+			let now = Utc::now().timestamp();
+
+			let registration_start =
+				check_start_time(string_to_timestamp(registration_start)?, now)?;
+			let sale_start = string_to_timestamp(sale_start)?;
+			let sale_end = string_to_timestamp(sale_end)?;
+			// The program derives the durations, these only catch a misordered schedule before
+			// it is sent
+			duration_between(registration_start, sale_start)?;
+			duration_between(sale_start, sale_end)?;
+
+			instructions::set_schedule(
+				&whitelist,
+				&wallet_pubkey,
+				registration_start,
+				sale_start,
+				sale_end,
+			)
+			.map_err(|err| anyhow!("Unable to create `SetSchedule` instruction: {}", err))?
+		}
 		Commands::Start(start) => match start {
 			Start::Registration { mint } => {
-				let whitelist = get_whitelist_address(&mint).0;
+				let whitelist = program.whitelist_address(&mint);
 				instructions::start_registration(&whitelist, &wallet_pubkey).map_err(|err| {
 					anyhow!("Unable to create `StartRegistration` instruction: {}", err)
 				})?
 			}
-			Start::Sale { mint } => {
-				let whitelist = get_whitelist_address(&mint).0;
-				instructions::start_token_sale(&whitelist, &wallet_pubkey).map_err(|err| {
-					anyhow!("Unable to create `StartTokenSale` instruction: {}", err)
-				})?
+			Start::Sale {
+				mint,
+				require_fully_funded,
+			} => {
+				let whitelist = program.whitelist_address(&mint);
+				let whitelist_data = client.get_account_data(&whitelist)?;
+				let wl_data = stuk_wl::state::Whitelist::unpack(&whitelist_data)?;
+				instructions::start_token_sale(
+					&whitelist,
+					&wallet_pubkey,
+					&wl_data.vault,
+					require_fully_funded,
+				)
+				.map_err(|err| anyhow!("Unable to create `StartTokenSale` instruction: {}", err))?
 			}
 		},
 		Commands::AllowRegister { allow, mint } => {
-			let whitelist = get_whitelist_address(&mint).0;
+			let whitelist = program.whitelist_address(&mint);
 			let allow_bool = match allow.as_str() {
 				"true" | "yes" | "y" => true,
 				"false" | "no" | "n" => false,
@@ -707,33 +1757,137 @@ fn main() -> Result<()> {
 				|err| anyhow!("Unable to create `AllowRegistration` instruction: {}", err),
 			)?
 		}
-		Commands::Register { mint } => {
-			let whitelist = get_whitelist_address(&mint).0;
-			let user_ticket = get_user_ticket_address(&wallet_pubkey, &whitelist).0;
+		Commands::Pause {
+			mint,
+			registration,
+			sale: _,
+			resume,
+		} => {
+			let whitelist = program.whitelist_address(&mint);
+			let target = if registration {
+				PauseTarget::Registration
+			} else {
+				PauseTarget::Sale
+			};
+			instructions::set_paused(&whitelist, &wallet_pubkey, target, !resume)
+				.map_err(|err| anyhow!("Unable to create `SetPaused` instruction: {}", err))?
+		}
+		Commands::Register { mint, referrer } => {
+			let whitelist = program.whitelist_address(&mint);
+			let user_ticket = program.ticket_address(&wallet_pubkey, &whitelist);
 			let whitelist_data = client.get_account_data(&whitelist)?;
-			let wl_data = stuk_wl::state::Whitelist::try_from_slice(&whitelist_data)?;
-
-			if wl_data.whitelist_size > 0 && {
-				let whitelist_accounts = client.get_program_accounts(&whitelist).unwrap();
-				let mut accounts = Vec::new();
-				for (pubkey, account) in whitelist_accounts {
-					if account.data.len() == stuk_wl::state::Ticket::LEN {
-						accounts.push(pubkey);
-					}
-				}
-				(wl_data.whitelist_size as usize) < accounts.len()
-			} {
+			let wl_data = stuk_wl::state::Whitelist::unpack(&whitelist_data)?;
+
+			if wl_data.whitelist_size > 0
+				&& whitelist_full(
+					wl_data.whitelist_size,
+					program.tickets(&client, &whitelist)?.len(),
+				) {
 				println!("Whitelist full");
 				std::process::exit(2);
 			}
+			if wl_data.authority_pays_rent {
+				return Err(anyhow!(
+					"Rent on this whitelist is paid by its authority {}, registration must be \
+					 co-signed by the authority",
+					wl_data.authority
+				));
+			}
+			if let Some(register_authority) = wl_data.register_authority() {
+				return Err(anyhow!(
+					"Registration on this whitelist is gated, it requires a voucher signed by \
+					 {}",
+					register_authority
+				));
+			}
 			println!("Ticket: {}", user_ticket);
 
-			instructions::register(&whitelist, &wallet_pubkey, &user_ticket)
-				.map_err(|err| anyhow!("Unable to create `Register` instruction: {}", err))?
+			// An allocation reserved by the authority is always passed, the ticket takes it
+			let allocation = program.allocation_address(&wallet_pubkey, &whitelist);
+			if client.get_account(&allocation).is_ok() {
+				println!("Allocation: {}", allocation);
+			}
+
+			match referrer {
+				Some(referrer) => {
+					let referrer_ticket = program.ticket_address(&referrer, &whitelist);
+					instructions::register_with_referral(
+						&whitelist,
+						&wallet_pubkey,
+						&user_ticket,
+						&referrer,
+						&referrer_ticket,
+						&allocation,
+					)
+					.map_err(|err| {
+						anyhow!(
+							"Unable to create `RegisterWithReferral` instruction: {}",
+							err
+						)
+					})?
+				}
+				None => {
+					instructions::register(&whitelist, &wallet_pubkey, &user_ticket, &allocation)
+						.map_err(|err| {
+							anyhow!("Unable to create `Register` instruction: {}", err)
+						})?
+				}
+			}
+		}
+		Commands::ReferralBonus { mint, bonus } => {
+			let whitelist = program.whitelist_address(&mint);
+			instructions::set_referral_bonus(&whitelist, &wallet_pubkey, &mint, bonus).map_err(
+				|err| anyhow!("Unable to create `SetReferralBonus` instruction: {}", err),
+			)?
+		}
+		Commands::Allocate { mint, user, amount } => {
+			let whitelist = program.whitelist_address(&mint);
+			let allocation = program.allocation_address(&user, &whitelist);
+			println!("Allocation: {}", allocation);
+			instructions::set_allocation(
+				&whitelist,
+				&wallet_pubkey,
+				&mint,
+				&user,
+				&allocation,
+				amount,
+			)
+			.map_err(|err| anyhow!("Unable to create `SetAllocation` instruction: {}", err))?
+		}
+		Commands::Authority(Authority::Set {
+			mint,
+			new_authority,
+		}) => {
+			let whitelist = program.whitelist_address(&mint);
+			confirm(
+				&format!(
+					"Transferring whitelist: {}\nMint: {}\nFrom authority: {}\nTo authority: {}",
+					whitelist, mint, wallet_pubkey, new_authority
+				),
+				skip_confirmation,
+			)?;
+
+			instructions::change_authority(&whitelist, &wallet_pubkey, &new_authority)
+				.map_err(|err| anyhow!("Unable to create `ChangeAuthority` instruction: {}", err))?
+		}
+		Commands::RegisterAuthority { mint, authority } => {
+			let whitelist = program.whitelist_address(&mint);
+			instructions::set_register_authority(&whitelist, &wallet_pubkey, authority.as_ref())
+				.map_err(|err| {
+					anyhow!(
+						"Unable to create `SetRegisterAuthority` instruction: {}",
+						err
+					)
+				})?
+		}
+		Commands::Cooldown { mint, seconds } => {
+			let whitelist = program.whitelist_address(&mint);
+			instructions::set_buy_cooldown(&whitelist, &wallet_pubkey, seconds)
+				.map_err(|err| anyhow!("Unable to create `SetBuyCooldown` instruction: {}", err))?
 		}
 		Commands::Unregister { mint } => {
-			let whitelist = get_whitelist_address(&mint).0;
-			let user_ticket = get_user_ticket_address(&wallet_pubkey, &whitelist).0;
+			let whitelist = program.whitelist_address(&mint);
+			let user_ticket = program.ticket_address(&wallet_pubkey, &whitelist);
 
 			let mint_account = client.get_account(&mint)?;
 			let token_program = mint_account.owner;
@@ -751,7 +1905,7 @@ fn main() -> Result<()> {
 				);
 
 			let data = client.get_account_data(&whitelist).unwrap().clone();
-			let unpacked_data = stuk_wl::state::Whitelist::try_from_slice(&data[..])?;
+			let unpacked_data = stuk_wl::state::Whitelist::unpack(&data[..])?;
 			let authority = unpacked_data.authority;
 
 			instructions::unregister(
@@ -766,27 +1920,325 @@ fn main() -> Result<()> {
 			)
 			.map_err(|err| anyhow!("Unable to create `Unregister` instruction: {}", err))?
 		}
-		Commands::Close { mint, recipient } => {
-			let whitelist = get_whitelist_address(&mint).0;
+		Commands::Cleanup { mint } => {
+			let whitelist = program.whitelist_address(&mint);
 			let mint_account = client.get_account(&mint)?;
 			let token_program = mint_account.owner;
-			let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
-				&mint,
-				&whitelist,
-				&token_program,
-			);
-			let recipient = match recipient {
-				Some(r) => r,
-				None => wallet_pubkey,
+			let user_token_account =
+				spl_associated_token_account::get_associated_token_address_with_program_id(
+					&wallet_pubkey,
+					&mint,
+					&token_program,
+				);
+
+			instructions::cleanup_token_account(
+				&whitelist,
+				&wallet_pubkey,
+				&mint,
+				&user_token_account,
+				&token_program,
+			)
+			.map_err(|err| {
+				anyhow!(
+					"Unable to create `CleanupTokenAccount` instruction: {}",
+					err
+				)
+			})?
+		}
+		Commands::ClaimTicketRent { mint } => {
+			let whitelist = program.whitelist_address(&mint);
+			let user_ticket = program.ticket_address(&wallet_pubkey, &whitelist);
+			let ticket_data = client.get_account_data(&user_ticket)?;
+			let ticket = stuk_wl::state::Ticket::unpack(&ticket_data)?;
+			if ticket.payer == wallet_pubkey {
+				return Err(anyhow!("Your ticket is already paid for by your wallet"));
+			}
+
+			instructions::claim_ticket_rent(&whitelist, &wallet_pubkey, &user_ticket, &ticket.payer)
+				.map_err(|err| anyhow!("Unable to create `ClaimTicketRent` instruction: {}", err))?
+		}
+		Commands::ReclaimRent { mint, treasury } => {
+			// Closes are packed into as few transactions as fit
+			if out_signed.is_some() {
+				return Err(anyhow!("reclaim-rent cannot be combined with --out-signed"));
+			}
+			let whitelist = program.whitelist_address(&mint);
+			let mint_account = client.get_account(&mint)?;
+			let token_program = mint_account.owner;
+
+			let whitelist_account = client
+				.get_account_with_commitment(&whitelist, client.commitment())?
+				.value;
+			let (treasury, whitelist_closed) = match whitelist_account {
+				Some(account) if !account.data.is_empty() => (
+					stuk_wl::state::Whitelist::unpack(&account.data)?.treasury,
+					false,
+				),
+				_ => (treasury.unwrap_or(wallet_pubkey), true),
+			};
+
+			let program_accounts = client.get_program_accounts(&program.id)?;
+			let mut tickets = Vec::new();
+			let mut skipped = 0;
+			for (pubkey, account) in program_accounts.iter() {
+				if !is_ticket_of(&account.data, &whitelist) {
+					continue;
+				}
+				let data = stuk_wl::state::Ticket::unpack(&account.data)?;
+				// Nothing is left to prove who the authority was, only the payer can sign
+				if whitelist_closed && data.payer != wallet_pubkey {
+					skipped += 1;
+					continue;
+				}
+				tickets.push(*pubkey);
+			}
+			if skipped > 0 {
+				println!(
+					"Skipping {} tickets paid for by other wallets, the whitelist is closed",
+					skipped
+				);
+			}
+
+			confirm(
+				&format!(
+					"Closing {} tickets of whitelist: {}\nMint: {}\nReclaiming rent to: {}",
+					tickets.len(),
+					whitelist,
+					mint,
+					treasury
+				),
+				skip_confirmation,
+			)?;
+
+			let mut closes = Vec::with_capacity(tickets.len());
+			for ticket in &tickets {
+				let ticket_token_account =
+					spl_associated_token_account::get_associated_token_address_with_program_id(
+						ticket,
+						&mint,
+						&token_program,
+					);
+				let instruction = instructions::force_close_ticket(
+					&whitelist,
+					&wallet_pubkey,
+					&mint,
+					&treasury,
+					ticket,
+					&ticket_token_account,
+					&token_program,
+				)
+				.map_err(|err| {
+					anyhow!("Unable to create `ForceCloseTicket` instruction: {}", err)
+				})?;
+				closes.push(program.target(instruction));
+			}
+
+			let signers = bulk_signers(&wallet_keypair, fee_payer.as_ref());
+			let mut failed_accounts = Vec::new();
+			let mut tickets = tickets.into_iter();
+			for mut transaction in
+				chunk_instructions(closes, &signers, client.get_latest_blockhash()?)?
+			{
+				let batch: Vec<Pubkey> = tickets
+					.by_ref()
+					.take(transaction.message.instructions.len())
+					.collect();
+				match send_chunk(&client, &mut transaction, &signers, no_wait) {
+					Ok(txid) => {
+						for ticket in &batch {
+							println!("Ticket closed: {}", ticket);
+						}
+						println!("TXID: {}", txid);
+					}
+					Err(e) => {
+						println!("Unable to close tickets: {:?}, reason: {}", batch, e);
+						failed_accounts.extend(batch);
+					}
+				}
+			}
+
+			if !failed_accounts.is_empty() {
+				return Err(anyhow!(
+					"{} ticket(s) could not be closed: {:?}",
+					failed_accounts.len(),
+					failed_accounts
+				));
+			}
+			return Ok(());
+		}
+		Commands::CloseTickets { mint, treasury } => {
+			// Every transaction carries a single instruction closing as many tickets as fit
+			if out_signed.is_some() {
+				return Err(anyhow!(
+					"close-tickets cannot be combined with --out-signed"
+				));
+			}
+			let whitelist = program.whitelist_address(&mint);
+			let mint_account = client.get_account(&mint)?;
+			let token_program = mint_account.owner;
+
+			let whitelist_account = client
+				.get_account_with_commitment(&whitelist, client.commitment())?
+				.value;
+			let (treasury, whitelist_closed) = match whitelist_account {
+				Some(account) if !account.data.is_empty() => (
+					stuk_wl::state::Whitelist::unpack(&account.data)?.treasury,
+					false,
+				),
+				_ => (treasury.unwrap_or(wallet_pubkey), true),
+			};
+
+			let mut tickets = Vec::new();
+			let mut skipped = 0;
+			let mut holding_tokens = 0;
+			let all_tickets = program.tickets(&client, &whitelist)?;
+			// Token accounts are fetched a page at a time, `getMultipleAccounts` takes at most 100
+			// addresses per request
+			for page in all_tickets.chunks(100) {
+				let token_accounts: Vec<Pubkey> = page
+					.iter()
+					.map(|(ticket, _)| {
+						spl_associated_token_account::get_associated_token_address_with_program_id(
+							ticket,
+							&mint,
+							&token_program,
+						)
+					})
+					.collect();
+				let balances = client.get_multiple_accounts(&token_accounts)?;
+				for (((ticket, data), token_account), balance) in
+					page.iter().zip(token_accounts).zip(balances)
+				{
+					// Nothing is left to prove who the authority was, only the payer can sign
+					if whitelist_closed && data.payer != wallet_pubkey {
+						skipped += 1;
+						continue;
+					}
+					let amount = match balance {
+						Some(account) => {
+							StateWithExtensions::<Account>::unpack(&account.data)?
+								.base
+								.amount
+						}
+						None => 0,
+					};
+					if amount > 0 {
+						holding_tokens += 1;
+						continue;
+					}
+					tickets.push((*ticket, token_account));
+				}
+			}
+			if skipped > 0 {
+				println!(
+					"Skipping {} tickets paid for by other wallets, the whitelist is closed",
+					skipped
+				);
+			}
+			if holding_tokens > 0 {
+				println!(
+					"Skipping {} tickets still holding tokens, use `burn` for those",
+					holding_tokens
+				);
+			}
+
+			confirm(
+				&format!(
+					"Closing {} tickets of whitelist: {}\nMint: {}\nReclaiming rent to: {}",
+					tickets.len(),
+					whitelist,
+					mint,
+					treasury
+				),
+				skip_confirmation,
+			)?;
+
+			let close_instruction = |batch: &[(Pubkey, Pubkey)]| -> Result<Instruction> {
+				let instruction = instructions::close_tickets(
+					&whitelist,
+					&wallet_pubkey,
+					&mint,
+					&treasury,
+					batch,
+					&token_program,
+				)
+				.map_err(|err| anyhow!("Unable to create `CloseTickets` instruction: {}", err))?;
+				Ok(program.target(instruction))
+			};
+
+			let signers = bulk_signers(&wallet_keypair, fee_payer.as_ref());
+			let payer = signers[0].pubkey();
+			let mut batches: Vec<Vec<(Pubkey, Pubkey)>> = Vec::new();
+			let mut batch = Vec::new();
+			for pair in tickets {
+				batch.push(pair);
+				if transaction_size(&[close_instruction(&batch)?], &payer)? <= PACKET_DATA_SIZE {
+					continue;
+				}
+				let overflow = batch.pop().unwrap();
+				batches.push(std::mem::replace(&mut batch, vec![overflow]));
+			}
+			if !batch.is_empty() {
+				batches.push(batch);
+			}
+
+			let mut failed_accounts = Vec::new();
+			for batch in batches {
+				let tickets: Vec<Pubkey> = batch.iter().map(|(ticket, _)| *ticket).collect();
+				let mut transaction =
+					Transaction::new_with_payer(&[close_instruction(&batch)?], Some(&payer));
+				match send_chunk(&client, &mut transaction, &signers, no_wait) {
+					Ok(txid) => {
+						for ticket in &tickets {
+							println!("Ticket closed: {}", ticket);
+						}
+						println!("TXID: {}", txid);
+					}
+					Err(e) => {
+						println!("Unable to close tickets: {:?}, reason: {}", tickets, e);
+						failed_accounts.extend(tickets);
+					}
+				}
+			}
+
+			if !failed_accounts.is_empty() {
+				return Err(anyhow!(
+					"{} ticket(s) could not be closed: {:?}",
+					failed_accounts.len(),
+					failed_accounts
+				));
+			}
+			return Ok(());
+		}
+		Commands::Close { mint, recipient } => {
+			let whitelist = program.whitelist_address(&mint);
+			let mint_account = client.get_account(&mint)?;
+			let token_program = mint_account.owner;
+			let vault = program.vault_address(&mint, &token_program);
+			let recipient = match recipient {
+				Some(r) => r,
+				None => wallet_pubkey,
 			};
 			let token_account =
 				spl_associated_token_account::get_associated_token_address_with_program_id(
-					&mint,
 					&recipient,
+					&mint,
 					&token_program,
 				);
 
-			instructions::terminate_whitelist(
+			let vault_balance = match client.get_account_data(&vault) {
+				Ok(data) => StateWithExtensions::<Account>::unpack(&data)?.base.amount,
+				Err(_) => 0,
+			};
+			confirm(
+				&format!(
+					"Closing whitelist: {}\nMint: {}\nSending {} tokens (base units) from vault: {}\nTo token account: {}\nReclaiming rent to: {}",
+					whitelist, mint, vault_balance, vault, token_account, wallet_pubkey
+				),
+				skip_confirmation,
+			)?;
+
+			let mut instruction = instructions::terminate_whitelist(
 				&whitelist,
 				&wallet_pubkey,
 				&vault,
@@ -795,11 +2247,175 @@ fn main() -> Result<()> {
 				&token_account,
 				&token_program,
 			)
-			.map_err(|err| anyhow!("Unable to create `TerminateWhitelist` instruction: {}", err))?
+			.map_err(|err| anyhow!("Unable to create `TerminateWhitelist` instruction: {}", err))?;
+			// The program checks a treasury PDA has been emptied before it lets the sale close
+			let wl_data = stuk_wl::state::Whitelist::unpack(&client.get_account_data(&whitelist)?)?;
+			if wl_data.treasury == program.treasury_address(&mint) {
+				instruction
+					.accounts
+					.push(AccountMeta::new_readonly(wl_data.treasury, false));
+			}
+			instruction
+		}
+		Commands::CloseVault { mint, recipient } => {
+			let whitelist = program.whitelist_address(&mint);
+			let mint_account = client.get_account(&mint)?;
+			let token_program = mint_account.owner;
+			let vault = program.vault_address(&mint, &token_program);
+			let recipient = recipient.unwrap_or(wallet_pubkey);
+			let token_account =
+				spl_associated_token_account::get_associated_token_address_with_program_id(
+					&recipient,
+					&mint,
+					&token_program,
+				);
+
+			let vault_balance =
+				StateWithExtensions::<Account>::unpack(&client.get_account_data(&vault)?)?
+					.base
+					.amount;
+			confirm(
+				&format!(
+					"Closing vault: {}\nMint: {}\nSending {} tokens (base units) to token account: {}\nReclaiming rent to: {}",
+					vault, mint, vault_balance, token_account, wallet_pubkey
+				),
+				skip_confirmation,
+			)?;
+
+			instructions::close_vault(
+				&whitelist,
+				&wallet_pubkey,
+				&vault,
+				&mint,
+				&token_account,
+				&token_program,
+			)
+			.map_err(|err| anyhow!("Unable to create `CloseVault` instruction: {}", err))?
+		}
+		Commands::Migrate { mint } => {
+			let whitelist = program.whitelist_address(&mint);
+
+			println!("Migrating whitelist: {}", whitelist);
+
+			instructions::migrate_whitelist(&whitelist, &wallet_pubkey).map_err(|err| {
+				anyhow!("Unable to create `MigrateWhitelist` instruction: {}", err)
+			})?
+		}
+		Commands::MigrateTicket { mint, user } => {
+			let whitelist = program.whitelist_address(&mint);
+			let user_ticket = program.ticket_address(&user, &whitelist);
+
+			println!("Migrating ticket: {}", user_ticket);
+
+			instructions::migrate_ticket(&user_ticket, &wallet_pubkey)
+				.map_err(|err| anyhow!("Unable to create `MigrateTicket` instruction: {}", err))?
+		}
+		Commands::Doctor { mint } => {
+			let whitelist = program.whitelist_address(&mint);
+			let mut problems = 0;
+
+			let whitelist_data = client.get_account_data(&whitelist)?;
+			match diagnose_whitelist(&whitelist_data) {
+				Ok(()) => println!("Whitelist {}: ok", whitelist),
+				Err(problem) => {
+					problems += 1;
+					println!("Whitelist {}: {}", whitelist, problem);
+				}
+			}
+
+			let program_accounts = client.get_program_accounts(&program.id)?;
+			let mut tickets = 0;
+			for (pubkey, account) in program_accounts.iter() {
+				if !is_ticket_of(&account.data, &whitelist) {
+					continue;
+				}
+				tickets += 1;
+				if let Err(problem) = diagnose_ticket(&account.data) {
+					problems += 1;
+					println!("Ticket {}: {}", pubkey, problem);
+				}
+			}
+			println!("Checked {} tickets", tickets);
+
+			if problems > 0 {
+				return Err(anyhow!(
+					"{} account(s) do not match the expected layout",
+					problems
+				));
+			}
+			return Ok(());
+		}
+		Commands::Whoami => {
+			let fee_payer_pubkey = fee_payer.as_ref().map(|fee_payer| fee_payer.pubkey());
+			for line in whoami_report(
+				&wallet_pubkey,
+				fee_payer_pubkey.as_ref(),
+				&client.url(),
+				client.commitment(),
+				&program,
+			) {
+				println!("{}", line);
+			}
+			return Ok(());
+		}
+		Commands::Estimate {
+			whitelist_size,
+			buy_limit,
+			decimals,
+		} => {
+			let estimate = estimate_sale(
+				whitelist_size,
+				buy_limit,
+				decimals,
+				client.get_minimum_balance_for_rent_exemption(stuk_wl::state::Whitelist::LEN)?,
+				client.get_minimum_balance_for_rent_exemption(stuk_wl::state::Ticket::LEN)?,
+				client.get_minimum_balance_for_rent_exemption(
+					ExtensionType::try_calculate_account_len::<Account>(&[])?,
+				)?,
+			)?;
+
+			println!(
+				"Whitelist rent: {} SOL",
+				lamports_to_sol(estimate.whitelist_rent)
+			);
+			println!(
+				"Ticket rent: {} SOL ({} tickets)",
+				lamports_to_sol(estimate.ticket_rent),
+				whitelist_size
+			);
+			println!("Vault rent: {} SOL", lamports_to_sol(estimate.vault_rent));
+			println!(
+				"Total rent: {} SOL",
+				lamports_to_sol(estimate.total_rent()?)
+			);
+			println!(
+				"Tokens required: {}",
+				spl_token_2022::amount_to_ui_amount(estimate.tokens_required, decimals)
+			);
+			return Ok(());
+		}
+		Commands::Precompute {
+			mint,
+			from_file,
+			out,
+		} => {
+			let contents = std::fs::read_to_string(&from_file).map_err(|err| {
+				anyhow!("Unable to read users file {}: {}", from_file.display(), err)
+			})?;
+			let users = read_users(&contents)?;
+			let whitelist = program.whitelist_address(&mint);
+			let record = ticket_cache_record(&program.id, &whitelist, &users);
+			std::fs::write(&out, serde_json::to_string_pretty(&record)?)?;
+			println!(
+				"Cached {} ticket addresses to: {}",
+				users.len(),
+				out.display()
+			);
+			return Ok(());
 		}
 		Commands::Info(info) => match info {
 			Info::Whitelist { mint } => {
-				let whitelist = get_whitelist_address(&mint).0;
+				let whitelist = program.whitelist_address(&mint);
 
 				let mint_decimals = {
 					let mint_account = client.get_account_data(&mint)?;
@@ -810,7 +2426,7 @@ fn main() -> Result<()> {
 				};
 
 				let data = client.get_account_data(&whitelist).unwrap().clone();
-				let d = stuk_wl::state::Whitelist::try_from_slice(&data)?;
+				let d = stuk_wl::state::Whitelist::unpack(&data)?;
 
 				let buy_limit = spl_token_2022::amount_to_ui_amount(d.buy_limit, mint_decimals);
 				let deposited = spl_token_2022::amount_to_ui_amount(d.deposited, mint_decimals);
@@ -827,6 +2443,18 @@ fn main() -> Result<()> {
 				println!("Registration duration: {:?}", d.registration_duration);
 				println!("Sale start time: {:?}", d.sale_timestamp);
 				println!("Sale duration: {:?}", d.sale_duration);
+				println!("Registered: {}/{}", d.registered, d.whitelist_size);
+				println!("Ticket ceiling: {}", d.max_tickets);
+				println!("Registration paused?: {}", d.registration_paused);
+				println!("Sale paused?: {}", d.sale_paused);
+				println!(
+					"Referral bonus: {}",
+					spl_token_2022::amount_to_ui_amount(d.referral_bonus, mint_decimals)
+				);
+				println!("Buy cooldown: {}s", d.cooldown_secs);
+				println!("Refund window: {}s", d.refund_window_secs);
+				println!("Authority pays rent?: {}", d.authority_pays_rent);
+				println!("Register authority: {:?}", d.register_authority());
 
 				std::process::exit(1);
 			}
@@ -838,13 +2466,13 @@ fn main() -> Result<()> {
 					)?;
 					mint_data.base.decimals
 				};
-				let whitelist = get_whitelist_address(&mint).0;
-				let ticket = get_user_ticket_address(&user, &whitelist).0;
+				let whitelist = program.whitelist_address(&mint);
+				let ticket = program.ticket_address(&user, &whitelist);
 				let ticket_ata =
 					spl_associated_token_account::get_associated_token_address(&ticket, &mint);
 
 				let data = client.get_account_data(&ticket).unwrap().clone();
-				let d = stuk_wl::state::Ticket::try_from_slice(&data)?;
+				let d = stuk_wl::state::Ticket::unpack(&data)?;
 
 				let allowance = spl_token_2022::amount_to_ui_amount(d.allowance, mint_decimals);
 				let amount_bought =
@@ -855,25 +2483,1784 @@ fn main() -> Result<()> {
 				println!("Ticket payer: {}", d.payer);
 				println!("Ticket allowance: {}", allowance);
 				println!("Amount purchased: {}", amount_bought);
+				println!(
+					"Amount claimed: {}",
+					spl_token_2022::amount_to_ui_amount(d.claimed, mint_decimals)
+				);
+				println!(
+					"Claimable now: {}",
+					spl_token_2022::amount_to_ui_amount(d.claimable(), mint_decimals)
+				);
+				println!("Ticket note: {}", d.note_string());
+				match d.referrer() {
+					Some(referrer) => println!("Referred by: {}", referrer),
+					None => println!("Referred by: none"),
+				}
+				println!("Last purchase time: {:?}", d.last_purchase_ts);
 
 				std::process::exit(1);
 			}
+			Info::Tickets { mint } => {
+				let mint_account = client.get_account(&mint)?;
+				let token_program = mint_account.owner;
+				let mint_decimals = StateWithExtensions::<Mint>::unpack(&mint_account.data)?
+					.base
+					.decimals;
+				let whitelist = program.whitelist_address(&mint);
+				let tickets = program.tickets(&client, &whitelist)?;
+
+				let mut rows = Vec::with_capacity(tickets.len());
+				// Balances are fetched a page at a time, `getMultipleAccounts` takes at most 100
+				// addresses per request
+				for page in tickets.chunks(100) {
+					let token_accounts: Vec<Pubkey> = page
+						.iter()
+						.map(|(ticket, _)| {
+							spl_associated_token_account::get_associated_token_address_with_program_id(
+								ticket,
+								&mint,
+								&token_program,
+							)
+						})
+						.collect();
+					let token_accounts = client.get_multiple_accounts(&token_accounts)?;
+					for ((ticket, data), token_account) in page.iter().zip(token_accounts) {
+						let balance = token_account
+							.map(|account| {
+								StateWithExtensions::<Account>::unpack(&account.data)
+									.map(|token_account| token_account.base.amount)
+							})
+							.transpose()?;
+						rows.push(TicketRow {
+							ticket: *ticket,
+							owner: data.owner,
+							allowance: data.allowance,
+							amount_bought: data.amount_bought,
+							balance,
+						});
+					}
+				}
+
+				print!("{}", format_ticket_table(rows, mint_decimals));
+				println!("{} tickets on whitelist: {}", tickets.len(), whitelist);
+				return Ok(());
+			}
+			Info::Whitelists => {
+				let whitelists = program.get_all_whitelists(&client)?;
+				for (whitelist, data) in &whitelists {
+					println!(
+						"Whitelist: {} Mint: {} Registered: {}/{}",
+						whitelist, data.mint, data.registered, data.whitelist_size
+					);
+				}
+				println!("{} whitelists on program: {}", whitelists.len(), program.id);
+				return Ok(());
+			}
 		},
 	};
 
-	let mut transaction = Transaction::new_with_payer(&[instruction], Some(&wallet_pubkey));
 	let latest_blockhash = client
 		.get_latest_blockhash()
 		.map_err(|err| anyhow!("Unable to get latest blockhash: {}", err))?;
-	transaction.sign(&[&wallet_keypair], latest_blockhash);
-	let txid = client
-		.send_and_confirm_transaction_with_spinner(&transaction)
-		.map_err(|err| anyhow!("Unable to send transaction: {}", err))?;
+	let transaction = signed_transaction(
+		program.target(instruction),
+		&wallet_keypair,
+		fee_payer.as_ref(),
+		latest_blockhash,
+	);
+
+	if let Some(path) = out_signed {
+		std::fs::write(&path, encode_signed_transaction(&transaction)?)?;
+		println!("Signed transaction written to: {}", path.display());
+		println!("Signature: {}", transaction.signatures[0]);
+		return Ok(());
+	}
+
+	let txid = send_transaction(&client, &transaction, no_wait).map_err(|err| {
+		anyhow!(
+			"Unable to send transaction: {}",
+			describe_client_error(&err)
+		)
+	})?;
 	println!("TXID: {}", txid);
+
+	if let Some((out_dir, mut record)) = sale_record {
+		record["txid"] = json!(txid.to_string());
+		let path = write_sale_record(&out_dir, &record)?;
+		println!("Sale record written to: {}", path.display());
+	}
 	Ok(())
 }
 
-fn string_to_timestamp(date_string: String) -> Result<i64, chrono::ParseError> {
-	let datetime = NaiveDateTime::parse_from_str(date_string.as_str(), "%Y-%m-%s %H:%M:%S")?;
-	Ok(datetime.and_utc().timestamp())
+/// The whitelist program the CLI talks to, the id declared by `stuk_wl` unless overridden with
+/// `--program-id`. Addresses are derived and instructions sent against this id
+#[derive(Clone, Debug)]
+struct WhitelistProgram {
+	id: Pubkey,
+	ticket_cache: Option<TicketCache>,
+}
+
+impl WhitelistProgram {
+	fn new(program_id: Option<Pubkey>) -> Self {
+		Self {
+			id: program_id.unwrap_or_else(stuk_wl::id),
+			ticket_cache: None,
+		}
+	}
+
+	/// Looks ticket addresses up in `cache` before deriving them
+	fn with_ticket_cache(mut self, cache: TicketCache) -> Self {
+		self.ticket_cache = Some(cache);
+		self
+	}
+
+	fn whitelist_address(&self, mint: &Pubkey) -> Pubkey {
+		get_whitelist_address_with_program_id(mint, &self.id).0
+	}
+
+	/// The whitelist's vault, its associated token account for `mint`
+	fn vault_address(&self, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+		spl_associated_token_account::get_associated_token_address_with_program_id(
+			&self.whitelist_address(mint),
+			mint,
+			token_program,
+		)
+	}
+
+	fn ticket_address(&self, user: &Pubkey, whitelist: &Pubkey) -> Pubkey {
+		self.ticket_cache
+			.as_ref()
+			.and_then(|cache| cache.ticket(user, whitelist))
+			.unwrap_or_else(|| get_user_ticket_address_with_program_id(user, whitelist, &self.id).0)
+	}
+
+	fn depositor_address(&self, depositor: &Pubkey, whitelist: &Pubkey) -> Pubkey {
+		get_depositor_address_with_program_id(depositor, whitelist, &self.id).0
+	}
+
+	fn allocation_address(&self, user: &Pubkey, whitelist: &Pubkey) -> Pubkey {
+		get_allocation_address_with_program_id(user, whitelist, &self.id).0
+	}
+
+	fn treasury_address(&self, mint: &Pubkey) -> Pubkey {
+		get_treasury_address_with_program_id(mint, &self.id).0
+	}
+
+	/// Points an instruction built by `stuk_wl::instructions` at this program
+	fn target(&self, mut instruction: Instruction) -> Instruction {
+		instruction.program_id = self.id;
+		instruction
+	}
+
+	/// Fetches every ticket of `whitelist`, leaving the RPC node to match the ticket size and the
+	/// whitelist stored after the version and bump
+	fn tickets(
+		&self,
+		client: &RpcClient,
+		whitelist: &Pubkey,
+	) -> Result<Vec<(Pubkey, stuk_wl::state::Ticket)>> {
+		let config = RpcProgramAccountsConfig {
+			filters: Some(vec![
+				RpcFilterType::DataSize(stuk_wl::state::Ticket::LEN as u64),
+				RpcFilterType::Memcmp(Memcmp::new_base58_encoded(2, whitelist.as_ref())),
+			]),
+			account_config: RpcAccountInfoConfig {
+				encoding: Some(UiAccountEncoding::Base64),
+				..RpcAccountInfoConfig::default()
+			},
+			..RpcProgramAccountsConfig::default()
+		};
+
+		client
+			.get_program_accounts_with_config(&self.id, config)?
+			.into_iter()
+			.map(|(pubkey, account)| -> Result<_> {
+				Ok((pubkey, stuk_wl::state::Ticket::unpack(&account.data)?))
+			})
+			.collect()
+	}
+
+	/// Fetches every whitelist created by the program, each carrying the mint of its sale
+	fn get_all_whitelists(
+		&self,
+		client: &RpcClient,
+	) -> Result<Vec<(Pubkey, stuk_wl::state::Whitelist)>> {
+		let config = RpcProgramAccountsConfig {
+			filters: Some(whitelist_filters()),
+			account_config: RpcAccountInfoConfig {
+				encoding: Some(UiAccountEncoding::Base64),
+				..RpcAccountInfoConfig::default()
+			},
+			..RpcProgramAccountsConfig::default()
+		};
+
+		client
+			.get_program_accounts_with_config(&self.id, config)?
+			.into_iter()
+			.map(|(pubkey, account)| -> Result<_> {
+				Ok((pubkey, stuk_wl::state::Whitelist::unpack(&account.data)?))
+			})
+			.collect()
+	}
+}
+
+/// Matches whitelists in the current layout by their size and leading version byte, which
+/// tickets and the program's other accounts do not share the size of
+fn whitelist_filters() -> Vec<RpcFilterType> {
+	vec![
+		RpcFilterType::DataSize(stuk_wl::state::Whitelist::LEN as u64),
+		RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+			0,
+			&[stuk_wl::state::Whitelist::VERSION],
+		)),
+	]
+}
+
+/// Whether `ticket_count` tickets leave no room on a whitelist of `whitelist_size`, a size of 0
+/// is unbounded
+fn whitelist_full(whitelist_size: u64, ticket_count: usize) -> bool {
+	whitelist_size > 0 && ticket_count as u64 >= whitelist_size
+}
+
+/// Sends a signed transaction and waits for it to be confirmed, with `no_wait` the signature is
+/// returned as soon as the RPC node accepts the transaction
+fn send_transaction(
+	client: &RpcClient,
+	transaction: &Transaction,
+	no_wait: bool,
+) -> Result<Signature, ClientError> {
+	if no_wait {
+		client.send_transaction(transaction)
+	} else {
+		client.send_and_confirm_transaction_with_spinner(transaction)
+	}
+}
+
+/// Parses a `YYYY-MM-DD HH:MM:SS` UTC date into a Unix timestamp, rejecting dates at or before
+/// the epoch as the program treats a timestamp of 0 as unset. A trailing `Z` or `UTC` marker is
+/// accepted, dates are always read as UTC
+fn string_to_timestamp(date_string: String) -> Result<i64> {
+	let trimmed = date_string.trim();
+	let date = trimmed
+		.strip_suffix('Z')
+		.or_else(|| trimmed.strip_suffix("UTC"))
+		.unwrap_or(trimmed)
+		.trim_end();
+	let datetime = NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").map_err(|err| {
+		anyhow!(
+			"Invalid date {:?}, expected YYYY-MM-DD HH:MM:SS in UTC: {}",
+			date_string,
+			err
+		)
+	})?;
+	check_timestamp(datetime.and_utc().timestamp())
+}
+
+fn check_timestamp(timestamp: i64) -> Result<i64> {
+	if timestamp <= 0 {
+		return Err(anyhow!("Time {} is not after the Unix epoch", timestamp));
+	}
+	Ok(timestamp)
+}
+
+/// Rejects a start time before `now`, the program's `check_times` would reject it on-chain
+fn check_start_time(timestamp: i64, now: i64) -> Result<i64> {
+	if timestamp < now {
+		return Err(anyhow!(
+			"Start time {} is in the past, the current time is {}",
+			timestamp,
+			now
+		));
+	}
+	Ok(timestamp)
+}
+
+/// Duration of a window from `start` to `end`, computed with a checked subtraction so that
+/// `start + duration` always fits in an `i64`
+fn duration_between(start: i64, end: i64) -> Result<i64> {
+	let duration = end
+		.checked_sub(start)
+		.ok_or_else(|| anyhow!("Duration from {} to {} overflows", start, end))?;
+	check_timestamp(start).map_err(|_| anyhow!("Cannot compute duration, no start time is set"))?;
+	if duration <= 0 {
+		return Err(anyhow!(
+			"Cannot compute duration, start time is after provided end time"
+		));
+	}
+	Ok(duration)
+}
+
+/// Describes a failed transaction using the whitelist program's error message when the failure
+/// came from one of its instructions
+fn describe_client_error(err: &ClientError) -> String {
+	match err.get_transaction_error() {
+		Some(transaction_error) => {
+			describe_transaction_error(&transaction_error).unwrap_or_else(|| err.to_string())
+		}
+		None => err.to_string(),
+	}
+}
+
+/// The whitelist program's error message for a transaction error raised by one of its
+/// instructions
+fn describe_transaction_error(err: &TransactionError) -> Option<String> {
+	match err {
+		TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
+			WhitelistError::from_code(*code).map(|whitelist_error| whitelist_error.to_string())
+		}
+		_ => None,
+	}
+}
+
+/// Accounts a purchase changes, along with their balances before it
+struct BuyQuoteAccounts {
+	token_account: Pubkey,
+	token_balance: u64,
+	treasury: Pubkey,
+	treasury_lamports: u64,
+	ticket: Pubkey,
+}
+
+/// Outcome of a simulated purchase, token amounts are in base units
+#[derive(Debug, PartialEq)]
+struct BuyQuote {
+	tokens_received: u64,
+	lamports_charged: u64,
+	remaining_allowance: u64,
+}
+
+/// Simulates a purchase and reads its outcome from the accounts the simulation returns, the
+/// transaction is never signed or sent
+fn quote_buy(
+	client: &RpcClient,
+	instruction: &Instruction,
+	payer: &Pubkey,
+	accounts: &BuyQuoteAccounts,
+) -> Result<BuyQuote> {
+	let transaction =
+		Transaction::new_unsigned(Message::new(std::slice::from_ref(instruction), Some(payer)));
+	let config = RpcSimulateTransactionConfig {
+		sig_verify: false,
+		replace_recent_blockhash: true,
+		accounts: Some(RpcSimulateTransactionAccountsConfig {
+			encoding: None,
+			addresses: vec![
+				accounts.token_account.to_string(),
+				accounts.treasury.to_string(),
+				accounts.ticket.to_string(),
+			],
+		}),
+		..RpcSimulateTransactionConfig::default()
+	};
+	let result = client
+		.simulate_transaction_with_config(&transaction, config)
+		.map_err(|err| anyhow!("Unable to simulate transaction: {}", err))?
+		.value;
+
+	if let Some(err) = result.err {
+		for log in result.logs.unwrap_or_default() {
+			println!("{}", log);
+		}
+		return Err(anyhow!(
+			"Purchase would fail: {}",
+			describe_transaction_error(&err).unwrap_or_else(|| err.to_string())
+		));
+	}
+
+	let simulated: Vec<_> = result
+		.accounts
+		.unwrap_or_default()
+		.into_iter()
+		.map(|account| account.and_then(|account| account.decode::<SdkAccount>()))
+		.collect();
+	let [Some(token_account), Some(treasury), Some(ticket)] = simulated.as_slice() else {
+		return Err(anyhow!("Simulation did not return the purchase's accounts"));
+	};
+
+	let token_balance = StateWithExtensions::<Account>::unpack(&token_account.data)?
+		.base
+		.amount;
+	let ticket = stuk_wl::state::Ticket::unpack(&ticket.data)?;
+	Ok(BuyQuote {
+		tokens_received: token_balance.saturating_sub(accounts.token_balance),
+		lamports_charged: treasury.lamports.saturating_sub(accounts.treasury_lamports),
+		remaining_allowance: ticket.allowance.saturating_sub(ticket.amount_bought),
+	})
+}
+
+/// Checks a whitelist account against the current layout, describing any mismatch along with
+/// how to fix it
+fn diagnose_whitelist(data: &[u8]) -> Result<(), String> {
+	use stuk_wl::state::{Whitelist, WhitelistV0};
+
+	if data.len() == WhitelistV0::LEN && WhitelistV0::try_from_slice(data).is_ok() {
+		return Err(format!(
+			"legacy layout ({} bytes), run `fsp-wl migrate <MINT>`",
+			data.len()
+		));
+	}
+	if data.len() != Whitelist::LEN {
+		return Err(format!(
+			"unexpected length {} bytes, expected {}",
+			data.len(),
+			Whitelist::LEN
+		));
+	}
+	match Whitelist::unpack(data) {
+		Ok(_) => Ok(()),
+		Err(_) if data[0] != Whitelist::VERSION => Err(format!(
+			"unsupported layout version {}, upgrade fsp-wl",
+			data[0]
+		)),
+		Err(err) => Err(format!("failed to deserialize: {}", err)),
+	}
+}
+
+/// Checks a ticket account against the current layout, describing any mismatch along with how
+/// to fix it
+fn diagnose_ticket(data: &[u8]) -> Result<(), String> {
+	use stuk_wl::state::{Ticket, TicketV0};
+
+	if data.len() == TicketV0::LEN {
+		if let Ok(legacy) = TicketV0::try_from_slice(data) {
+			return Err(format!(
+				"legacy layout ({} bytes), run `fsp-wl migrate-ticket <MINT> {}`",
+				data.len(),
+				legacy.owner
+			));
+		}
+	}
+	if data.len() != Ticket::LEN {
+		return Err(format!(
+			"unexpected length {} bytes, expected {}",
+			data.len(),
+			Ticket::LEN
+		));
+	}
+	match Ticket::unpack(data) {
+		Ok(_) => Ok(()),
+		Err(_) if data[0] != Ticket::VERSION => Err(format!(
+			"unsupported layout version {}, upgrade fsp-wl",
+			data[0]
+		)),
+		Err(err) => Err(format!("failed to deserialize: {}", err)),
+	}
+}
+
+/// Whether a program account is a ticket of `whitelist`. Tickets store the whitelist after the
+/// version and bump, or after the bump alone in the unversioned layout. Depositor ledgers share
+/// the current prefix and are told apart by their length
+fn is_ticket_of(data: &[u8], whitelist: &Pubkey) -> bool {
+	let whitelist = whitelist.as_ref();
+	if data.get(2..34) == Some(whitelist) {
+		data.len() != stuk_wl::state::Depositor::LEN
+	} else {
+		data.get(1..33) == Some(whitelist)
+	}
+}
+
+/// Splits a purchase into chunks of at most `chunk` tokens, capped to the remaining allowance
+fn split_amount(amount: u64, chunk: u64, remaining_allowance: u64) -> Result<Vec<u64>> {
+	if chunk == 0 {
+		return Err(anyhow!("Chunk size must be greater than 0"));
+	}
+
+	let mut remaining = amount.min(remaining_allowance);
+	let mut chunks = Vec::with_capacity(remaining.div_ceil(chunk) as usize);
+	while remaining > 0 {
+		let next = remaining.min(chunk);
+		chunks.push(next);
+		remaining -= next;
+	}
+	Ok(chunks)
+}
+
+/// Ticket addresses derived ahead of time by `precompute` for the users of one whitelist
+#[derive(Clone, Debug, Default, PartialEq)]
+struct TicketCache {
+	whitelist: Pubkey,
+	tickets: HashMap<Pubkey, Pubkey>,
+}
+
+impl TicketCache {
+	fn load(path: &Path, program_id: &Pubkey) -> Result<Self> {
+		let contents = std::fs::read_to_string(path)
+			.map_err(|err| anyhow!("Unable to read ticket cache {}: {}", path.display(), err))?;
+		Self::from_record(&serde_json::from_str(&contents)?, program_id)
+	}
+
+	/// Reads a cache written by `ticket_cache_record`, rejecting one derived for another
+	/// program as every address in it would be wrong
+	fn from_record(record: &Value, program_id: &Pubkey) -> Result<Self> {
+		let pubkey = |value: &Value| -> Result<Pubkey> {
+			value
+				.as_str()
+				.ok_or_else(|| anyhow!("Ticket cache holds a non-string address: {}", value))?
+				.parse()
+				.map_err(|err| anyhow!("Ticket cache holds an invalid address {}: {}", value, err))
+		};
+
+		let cached_program_id = pubkey(&record["program_id"])?;
+		if &cached_program_id != program_id {
+			return Err(anyhow!(
+				"Ticket cache was derived for program {}, not {}, run `precompute` again",
+				cached_program_id,
+				program_id
+			));
+		}
+
+		let tickets = record["tickets"]
+			.as_object()
+			.ok_or_else(|| anyhow!("Ticket cache has no tickets"))?
+			.iter()
+			.map(|(user, entry)| Ok((pubkey(&json!(user))?, pubkey(&entry["ticket"])?)))
+			.collect::<Result<HashMap<_, _>>>()?;
+
+		Ok(Self {
+			whitelist: pubkey(&record["whitelist"])?,
+			tickets,
+		})
+	}
+
+	/// The cached ticket of `user`, if the cache was built for `whitelist` and lists them
+	fn ticket(&self, user: &Pubkey, whitelist: &Pubkey) -> Option<Pubkey> {
+		if whitelist != &self.whitelist {
+			return None;
+		}
+		self.tickets.get(user).copied()
+	}
+}
+
+/// Parses a users file, one public key per line. Blank lines and lines starting with `#` are
+/// skipped
+fn read_users(contents: &str) -> Result<Vec<Pubkey>> {
+	contents
+		.lines()
+		.enumerate()
+		.map(|(index, line)| (index + 1, line.trim()))
+		.filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+		.map(|(number, line)| {
+			line.parse()
+				.map_err(|err| anyhow!("Invalid public key on line {}: {} ({})", number, line, err))
+		})
+		.collect()
+}
+
+/// Derives the ticket address and bump of each of `users` on `whitelist` for the cache written
+/// by `precompute`
+fn ticket_cache_record(program_id: &Pubkey, whitelist: &Pubkey, users: &[Pubkey]) -> Value {
+	let tickets = users
+		.iter()
+		.map(|user| {
+			let (ticket, bump) =
+				get_user_ticket_address_with_program_id(user, whitelist, program_id);
+			(
+				user.to_string(),
+				json!({ "ticket": ticket.to_string(), "bump": bump }),
+			)
+		})
+		.collect::<serde_json::Map<_, _>>();
+
+	json!({
+		"program_id": program_id.to_string(),
+		"whitelist": whitelist.to_string(),
+		"tickets": tickets,
+	})
+}
+
+/// Lines printed by `whoami`, the identity, cluster and program a command would run against
+fn whoami_report(
+	wallet: &Pubkey,
+	fee_payer: Option<&Pubkey>,
+	rpc_url: &str,
+	commitment: CommitmentConfig,
+	program: &WhitelistProgram,
+) -> Vec<String> {
+	let program_source = if program.id == stuk_wl::id() {
+		"declared"
+	} else {
+		"--program-id override"
+	};
+	vec![
+		format!("Payer: {}", wallet),
+		format!("Fee payer: {}", fee_payer.unwrap_or(wallet)),
+		format!("RPC URL: {}", rpc_url),
+		format!("Commitment: {:?}", commitment.commitment),
+		format!("Program id: {} ({})", program.id, program_source),
+	]
+}
+
+/// Writes `sale.json` to `out_dir`, creating the directory if needed
+fn write_sale_record(out_dir: &Path, record: &Value) -> Result<PathBuf> {
+	std::fs::create_dir_all(out_dir)?;
+	let path = out_dir.join("sale.json");
+	std::fs::write(&path, serde_json::to_string_pretty(record)?)?;
+	Ok(path)
+}
+
+/// Builds a transaction for `instruction` signed by `signer`, with fees paid by `fee_payer` when
+/// one is given, which then signs as well and comes first in the account keys
+fn signed_transaction(
+	instruction: Instruction,
+	signer: &Keypair,
+	fee_payer: Option<&Keypair>,
+	recent_blockhash: Hash,
+) -> Transaction {
+	let fee_payer = fee_payer.unwrap_or(signer);
+	let mut transaction = Transaction::new_with_payer(&[instruction], Some(&fee_payer.pubkey()));
+	if fee_payer.pubkey() == signer.pubkey() {
+		transaction.sign(&[signer], recent_blockhash);
+	} else {
+		transaction.sign(&[fee_payer, signer], recent_blockhash);
+	}
+	transaction
+}
+
+/// Signers for a bulk command, the fee payer first when one is given
+fn bulk_signers<'a>(signer: &'a Keypair, fee_payer: Option<&'a Keypair>) -> Vec<&'a Keypair> {
+	match fee_payer {
+		Some(fee_payer) if fee_payer.pubkey() != signer.pubkey() => vec![fee_payer, signer],
+		_ => vec![signer],
+	}
+}
+
+/// Serialized size of a transaction carrying `instructions` with one signature per required
+/// signer, the blockhash does not affect the size
+fn transaction_size(instructions: &[Instruction], payer: &Pubkey) -> Result<usize> {
+	let transaction = Transaction::new_unsigned(Message::new(instructions, Some(payer)));
+	Ok(bincode::serialize(&transaction)?.len())
+}
+
+/// Signs `transaction` with the keypairs of `signers` it requires, re-signing from scratch when
+/// `recent_blockhash` differs from the one it was signed with
+fn sign_with(
+	transaction: &mut Transaction,
+	signers: &[&Keypair],
+	recent_blockhash: Hash,
+) -> Result<()> {
+	let required = &transaction.message.account_keys
+		[..transaction.message.header.num_required_signatures as usize];
+	let signers: Vec<&Keypair> = signers
+		.iter()
+		.copied()
+		.filter(|signer| required.contains(&signer.pubkey()))
+		.collect();
+	transaction.try_sign(signers.as_slice(), recent_blockhash)?;
+	Ok(())
+}
+
+/// Packs `instructions` in order into as few transactions as fit within the 1232 byte packet
+/// limit, accounting for each transaction's signatures and blockhash. The first of `signers`
+/// pays the fees, each transaction is signed by the signers it requires
+fn chunk_instructions(
+	instructions: Vec<Instruction>,
+	signers: &[&Keypair],
+	recent_blockhash: Hash,
+) -> Result<Vec<Transaction>> {
+	let payer = signers
+		.first()
+		.ok_or_else(|| anyhow!("A fee payer is required"))?
+		.pubkey();
+
+	let mut chunks: Vec<Vec<Instruction>> = Vec::new();
+	let mut chunk = Vec::new();
+	for instruction in instructions {
+		chunk.push(instruction);
+		if transaction_size(&chunk, &payer)? <= PACKET_DATA_SIZE {
+			continue;
+		}
+		let overflow = chunk.pop().unwrap();
+		if chunk.is_empty()
+			|| transaction_size(std::slice::from_ref(&overflow), &payer)? > PACKET_DATA_SIZE
+		{
+			return Err(anyhow!("Instruction is too large to fit in a transaction"));
+		}
+		chunks.push(std::mem::replace(&mut chunk, vec![overflow]));
+	}
+	if !chunk.is_empty() {
+		chunks.push(chunk);
+	}
+
+	chunks
+		.iter()
+		.map(|chunk| {
+			let mut transaction = Transaction::new_with_payer(chunk, Some(&payer));
+			sign_with(&mut transaction, signers, recent_blockhash)?;
+			Ok(transaction)
+		})
+		.collect()
+}
+
+/// Sends a transaction from `chunk_instructions`, re-signed with a fresh blockhash as a long bulk
+/// run can outlast the blockhash it was signed with
+fn send_chunk(
+	client: &RpcClient,
+	transaction: &mut Transaction,
+	signers: &[&Keypair],
+	no_wait: bool,
+) -> Result<Signature> {
+	sign_with(transaction, signers, client.get_latest_blockhash()?)?;
+	send_transaction(client, transaction, no_wait)
+		.map_err(|err| anyhow!(describe_client_error(&err)))
+}
+
+/// Serializes a fully signed transaction and encodes it as base64, the form accepted by
+/// `sendTransaction` with base64 encoding
+fn encode_signed_transaction(transaction: &Transaction) -> Result<String> {
+	if !transaction.is_signed() {
+		return Err(anyhow!("Transaction is missing signatures"));
+	}
+	Ok(STANDARD.encode(bincode::serialize(transaction)?))
+}
+
+/// Prompts for confirmation before a destructive command, `--yes` skips the prompt. Refuses to
+/// proceed when stdin is not a terminal so that scripts must opt in explicitly
+fn confirm(summary: &str, skip: bool) -> Result<()> {
+	let stdin = std::io::stdin();
+	let interactive = stdin.is_terminal();
+	confirm_with(summary, skip, interactive, &mut stdin.lock())
+}
+
+fn confirm_with(
+	summary: &str,
+	skip: bool,
+	interactive: bool,
+	input: &mut impl BufRead,
+) -> Result<()> {
+	if skip {
+		return Ok(());
+	}
+	if !interactive {
+		return Err(anyhow!(
+			"Refusing to run a destructive command without a terminal, pass --yes to proceed"
+		));
+	}
+
+	println!("{}", summary);
+	print!("Proceed? [y/N]: ");
+	std::io::stdout().flush()?;
+
+	let mut answer = String::new();
+	input.read_line(&mut answer)?;
+	match answer.trim().to_lowercase().as_str() {
+		"y" | "yes" => Ok(()),
+		_ => Err(anyhow!("Aborted")),
+	}
+}
+
+/// Writes the number of tickets a bulk command failed on and their addresses to `output`,
+/// returning that number so the caller can pick its exit code
+fn report_failures(failed_accounts: &[&Pubkey], output: &mut impl Write) -> Result<usize> {
+	writeln!(output, "Number of failures: {}", failed_accounts.len())?;
+	if !failed_accounts.is_empty() {
+		writeln!(output, "Failed accounts: {:?}", failed_accounts)?;
+	}
+	Ok(failed_accounts.len())
+}
+
+/// Summarises a purchase of `amount` whole tokens, priced with the same `buy_cost` the program
+/// charges
+fn buy_summary(amount: u64, mint: &Pubkey, token_price: u64, decimals: u8) -> Result<String> {
+	let amount_base_units = 10u64
+		.checked_pow(decimals as u32)
+		.and_then(|unit| amount.checked_mul(unit))
+		.ok_or_else(|| anyhow!("{} tokens do not fit in base units", amount))?;
+	let cost = stuk_wl::buy_cost(amount_base_units, token_price, decimals)?;
+	Ok(format!(
+		"Buying {} tokens of mint: {}\nCost: {} SOL",
+		amount,
+		mint,
+		lamports_to_sol(cost)
+	))
+}
+
+/// Rent and tokens needed to run a sale, rent is in lamports and tokens in base units
+#[derive(Debug, PartialEq)]
+struct SaleEstimate {
+	whitelist_rent: u64,
+	ticket_rent: u64,
+	vault_rent: u64,
+	tokens_required: u64,
+}
+
+impl SaleEstimate {
+	fn total_rent(&self) -> Result<u64> {
+		self.whitelist_rent
+			.checked_add(self.ticket_rent)
+			.and_then(|rent| rent.checked_add(self.vault_rent))
+			.ok_or_else(|| anyhow!("Overflow computing the total rent"))
+	}
+}
+
+/// Estimates a sale of `whitelist_size` tickets of `buy_limit` whole tokens each, given the rent
+/// exemption of a single whitelist, ticket and vault account
+fn estimate_sale(
+	whitelist_size: u64,
+	buy_limit: u64,
+	decimals: u8,
+	whitelist_rent: u64,
+	ticket_rent: u64,
+	vault_rent: u64,
+) -> Result<SaleEstimate> {
+	if whitelist_size == 0 {
+		return Err(anyhow!(
+			"Whitelist size is unbounded, the cost of a sale cannot be estimated"
+		));
+	}
+
+	let ticket_rent = ticket_rent
+		.checked_mul(whitelist_size)
+		.ok_or_else(|| anyhow!("Overflow computing the ticket rent"))?;
+	let buy_limit = 10u64
+		.checked_pow(decimals as u32)
+		.and_then(|one_token| buy_limit.checked_mul(one_token))
+		.ok_or_else(|| anyhow!("Overflow converting the buy limit to base units"))?;
+
+	Ok(SaleEstimate {
+		whitelist_rent,
+		ticket_rent,
+		vault_rent,
+		tokens_required: fill_amount(whitelist_size, buy_limit, 0)?,
+	})
+}
+
+/// Computes the number of tokens, in base units, required for the vault to cover every ticket
+fn fill_amount(whitelist_size: u64, buy_limit: u64, vault_balance: u64) -> Result<u64> {
+	if whitelist_size == 0 {
+		return Err(anyhow!(
+			"Whitelist size is unbounded, a fill amount cannot be computed"
+		));
+	}
+
+	let required = whitelist_size
+		.checked_mul(buy_limit)
+		.ok_or_else(|| anyhow!("Overflow computing the fill amount"))?;
+
+	Ok(required.saturating_sub(vault_balance))
+}
+
+/// A ticket as listed by `info tickets`
+#[derive(Debug)]
+struct TicketRow {
+	ticket: Pubkey,
+	owner: Pubkey,
+	allowance: u64,
+	amount_bought: u64,
+	/// Balance of the ticket's token account, `None` when it has not been created
+	balance: Option<u64>,
+}
+
+/// Lays the tickets out as a table in UI amounts, largest purchase first
+fn format_ticket_table(mut rows: Vec<TicketRow>, decimals: u8) -> String {
+	rows.sort_by(|a, b| b.amount_bought.cmp(&a.amount_bought));
+
+	let ui_amount = |amount| spl_token_2022::amount_to_ui_amount(amount, decimals).to_string();
+	let mut table = format!(
+		"{:<44}  {:<44}  {:>14}  {:>14}  {:>14}\n",
+		"Ticket", "Owner", "Allowance", "Bought", "Balance"
+	);
+	for row in rows {
+		table.push_str(&format!(
+			"{:<44}  {:<44}  {:>14}  {:>14}  {:>14}\n",
+			row.ticket.to_string(),
+			row.owner.to_string(),
+			ui_amount(row.allowance),
+			ui_amount(row.amount_bought),
+			row.balance.map_or("-".to_string(), ui_amount),
+		));
+	}
+	table
+}
+
+/// A ticket swept to the treasury by `burn bulk`, with the balances read before it was burned
+#[derive(Debug)]
+struct BurnRow {
+	ticket: Pubkey,
+	/// Tokens held by the ticket's token account, in base units
+	tokens: u64,
+	/// Lamports of the ticket and its token account
+	lamports: u64,
+}
+
+/// What burning `ticket` sweeps to the treasury, its lamports along with the tokens and
+/// lamports of its token account when one exists
+fn burn_row(
+	ticket: Pubkey,
+	ticket_lamports: u64,
+	token_account: Option<&SdkAccount>,
+) -> Result<BurnRow> {
+	let (tokens, token_lamports) = match token_account {
+		Some(account) => (
+			StateWithExtensions::<Account>::unpack(&account.data)?
+				.base
+				.amount,
+			account.lamports,
+		),
+		None => (0, 0),
+	};
+	Ok(BurnRow {
+		ticket,
+		tokens,
+		lamports: ticket_lamports + token_lamports,
+	})
+}
+
+/// Total tokens and lamports swept by the burns in `rows`
+fn burn_totals(rows: &[BurnRow]) -> (u64, u64) {
+	rows.iter().fold((0, 0), |(tokens, lamports), row| {
+		(tokens + row.tokens, lamports + row.lamports)
+	})
+}
+
+/// Lays the burns out as CSV for `burn bulk --report`, tokens in base units
+fn format_burn_report(rows: &[BurnRow]) -> String {
+	let mut report = String::from("ticket,tokens,lamports\n");
+	for row in rows {
+		report.push_str(&format!("{},{},{}\n", row.ticket, row.tokens, row.lamports));
+	}
+	report
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_fill_amount() {
+		let buy_limit = 10 * 10u64.pow(9);
+		assert_eq!(fill_amount(5, buy_limit, 0).unwrap(), 50 * 10u64.pow(9));
+		assert_eq!(
+			fill_amount(5, buy_limit, 20 * 10u64.pow(9)).unwrap(),
+			30 * 10u64.pow(9)
+		);
+		assert_eq!(fill_amount(5, buy_limit, 60 * 10u64.pow(9)).unwrap(), 0);
+		assert!(fill_amount(0, buy_limit, 0).is_err());
+		assert!(fill_amount(u64::MAX, buy_limit, 0).is_err());
+	}
+
+	#[test]
+	fn test_estimate_sale() {
+		let estimate = estimate_sale(5, 10, 9, 2_000_000, 1_500_000, 2_039_280).unwrap();
+		assert_eq!(
+			estimate,
+			SaleEstimate {
+				whitelist_rent: 2_000_000,
+				ticket_rent: 7_500_000,
+				vault_rent: 2_039_280,
+				tokens_required: 50 * 10u64.pow(9),
+			}
+		);
+		assert_eq!(estimate.total_rent().unwrap(), 11_539_280);
+
+		assert!(estimate_sale(0, 10, 9, 2_000_000, 1_500_000, 2_039_280).is_err());
+		assert!(estimate_sale(5, u64::MAX, 9, 2_000_000, 1_500_000, 2_039_280).is_err());
+		assert!(estimate_sale(u64::MAX, 10, 9, 2_000_000, 1_500_000, 2_039_280).is_err());
+	}
+
+	#[test]
+	fn test_split_amount() {
+		assert_eq!(split_amount(300, 100, 1000).unwrap(), vec![100, 100, 100]);
+		assert_eq!(split_amount(250, 100, 1000).unwrap(), vec![100, 100, 50]);
+		// Stops once the allowance is exhausted
+		assert_eq!(split_amount(300, 100, 150).unwrap(), vec![100, 50]);
+		assert!(split_amount(300, 100, 0).unwrap().is_empty());
+		assert!(split_amount(300, 0, 1000).is_err());
+	}
+
+	#[test]
+	fn test_write_sale_record() {
+		let out_dir = std::env::temp_dir().join(format!("fsp-wl-sale-{}", Pubkey::new_unique()));
+		let mut record = json!({
+			"whitelist": Pubkey::new_unique().to_string(),
+			"whitelist_bump": 255,
+			"vault": Pubkey::new_unique().to_string(),
+			"treasury": Pubkey::new_unique().to_string(),
+			"mint": Pubkey::new_unique().to_string(),
+			"config": { "token_price": 1, "buy_limit": 10, "whitelist_size": 5 },
+		});
+		record["txid"] = json!("txid");
+
+		let path = write_sale_record(&out_dir, &record).unwrap();
+		let written: Value =
+			serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+		std::fs::remove_dir_all(&out_dir).unwrap();
+
+		assert_eq!(path.file_name().unwrap(), "sale.json");
+		for key in [
+			"whitelist",
+			"whitelist_bump",
+			"vault",
+			"treasury",
+			"mint",
+			"config",
+			"txid",
+		] {
+			assert!(written.get(key).is_some(), "missing key: {}", key);
+		}
+		assert_eq!(written["config"]["buy_limit"], 10);
+	}
+
+	#[test]
+	fn test_chunk_instructions() {
+		let fee_payer = Keypair::new();
+		let authority = Keypair::new();
+		let signers = bulk_signers(&authority, Some(&fee_payer));
+		let transfers: Vec<Instruction> = (0..100)
+			.map(|_| {
+				solana_sdk::system_instruction::transfer(
+					&authority.pubkey(),
+					&Pubkey::new_unique(),
+					1,
+				)
+			})
+			.collect();
+
+		let transactions =
+			chunk_instructions(transfers.clone(), &signers, Hash::new_unique()).unwrap();
+		assert!(transactions.len() > 1);
+
+		let mut offset = 0;
+		for transaction in &transactions {
+			let message = &transaction.message;
+			assert!(bincode::serialize(transaction).unwrap().len() <= PACKET_DATA_SIZE);
+			assert!(transaction.verify().is_ok());
+			assert_eq!(message.account_keys[0], fee_payer.pubkey());
+			for (index, instruction) in message.instructions.iter().enumerate() {
+				assert_eq!(
+					message.account_keys[instruction.accounts[1] as usize],
+					transfers[offset + index].accounts[1].pubkey
+				);
+			}
+			let len = message.instructions.len();
+			// The next instruction would not have fit, so no fewer transactions can carry the
+			// instructions in order
+			if offset + len < transfers.len() {
+				assert!(
+					transaction_size(&transfers[offset..=offset + len], &fee_payer.pubkey())
+						.unwrap() > PACKET_DATA_SIZE
+				);
+			}
+			offset += len;
+		}
+		assert_eq!(offset, transfers.len());
+
+		let oversized =
+			Instruction::new_with_bytes(Pubkey::new_unique(), &[0; PACKET_DATA_SIZE], vec![]);
+		assert!(chunk_instructions(vec![oversized], &signers, Hash::new_unique()).is_err());
+		assert!(chunk_instructions(Vec::new(), &signers, Hash::new_unique())
+			.unwrap()
+			.is_empty());
+	}
+
+	#[test]
+	fn test_string_to_timestamp() {
+		assert_eq!(
+			string_to_timestamp("2025-01-15 10:00:00".to_string()).unwrap(),
+			1_736_935_200
+		);
+		// Leap day
+		assert_eq!(
+			string_to_timestamp("2024-02-29 23:59:59".to_string()).unwrap(),
+			1_709_251_199
+		);
+		for utc in [
+			"2025-01-15 10:00:00Z",
+			"2025-01-15 10:00:00 UTC",
+			" 2025-01-15 10:00:00 ",
+		] {
+			assert_eq!(
+				string_to_timestamp(utc.to_string()).unwrap(),
+				1_736_935_200,
+				"{:?}",
+				utc
+			);
+		}
+
+		for malformed in [
+			"2025-01-15",
+			"2023-02-29 00:00:00",
+			"15/01/2025 10:00:00",
+			"soon",
+		] {
+			let err = string_to_timestamp(malformed.to_string())
+				.unwrap_err()
+				.to_string();
+			assert!(err.contains(&format!("{:?}", malformed)), "{}", err);
+		}
+		assert!(string_to_timestamp("1970-01-01 00:00:00".to_string()).is_err());
+	}
+
+	#[test]
+	fn test_time_bounds() {
+		// A date before the epoch parses to a negative timestamp
+		assert!(check_timestamp(-86_400).is_err());
+		assert!(check_timestamp(0).is_err());
+		assert_eq!(check_timestamp(1).unwrap(), 1);
+
+		assert!(check_start_time(999, 1_000).is_err());
+		assert_eq!(check_start_time(1_000, 1_000).unwrap(), 1_000);
+
+		assert_eq!(duration_between(1_000, 1_500).unwrap(), 500);
+		assert!(duration_between(1_500, 1_000).is_err());
+		assert!(duration_between(0, 1_000).is_err());
+		// The end is the furthest a window can reach, `start + duration` must stay in an `i64`
+		assert_eq!(duration_between(1, i64::MAX).unwrap(), i64::MAX - 1);
+		assert!(duration_between(i64::MIN, i64::MAX).is_err());
+	}
+
+	#[test]
+	fn test_confirm() {
+		let mut no_input = std::io::Cursor::new("");
+		assert!(confirm_with("summary", true, false, &mut no_input).is_ok());
+		assert!(confirm_with("summary", false, false, &mut no_input).is_err());
+
+		let mut accept = std::io::Cursor::new("y\n");
+		assert!(confirm_with("summary", false, true, &mut accept).is_ok());
+		let mut decline = std::io::Cursor::new("n\n");
+		assert!(confirm_with("summary", false, true, &mut decline).is_err());
+		let mut empty = std::io::Cursor::new("\n");
+		assert!(confirm_with("summary", false, true, &mut empty).is_err());
+	}
+
+	#[test]
+	fn test_report_failures() {
+		let mut output = Vec::new();
+		assert_eq!(report_failures(&[], &mut output).unwrap(), 0);
+		assert_eq!(
+			String::from_utf8(output).unwrap(),
+			"Number of failures: 0\n"
+		);
+
+		let ticket = Pubkey::new_unique();
+		let mut output = Vec::new();
+		assert_eq!(
+			report_failures(&[&ticket, &ticket], &mut output).unwrap(),
+			2
+		);
+		let output = String::from_utf8(output).unwrap();
+		assert!(output.starts_with("Number of failures: 2\n"));
+		assert!(output.contains(&ticket.to_string()));
+	}
+
+	#[test]
+	fn test_burn_report() {
+		use {
+			solana_program::{program_option::COption, program_pack::Pack},
+			spl_token_2022::state::AccountState,
+		};
+
+		let rows: Vec<BurnRow> = [(5, 2_039_280), (0, 1_893_120), (7, 3_000_000)]
+			.into_iter()
+			.map(|(tokens, lamports)| BurnRow {
+				ticket: Pubkey::new_unique(),
+				tokens: tokens * 10u64.pow(9),
+				lamports,
+			})
+			.collect();
+		assert_eq!(burn_totals(&rows), (12 * 10u64.pow(9), 6_932_400));
+		assert_eq!(burn_totals(&[]), (0, 0));
+
+		let report = format_burn_report(&rows);
+		let lines: Vec<&str> = report.lines().collect();
+		assert_eq!(lines.len(), 4);
+		assert_eq!(lines[0], "ticket,tokens,lamports");
+		assert_eq!(lines[1], format!("{},5000000000,2039280", rows[0].ticket));
+		assert_eq!(lines[2], format!("{},0,1893120", rows[1].ticket));
+
+		// A ticket holding tokens is tallied from its token account, one without is not
+		let ticket = Pubkey::new_unique();
+		let mut token_data = vec![0; Account::LEN];
+		Account::pack(
+			Account {
+				mint: Pubkey::new_unique(),
+				owner: ticket,
+				amount: 4 * 10u64.pow(9),
+				delegate: COption::None,
+				state: AccountState::Initialized,
+				is_native: COption::None,
+				delegated_amount: 0,
+				close_authority: COption::None,
+			},
+			&mut token_data,
+		)
+		.unwrap();
+		let token_account = SdkAccount {
+			lamports: 2_039_280,
+			data: token_data,
+			owner: spl_token_2022::id(),
+			executable: false,
+			rent_epoch: 0,
+		};
+		let row = burn_row(ticket, 1_893_120, Some(&token_account)).unwrap();
+		assert_eq!(row.tokens, 4 * 10u64.pow(9));
+		assert_eq!(row.lamports, 3_932_400);
+		let empty = burn_row(ticket, 1_893_120, None).unwrap();
+		assert_eq!((empty.tokens, empty.lamports), (0, 1_893_120));
+		assert_eq!(burn_totals(&[row, empty]), (4 * 10u64.pow(9), 5_825_520));
+	}
+
+	#[test]
+	fn test_buy_summary() {
+		let mint = Pubkey::new_unique();
+		// 3 tokens at 0.25 SOL per whole token, independent of the mint's decimals
+		for decimals in [0, 6, 9] {
+			assert_eq!(
+				buy_summary(3, &mint, 250_000_000, decimals).unwrap(),
+				format!("Buying 3 tokens of mint: {}\nCost: 0.75 SOL", mint)
+			);
+		}
+		assert!(buy_summary(u64::MAX, &mint, u64::MAX, 0).is_err());
+		assert!(buy_summary(u64::MAX / 10, &mint, 1, 9).is_err());
+	}
+
+	#[test]
+	fn test_quote_buy() {
+		use {
+			solana_client::rpc_request::RpcRequest,
+			solana_program::{program_option::COption, program_pack::Pack},
+			spl_token_2022::state::AccountState,
+			std::collections::HashMap,
+		};
+
+		let payer = Pubkey::new_unique();
+		let mint = Pubkey::new_unique();
+		let whitelist = stuk_wl::get_whitelist_address(&mint).0;
+		let ticket_address = stuk_wl::get_user_ticket_address(&payer, &whitelist).0;
+		let decimals = 9;
+		let token_price = 250_000_000;
+		let amount = spl_token_2022::ui_amount_to_amount(3.0, decimals);
+
+		// The simulated state after buying 3 of the ticket's 10 tokens
+		let mut token_data = vec![0; Account::LEN];
+		Account::pack(
+			Account {
+				mint,
+				owner: payer,
+				amount,
+				delegate: COption::None,
+				state: AccountState::Initialized,
+				is_native: COption::None,
+				delegated_amount: 0,
+				close_authority: COption::None,
+			},
+			&mut token_data,
+		)
+		.unwrap();
+		let ticket_data = borsh::to_vec(&stuk_wl::state::Ticket {
+			version: stuk_wl::state::Ticket::VERSION,
+			bump: 255,
+			whitelist,
+			owner: payer,
+			payer,
+			allowance: spl_token_2022::ui_amount_to_amount(10.0, decimals),
+			amount_bought: amount,
+			claimed: 0,
+			note: [0; stuk_wl::state::Ticket::NOTE_LEN],
+			referrer: Pubkey::default(),
+			last_purchase_ts: 0,
+		})
+		.unwrap();
+		let treasury_lamports = 1_000_000_000;
+		let cost = stuk_wl::buy_cost(amount, token_price, decimals).unwrap();
+		let ui_account = |lamports: u64, data: &[u8], owner: Pubkey| {
+			json!({
+				"lamports": lamports,
+				"data": [solana_program::bs58::encode(data).into_string(), "base58"],
+				"owner": owner.to_string(),
+				"executable": false,
+				"rentEpoch": 0,
+				"space": data.len(),
+			})
+		};
+
+		let mut mocks = HashMap::new();
+		mocks.insert(
+			RpcRequest::SimulateTransaction,
+			json!({
+				"context": { "slot": 1 },
+				"value": {
+					"err": null,
+					"logs": [],
+					"accounts": [
+						ui_account(2_039_280, &token_data, spl_token_2022::id()),
+						ui_account(treasury_lamports + cost, &[], Pubkey::default()),
+						ui_account(1_600_000, &ticket_data, stuk_wl::id()),
+					],
+				},
+			}),
+		);
+		// Sending would fail to parse this, so a quote that sends anything returns an error
+		mocks.insert(RpcRequest::SendTransaction, Value::Null);
+		let client = RpcClient::new_mock_with_mocks("succeeds".to_string(), mocks);
+
+		let instruction = instructions::buy_tokens(
+			&whitelist,
+			&Pubkey::new_unique(),
+			&mint,
+			&payer,
+			&ticket_address,
+			&Pubkey::new_unique(),
+			&Pubkey::new_unique(),
+			3,
+			Some(token_price),
+			false,
+			&spl_token_2022::id(),
+		)
+		.unwrap();
+		let accounts = BuyQuoteAccounts {
+			token_account: Pubkey::new_unique(),
+			token_balance: 0,
+			treasury: Pubkey::new_unique(),
+			treasury_lamports,
+			ticket: ticket_address,
+		};
+
+		assert_eq!(
+			quote_buy(&client, &instruction, &payer, &accounts).unwrap(),
+			BuyQuote {
+				tokens_received: amount,
+				lamports_charged: 750_000_000,
+				remaining_allowance: spl_token_2022::ui_amount_to_amount(7.0, decimals),
+			}
+		);
+	}
+
+	#[test]
+	fn test_ticket_cache() {
+		let program_id = Pubkey::new_unique();
+		let program = WhitelistProgram::new(Some(program_id));
+		let whitelist = program.whitelist_address(&Pubkey::new_unique());
+		let users: Vec<Pubkey> = (0..5).map(|_| Pubkey::new_unique()).collect();
+
+		let contents = format!(
+			"# allowlist\n{}\n\n  {}  \n{}\n{}\n{}\n",
+			users[0], users[1], users[2], users[3], users[4]
+		);
+		assert_eq!(read_users(&contents).unwrap(), users);
+		assert!(read_users("not a key")
+			.unwrap_err()
+			.to_string()
+			.contains("line 1"));
+
+		let record = ticket_cache_record(&program_id, &whitelist, &users);
+		let cache = TicketCache::from_record(&record, &program_id).unwrap();
+		let cached = program.clone().with_ticket_cache(cache);
+		for user in users.iter() {
+			let (ticket, bump) =
+				get_user_ticket_address_with_program_id(user, &whitelist, &program_id);
+			assert_eq!(cached.ticket_address(user, &whitelist), ticket);
+			assert_eq!(record["tickets"][user.to_string()]["bump"], json!(bump));
+		}
+
+		// Users missing from the cache and other whitelists are still derived
+		let stranger = Pubkey::new_unique();
+		assert_eq!(
+			cached.ticket_address(&stranger, &whitelist),
+			program.ticket_address(&stranger, &whitelist)
+		);
+		let other_whitelist = program.whitelist_address(&Pubkey::new_unique());
+		assert_eq!(
+			cached.ticket_address(&users[0], &other_whitelist),
+			program.ticket_address(&users[0], &other_whitelist)
+		);
+
+		// A cache derived for another program is rejected
+		assert!(TicketCache::from_record(&record, &stuk_wl::id())
+			.unwrap_err()
+			.to_string()
+			.contains("precompute"));
+	}
+
+	#[test]
+	fn test_program_id_override() {
+		let mint = Pubkey::new_unique();
+		let user = Pubkey::new_unique();
+		let ix = |program: &WhitelistProgram| {
+			let whitelist = program.whitelist_address(&mint);
+			let ticket = program.ticket_address(&user, &whitelist);
+			let allocation = program.allocation_address(&user, &whitelist);
+			program.target(instructions::register(&whitelist, &user, &ticket, &allocation).unwrap())
+		};
+
+		// Without an override everything matches the declared program
+		let declared = WhitelistProgram::new(None);
+		let whitelist = stuk_wl::get_whitelist_address(&mint).0;
+		assert_eq!(declared.whitelist_address(&mint), whitelist);
+		assert_eq!(
+			declared.ticket_address(&user, &whitelist),
+			stuk_wl::get_user_ticket_address(&user, &whitelist).0
+		);
+		assert_eq!(
+			declared.depositor_address(&user, &whitelist),
+			stuk_wl::get_depositor_address(&user, &whitelist).0
+		);
+		assert_eq!(ix(&declared).program_id, stuk_wl::id());
+
+		let program_id = Pubkey::new_unique();
+		let local = WhitelistProgram::new(Some(program_id));
+		let whitelist =
+			Pubkey::find_program_address(&[b"___whitelist", mint.as_ref()], &program_id).0;
+		let ticket = Pubkey::find_program_address(
+			&[b"___whitelist", user.as_ref(), whitelist.as_ref()],
+			&program_id,
+		)
+		.0;
+		assert_eq!(local.whitelist_address(&mint), whitelist);
+		assert_eq!(local.ticket_address(&user, &whitelist), ticket);
+		assert_ne!(whitelist, declared.whitelist_address(&mint));
+
+		let ix = ix(&local);
+		assert_eq!(ix.program_id, program_id);
+		let order = stuk_wl::account_order::register::WHITELIST;
+		assert_eq!(ix.accounts[order].pubkey, whitelist);
+		let order = stuk_wl::account_order::register::USER_TICKET;
+		assert_eq!(ix.accounts[order].pubkey, ticket);
+	}
+
+	#[test]
+	fn test_whitelist_full() {
+		assert!(!whitelist_full(5, 0));
+		assert!(!whitelist_full(5, 4));
+		// The last free place is taken
+		assert!(whitelist_full(5, 5));
+		assert!(whitelist_full(5, 6));
+		assert!(!whitelist_full(0, 1_000));
+	}
+
+	#[test]
+	fn test_whitelist_filters() {
+		let matches = |data: &[u8]| {
+			whitelist_filters().iter().all(|filter| match filter {
+				RpcFilterType::DataSize(size) => data.len() as u64 == *size,
+				RpcFilterType::Memcmp(memcmp) => memcmp.bytes_match(data),
+				_ => false,
+			})
+		};
+		let whitelist = |mint: Pubkey| {
+			stuk_wl::state::Whitelist::from(stuk_wl::state::WhitelistV0 {
+				bump: 255,
+				authority: Pubkey::new_unique(),
+				vault: Pubkey::new_unique(),
+				treasury: Pubkey::new_unique(),
+				mint,
+				token_price: 1,
+				buy_limit: 10,
+				deposited: 0,
+				whitelist_size: 5,
+				allow_registration: true,
+				registration_timestamp: 0,
+				registration_duration: 0,
+				sale_timestamp: 0,
+				sale_duration: 0,
+			})
+		};
+
+		let mints = [Pubkey::new_unique(), Pubkey::new_unique()];
+		let mut accounts: Vec<Vec<u8>> = mints
+			.iter()
+			.map(|mint| borsh::to_vec(&whitelist(*mint)).unwrap())
+			.collect();
+		accounts.push(
+			borsh::to_vec(&stuk_wl::state::Ticket {
+				version: stuk_wl::state::Ticket::VERSION,
+				bump: 255,
+				whitelist: Pubkey::new_unique(),
+				owner: Pubkey::new_unique(),
+				payer: Pubkey::new_unique(),
+				allowance: 10,
+				amount_bought: 0,
+				claimed: 0,
+				note: [0; stuk_wl::state::Ticket::NOTE_LEN],
+				referrer: Pubkey::default(),
+				last_purchase_ts: 0,
+			})
+			.unwrap(),
+		);
+
+		// Both whitelists are discovered and resolve to their mints, the ticket is not
+		let discovered: Vec<Pubkey> = accounts
+			.iter()
+			.filter(|data| matches(data))
+			.map(|data| stuk_wl::state::Whitelist::unpack(data).unwrap().mint)
+			.collect();
+		assert_eq!(discovered, mints);
+	}
+
+	#[test]
+	fn test_vault_address() {
+		let mint = Pubkey::new_unique();
+		let program_id = Pubkey::new_unique();
+		let program = WhitelistProgram::new(Some(program_id));
+		let whitelist = program.whitelist_address(&mint);
+
+		for token_program in [spl_token::id(), spl_token_2022::id()] {
+			// The vault `init` creates and prints is owned by the whitelist, `close` has to
+			// drain the same account
+			let vault = program.vault_address(&mint, &token_program);
+			assert_eq!(
+				vault,
+				Pubkey::find_program_address(
+					&[whitelist.as_ref(), token_program.as_ref(), mint.as_ref()],
+					&spl_associated_token_account::id(),
+				)
+				.0
+			);
+			assert_ne!(
+				vault,
+				spl_associated_token_account::get_associated_token_address_with_program_id(
+					&mint,
+					&whitelist,
+					&token_program,
+				)
+			);
+		}
+	}
+
+	#[test]
+	fn test_whoami_report() {
+		let wallet = Pubkey::new_unique();
+		let url = "http://localhost:8899";
+		let report = whoami_report(
+			&wallet,
+			None,
+			url,
+			CommitmentConfig::confirmed(),
+			&WhitelistProgram::new(None),
+		);
+		assert_eq!(
+			report,
+			vec![
+				format!("Payer: {}", wallet),
+				format!("Fee payer: {}", wallet),
+				format!("RPC URL: {}", url),
+				"Commitment: Confirmed".to_string(),
+				format!("Program id: {} (declared)", stuk_wl::id()),
+			]
+		);
+
+		// The override is reported rather than the declared id
+		let program_id = Pubkey::new_unique();
+		let fee_payer = Pubkey::new_unique();
+		let report = whoami_report(
+			&wallet,
+			Some(&fee_payer),
+			url,
+			CommitmentConfig::confirmed(),
+			&WhitelistProgram::new(Some(program_id)),
+		);
+		assert_eq!(report[1], format!("Fee payer: {}", fee_payer));
+		assert_eq!(
+			report[4],
+			format!("Program id: {} (--program-id override)", program_id)
+		);
+	}
+
+	#[test]
+	fn test_send_transaction_no_wait() {
+		use {
+			solana_client::rpc_request::RpcRequest,
+			solana_sdk::{hash::Hash, signature::Keypair, system_instruction},
+			std::collections::HashMap,
+		};
+
+		let mut mocks = HashMap::new();
+		// Polling for confirmation would fail to parse this, so only a send that returns without
+		// waiting succeeds
+		mocks.insert(RpcRequest::GetSignatureStatuses, Value::Null);
+		let client = RpcClient::new_mock_with_mocks("succeeds".to_string(), mocks);
+
+		let payer = Keypair::new();
+		let transaction = Transaction::new_signed_with_payer(
+			&[system_instruction::transfer(
+				&payer.pubkey(),
+				&Pubkey::new_unique(),
+				1,
+			)],
+			Some(&payer.pubkey()),
+			&[&payer],
+			Hash::default(),
+		);
+
+		assert_eq!(
+			send_transaction(&client, &transaction, true).unwrap(),
+			transaction.signatures[0]
+		);
+	}
+
+	#[test]
+	fn test_signed_transaction_with_fee_payer() {
+		let authority = Keypair::new();
+		let fee_payer = Keypair::new();
+		let instruction = || {
+			instructions::amend_whitelist_size(&Pubkey::new_unique(), &authority.pubkey(), 10)
+				.unwrap()
+		};
+
+		let transaction = signed_transaction(
+			instruction(),
+			&authority,
+			Some(&fee_payer),
+			Hash::new_unique(),
+		);
+		assert_eq!(transaction.message.account_keys[0], fee_payer.pubkey());
+		assert_eq!(transaction.message.account_keys[1], authority.pubkey());
+		assert_eq!(transaction.message.header.num_required_signatures, 2);
+		assert!(transaction.is_signed());
+		assert!(transaction.verify().is_ok());
+
+		// Without a fee payer the authority pays and is the only signer
+		let transaction = signed_transaction(instruction(), &authority, None, Hash::new_unique());
+		assert_eq!(transaction.message.account_keys[0], authority.pubkey());
+		assert_eq!(transaction.signatures.len(), 1);
+		assert!(transaction.verify().is_ok());
+	}
+
+	#[test]
+	fn test_encode_signed_transaction() {
+		use solana_sdk::{hash::Hash, signature::Keypair, system_instruction};
+
+		let payer = Keypair::new();
+		let authority = Keypair::new();
+		let instruction =
+			instructions::amend_whitelist_size(&Pubkey::new_unique(), &authority.pubkey(), 10)
+				.unwrap();
+		let transfer = system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1);
+
+		let mut transaction =
+			Transaction::new_with_payer(&[instruction, transfer], Some(&payer.pubkey()));
+		transaction.partial_sign(&[&payer], Hash::new_unique());
+		// A transaction the relayer could not send as is must not be written out
+		assert!(encode_signed_transaction(&transaction).is_err());
+
+		transaction.partial_sign(&[&authority], transaction.message.recent_blockhash);
+		let encoded = encode_signed_transaction(&transaction).unwrap();
+		let decoded: Transaction =
+			bincode::deserialize(&STANDARD.decode(encoded).unwrap()).unwrap();
+		assert_eq!(decoded, transaction);
+		assert_eq!(decoded.signatures.len(), 2);
+		assert_eq!(decoded.message.header.num_required_signatures, 2);
+		assert!(decoded.verify().is_ok());
+	}
+
+	#[test]
+	fn test_diagnose_accounts() {
+		let whitelist = Pubkey::new_unique();
+		let ticket = stuk_wl::state::Ticket {
+			version: stuk_wl::state::Ticket::VERSION,
+			bump: 255,
+			whitelist,
+			owner: Pubkey::new_unique(),
+			payer: Pubkey::new_unique(),
+			allowance: 10,
+			amount_bought: 0,
+			claimed: 0,
+			note: [0; stuk_wl::state::Ticket::NOTE_LEN],
+			referrer: Pubkey::default(),
+			last_purchase_ts: 0,
+		};
+		let data = borsh::to_vec(&ticket).unwrap();
+		assert!(is_ticket_of(&data, &whitelist));
+		assert!(diagnose_ticket(&data).is_ok());
+
+		let mut short = data.clone();
+		short.truncate(data.len() - 8);
+		assert!(diagnose_ticket(&short)
+			.unwrap_err()
+			.contains("unexpected length"));
+
+		let unversioned = borsh::to_vec(&stuk_wl::state::TicketV0 {
+			bump: 255,
+			whitelist,
+			owner: ticket.owner,
+			payer: ticket.payer,
+			allowance: 10,
+			amount_bought: 0,
+		})
+		.unwrap();
+		assert!(diagnose_ticket(&unversioned)
+			.unwrap_err()
+			.contains(&format!("migrate-ticket <MINT> {}", ticket.owner)));
+
+		let legacy = stuk_wl::state::WhitelistV0 {
+			bump: 255,
+			authority: Pubkey::new_unique(),
+			vault: Pubkey::new_unique(),
+			treasury: Pubkey::new_unique(),
+			mint: Pubkey::new_unique(),
+			token_price: 1,
+			buy_limit: 10,
+			deposited: 0,
+			whitelist_size: 5,
+			allow_registration: true,
+			registration_timestamp: 0,
+			registration_duration: 0,
+			sale_timestamp: 0,
+			sale_duration: 0,
+		};
+		let legacy_data = borsh::to_vec(&legacy).unwrap();
+		assert!(diagnose_whitelist(&legacy_data)
+			.unwrap_err()
+			.contains("fsp-wl migrate"));
+
+		let current_data = borsh::to_vec(&stuk_wl::state::Whitelist::from(legacy)).unwrap();
+		assert!(diagnose_whitelist(&current_data).is_ok());
+		assert!(diagnose_whitelist(&current_data[1..])
+			.unwrap_err()
+			.contains("unexpected length"));
+	}
+
+	#[test]
+	fn test_pause_flags() {
+		let mint = Pubkey::new_unique().to_string();
+
+		let args = Cli::try_parse_from(["fsp-wl", "pause", &mint, "--sale", "--resume"]).unwrap();
+		match args.cmd {
+			Commands::Pause {
+				registration,
+				sale,
+				resume,
+				..
+			} => assert!(!registration && sale && resume),
+			_ => panic!("expected the pause command"),
+		}
+
+		// Exactly one of registration or buying is paused per transaction
+		assert!(Cli::try_parse_from(["fsp-wl", "pause", &mint]).is_err());
+		assert!(
+			Cli::try_parse_from(["fsp-wl", "pause", &mint, "--registration", "--sale"]).is_err()
+		);
+	}
+
+	#[test]
+	fn test_format_ticket_table() {
+		let rows: Vec<TicketRow> = [(10, 2, Some(2)), (10, 0, None), (15, 9, Some(4))]
+			.into_iter()
+			.map(|(allowance, amount_bought, balance)| TicketRow {
+				ticket: Pubkey::new_unique(),
+				owner: Pubkey::new_unique(),
+				allowance: allowance * 10u64.pow(6),
+				amount_bought: amount_bought * 10u64.pow(6),
+				balance: balance.map(|balance| balance * 10u64.pow(6)),
+			})
+			.collect();
+		let owners: Vec<String> = rows.iter().map(|row| row.owner.to_string()).collect();
+
+		let table = format_ticket_table(rows, 6);
+		let lines: Vec<&str> = table.lines().collect();
+		assert_eq!(lines.len(), 4);
+		assert_eq!(
+			lines[0].split_whitespace().collect::<Vec<_>>(),
+			["Ticket", "Owner", "Allowance", "Bought", "Balance"]
+		);
+
+		// Sorted by amount bought, largest first, with a missing token account shown as `-`
+		let columns: Vec<Vec<&str>> = lines[1..]
+			.iter()
+			.map(|line| line.split_whitespace().collect())
+			.collect();
+		assert_eq!(columns[0][1..], [owners[2].as_str(), "15", "9", "4"]);
+		assert_eq!(columns[1][1..], [owners[0].as_str(), "10", "2", "2"]);
+		assert_eq!(columns[2][1..], [owners[1].as_str(), "10", "0", "-"]);
+		assert!(lines.iter().all(|line| line.len() == lines[0].len()));
+	}
 }
@@ -66,6 +66,13 @@ export class InstructionBuilder {
                 isWritable: false,
             },
         ];
+        if (instruction.treasuryIsPda) {
+            accounts.push({
+                pubkey: instruction.treasury,
+                isSigner: false,
+                isWritable: true,
+            });
+        }
 
         const data = instruction.serialize();
         return InstructionBuilder.createInstruction(accounts, data);
@@ -84,7 +91,7 @@ export class InstructionBuilder {
             {
                 pubkey: whitelist,
                 isSigner: false,
-                isWritable: false,
+                isWritable: true,
             },
             {
                 pubkey: authority,
@@ -123,21 +130,43 @@ export class InstructionBuilder {
         authority: PublicKey,
         mint: PublicKey,
         user: PublicKey,
+        tokenProgram: PublicKey,
         instruction: RemoveUser,
     ): TransactionInstruction {
         const whitelist = getWhitelistAddress(mint)[0];
         const ticket = getTicketAddress(user, whitelist)[0];
 
+        const vault = getAssociatedTokenAddressSync(
+            mint,
+            whitelist,
+            true,
+            tokenProgram,
+            ASSOCIATED_TOKEN_PROGRAM_ID
+        );
+
+        const ticketTokenAccount = getAssociatedTokenAddressSync(
+            mint,
+            ticket,
+            true,
+            tokenProgram,
+            ASSOCIATED_TOKEN_PROGRAM_ID
+        );
+
         const accounts = [
             {
                 pubkey: whitelist,
                 isSigner: false,
-                isWritable: false,
+                isWritable: true,
             },
             {
                 pubkey: authority,
                 isSigner: true,
-                isWritable: false,
+                isWritable: true,
+            },
+            {
+                pubkey: vault,
+                isSigner: false,
+                isWritable: true,
             },
             {
                 pubkey: mint,
@@ -154,6 +183,16 @@ export class InstructionBuilder {
                 isSigner: false,
                 isWritable: true,
             },
+            {
+                pubkey: ticketTokenAccount,
+                isSigner: false,
+                isWritable: true,
+            },
+            {
+                pubkey: tokenProgram,
+                isSigner: false,
+                isWritable: false,
+            },
             {
                 pubkey: SystemProgram.programId,
                 isSigner: false,
@@ -522,10 +561,19 @@ export class InstructionBuilder {
     static startTokenSale(
         authority: PublicKey,
         mint: PublicKey,
+        tokenProgram: PublicKey,
         instruction: StartTokenSale
     ): TransactionInstruction {
         const whitelist = getWhitelistAddress(mint)[0];
 
+        const vault = getAssociatedTokenAddressSync(
+            mint,
+            whitelist,
+            true,
+            tokenProgram,
+            ASSOCIATED_TOKEN_PROGRAM_ID
+        );
+
         const accounts = [
             {
                 pubkey: whitelist,
@@ -537,6 +585,11 @@ export class InstructionBuilder {
                 isSigner: true,
                 isWritable: true,
             },
+            {
+                pubkey: vault,
+                isSigner: false,
+                isWritable: false,
+            },
         ];
 
         const data = instruction.serialize();
@@ -705,7 +758,7 @@ export class InstructionBuilder {
             {
                 pubkey: whitelist,
                 isSigner: false,
-                isWritable: false,
+                isWritable: true,
             },
             {
                 pubkey: authority,
@@ -862,6 +915,13 @@ export class InitWhitelist {
     registrationDuration: Numberi64;
     saleTimestamp: Numberi64;
     saleDuration: Numberi64;
+    maxTickets: Numberu64;
+    refundWindowSecs: Numberu64;
+    authorityPaysRent: boolean;
+    // Permits a token price of 0, a free sale, which is rejected otherwise
+    allowFree: boolean;
+    // Pays proceeds into the program owned treasury PDA, `treasury` must then be that PDA
+    treasuryIsPda: boolean;
 
     constructor(fields: {
         treasury: PublicKey,
@@ -873,6 +933,11 @@ export class InitWhitelist {
         registrationEndTime: Date,
         saleStartTime: Date,
         saleEndTime: Date,
+        maxTickets: Numberu64,
+        refundWindowSecs: Numberu64,
+        authorityPaysRent: boolean,
+        allowFree?: boolean,
+        treasuryIsPda?: boolean,
     }) {
         WhitelistInstruction.InitialiseWhitelist;
         this.treasury = fields.treasury;
@@ -890,6 +955,11 @@ export class InitWhitelist {
             toUnixTimestamp(fields.saleStartTime),
             toUnixTimestamp(fields.saleEndTime)
         );
+        this.maxTickets = fields.maxTickets;
+        this.refundWindowSecs = fields.refundWindowSecs;
+        this.authorityPaysRent = fields.authorityPaysRent;
+        this.allowFree = fields.allowFree ?? false;
+        this.treasuryIsPda = fields.treasuryIsPda ?? false;
     }
 
     static instructionType = WhitelistInstruction.InitialiseWhitelist;
@@ -906,6 +976,11 @@ export class InitWhitelist {
             registrationDuration: "i64",
             saleTimestamp: "i64",
             saleDuration: "i64",
+            maxTickets: "u64",
+            refundWindowSecs: "u64",
+            authorityPaysRent: "bool",
+            allowFree: "bool",
+            treasuryIsPda: "bool",
             tokenProgram: { array: { type: "u8", len: 32 } },
         }
     };
@@ -939,14 +1014,22 @@ export class RemoveUser {
 
 export class BuyTokens {
     amount: Numberu64;
+    // Highest token price the buyer accepts, the buy fails if the price has been raised above it
+    maxPrice?: Numberu64;
+    // Buy what the vault holds when it cannot cover the full amount instead of failing
+    allowPartial: boolean;
 
-    constructor(amount: Numberu64) {
+    constructor(amount: Numberu64, maxPrice?: Numberu64, allowPartial: boolean = false) {
         this.amount = amount;
+        this.maxPrice = maxPrice;
+        this.allowPartial = allowPartial;
     }
 
     static schema: Schema = {
         struct: {
             amount: "u64",
+            maxPrice: { option: "u64" },
+            allowPartial: "bool",
         }
     }
 
@@ -986,17 +1069,20 @@ export class AmendTimes {
     registrationDuration?: Numberi64;
     saleTimestamp?: Numberi64;
     saleDuration?: Numberi64;
+    allowOverlap: boolean;
 
     constructor(fields: {
         registrationTimestamp?: Numberi64,
         registrationDuration?: Numberi64,
         saleTimestamp?: Numberi64,
         saleDuration?: Numberi64,
+        allowOverlap: boolean,
     }) {
         this.registrationTimestamp = fields.registrationTimestamp;
         this.registrationDuration = fields.registrationDuration;
         this.saleTimestamp = fields.saleTimestamp;
         this.saleDuration = fields.saleDuration;
+        this.allowOverlap = fields.allowOverlap;
     }
 
     static schema: Schema = {
@@ -1005,6 +1091,7 @@ export class AmendTimes {
             registrationDuration: "i64",
             saleTimestamp: "i64",
             saleDuration: "i64",
+            allowOverlap: "bool",
         }
     }
 
@@ -1096,12 +1183,26 @@ export class StartRegistration {
 }
 
 export class StartTokenSale {
+    requireFullyFunded: boolean;
+
+    constructor(requireFullyFunded: boolean) {
+        this.requireFullyFunded = requireFullyFunded;
+    }
+
+    static schema: Schema = {
+        struct: {
+            requireFullyFunded: "bool",
+        }
+    }
+
     static instructionType = WhitelistInstruction.StartTokenSale;
 
     serialize(): Buffer {
         const instructionTypeBuffer = Buffer.alloc(1);
         instructionTypeBuffer.writeUint8(StartTokenSale.instructionType);
-        return instructionTypeBuffer;
+        return Buffer.concat(
+            [instructionTypeBuffer, serialize(StartTokenSale.schema, this)]
+        );
     }
 }
 
@@ -4,6 +4,7 @@ import { Numberu64, Numberi64 } from "./numbers";
 
 
 class WhitelistSchema {
+    version: number;
     bump: number;
     authority: PublicKey;
     vault: PublicKey;
@@ -18,8 +19,18 @@ class WhitelistSchema {
     registrationDuration: Numberi64;
     saleTimestamp: Numberi64;
     saleDuration: Numberi64;
+    registered: Numberu64;
+    registrationPaused: boolean;
+    salePaused: boolean;
+    referralBonus: Numberu64;
+    maxTickets: Numberu64;
+    cooldownSecs: Numberu64;
+    refundWindowSecs: Numberu64;
+    authorityPaysRent: boolean;
+    registerAuthority: PublicKey;
 
     constructor(fields: {
+        version: number,
         bump: number,
         authority: PublicKey,
         vault: PublicKey,
@@ -34,7 +45,17 @@ class WhitelistSchema {
         registrationDuration: Numberi64,
         saleTimestamp: Numberi64,
         saleDuration: Numberi64,
+        registered: Numberu64,
+        registrationPaused: boolean,
+        salePaused: boolean,
+        referralBonus: Numberu64,
+        maxTickets: Numberu64,
+        cooldownSecs: Numberu64,
+        refundWindowSecs: Numberu64,
+        authorityPaysRent: boolean,
+        registerAuthority: PublicKey,
     }) {
+        this.version = fields.version;
         this.bump = fields.bump;
         this.authority = fields.authority;
         this.vault = fields.vault;
@@ -47,10 +68,20 @@ class WhitelistSchema {
         this.registrationDuration = fields.registrationDuration;
         this.saleTimestamp = fields.saleTimestamp;
         this.saleDuration = fields.saleDuration;
+        this.registered = fields.registered;
+        this.registrationPaused = fields.registrationPaused;
+        this.salePaused = fields.salePaused;
+        this.referralBonus = fields.referralBonus;
+        this.maxTickets = fields.maxTickets;
+        this.cooldownSecs = fields.cooldownSecs;
+        this.refundWindowSecs = fields.refundWindowSecs;
+        this.authorityPaysRent = fields.authorityPaysRent;
+        this.registerAuthority = fields.registerAuthority;
     }
 
     static schema: Schema = {
         struct: {
+            version: "u8",
             bump: "u8",
             authority: { array: { type: "u8", len: 32 } },
             vault: { array: { type: "u8", len: 32 } },
@@ -65,6 +96,15 @@ class WhitelistSchema {
             registrationDuration: "i64",
             saleTimestamp: "i64",
             saleDuration: "i64",
+            registered: "u64",
+            registrationPaused: "bool",
+            salePaused: "bool",
+            referralBonus: "u64",
+            maxTickets: "u64",
+            cooldownSecs: "u64",
+            refundWindowSecs: "u64",
+            authorityPaysRent: "bool",
+            registerAuthority: { array: { type: "u8", len: 32 } },
         }
     };
 
@@ -74,37 +114,57 @@ class WhitelistSchema {
 }
 
 class TicketSchema {
+    version: number;
     bump: number;
     whitelist: PublicKey;
     owner: PublicKey;
     payer: PublicKey;
     allowance: PublicKey;
     amountBought: PublicKey;
+    claimed: Numberu64;
+    note: Uint8Array;
+    referrer: PublicKey;
+    lastPurchaseTs: Numberi64;
 
     constructor(fields: {
+        version: number,
         bump: number,
         whitelist: PublicKey,
         owner: PublicKey,
         payer: PublicKey,
         allowance: PublicKey,
         amountBought: PublicKey,
+        claimed: Numberu64,
+        note: Uint8Array,
+        referrer: PublicKey,
+        lastPurchaseTs: Numberi64,
     }) {
+        this.version = fields.version;
         this.bump = fields.bump;
         this.whitelist = fields.whitelist;
         this.owner = fields.owner;
         this.payer = fields.payer;
         this.allowance = fields.allowance;
         this.amountBought = fields.amountBought;
+        this.claimed = fields.claimed;
+        this.note = fields.note;
+        this.referrer = fields.referrer;
+        this.lastPurchaseTs = fields.lastPurchaseTs;
     }
 
     static schema: Schema = {
         struct: {
+            version: "u8",
             bump: "u8",
             whitelist: { array: { type: "u8", len: 32 } },
             owner: { array: { type: "u8", len: 32 } },
             payer: { array: { type: "u8", len: 32 } },
             allowance: "u64",
             amountBought: "u64",
+            claimed: "u64",
+            note: { array: { type: "u8", len: 32 } },
+            referrer: { array: { type: "u8", len: 32 } },
+            lastPurchaseTs: "i64",
         }
     };
 
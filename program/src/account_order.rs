@@ -0,0 +1,1371 @@
+//! Positions of the accounts each instruction expects. The builders in `instructions` and the
+//! handlers in `processor` both index accounts through these constants so the two cannot drift
+//! apart, see the `Accounts expected` list on each `WhitelistInstruction` variant for roles
+
+use solana_program::{account_info::AccountInfo, program_error::ProgramError, pubkey::Pubkey};
+
+/// The associated token account program, shared by the Token and Token-2022 programs
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: Pubkey = spl_associated_token_account::ID;
+
+/// Returns the account at `index`, failing in the same way as `next_account_info` when too few
+/// accounts are supplied
+pub fn account_at<'a, 'b>(
+	accounts: &'a [AccountInfo<'b>],
+	index: usize,
+) -> Result<&'a AccountInfo<'b>, ProgramError> {
+	accounts
+		.get(index)
+		.ok_or(ProgramError::NotEnoughAccountKeys)
+}
+
+/// `InitialiseWhitelist`
+pub mod initialise_whitelist {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const VAULT: usize = 2;
+	pub const MINT: usize = 3;
+	pub const TOKEN_PROGRAM: usize = 4;
+	pub const SYSTEM_PROGRAM: usize = 5;
+	pub const ASSOCIATED_TOKEN_PROGRAM: usize = 6;
+	pub const ACCOUNTS: usize = 7;
+	/// Appended when `treasury_is_pda` is set
+	pub const TREASURY: usize = 7;
+}
+
+/// `AddUser`
+pub mod add_user {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const MINT: usize = 2;
+	pub const USER: usize = 3;
+	pub const USER_TICKET: usize = 4;
+	pub const SYSTEM_PROGRAM: usize = 5;
+	pub const ACCOUNTS: usize = 6;
+}
+
+/// `RemoveUser`
+pub mod remove_user {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const VAULT: usize = 2;
+	pub const MINT: usize = 3;
+	pub const USER: usize = 4;
+	pub const USER_TICKET: usize = 5;
+	pub const TICKET_TOKEN: usize = 6;
+	pub const TOKEN_PROGRAM: usize = 7;
+	pub const SYSTEM_PROGRAM: usize = 8;
+	pub const ACCOUNTS: usize = 9;
+}
+
+/// `AmendWhitelistSize`
+pub mod amend_whitelist_size {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const ACCOUNTS: usize = 2;
+}
+
+/// `AmendTimes`
+pub mod amend_times {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const ACCOUNTS: usize = 2;
+}
+
+/// `AllowRegister`
+pub mod allow_register {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const ACCOUNTS: usize = 2;
+}
+
+/// `Register`
+pub mod register {
+	pub const WHITELIST: usize = 0;
+	pub const USER: usize = 1;
+	pub const USER_TICKET: usize = 2;
+	pub const SYSTEM_PROGRAM: usize = 3;
+	pub const ALLOCATION: usize = 4;
+	pub const ACCOUNTS: usize = 5;
+	/// Appended when the whitelist's `authority_pays_rent` is set
+	pub const AUTHORITY: usize = 5;
+}
+
+/// `Unregister`
+pub mod unregister {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const VAULT: usize = 2;
+	pub const MINT: usize = 3;
+	pub const USER: usize = 4;
+	pub const USER_TICKET: usize = 5;
+	pub const TICKET_TOKEN: usize = 6;
+	pub const TOKEN_PROGRAM: usize = 7;
+	pub const SYSTEM_PROGRAM: usize = 8;
+	pub const ACCOUNTS: usize = 9;
+}
+
+/// `Buy`
+pub mod buy {
+	pub const WHITELIST: usize = 0;
+	pub const VAULT: usize = 1;
+	pub const MINT: usize = 2;
+	pub const USER: usize = 3;
+	pub const USER_TICKET: usize = 4;
+	pub const TICKET_TOKEN: usize = 5;
+	pub const USER_TOKEN: usize = 6;
+	pub const TOKEN_PROGRAM: usize = 7;
+	pub const SYSTEM_PROGRAM: usize = 8;
+	pub const ASSOCIATED_TOKEN_PROGRAM: usize = 9;
+	pub const ACCOUNTS: usize = 10;
+}
+
+/// `DepositTokens`
+pub mod deposit_tokens {
+	pub const WHITELIST: usize = 0;
+	pub const VAULT: usize = 1;
+	pub const DEPOSITOR: usize = 2;
+	pub const DEPOSITOR_TOKEN: usize = 3;
+	pub const MINT: usize = 4;
+	pub const TOKEN_PROGRAM: usize = 5;
+	pub const ACCOUNTS: usize = 6;
+}
+
+/// `StartRegistration`
+pub mod start_registration {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const ACCOUNTS: usize = 2;
+}
+
+/// `StartTokenSale`
+pub mod start_token_sale {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const VAULT: usize = 2;
+	pub const ACCOUNTS: usize = 3;
+}
+
+/// `TransferTokens`
+pub mod transfer_tokens {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const VAULT: usize = 2;
+	pub const MINT: usize = 3;
+	pub const USER: usize = 4;
+	pub const TICKET: usize = 5;
+	pub const TICKET_TOKEN: usize = 6;
+	pub const TOKEN_PROGRAM: usize = 7;
+	pub const SYSTEM_PROGRAM: usize = 8;
+	pub const ASSOCIATED_TOKEN_PROGRAM: usize = 9;
+	pub const ACCOUNTS: usize = 10;
+}
+
+/// `WithdrawTokens`
+pub mod withdraw_tokens {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const VAULT: usize = 2;
+	pub const MINT: usize = 3;
+	pub const RECIPIENT_TOKEN: usize = 4;
+	pub const TOKEN_PROGRAM: usize = 5;
+	pub const ACCOUNTS: usize = 6;
+}
+
+/// `BurnTicket`
+pub mod burn_ticket {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const MINT: usize = 2;
+	pub const TREASURY: usize = 3;
+	pub const TREASURY_TOKEN: usize = 4;
+	pub const TICKET: usize = 5;
+	pub const TICKET_TOKEN: usize = 6;
+	pub const TOKEN_PROGRAM: usize = 7;
+	pub const SYSTEM_PROGRAM: usize = 8;
+	pub const ASSOCIATED_TOKEN_PROGRAM: usize = 9;
+	pub const ACCOUNTS: usize = 10;
+}
+
+/// `TerminateWhitelist`
+pub mod terminate_whitelist {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const VAULT: usize = 2;
+	pub const MINT: usize = 3;
+	pub const RECIPIENT: usize = 4;
+	pub const RECIPIENT_TOKEN: usize = 5;
+	pub const TOKEN_PROGRAM: usize = 6;
+	pub const SYSTEM_PROGRAM: usize = 7;
+	pub const ACCOUNTS: usize = 8;
+	/// Appended when the whitelist's treasury is its PDA
+	pub const TREASURY: usize = 8;
+}
+
+/// `MigrateWhitelist`
+pub mod migrate_whitelist {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const SYSTEM_PROGRAM: usize = 2;
+	pub const ACCOUNTS: usize = 3;
+}
+
+/// `SetTicketNote`
+pub mod set_ticket_note {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const USER_TICKET: usize = 2;
+	pub const ACCOUNTS: usize = 3;
+}
+
+/// `DepositFor`
+pub mod deposit_for {
+	pub const WHITELIST: usize = 0;
+	pub const VAULT: usize = 1;
+	pub const DEPOSITOR: usize = 2;
+	pub const DEPOSITOR_TOKEN: usize = 3;
+	pub const MINT: usize = 4;
+	pub const TOKEN_PROGRAM: usize = 5;
+	pub const DEPOSITOR_LEDGER: usize = 6;
+	pub const SYSTEM_PROGRAM: usize = 7;
+	pub const ACCOUNTS: usize = 8;
+}
+
+/// `WithdrawDeposit`
+pub mod withdraw_deposit {
+	pub const WHITELIST: usize = 0;
+	pub const VAULT: usize = 1;
+	pub const DEPOSITOR: usize = 2;
+	pub const DEPOSITOR_TOKEN: usize = 3;
+	pub const MINT: usize = 4;
+	pub const TOKEN_PROGRAM: usize = 5;
+	pub const DEPOSITOR_LEDGER: usize = 6;
+	pub const SYSTEM_PROGRAM: usize = 7;
+	pub const ACCOUNTS: usize = 8;
+}
+
+/// `SetPaused`
+pub mod set_paused {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const ACCOUNTS: usize = 2;
+}
+
+/// `RegisterWithReferral`
+pub mod register_with_referral {
+	pub const WHITELIST: usize = 0;
+	pub const USER: usize = 1;
+	pub const USER_TICKET: usize = 2;
+	pub const SYSTEM_PROGRAM: usize = 3;
+	pub const REFERRER_TICKET: usize = 4;
+	pub const ALLOCATION: usize = 5;
+	pub const ACCOUNTS: usize = 6;
+	/// Appended when the whitelist's `authority_pays_rent` is set
+	pub const AUTHORITY: usize = 6;
+}
+
+/// `SetReferralBonus`
+pub mod set_referral_bonus {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const MINT: usize = 2;
+	pub const ACCOUNTS: usize = 3;
+}
+
+/// `AddUserAndSeed`, the `AddUser` accounts followed by those needed to seed the ticket
+pub mod add_user_and_seed {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const MINT: usize = 2;
+	pub const USER: usize = 3;
+	pub const USER_TICKET: usize = 4;
+	pub const SYSTEM_PROGRAM: usize = 5;
+	pub const VAULT: usize = 6;
+	pub const TICKET_TOKEN: usize = 7;
+	pub const TOKEN_PROGRAM: usize = 8;
+	pub const ASSOCIATED_TOKEN_PROGRAM: usize = 9;
+	pub const ACCOUNTS: usize = 10;
+}
+
+/// `ForceCloseTicket`
+pub mod force_close_ticket {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const MINT: usize = 2;
+	pub const TREASURY: usize = 3;
+	pub const TICKET: usize = 4;
+	pub const TICKET_TOKEN: usize = 5;
+	pub const TOKEN_PROGRAM: usize = 6;
+	pub const ACCOUNTS: usize = 7;
+}
+
+/// `CleanupTokenAccount`
+pub mod cleanup_token_account {
+	pub const WHITELIST: usize = 0;
+	pub const OWNER: usize = 1;
+	pub const MINT: usize = 2;
+	pub const TOKEN_ACCOUNT: usize = 3;
+	pub const TOKEN_PROGRAM: usize = 4;
+	pub const ACCOUNTS: usize = 5;
+}
+
+/// `SetBuyCooldown`
+pub mod set_buy_cooldown {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const ACCOUNTS: usize = 2;
+}
+
+/// `Refund`
+pub mod refund {
+	pub const WHITELIST: usize = 0;
+	pub const VAULT: usize = 1;
+	pub const MINT: usize = 2;
+	pub const OWNER: usize = 3;
+	pub const TICKET: usize = 4;
+	pub const OWNER_TOKEN: usize = 5;
+	pub const TOKEN_PROGRAM: usize = 6;
+	pub const ACCOUNTS: usize = 7;
+}
+
+/// `ClaimTicketRent`
+pub mod claim_ticket_rent {
+	pub const WHITELIST: usize = 0;
+	pub const OWNER: usize = 1;
+	pub const TICKET: usize = 2;
+	pub const PAYER: usize = 3;
+	pub const SYSTEM_PROGRAM: usize = 4;
+	pub const ACCOUNTS: usize = 5;
+}
+
+/// `SetAllocation`
+pub mod set_allocation {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const MINT: usize = 2;
+	pub const USER: usize = 3;
+	pub const ALLOCATION: usize = 4;
+	pub const SYSTEM_PROGRAM: usize = 5;
+	pub const ACCOUNTS: usize = 6;
+}
+
+/// `SetSchedule`
+pub mod set_schedule {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const ACCOUNTS: usize = 2;
+}
+
+/// `CloseTickets`, `ACCOUNTS` counts the fixed accounts ahead of the ticket pairs
+pub mod close_tickets {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const MINT: usize = 2;
+	pub const TREASURY: usize = 3;
+	pub const TOKEN_PROGRAM: usize = 4;
+	/// Index of the first ticket, each ticket is followed by its token account
+	pub const TICKETS: usize = 5;
+	pub const ACCOUNTS: usize = 5;
+}
+
+/// `WithdrawSol`, the ticket is optional
+pub mod withdraw_sol {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const TREASURY: usize = 2;
+	pub const TICKET: usize = 3;
+	pub const ACCOUNTS: usize = 4;
+}
+
+/// `SetRegisterAuthority`
+pub mod set_register_authority {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const ACCOUNTS: usize = 2;
+}
+
+/// `RegisterWithSignature`, the `Register` accounts followed by the instructions sysvar
+pub mod register_with_signature {
+	pub const WHITELIST: usize = 0;
+	pub const USER: usize = 1;
+	pub const USER_TICKET: usize = 2;
+	pub const SYSTEM_PROGRAM: usize = 3;
+	pub const INSTRUCTIONS_SYSVAR: usize = 4;
+	pub const ALLOCATION: usize = 5;
+	pub const ACCOUNTS: usize = 6;
+	/// Appended when the whitelist's `authority_pays_rent` is set
+	pub const AUTHORITY: usize = 6;
+}
+
+/// `WithdrawTreasury`
+pub mod withdraw_treasury {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const TREASURY: usize = 2;
+	pub const RECIPIENT: usize = 3;
+	pub const ACCOUNTS: usize = 4;
+}
+
+/// `ChangeAuthority`
+pub mod change_authority {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const ACCOUNTS: usize = 2;
+}
+
+/// `UpdatePrice`
+pub mod update_price {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const ACCOUNTS: usize = 2;
+}
+
+/// `CloseVault`
+pub mod close_vault {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const VAULT: usize = 2;
+	pub const MINT: usize = 3;
+	pub const RECIPIENT_TOKEN: usize = 4;
+	pub const TOKEN_PROGRAM: usize = 5;
+	pub const ACCOUNTS: usize = 6;
+}
+
+/// `UpdateBuyLimit`
+pub mod update_buy_limit {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const MINT: usize = 2;
+	pub const ACCOUNTS: usize = 3;
+}
+
+/// `SyncTicketAllowance`
+pub mod sync_ticket_allowance {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const USER_TICKET: usize = 2;
+	pub const ACCOUNTS: usize = 3;
+}
+
+/// `BatchAddUser`, `ACCOUNTS` counts the fixed accounts ahead of the user pairs
+pub mod batch_add_user {
+	pub const WHITELIST: usize = 0;
+	pub const AUTHORITY: usize = 1;
+	pub const MINT: usize = 2;
+	pub const SYSTEM_PROGRAM: usize = 3;
+	/// Index of the first user, each user is followed by its ticket account
+	pub const USERS: usize = 4;
+	pub const ACCOUNTS: usize = 4;
+}
+
+/// `MigrateTicket`
+pub mod migrate_ticket {
+	pub const USER_TICKET: usize = 0;
+	pub const PAYER: usize = 1;
+	pub const SYSTEM_PROGRAM: usize = 2;
+	pub const ACCOUNTS: usize = 3;
+}
+
+#[cfg(test)]
+mod tests {
+	use {
+		super::*,
+		crate::instructions::{self, PauseTarget},
+		solana_program::{instruction::Instruction, system_program, sysvar},
+	};
+
+	/// Asserts every `(index, key)` pair lands where the processor reads it and that the builder
+	/// supplies exactly `len` accounts
+	fn assert_order(ix: &Instruction, len: usize, expected: &[(usize, Pubkey)]) {
+		assert_eq!(ix.accounts.len(), len);
+		for (index, key) in expected {
+			assert_eq!(ix.accounts[*index].pubkey, *key, "account index {}", index);
+		}
+	}
+
+	#[test]
+	fn test_builders_match_processor_order() {
+		let whitelist = Pubkey::new_unique();
+		let authority = Pubkey::new_unique();
+		let vault = Pubkey::new_unique();
+		let mint = Pubkey::new_unique();
+		let user = Pubkey::new_unique();
+		let ticket = Pubkey::new_unique();
+		let ticket_token = Pubkey::new_unique();
+		let user_token = Pubkey::new_unique();
+		let recipient = Pubkey::new_unique();
+		let recipient_token = Pubkey::new_unique();
+		let ledger = Pubkey::new_unique();
+		let treasury = Pubkey::new_unique();
+		let allocation = Pubkey::new_unique();
+		let token_program = spl_token_2022::id();
+		let system = system_program::id();
+
+		let ix = instructions::init_whitelist(
+			&whitelist,
+			&authority,
+			&vault,
+			&mint,
+			&recipient,
+			1,
+			1,
+			1,
+			false,
+			0,
+			0,
+			0,
+			0,
+			0,
+			0,
+			false,
+			false,
+			false,
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			initialise_whitelist::ACCOUNTS,
+			&[
+				(initialise_whitelist::WHITELIST, whitelist),
+				(initialise_whitelist::AUTHORITY, authority),
+				(initialise_whitelist::VAULT, vault),
+				(initialise_whitelist::MINT, mint),
+				(initialise_whitelist::TOKEN_PROGRAM, token_program),
+				(initialise_whitelist::SYSTEM_PROGRAM, system),
+				(
+					initialise_whitelist::ASSOCIATED_TOKEN_PROGRAM,
+					ASSOCIATED_TOKEN_PROGRAM_ID,
+				),
+			],
+		);
+
+		let ix = instructions::init_whitelist(
+			&whitelist,
+			&authority,
+			&vault,
+			&mint,
+			&treasury,
+			1,
+			1,
+			1,
+			false,
+			0,
+			0,
+			0,
+			0,
+			0,
+			0,
+			false,
+			false,
+			true,
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			initialise_whitelist::ACCOUNTS + 1,
+			&[(initialise_whitelist::TREASURY, treasury)],
+		);
+
+		let ix = instructions::add_user(&whitelist, &authority, &mint, &user, &ticket).unwrap();
+		assert_order(
+			&ix,
+			add_user::ACCOUNTS,
+			&[
+				(add_user::WHITELIST, whitelist),
+				(add_user::AUTHORITY, authority),
+				(add_user::MINT, mint),
+				(add_user::USER, user),
+				(add_user::USER_TICKET, ticket),
+				(add_user::SYSTEM_PROGRAM, system),
+			],
+		);
+
+		let ix = instructions::remove_user(
+			&whitelist,
+			&authority,
+			&vault,
+			&mint,
+			&user,
+			&ticket,
+			&ticket_token,
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			remove_user::ACCOUNTS,
+			&[
+				(remove_user::WHITELIST, whitelist),
+				(remove_user::AUTHORITY, authority),
+				(remove_user::VAULT, vault),
+				(remove_user::MINT, mint),
+				(remove_user::USER, user),
+				(remove_user::USER_TICKET, ticket),
+				(remove_user::TICKET_TOKEN, ticket_token),
+				(remove_user::TOKEN_PROGRAM, token_program),
+				(remove_user::SYSTEM_PROGRAM, system),
+			],
+		);
+
+		let ix = instructions::buy_tokens(
+			&whitelist,
+			&vault,
+			&mint,
+			&user,
+			&ticket,
+			&ticket_token,
+			&user_token,
+			1,
+			None,
+			false,
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			buy::ACCOUNTS,
+			&[
+				(buy::WHITELIST, whitelist),
+				(buy::VAULT, vault),
+				(buy::MINT, mint),
+				(buy::USER, user),
+				(buy::USER_TICKET, ticket),
+				(buy::TICKET_TOKEN, ticket_token),
+				(buy::USER_TOKEN, user_token),
+				(buy::TOKEN_PROGRAM, token_program),
+				(buy::SYSTEM_PROGRAM, system),
+				(buy::ASSOCIATED_TOKEN_PROGRAM, ASSOCIATED_TOKEN_PROGRAM_ID),
+			],
+		);
+
+		let authority_only = [
+			(
+				instructions::amend_whitelist_size(&whitelist, &authority, 1).unwrap(),
+				amend_whitelist_size::ACCOUNTS,
+				amend_whitelist_size::WHITELIST,
+				amend_whitelist_size::AUTHORITY,
+			),
+			(
+				instructions::amend_times(&whitelist, &authority, None, None, None, None, false)
+					.unwrap(),
+				amend_times::ACCOUNTS,
+				amend_times::WHITELIST,
+				amend_times::AUTHORITY,
+			),
+			(
+				instructions::allow_registration(&whitelist, &authority, true).unwrap(),
+				allow_register::ACCOUNTS,
+				allow_register::WHITELIST,
+				allow_register::AUTHORITY,
+			),
+			(
+				instructions::start_registration(&whitelist, &authority).unwrap(),
+				start_registration::ACCOUNTS,
+				start_registration::WHITELIST,
+				start_registration::AUTHORITY,
+			),
+			(
+				instructions::set_paused(&whitelist, &authority, PauseTarget::Sale, true).unwrap(),
+				set_paused::ACCOUNTS,
+				set_paused::WHITELIST,
+				set_paused::AUTHORITY,
+			),
+		];
+		for (ix, len, whitelist_index, authority_index) in authority_only {
+			assert_order(
+				&ix,
+				len,
+				&[(whitelist_index, whitelist), (authority_index, authority)],
+			);
+		}
+
+		let ix = instructions::register(&whitelist, &user, &ticket, &allocation).unwrap();
+		assert_order(
+			&ix,
+			register::ACCOUNTS,
+			&[
+				(register::WHITELIST, whitelist),
+				(register::USER, user),
+				(register::USER_TICKET, ticket),
+				(register::SYSTEM_PROGRAM, system),
+				(register::ALLOCATION, allocation),
+			],
+		);
+
+		let ix = instructions::register_with_referral(
+			&whitelist,
+			&user,
+			&ticket,
+			&authority,
+			&ledger,
+			&allocation,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			register_with_referral::ACCOUNTS,
+			&[
+				(register_with_referral::WHITELIST, whitelist),
+				(register_with_referral::USER, user),
+				(register_with_referral::USER_TICKET, ticket),
+				(register_with_referral::SYSTEM_PROGRAM, system),
+				(register_with_referral::REFERRER_TICKET, ledger),
+				(register_with_referral::ALLOCATION, allocation),
+			],
+		);
+
+		let ix =
+			instructions::sponsored_register(&whitelist, &user, &ticket, &allocation, &authority)
+				.unwrap();
+		assert_order(
+			&ix,
+			register::ACCOUNTS + 1,
+			&[
+				(register::USER, user),
+				(register::USER_TICKET, ticket),
+				(register::ALLOCATION, allocation),
+				(register::AUTHORITY, authority),
+			],
+		);
+
+		let ix = instructions::sponsored_register_with_referral(
+			&whitelist,
+			&user,
+			&ticket,
+			&recipient,
+			&ledger,
+			&allocation,
+			&authority,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			register_with_referral::ACCOUNTS + 1,
+			&[
+				(register_with_referral::REFERRER_TICKET, ledger),
+				(register_with_referral::ALLOCATION, allocation),
+				(register_with_referral::AUTHORITY, authority),
+			],
+		);
+		// `RegisterWithReferral` hands its accounts to the `Register` handler, so the shared
+		// prefix has to line up as well
+		assert_eq!(register_with_referral::WHITELIST, register::WHITELIST);
+		assert_eq!(register_with_referral::USER, register::USER);
+		assert_eq!(register_with_referral::USER_TICKET, register::USER_TICKET);
+		assert_eq!(
+			register_with_referral::SYSTEM_PROGRAM,
+			register::SYSTEM_PROGRAM
+		);
+
+		let ix = instructions::set_referral_bonus(&whitelist, &authority, &mint, 1).unwrap();
+		assert_order(
+			&ix,
+			set_referral_bonus::ACCOUNTS,
+			&[
+				(set_referral_bonus::WHITELIST, whitelist),
+				(set_referral_bonus::AUTHORITY, authority),
+				(set_referral_bonus::MINT, mint),
+			],
+		);
+
+		let ix = instructions::add_user_and_seed(
+			&whitelist,
+			&authority,
+			&mint,
+			&user,
+			&ticket,
+			&vault,
+			&ticket_token,
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			add_user_and_seed::ACCOUNTS,
+			&[
+				(add_user_and_seed::WHITELIST, whitelist),
+				(add_user_and_seed::AUTHORITY, authority),
+				(add_user_and_seed::MINT, mint),
+				(add_user_and_seed::USER, user),
+				(add_user_and_seed::USER_TICKET, ticket),
+				(add_user_and_seed::SYSTEM_PROGRAM, system),
+				(add_user_and_seed::VAULT, vault),
+				(add_user_and_seed::TICKET_TOKEN, ticket_token),
+				(add_user_and_seed::TOKEN_PROGRAM, token_program),
+				(
+					add_user_and_seed::ASSOCIATED_TOKEN_PROGRAM,
+					ASSOCIATED_TOKEN_PROGRAM_ID,
+				),
+			],
+		);
+		// `AddUserAndSeed` hands its accounts to the `AddUser` handler first
+		assert_eq!(add_user_and_seed::WHITELIST, add_user::WHITELIST);
+		assert_eq!(add_user_and_seed::AUTHORITY, add_user::AUTHORITY);
+		assert_eq!(add_user_and_seed::MINT, add_user::MINT);
+		assert_eq!(add_user_and_seed::USER, add_user::USER);
+		assert_eq!(add_user_and_seed::USER_TICKET, add_user::USER_TICKET);
+		assert_eq!(add_user_and_seed::SYSTEM_PROGRAM, add_user::SYSTEM_PROGRAM);
+
+		let ix = instructions::unregister(
+			&whitelist,
+			&authority,
+			&vault,
+			&mint,
+			&user,
+			&ticket,
+			&ticket_token,
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			unregister::ACCOUNTS,
+			&[
+				(unregister::WHITELIST, whitelist),
+				(unregister::AUTHORITY, authority),
+				(unregister::VAULT, vault),
+				(unregister::MINT, mint),
+				(unregister::USER, user),
+				(unregister::USER_TICKET, ticket),
+				(unregister::TICKET_TOKEN, ticket_token),
+				(unregister::TOKEN_PROGRAM, token_program),
+				(unregister::SYSTEM_PROGRAM, system),
+			],
+		);
+
+		let ix = instructions::deposit_tokens(
+			&whitelist,
+			&vault,
+			&user,
+			&user_token,
+			&mint,
+			1,
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			deposit_tokens::ACCOUNTS,
+			&[
+				(deposit_tokens::WHITELIST, whitelist),
+				(deposit_tokens::VAULT, vault),
+				(deposit_tokens::DEPOSITOR, user),
+				(deposit_tokens::DEPOSITOR_TOKEN, user_token),
+				(deposit_tokens::MINT, mint),
+				(deposit_tokens::TOKEN_PROGRAM, token_program),
+			],
+		);
+
+		let ix = instructions::start_token_sale(&whitelist, &authority, &vault, false).unwrap();
+		assert_order(
+			&ix,
+			start_token_sale::ACCOUNTS,
+			&[
+				(start_token_sale::WHITELIST, whitelist),
+				(start_token_sale::AUTHORITY, authority),
+				(start_token_sale::VAULT, vault),
+			],
+		);
+
+		let ix = instructions::transfer_tokens(
+			&whitelist,
+			&authority,
+			&vault,
+			&mint,
+			&user,
+			&ticket,
+			&ticket_token,
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			transfer_tokens::ACCOUNTS,
+			&[
+				(transfer_tokens::WHITELIST, whitelist),
+				(transfer_tokens::AUTHORITY, authority),
+				(transfer_tokens::VAULT, vault),
+				(transfer_tokens::MINT, mint),
+				(transfer_tokens::USER, user),
+				(transfer_tokens::TICKET, ticket),
+				(transfer_tokens::TICKET_TOKEN, ticket_token),
+				(transfer_tokens::TOKEN_PROGRAM, token_program),
+				(transfer_tokens::SYSTEM_PROGRAM, system),
+				(
+					transfer_tokens::ASSOCIATED_TOKEN_PROGRAM,
+					ASSOCIATED_TOKEN_PROGRAM_ID,
+				),
+			],
+		);
+
+		let ix = instructions::withdraw_tokens(
+			&whitelist,
+			&authority,
+			&vault,
+			&mint,
+			&recipient_token,
+			1,
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			withdraw_tokens::ACCOUNTS,
+			&[
+				(withdraw_tokens::WHITELIST, whitelist),
+				(withdraw_tokens::AUTHORITY, authority),
+				(withdraw_tokens::VAULT, vault),
+				(withdraw_tokens::MINT, mint),
+				(withdraw_tokens::RECIPIENT_TOKEN, recipient_token),
+				(withdraw_tokens::TOKEN_PROGRAM, token_program),
+			],
+		);
+
+		let ix = instructions::burn_ticket(
+			&whitelist,
+			&authority,
+			&mint,
+			&recipient,
+			&recipient_token,
+			&ticket,
+			&ticket_token,
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			burn_ticket::ACCOUNTS,
+			&[
+				(burn_ticket::WHITELIST, whitelist),
+				(burn_ticket::AUTHORITY, authority),
+				(burn_ticket::MINT, mint),
+				(burn_ticket::TREASURY, recipient),
+				(burn_ticket::TREASURY_TOKEN, recipient_token),
+				(burn_ticket::TICKET, ticket),
+				(burn_ticket::TICKET_TOKEN, ticket_token),
+				(burn_ticket::TOKEN_PROGRAM, token_program),
+				(burn_ticket::SYSTEM_PROGRAM, system),
+				(
+					burn_ticket::ASSOCIATED_TOKEN_PROGRAM,
+					ASSOCIATED_TOKEN_PROGRAM_ID,
+				),
+			],
+		);
+
+		let ix = instructions::terminate_whitelist(
+			&whitelist,
+			&authority,
+			&vault,
+			&mint,
+			&recipient,
+			&recipient_token,
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			terminate_whitelist::ACCOUNTS,
+			&[
+				(terminate_whitelist::WHITELIST, whitelist),
+				(terminate_whitelist::AUTHORITY, authority),
+				(terminate_whitelist::VAULT, vault),
+				(terminate_whitelist::MINT, mint),
+				(terminate_whitelist::RECIPIENT, recipient),
+				(terminate_whitelist::RECIPIENT_TOKEN, recipient_token),
+				(terminate_whitelist::TOKEN_PROGRAM, token_program),
+				(terminate_whitelist::SYSTEM_PROGRAM, system),
+			],
+		);
+
+		let ix = instructions::migrate_whitelist(&whitelist, &authority).unwrap();
+		assert_order(
+			&ix,
+			migrate_whitelist::ACCOUNTS,
+			&[
+				(migrate_whitelist::WHITELIST, whitelist),
+				(migrate_whitelist::AUTHORITY, authority),
+				(migrate_whitelist::SYSTEM_PROGRAM, system),
+			],
+		);
+
+		let ix = instructions::set_ticket_note(&whitelist, &authority, &ticket, "note").unwrap();
+		assert_order(
+			&ix,
+			set_ticket_note::ACCOUNTS,
+			&[
+				(set_ticket_note::WHITELIST, whitelist),
+				(set_ticket_note::AUTHORITY, authority),
+				(set_ticket_note::USER_TICKET, ticket),
+			],
+		);
+
+		let ix = instructions::deposit_for(
+			&whitelist,
+			&vault,
+			&user,
+			&user_token,
+			&mint,
+			&ledger,
+			1,
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			deposit_for::ACCOUNTS,
+			&[
+				(deposit_for::WHITELIST, whitelist),
+				(deposit_for::VAULT, vault),
+				(deposit_for::DEPOSITOR, user),
+				(deposit_for::DEPOSITOR_TOKEN, user_token),
+				(deposit_for::MINT, mint),
+				(deposit_for::TOKEN_PROGRAM, token_program),
+				(deposit_for::DEPOSITOR_LEDGER, ledger),
+				(deposit_for::SYSTEM_PROGRAM, system),
+			],
+		);
+		// `DepositFor` hands its accounts to the `DepositTokens` handler, so the shared prefix has
+		// to line up as well
+		assert_eq!(deposit_for::WHITELIST, deposit_tokens::WHITELIST);
+		assert_eq!(deposit_for::VAULT, deposit_tokens::VAULT);
+		assert_eq!(deposit_for::DEPOSITOR, deposit_tokens::DEPOSITOR);
+		assert_eq!(
+			deposit_for::DEPOSITOR_TOKEN,
+			deposit_tokens::DEPOSITOR_TOKEN
+		);
+		assert_eq!(deposit_for::MINT, deposit_tokens::MINT);
+		assert_eq!(deposit_for::TOKEN_PROGRAM, deposit_tokens::TOKEN_PROGRAM);
+
+		let ix = instructions::withdraw_deposit(
+			&whitelist,
+			&vault,
+			&user,
+			&user_token,
+			&mint,
+			&ledger,
+			1,
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			withdraw_deposit::ACCOUNTS,
+			&[
+				(withdraw_deposit::WHITELIST, whitelist),
+				(withdraw_deposit::VAULT, vault),
+				(withdraw_deposit::DEPOSITOR, user),
+				(withdraw_deposit::DEPOSITOR_TOKEN, user_token),
+				(withdraw_deposit::MINT, mint),
+				(withdraw_deposit::TOKEN_PROGRAM, token_program),
+				(withdraw_deposit::DEPOSITOR_LEDGER, ledger),
+				(withdraw_deposit::SYSTEM_PROGRAM, system),
+			],
+		);
+
+		let ix = instructions::force_close_ticket(
+			&whitelist,
+			&authority,
+			&mint,
+			&recipient,
+			&ticket,
+			&ticket_token,
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			force_close_ticket::ACCOUNTS,
+			&[
+				(force_close_ticket::WHITELIST, whitelist),
+				(force_close_ticket::AUTHORITY, authority),
+				(force_close_ticket::MINT, mint),
+				(force_close_ticket::TREASURY, recipient),
+				(force_close_ticket::TICKET, ticket),
+				(force_close_ticket::TICKET_TOKEN, ticket_token),
+				(force_close_ticket::TOKEN_PROGRAM, token_program),
+			],
+		);
+
+		let ix = instructions::cleanup_token_account(
+			&whitelist,
+			&user,
+			&mint,
+			&user_token,
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			cleanup_token_account::ACCOUNTS,
+			&[
+				(cleanup_token_account::WHITELIST, whitelist),
+				(cleanup_token_account::OWNER, user),
+				(cleanup_token_account::MINT, mint),
+				(cleanup_token_account::TOKEN_ACCOUNT, user_token),
+				(cleanup_token_account::TOKEN_PROGRAM, token_program),
+			],
+		);
+
+		let ix = instructions::set_buy_cooldown(&whitelist, &authority, 30).unwrap();
+		assert_order(
+			&ix,
+			set_buy_cooldown::ACCOUNTS,
+			&[
+				(set_buy_cooldown::WHITELIST, whitelist),
+				(set_buy_cooldown::AUTHORITY, authority),
+			],
+		);
+
+		let ix = instructions::refund(
+			&whitelist,
+			&vault,
+			&mint,
+			&user,
+			&ticket,
+			&user_token,
+			1,
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			refund::ACCOUNTS,
+			&[
+				(refund::WHITELIST, whitelist),
+				(refund::VAULT, vault),
+				(refund::MINT, mint),
+				(refund::OWNER, user),
+				(refund::TICKET, ticket),
+				(refund::OWNER_TOKEN, user_token),
+				(refund::TOKEN_PROGRAM, token_program),
+			],
+		);
+
+		let ix = instructions::claim_ticket_rent(&whitelist, &user, &ticket, &authority).unwrap();
+		assert_order(
+			&ix,
+			claim_ticket_rent::ACCOUNTS,
+			&[
+				(claim_ticket_rent::WHITELIST, whitelist),
+				(claim_ticket_rent::OWNER, user),
+				(claim_ticket_rent::TICKET, ticket),
+				(claim_ticket_rent::PAYER, authority),
+				(claim_ticket_rent::SYSTEM_PROGRAM, system),
+			],
+		);
+
+		let ix =
+			instructions::set_allocation(&whitelist, &authority, &mint, &user, &ledger, 1).unwrap();
+		assert_order(
+			&ix,
+			set_allocation::ACCOUNTS,
+			&[
+				(set_allocation::WHITELIST, whitelist),
+				(set_allocation::AUTHORITY, authority),
+				(set_allocation::MINT, mint),
+				(set_allocation::USER, user),
+				(set_allocation::ALLOCATION, ledger),
+				(set_allocation::SYSTEM_PROGRAM, system),
+			],
+		);
+
+		let ix = instructions::set_schedule(&whitelist, &authority, 1, 2, 3).unwrap();
+		assert_order(
+			&ix,
+			set_schedule::ACCOUNTS,
+			&[
+				(set_schedule::WHITELIST, whitelist),
+				(set_schedule::AUTHORITY, authority),
+			],
+		);
+
+		let other_ticket = Pubkey::new_unique();
+		let other_ticket_token = Pubkey::new_unique();
+		let ix = instructions::close_tickets(
+			&whitelist,
+			&authority,
+			&mint,
+			&recipient,
+			&[(ticket, ticket_token), (other_ticket, other_ticket_token)],
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			close_tickets::ACCOUNTS + 4,
+			&[
+				(close_tickets::WHITELIST, whitelist),
+				(close_tickets::AUTHORITY, authority),
+				(close_tickets::MINT, mint),
+				(close_tickets::TREASURY, recipient),
+				(close_tickets::TOKEN_PROGRAM, token_program),
+				(close_tickets::TICKETS, ticket),
+				(close_tickets::TICKETS + 1, ticket_token),
+				(close_tickets::TICKETS + 2, other_ticket),
+				(close_tickets::TICKETS + 3, other_ticket_token),
+			],
+		);
+
+		let ix = instructions::withdraw_sol(&whitelist, &authority, &recipient, Some(&ticket), 1)
+			.unwrap();
+		assert_order(
+			&ix,
+			withdraw_sol::ACCOUNTS,
+			&[
+				(withdraw_sol::WHITELIST, whitelist),
+				(withdraw_sol::AUTHORITY, authority),
+				(withdraw_sol::TREASURY, recipient),
+				(withdraw_sol::TICKET, ticket),
+			],
+		);
+
+		let ix =
+			instructions::set_register_authority(&whitelist, &authority, Some(&recipient)).unwrap();
+		assert_order(
+			&ix,
+			set_register_authority::ACCOUNTS,
+			&[
+				(set_register_authority::WHITELIST, whitelist),
+				(set_register_authority::AUTHORITY, authority),
+			],
+		);
+
+		let ix = instructions::sponsored_register_with_signature(
+			&whitelist,
+			&user,
+			&ticket,
+			&allocation,
+			1,
+			&authority,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			register_with_signature::ACCOUNTS + 1,
+			&[
+				(register_with_signature::WHITELIST, whitelist),
+				(register_with_signature::USER, user),
+				(register_with_signature::USER_TICKET, ticket),
+				(register_with_signature::SYSTEM_PROGRAM, system),
+				(
+					register_with_signature::INSTRUCTIONS_SYSVAR,
+					sysvar::instructions::id(),
+				),
+				(register_with_signature::ALLOCATION, allocation),
+				(register_with_signature::AUTHORITY, authority),
+			],
+		);
+
+		let ix = instructions::withdraw_treasury(&whitelist, &authority, &treasury, &recipient, 1)
+			.unwrap();
+		assert_order(
+			&ix,
+			withdraw_treasury::ACCOUNTS,
+			&[
+				(withdraw_treasury::WHITELIST, whitelist),
+				(withdraw_treasury::AUTHORITY, authority),
+				(withdraw_treasury::TREASURY, treasury),
+				(withdraw_treasury::RECIPIENT, recipient),
+			],
+		);
+
+		let ix = instructions::change_authority(&whitelist, &authority, &user).unwrap();
+		assert_order(
+			&ix,
+			change_authority::ACCOUNTS,
+			&[
+				(change_authority::WHITELIST, whitelist),
+				(change_authority::AUTHORITY, authority),
+			],
+		);
+
+		let ix = instructions::update_price(&whitelist, &authority, 1).unwrap();
+		assert_order(
+			&ix,
+			update_price::ACCOUNTS,
+			&[
+				(update_price::WHITELIST, whitelist),
+				(update_price::AUTHORITY, authority),
+			],
+		);
+
+		let ix = instructions::close_vault(
+			&whitelist,
+			&authority,
+			&vault,
+			&mint,
+			&recipient_token,
+			&token_program,
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			close_vault::ACCOUNTS,
+			&[
+				(close_vault::WHITELIST, whitelist),
+				(close_vault::AUTHORITY, authority),
+				(close_vault::VAULT, vault),
+				(close_vault::MINT, mint),
+				(close_vault::RECIPIENT_TOKEN, recipient_token),
+				(close_vault::TOKEN_PROGRAM, token_program),
+			],
+		);
+
+		let ix = instructions::update_buy_limit(&whitelist, &authority, &mint, 1).unwrap();
+		assert_order(
+			&ix,
+			update_buy_limit::ACCOUNTS,
+			&[
+				(update_buy_limit::WHITELIST, whitelist),
+				(update_buy_limit::AUTHORITY, authority),
+				(update_buy_limit::MINT, mint),
+			],
+		);
+
+		let ix = instructions::sync_ticket_allowance(&whitelist, &authority, &ticket).unwrap();
+		assert_order(
+			&ix,
+			sync_ticket_allowance::ACCOUNTS,
+			&[
+				(sync_ticket_allowance::WHITELIST, whitelist),
+				(sync_ticket_allowance::AUTHORITY, authority),
+				(sync_ticket_allowance::USER_TICKET, ticket),
+			],
+		);
+
+		let other_user = Pubkey::new_unique();
+		let ix = instructions::batch_add_user(
+			&whitelist,
+			&authority,
+			&mint,
+			&[(user, ticket), (other_user, other_ticket)],
+		)
+		.unwrap();
+		assert_order(
+			&ix,
+			batch_add_user::ACCOUNTS + 4,
+			&[
+				(batch_add_user::WHITELIST, whitelist),
+				(batch_add_user::AUTHORITY, authority),
+				(batch_add_user::MINT, mint),
+				(batch_add_user::SYSTEM_PROGRAM, system),
+				(batch_add_user::USERS, user),
+				(batch_add_user::USERS + 1, ticket),
+				(batch_add_user::USERS + 2, other_user),
+				(batch_add_user::USERS + 3, other_ticket),
+			],
+		);
+
+		let ix = instructions::migrate_ticket(&ticket, &authority).unwrap();
+		assert_order(
+			&ix,
+			migrate_ticket::ACCOUNTS,
+			&[
+				(migrate_ticket::USER_TICKET, ticket),
+				(migrate_ticket::PAYER, authority),
+				(migrate_ticket::SYSTEM_PROGRAM, system),
+			],
+		);
+	}
+}
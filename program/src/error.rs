@@ -19,8 +19,8 @@ pub enum WhitelistError {
 	SignerError,
 	#[error("Account mismatch")]
 	AccountMismatch,
-    #[error("Incorrect token program")]
-    IncorrectTokenProgram,
+	#[error("Incorrect token program")]
+	IncorrectTokenProgram,
 	#[error("Whitelist Already Initialized")]
 	WhitelistAlreadyInitialized,
 	#[error("Whitelist Not Initialized")]
@@ -75,6 +75,83 @@ pub enum WhitelistError {
 	BuyLimitExceeded,
 	#[error("Overflow")]
 	Overflow,
+	#[error("Whitelist has already been migrated")]
+	WhitelistAlreadyMigrated,
+	#[error("Unsupported account version")]
+	UnsupportedVersion,
+	#[error("Ticket note exceeds the maximum length")]
+	NoteTooLong,
+	#[error("Vault does not hold enough tokens to fill every ticket")]
+	Undersubscribed,
+	#[error("Insufficient SOL to cover purchase")]
+	InsufficientSol,
+	#[error("Claim exceeds the amount bought")]
+	ClaimExceedsPurchase,
+	#[error("Whitelist is full")]
+	WhitelistFull,
+	#[error("Withdrawal exceeds the amount deposited")]
+	WithdrawalExceedsDeposit,
+	#[error("Token price exceeds the buyer's maximum price")]
+	PriceExceeded,
+	#[error("Registration is paused")]
+	RegistrationPaused,
+	#[error("Token sale is paused")]
+	SalePaused,
+	#[error("Referrer must be another user registered to the whitelist")]
+	InvalidReferrer,
+	#[error("Whitelist has reached its maximum number of tickets")]
+	TicketLimitReached,
+	#[error("Invalid token amount")]
+	InvalidTokenAmount,
+	#[error("Ticket token account still holds tokens")]
+	TicketNotEmpty,
+	#[error("Registration window runs into the token sale")]
+	WindowsOverlap,
+	#[error("Amount must be greater than zero")]
+	ZeroAmount,
+	#[error("Mint has a freeze authority")]
+	FreezableMint,
+	#[error("Vault token account is frozen")]
+	VaultFrozen,
+	#[error("Token account still holds tokens")]
+	TokenAccountNotEmpty,
+	#[error("Buy arrived within the cooldown since the ticket's last purchase")]
+	Cooldown,
+	#[error("The refund window for this ticket's purchase has closed")]
+	RefundWindowClosed,
+	#[error("Refund exceeds the tokens bought")]
+	RefundExceedsPurchase,
+	#[error("Token price is zero but the sale was not marked as free")]
+	ZeroPrice,
+	#[error("The ticket's latest purchase can still be refunded")]
+	RefundWindowOpen,
+	#[error("Registration requires a voucher signed by the register authority")]
+	SignatureRequired,
+	#[error("Registration voucher was not signed by the register authority for this user")]
+	InvalidSignature,
+	#[error("Treasury PDA still holds proceeds")]
+	TreasuryNotEmpty,
+	#[error("Authority cannot be the default pubkey")]
+	InvalidAuthority,
+	#[error("Incorrect system program")]
+	IncorrectSystemProgram,
+	#[error("Incorrect associated token program")]
+	IncorrectAssociatedTokenProgram,
+	#[error("Batch must contain between one and `MAX_BATCH_USERS` users")]
+	InvalidBatchSize,
+	#[error("Accounts do not match the users given in the instruction data")]
+	BatchAccountsMismatch,
+	#[error("Ticket has already been migrated")]
+	TicketAlreadyMigrated,
+	#[error("Ticket still holds purchase SOL that has not been withdrawn")]
+	TicketHoldsProceeds,
+}
+
+impl WhitelistError {
+	/// Maps a custom program error code back to the `WhitelistError` it was created from
+	pub fn from_code(code: u32) -> Option<Self> {
+		Self::from_u32(code)
+	}
 }
 
 impl From<WhitelistError> for ProgramError {
@@ -0,0 +1,103 @@
+use {
+	crate::{instructions::PauseTarget, state::Whitelist},
+	borsh::{BorshDeserialize, BorshSerialize},
+	solana_program::{entrypoint::ProgramResult, msg, pubkey::Pubkey},
+	std::fmt::Write,
+};
+
+/// Marks an event among a transaction's log messages
+pub const EVENT_LOG_PREFIX: &str = "Event: ";
+
+/// Emitted by authority actions with the values they changed before and after, so the history of
+/// a whitelist's configuration can be rebuilt from its transaction logs. Each is logged as its
+/// borsh encoding in hex after `EVENT_LOG_PREFIX`, see `WhitelistEvent::from_log`
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub enum WhitelistEvent {
+	/// `AmendWhitelistSize`
+	WhitelistSizeAmended {
+		whitelist: Pubkey,
+		old_size: u64,
+		new_size: u64,
+	},
+	/// `AmendTimes` and `SetSchedule`
+	TimesAmended {
+		whitelist: Pubkey,
+		old: SaleSchedule,
+		new: SaleSchedule,
+	},
+	/// `SetPaused`
+	PausedSet {
+		whitelist: Pubkey,
+		target: PauseTarget,
+		old_paused: bool,
+		new_paused: bool,
+	},
+	/// `ChangeAuthority`
+	AuthorityChanged {
+		whitelist: Pubkey,
+		old_authority: Pubkey,
+		new_authority: Pubkey,
+	},
+	/// `UpdatePrice`
+	PriceUpdated {
+		whitelist: Pubkey,
+		old_price: u64,
+		new_price: u64,
+	},
+	/// `UpdateBuyLimit`, limits in base units
+	BuyLimitUpdated {
+		whitelist: Pubkey,
+		old_buy_limit: u64,
+		new_buy_limit: u64,
+	},
+}
+
+/// The registration and sale windows of a whitelist
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct SaleSchedule {
+	pub registration_timestamp: i64,
+	pub registration_duration: i64,
+	pub sale_timestamp: i64,
+	pub sale_duration: i64,
+}
+
+impl From<&Whitelist> for SaleSchedule {
+	fn from(whitelist: &Whitelist) -> Self {
+		Self {
+			registration_timestamp: whitelist.registration_timestamp,
+			registration_duration: whitelist.registration_duration,
+			sale_timestamp: whitelist.sale_timestamp,
+			sale_duration: whitelist.sale_duration,
+		}
+	}
+}
+
+impl WhitelistEvent {
+	pub fn emit(&self) -> ProgramResult {
+		let data = borsh::to_vec(self)?;
+		let mut hex = String::with_capacity(data.len() * 2);
+		for byte in data {
+			// Writing to a `String` cannot fail
+			let _ = write!(hex, "{:02x}", byte);
+		}
+		msg!("{}{}", EVENT_LOG_PREFIX, hex);
+		Ok(())
+	}
+
+	/// Decodes the event logged on `log`, with or without the runtime's `Program log: ` prefix.
+	/// `None` when the line is not an event
+	pub fn from_log(log: &str) -> Option<Self> {
+		let hex = log
+			.strip_prefix("Program log: ")
+			.unwrap_or(log)
+			.strip_prefix(EVENT_LOG_PREFIX)?;
+		if hex.len() % 2 != 0 {
+			return None;
+		}
+		let data = (0..hex.len())
+			.step_by(2)
+			.map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+			.collect::<Option<Vec<u8>>>()?;
+		Self::try_from_slice(&data).ok()
+	}
+}
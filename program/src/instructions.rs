@@ -1,13 +1,24 @@
 use {
+	crate::account_order,
 	borsh::{BorshDeserialize, BorshSchema, BorshSerialize},
 	solana_program::{
+		ed25519_program,
 		instruction::{AccountMeta, Instruction},
 		program_error::ProgramError,
 		pubkey::Pubkey,
-		system_program,
+		system_program, sysvar,
 	},
 };
 
+/// The part of a whitelist that `SetPaused` pauses or resumes
+#[derive(BorshDeserialize, BorshSerialize, BorshSchema, Clone, Copy, Debug, PartialEq)]
+pub enum PauseTarget {
+	/// Users may not register, users added by the authority are unaffected
+	Registration,
+	/// Ticket holders may not buy
+	Sale,
+}
+
 #[derive(BorshDeserialize, BorshSerialize, BorshSchema, Debug, PartialEq)]
 pub enum WhitelistInstruction {
 	/// Initialises an instance of a whitelist
@@ -43,6 +54,31 @@ pub enum WhitelistInstruction {
 	///  time has elapsed. Failing to set this value will not allow termination of the whitelist
 	///  until all tokens are sold (not recommended).
 	///
+	///  `max_tickets`: a ceiling on the number of tickets open on the whitelist at once, enforced
+	///  even when `whitelist_size` is `0` to stop an unlimited whitelist being flooded with
+	///  ticket accounts. Set to `0` to disable the ceiling
+	///
+	///  `refund_window_secs`: seconds after a ticket's latest purchase during which its owner
+	///  may return tokens bought with `Refund`, after which sales are final. Set to `0` to
+	///  disable refunds
+	///
+	///  `authority_pays_rent`: charges the rent of tickets opened with `Register` to the
+	///  authority, who must co-sign each registration, rather than to the registering user
+	///
+	///  `allow_free`: permits a `token_price` of `0`, a free drop where buyers pay no SOL. A zero
+	///  price without it is rejected as it would give the supply away by mistake
+	///
+	///  `treasury_is_pda`: pays proceeds into a program owned treasury, `treasury` must then be
+	///  `get_treasury_address(mint)`. The treasury is created alongside the whitelist and SOL
+	///  leaves it only through `WithdrawTreasury`
+	///
+	/// Re-running the instruction against a whitelist whose vault does not exist, including one
+	/// closed with `CloseVault`, creates only the vault, the stored whitelist state is kept and
+	/// the other arguments are ignored. The authority must match the stored authority
+	///
+	/// Mints with a freeze authority are rejected, a frozen vault would stop every deposit,
+	/// purchase and withdrawal until it is thawed
+	///
 	/// Accounts expected:
 	///
 	/// 0. `[writable]` Whitelist account
@@ -52,6 +88,7 @@ pub enum WhitelistInstruction {
 	/// 4. `[]` Token program
 	/// 5. `[]` System program
 	/// 6. `[]` Assoc token program
+	/// 7. `[writable]` (Optional) Treasury PDA, when `treasury_is_pda` is set
 	InitialiseWhitelist {
 		treasury: Pubkey,
 		token_price: u64,
@@ -62,9 +99,14 @@ pub enum WhitelistInstruction {
 		registration_duration: i64,
 		sale_start_timestamp: i64,
 		sale_duration: i64,
+		max_tickets: u64,
+		refund_window_secs: u64,
+		authority_pays_rent: bool,
+		allow_free: bool,
+		treasury_is_pda: bool,
 	},
 
-	/// Adds a user to the whitelist
+	/// Adds a user to the whitelist, a user who already has a ticket is rejected
 	///
 	/// Accounts expected:
 	///
@@ -78,14 +120,21 @@ pub enum WhitelistInstruction {
 
 	/// Reclaims rent from an initialised `UserData` account
 	///
+	/// Tokens held by the ticket's token account are returned to the vault and the token
+	/// account is closed with its rent going to the authority, a ticket still holding tokens
+	/// once the vault has been closed cannot be removed
+	///
 	/// Accounts expected:
 	///
 	/// 0. `[writable]` Whitelist account
-	/// 1. `[writable]` Authority
-	/// 2. `[]` Token mint
-	/// 3. `[]` User account
-	/// 4. `[writable]` User whitelist account
-	/// 5. `[writable, signer]` Payer account
+	/// 1. `[writable, signer]` Authority
+	/// 2. `[writable]` Vault
+	/// 3. `[]` Token mint
+	/// 4. `[]` User account
+	/// 5. `[writable]` User whitelist account
+	/// 6. `[writable]` Ticket token account, may be uninitialised
+	/// 7. `[]` Token program
+	/// 8. `[]` System program
 	RemoveUser,
 
 	/// Permits the authority to change the whitelist size
@@ -104,6 +153,9 @@ pub enum WhitelistInstruction {
 	/// Note: This can only be called before the respective current start times.
 	/// Attempting to amend start times after they have already elapsed will result in an error
 	///
+	/// `allow_overlap`: registration scheduled to end after the sale starts is rejected unless
+	/// this is set
+	///
 	/// Accounts expected:
 	///
 	/// 0. `[writable]` Whitelist account
@@ -113,6 +165,7 @@ pub enum WhitelistInstruction {
 		registration_duration: Option<i64>,
 		sale_start_timestamp: Option<i64>,
 		sale_duration: Option<i64>,
+		allow_overlap: bool,
 	},
 
 	/// Allow users to register for the whitelist
@@ -132,19 +185,34 @@ pub enum WhitelistInstruction {
 	/// it can be turned off by calling `AllowRegister` and setting it to false
 	/// or setting `allow_registration` to false on initialisation of the whitelist
 	///
+	/// On a whitelist with `authority_pays_rent` set the authority must co-sign and pays the
+	/// ticket's rent, see `sponsored_register`
+	///
+	/// A user who already has a ticket is rejected, the existing ticket is left untouched
+	///
 	/// Accounts expected:
 	///
 	/// 0. `[writable]` Whitelist account
-	/// 1. `[]` Mint account
-	/// 2. `[writable, signer]` User account
-	/// 3. `[writable]` User whitelist account
-	/// 4. `[]` System program
+	/// 1. `[writable, signer]` User account
+	/// 2. `[writable]` User whitelist account
+	/// 3. `[]` System program
+	/// 4. `[]` User allocation account, may be uninitialised
+	/// 5. `[writable, signer]` (Optional) Authority, when `authority_pays_rent` is set
+	///
+	/// When the authority has set the user's allocation with `SetAllocation` the new ticket
+	/// takes the allocation's allowance rather than `buy_limit`
 	Register,
 
 	/// Allows a user to deregister from the whitelist and reclaim lamports used for rent
 	/// Note that this will only reclaim lamports for the user if they are the payer for
 	/// the account, else this will return the lamports to the authority
 	///
+	/// The user must sign, and can only unregister while registration is open and before the
+	/// sale starts. A ticket holding purchase SOL above its rent is rejected
+	///
+	/// Tokens held by the ticket's token account are returned to the vault and the token
+	/// account is closed with its rent going to the authority
+	///
 	/// Accounts expected:
 	///
 	/// 0. `[writable]` Whitelist account
@@ -153,12 +221,24 @@ pub enum WhitelistInstruction {
 	/// 3. `[]` Mint account
 	/// 4. `[writable, signer]` User account
 	/// 5. `[writable]` User whitelist account
-	/// 6. `[]` Token program
-	/// 7. `[]` System program
+	/// 6. `[writable]` Ticket token account, may be uninitialised
+	/// 7. `[]` Token program
+	/// 8. `[]` System program
 	Unregister,
 
 	/// Buy tokens
 	///
+	/// `max_price`: the highest `token_price` the buyer accepts, usually the price they were
+	/// quoted. The purchase fails if the price has since been raised above it
+	///
+	/// `allow_partial`: when the vault holds less than `amount`, buy what it holds instead of
+	/// failing with `InsufficientFunds`. Only the tokens delivered are paid for and the filled
+	/// amount is logged
+	///
+	/// A purchase failing several checks reports the first of, in order: `SalePaused`,
+	/// `SaleNotStarted` / `SaleEnded`, `VaultFrozen`, `Cooldown`, `BuyLimitExceeded`,
+	/// `InsufficientFunds` and `InsufficientSol`
+	///
 	/// Accounts expected:
 	///
 	/// 0. `[]` Whitelist account
@@ -171,7 +251,11 @@ pub enum WhitelistInstruction {
 	/// 7. `[]` Token program
 	/// 8. `[]` System program
 	/// 9. `[]` Associated token account program
-	Buy { amount: u64 },
+	Buy {
+		amount: u64,
+		max_price: Option<u64>,
+		allow_partial: bool,
+	},
 
 	/// Deposits tokens into the vault
 	///
@@ -200,13 +284,16 @@ pub enum WhitelistInstruction {
 	/// Warning: This instruction executes even if a `sale_start_time` is provided and will set
 	/// the corresponding field in the program state to `None`. If used before registration has
 	/// commenced, this will also set the corresponding field to `None`.
+	/// If `require_fully_funded` is set the sale will only start if the vault holds enough
+	/// tokens to fill every ticket, or at least a single ticket when the whitelist size is
+	/// unbounded.
 	///
 	/// Accounts expected:
 	///
 	/// 0. `[writable]` Whitelist account
 	/// 1. `[writable, signer]` Authority
 	/// 2. `[]` Token vault
-	StartTokenSale,
+	StartTokenSale { require_fully_funded: bool },
 
 	/// Transfers tokens to Ticket PDA
 	/// This instruction transfers tokens to the ticket PDA before the token sale commences.
@@ -250,7 +337,7 @@ pub enum WhitelistInstruction {
 	///
 	/// Accounts expected:
 	///
-	/// 0. `[]` Whitelist account
+	/// 0. `[writable]` Whitelist account
 	/// 1. `[writable, signer]` Authority
 	/// 2. `[]` Mint
 	/// 3. `[writable]` Treasury
@@ -281,8 +368,382 @@ pub enum WhitelistInstruction {
 	/// 5. `[writable]` Recipient token account
 	/// 6. `[]` Token program
 	/// 7. `[]` System program
-	/// 8. `[]` Associated token account program
+	/// 8. `[]` (Optional) Treasury PDA, required when it is the whitelist's treasury
+	///
+	/// A vault already closed with `CloseVault` is skipped, only the whitelist is closed
+	///
+	/// A whitelist whose treasury is its PDA cannot be terminated until the proceeds have been
+	/// taken out with `WithdrawTreasury`, nothing could authorise moving them once it is closed
 	TerminateWhitelist,
+
+	/// Migrates a whitelist account created with an older layout to the current layout
+	/// The account is reallocated to `Whitelist::LEN`, the authority funds the additional rent
+	/// and any fields that did not previously exist are set to their defaults. This can only
+	/// be executed once per whitelist.
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account
+	/// 1. `[writable, signer]` Authority
+	/// 2. `[]` System program
+	MigrateWhitelist,
+
+	/// Sets an informational note on a ticket, e.g. "partner allocation"
+	/// The note has no effect on registration or the token sale.
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[]` Whitelist account
+	/// 1. `[signer]` Authority
+	/// 2. `[writable]` Ticket account
+	SetTicketNote { note: [u8; 32] },
+
+	/// Deposits tokens into the vault and credits them to the depositor's ledger, allowing a
+	/// third party to supply tokens and reclaim them with `WithdrawDeposit` before the sale
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account
+	/// 1. `[writable]` Token vault
+	/// 2. `[writable, signer]` Depositor account
+	/// 3. `[writable]` Depositor token account
+	/// 4. `[]` Token mint
+	/// 5. `[]` Token program
+	/// 6. `[writable]` Depositor ledger account
+	/// 7. `[]` System program
+	DepositFor { amount: u64 },
+
+	/// Returns tokens credited by `DepositFor` from the vault to the depositor, only permitted
+	/// before the sale starts. The ledger is closed once the full deposit has been reclaimed
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account
+	/// 1. `[writable]` Token vault
+	/// 2. `[writable, signer]` Depositor account
+	/// 3. `[writable]` Depositor token account
+	/// 4. `[]` Token mint
+	/// 5. `[]` Token program
+	/// 6. `[writable]` Depositor ledger account
+	/// 7. `[]` System program
+	WithdrawDeposit { amount: u64 },
+
+	/// Pauses or resumes registration or buying independently of the other, the registration
+	/// and sale windows keep running while paused
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account
+	/// 1. `[signer]` Authority
+	SetPaused { target: PauseTarget, paused: bool },
+
+	/// Registers like `Register` and records `referrer` on the new ticket. The referrer's own
+	/// ticket is credited the whitelist's referral bonus as extra allowance, only when a new
+	/// ticket is opened
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account
+	/// 1. `[writable, signer]` User account
+	/// 2. `[writable]` User ticket account
+	/// 3. `[]` System program
+	/// 4. `[writable]` Referrer's ticket account
+	/// 5. `[]` User allocation account, may be uninitialised
+	/// 6. `[writable, signer]` (Optional) Authority, when `authority_pays_rent` is set
+	///
+	/// Honours the user's allocation like `Register`
+	RegisterWithReferral { referrer: Pubkey },
+
+	/// Sets the extra allowance, in whole tokens, credited to a referrer for each user who
+	/// registers with `RegisterWithReferral`, 0 disables the bonus
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account
+	/// 1. `[signer]` Authority
+	/// 2. `[]` Token mint
+	SetReferralBonus { referral_bonus: u64 },
+
+	/// Adds a user like `AddUser` and seeds the new ticket's token account with `buy_limit`
+	/// tokens from the vault, creating the token account if needed. A ticket token account that
+	/// already holds tokens is only topped up to `buy_limit`
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account
+	/// 1. `[writable, signer]` Authority
+	/// 2. `[]` Token mint
+	/// 3. `[]` User account
+	/// 4. `[writable]` User ticket account
+	/// 5. `[]` System program
+	/// 6. `[writable]` Token vault
+	/// 7. `[writable]` Ticket token account
+	/// 8. `[]` Token program
+	/// 9. `[]` Assoc token program
+	AddUserAndSeed,
+
+	/// Closes a ticket and sends its rent to the treasury without touching the rest of the sale,
+	/// including tickets left behind after their whitelist was terminated. The ticket must be the
+	/// PDA of its owner on the whitelist derived from the mint, and its token account must not
+	/// hold any tokens. An empty ticket token account is closed as well.
+	///
+	/// While the whitelist is live the whitelist authority must sign and the treasury must be the
+	/// whitelist's treasury. Once the whitelist is closed its authority can no longer be read, so
+	/// the ticket's payer must sign instead, which is the authority for tickets it added. The
+	/// payer is only owed the rent, so a ticket still holding purchase SOL above it is rejected.
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account, may be closed
+	/// 1. `[signer]` Authority, or the ticket payer once the whitelist is closed
+	/// 2. `[]` Token mint
+	/// 3. `[writable]` Treasury
+	/// 4. `[writable]` Ticket account
+	/// 5. `[writable]` Ticket token account, may be uninitialised
+	/// 6. `[]` Token program
+	ForceCloseTicket,
+
+	/// Closes the owner's empty token account for a sale's mint and returns its rent to the
+	/// owner, typically the account `Buy` created once its tokens have been moved on. The
+	/// account must be the owner's ATA for the mint of the whitelist, which may already be
+	/// closed, and must not hold any tokens
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[]` Whitelist account, may be closed
+	/// 1. `[writable, signer]` Owner
+	/// 2. `[]` Token mint
+	/// 3. `[writable]` Owner token account
+	/// 4. `[]` Token program
+	CleanupTokenAccount,
+
+	/// Sets the seconds a ticket must wait after a purchase before `Buy` accepts another from
+	/// it, 0 disables the cooldown. A ticket's first purchase is never held back
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account
+	/// 1. `[signer]` Authority
+	SetBuyCooldown { cooldown_secs: u64 },
+
+	/// Returns `amount` whole tokens bought from the owner's token account to the vault and
+	/// pays their current price back to the owner from the SOL held by the ticket. Only permitted
+	/// within the whitelist's `refund_window_secs` of the ticket's latest purchase, the refunded
+	/// amount may be bought again
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[]` Whitelist account
+	/// 1. `[writable]` Token vault
+	/// 2. `[]` Token mint
+	/// 3. `[writable, signer]` Owner
+	/// 4. `[writable]` Ticket account
+	/// 5. `[writable]` Owner token account
+	/// 6. `[]` Token program
+	Refund { amount: u64 },
+
+	/// Moves the rent of a ticket funded by someone else, typically the authority through
+	/// `AddUser`, onto its owner. The owner pays the ticket's rent to the recorded payer and
+	/// becomes the payer, so the rent is returned to the owner when the ticket is closed
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[]` Whitelist account
+	/// 1. `[writable, signer]` Owner
+	/// 2. `[writable]` Ticket account
+	/// 3. `[writable]` Current payer of the ticket
+	/// 4. `[]` System program
+	ClaimTicketRent,
+
+	/// Reserves an allowance, in whole tokens, for `user` ahead of registration. A ticket the
+	/// user opens with `Register` afterwards takes this allowance instead of `buy_limit`, calling
+	/// it again overwrites the allocation. Tickets already open are unaffected
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[]` Whitelist account
+	/// 1. `[writable, signer]` Authority
+	/// 2. `[]` Token mint
+	/// 3. `[]` User account
+	/// 4. `[writable]` User allocation account
+	/// 5. `[]` System program
+	SetAllocation { allowance: u64 },
+
+	/// Sets the whole schedule at once: registration opens at `registration_start` and closes
+	/// as the sale opens at `sale_start`, which runs until `sale_end`. The durations are derived
+	/// here rather than by the client, and the timestamps must be in the future and in that
+	/// order. Rejected once registration or the sale has started, like `AmendTimes`
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account
+	/// 1. `[signer]` Authority
+	SetSchedule {
+		registration_start: i64,
+		sale_start: i64,
+		sale_end: i64,
+	},
+
+	/// Closes a batch of tickets once the sale has ended and sends their rent to the treasury,
+	/// a lighter alternative to `BurnTicket` for tickets that never held any tokens. Each ticket
+	/// is passed with its token account, which is closed as well when empty. Tickets whose token
+	/// account still holds tokens are skipped and left for `BurnTicket`
+	///
+	/// While the whitelist is live the whitelist authority must sign, the treasury must be the
+	/// whitelist's treasury and the sale must have ended. Once the whitelist is closed the signer
+	/// must be the payer of every ticket, like `ForceCloseTicket`, and tickets still holding
+	/// purchase SOL above their rent are skipped
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account, may be closed
+	/// 1. `[signer]` Authority, or the ticket payer once the whitelist is closed
+	/// 2. `[]` Token mint
+	/// 3. `[writable]` Treasury
+	/// 4. `[]` Token program
+	/// 5. `[writable]` First ticket account
+	/// 6. `[writable]` First ticket token account, may be uninitialised
+	/// ... followed by further ticket and ticket token account pairs
+	CloseTickets,
+
+	/// Moves `amount` lamports to the treasury from a ticket, where `Buy` pays the SOL for each
+	/// purchase, or from the whitelist account itself when no ticket is passed. The source keeps
+	/// its rent-exempt minimum so the ticket stays open. A ticket whose latest purchase can still
+	/// be refunded is rejected, the refund is paid from that SOL
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account
+	/// 1. `[signer]` Authority
+	/// 2. `[writable]` Treasury
+	/// 3. `[writable]` (Optional) Ticket account to withdraw from
+	WithdrawSol { amount: u64 },
+
+	/// Gates registration behind vouchers signed by `register_authority`, e.g. a backend key.
+	/// Once set, `Register` and `RegisterWithReferral` are rejected and users register with
+	/// `RegisterWithSignature` instead. `None` opens registration again
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account
+	/// 1. `[signer]` Authority
+	SetRegisterAuthority { register_authority: Option<Pubkey> },
+
+	/// Registers the user like `Register` on presenting a voucher from the whitelist's register
+	/// authority. The voucher is an Ed25519 signature over
+	/// `register_voucher_message(user, whitelist, nonce)`, checked by an Ed25519 program
+	/// instruction placed immediately before this one, see `register_voucher`. The signature
+	/// itself is verified by the runtime, the program checks the instruction was signed by the
+	/// register authority over this user, whitelist and `nonce`
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account
+	/// 1. `[writable, signer]` User account
+	/// 2. `[writable]` User ticket account
+	/// 3. `[]` System program
+	/// 4. `[]` Instructions sysvar
+	/// 5. `[]` User allocation account, may be uninitialised
+	/// 6. `[writable, signer]` (Optional) Authority, when `authority_pays_rent` is set
+	///
+	/// Honours the user's allocation like `Register`
+	RegisterWithSignature { nonce: u64 },
+
+	/// Moves `amount` lamports out of the program owned treasury of a whitelist initialised with
+	/// `treasury_is_pda` to `recipient`. Only the authority can withdraw, and the treasury keeps
+	/// its rent-exempt minimum
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[]` Whitelist account
+	/// 1. `[signer]` Authority
+	/// 2. `[writable]` Treasury PDA
+	/// 3. `[writable]` Recipient
+	WithdrawTreasury { amount: u64 },
+
+	/// Hands the whitelist to `new_authority`, after which only it can run authority
+	/// instructions. The default pubkey is rejected as nobody could sign for it
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account
+	/// 1. `[signer]` Current authority
+	ChangeAuthority { new_authority: Pubkey },
+
+	/// Permits the authority to change `token_price`, the price of one whole token in lamports.
+	/// Rejected once the sale has started so buyers are never charged a price other than the one
+	/// they saw, and a price of `0` is rejected like at initialisation
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account
+	/// 1. `[signer]` Authority
+	UpdatePrice { token_price: u64 },
+
+	/// Winds down the token side of a whitelist once its sale has ended while keeping the
+	/// whitelist account, e.g. for record-keeping. Any tokens left in the vault are sent to the
+	/// recipient token account and the vault is closed with its rent going to the authority.
+	/// The whitelist's `vault` is cleared and `deposited` reset, re-running `InitialiseWhitelist`
+	/// creates a new vault
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account
+	/// 1. `[writable, signer]` Authority
+	/// 2. `[writable]` Token vault
+	/// 3. `[]` Token mint
+	/// 4. `[writable]` Recipient token account
+	/// 5. `[]` Token program
+	CloseVault,
+
+	/// Permits the authority to change `buy_limit`, given in whole tokens like at initialisation.
+	/// Only tickets registered afterwards receive the new limit, existing tickets keep their
+	/// allowance until they are passed to `SyncTicketAllowance`
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account
+	/// 1. `[signer]` Authority
+	/// 2. `[]` Token mint
+	UpdateBuyLimit { buy_limit: u64 },
+
+	/// Raises a ticket's allowance to the whitelist's current `buy_limit`, leaving
+	/// `amount_bought` as is. An allowance already at or above the limit, e.g. from an allocation
+	/// or referral bonuses, is left unchanged
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[]` Whitelist account
+	/// 1. `[signer]` Authority
+	/// 2. `[writable]` Ticket account
+	SyncTicketAllowance,
+
+	/// Adds each of `users` to the whitelist like `AddUser`, at most `MAX_BATCH_USERS` at a time.
+	/// Every user must be passed, in the same order, as a user and ticket account pair
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` Whitelist account
+	/// 1. `[writable, signer]` Authority
+	/// 2. `[]` Token mint
+	/// 3. `[]` System program
+	/// 4. `[]` First user account
+	/// 5. `[writable]` First user whitelist account
+	/// ... followed by further user and user whitelist account pairs
+	BatchAddUser { users: Vec<Pubkey> },
+
+	/// Migrates a ticket created before tickets carried a `version` to the current layout, like
+	/// `MigrateWhitelist`. The account is reallocated to `Ticket::LEN` with the payer funding the
+	/// additional rent, which stays with the ticket. Purchases are carried over as claimed and
+	/// the new fields are set to their defaults. Anyone may migrate a ticket as its data is
+	/// unchanged, and each ticket can only be migrated once
+	///
+	/// Accounts expected:
+	///
+	/// 0. `[writable]` User whitelist account
+	/// 1. `[writable, signer]` Payer
+	/// 2. `[]` System program
+	MigrateTicket,
 }
 
 /// Creates an 'InitialiseWhitelist' instruction
@@ -300,9 +761,14 @@ pub fn init_whitelist(
 	registration_duration: i64,
 	sale_start_timestamp: i64,
 	sale_duration: i64,
+	max_tickets: u64,
+	refund_window_secs: u64,
+	authority_pays_rent: bool,
+	allow_free: bool,
+	treasury_is_pda: bool,
 	token_program: &Pubkey,
 ) -> Result<Instruction, ProgramError> {
-	let mut accounts = Vec::with_capacity(7);
+	let mut accounts = Vec::with_capacity(account_order::initialise_whitelist::ACCOUNTS + 1);
 
 	accounts.push(AccountMeta::new(*whitelist, false));
 	accounts.push(AccountMeta::new(*authority, true));
@@ -311,10 +777,14 @@ pub fn init_whitelist(
 	accounts.push(AccountMeta::new_readonly(*token_program, false));
 	accounts.push(AccountMeta::new_readonly(system_program::id(), false));
 	accounts.push(AccountMeta::new_readonly(
-		spl_associated_token_account::id(),
+		account_order::ASSOCIATED_TOKEN_PROGRAM_ID,
 		false,
 	));
 
+	if treasury_is_pda {
+		accounts.push(AccountMeta::new(*treasury, false));
+	}
+
 	Ok(Instruction::new_with_borsh(
 		crate::id(),
 		&WhitelistInstruction::InitialiseWhitelist {
@@ -327,6 +797,11 @@ pub fn init_whitelist(
 			registration_duration,
 			sale_start_timestamp,
 			sale_duration,
+			max_tickets,
+			refund_window_secs,
+			authority_pays_rent,
+			allow_free,
+			treasury_is_pda,
 		},
 		accounts,
 	))
@@ -339,9 +814,9 @@ pub fn add_user(
 	user: &Pubkey,
 	user_ticket: &Pubkey,
 ) -> Result<Instruction, ProgramError> {
-	let mut accounts = Vec::with_capacity(6);
+	let mut accounts = Vec::with_capacity(account_order::add_user::ACCOUNTS);
 
-	accounts.push(AccountMeta::new_readonly(*whitelist, false));
+	accounts.push(AccountMeta::new(*whitelist, false));
 	accounts.push(AccountMeta::new(*authority, true));
 	accounts.push(AccountMeta::new_readonly(*mint, false));
 	accounts.push(AccountMeta::new_readonly(*user, false));
@@ -358,17 +833,23 @@ pub fn add_user(
 pub fn remove_user(
 	whitelist: &Pubkey,
 	authority: &Pubkey,
+	vault: &Pubkey,
 	mint: &Pubkey,
 	user: &Pubkey,
 	user_ticket: &Pubkey,
+	ticket_token_account: &Pubkey,
+	token_program: &Pubkey,
 ) -> Result<Instruction, ProgramError> {
-	let mut accounts = Vec::with_capacity(6);
+	let mut accounts = Vec::with_capacity(account_order::remove_user::ACCOUNTS);
 
 	accounts.push(AccountMeta::new(*whitelist, false));
 	accounts.push(AccountMeta::new(*authority, true));
+	accounts.push(AccountMeta::new(*vault, false));
 	accounts.push(AccountMeta::new_readonly(*mint, false));
 	accounts.push(AccountMeta::new_readonly(*user, false));
 	accounts.push(AccountMeta::new(*user_ticket, false));
+	accounts.push(AccountMeta::new(*ticket_token_account, false));
+	accounts.push(AccountMeta::new_readonly(*token_program, false));
 	accounts.push(AccountMeta::new_readonly(system_program::id(), false));
 
 	Ok(Instruction::new_with_borsh(
@@ -387,9 +868,11 @@ pub fn buy_tokens(
 	ticket_token_account: &Pubkey,
 	user_token_account: &Pubkey,
 	amount: u64,
+	max_price: Option<u64>,
+	allow_partial: bool,
 	token_program: &Pubkey,
 ) -> Result<Instruction, ProgramError> {
-	let mut accounts = Vec::with_capacity(10);
+	let mut accounts = Vec::with_capacity(account_order::buy::ACCOUNTS);
 
 	accounts.push(AccountMeta::new_readonly(*whitelist, false));
 	accounts.push(AccountMeta::new(*vault, false));
@@ -401,13 +884,17 @@ pub fn buy_tokens(
 	accounts.push(AccountMeta::new_readonly(*token_program, false));
 	accounts.push(AccountMeta::new_readonly(system_program::id(), false));
 	accounts.push(AccountMeta::new_readonly(
-		spl_associated_token_account::id(),
+		account_order::ASSOCIATED_TOKEN_PROGRAM_ID,
 		false,
 	));
 
 	Ok(Instruction::new_with_borsh(
 		crate::id(),
-		&WhitelistInstruction::Buy { amount },
+		&WhitelistInstruction::Buy {
+			amount,
+			max_price,
+			allow_partial,
+		},
 		accounts,
 	))
 }
@@ -417,7 +904,7 @@ pub fn amend_whitelist_size(
 	authority: &Pubkey,
 	size: u64,
 ) -> Result<Instruction, ProgramError> {
-	let mut accounts = Vec::with_capacity(2);
+	let mut accounts = Vec::with_capacity(account_order::amend_whitelist_size::ACCOUNTS);
 
 	accounts.push(AccountMeta::new(*whitelist, false));
 	accounts.push(AccountMeta::new(*authority, true));
@@ -436,8 +923,9 @@ pub fn amend_times(
 	registration_duration: Option<i64>,
 	sale_start_timestamp: Option<i64>,
 	sale_duration: Option<i64>,
+	allow_overlap: bool,
 ) -> Result<Instruction, ProgramError> {
-	let mut accounts = Vec::with_capacity(2);
+	let mut accounts = Vec::with_capacity(account_order::amend_times::ACCOUNTS);
 
 	accounts.push(AccountMeta::new(*whitelist, false));
 	accounts.push(AccountMeta::new(*authority, true));
@@ -449,6 +937,7 @@ pub fn amend_times(
 			registration_duration,
 			sale_start_timestamp,
 			sale_duration,
+			allow_overlap,
 		},
 		accounts,
 	))
@@ -459,7 +948,7 @@ pub fn allow_registration(
 	authority: &Pubkey,
 	allow_registration: bool,
 ) -> Result<Instruction, ProgramError> {
-	let mut accounts = Vec::with_capacity(2);
+	let mut accounts = Vec::with_capacity(account_order::allow_register::ACCOUNTS);
 
 	accounts.push(AccountMeta::new(*whitelist, false));
 	accounts.push(AccountMeta::new(*authority, true));
@@ -475,13 +964,15 @@ pub fn register(
 	whitelist: &Pubkey,
 	user: &Pubkey,
 	user_ticket: &Pubkey,
+	allocation: &Pubkey,
 ) -> Result<Instruction, ProgramError> {
-	let mut accounts = Vec::with_capacity(4);
+	let mut accounts = Vec::with_capacity(account_order::register::ACCOUNTS);
 
 	accounts.push(AccountMeta::new(*whitelist, false));
 	accounts.push(AccountMeta::new(*user, true));
 	accounts.push(AccountMeta::new(*user_ticket, false));
 	accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+	accounts.push(AccountMeta::new_readonly(*allocation, false));
 
 	Ok(Instruction::new_with_borsh(
 		crate::id(),
@@ -490,6 +981,22 @@ pub fn register(
 	))
 }
 
+/// Creates a `Register` instruction for a whitelist with `authority_pays_rent` set, the authority
+/// co-signs and pays the new ticket's rent
+pub fn sponsored_register(
+	whitelist: &Pubkey,
+	user: &Pubkey,
+	user_ticket: &Pubkey,
+	allocation: &Pubkey,
+	authority: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+	let mut instruction = register(whitelist, user, user_ticket, allocation)?;
+	instruction
+		.accounts
+		.push(AccountMeta::new(*authority, true));
+	Ok(instruction)
+}
+
 pub fn unregister(
 	whitelist: &Pubkey,
 	authority: &Pubkey,
@@ -500,10 +1007,10 @@ pub fn unregister(
 	ticket_token_account: &Pubkey,
 	token_program: &Pubkey,
 ) -> Result<Instruction, ProgramError> {
-	let mut accounts = Vec::with_capacity(9);
+	let mut accounts = Vec::with_capacity(account_order::unregister::ACCOUNTS);
 
 	accounts.push(AccountMeta::new(*whitelist, false));
-	accounts.push(AccountMeta::new_readonly(*authority, false));
+	accounts.push(AccountMeta::new(*authority, false));
 	accounts.push(AccountMeta::new(*vault, false));
 	accounts.push(AccountMeta::new_readonly(*mint, false));
 	accounts.push(AccountMeta::new(*user, true));
@@ -528,7 +1035,7 @@ pub fn deposit_tokens(
 	amount: u64,
 	token_program: &Pubkey,
 ) -> Result<Instruction, ProgramError> {
-	let mut accounts = Vec::with_capacity(6);
+	let mut accounts = Vec::with_capacity(account_order::deposit_tokens::ACCOUNTS);
 
 	accounts.push(AccountMeta::new(*whitelist, false));
 	accounts.push(AccountMeta::new(*vault, false));
@@ -548,7 +1055,7 @@ pub fn start_registration(
 	whitelist: &Pubkey,
 	authority: &Pubkey,
 ) -> Result<Instruction, ProgramError> {
-	let mut accounts = Vec::with_capacity(2);
+	let mut accounts = Vec::with_capacity(account_order::start_registration::ACCOUNTS);
 
 	accounts.push(AccountMeta::new(*whitelist, false));
 	accounts.push(AccountMeta::new(*authority, true));
@@ -563,15 +1070,20 @@ pub fn start_registration(
 pub fn start_token_sale(
 	whitelist: &Pubkey,
 	authority: &Pubkey,
+	vault: &Pubkey,
+	require_fully_funded: bool,
 ) -> Result<Instruction, ProgramError> {
-	let mut accounts = Vec::with_capacity(2);
+	let mut accounts = Vec::with_capacity(account_order::start_token_sale::ACCOUNTS);
 
 	accounts.push(AccountMeta::new(*whitelist, false));
 	accounts.push(AccountMeta::new(*authority, true));
+	accounts.push(AccountMeta::new_readonly(*vault, false));
 
 	Ok(Instruction::new_with_borsh(
 		crate::id(),
-		&WhitelistInstruction::StartTokenSale,
+		&WhitelistInstruction::StartTokenSale {
+			require_fully_funded,
+		},
 		accounts,
 	))
 }
@@ -586,7 +1098,7 @@ pub fn transfer_tokens(
 	ticket_token_account: &Pubkey,
 	token_program: &Pubkey,
 ) -> Result<Instruction, ProgramError> {
-	let mut accounts = Vec::with_capacity(10);
+	let mut accounts = Vec::with_capacity(account_order::transfer_tokens::ACCOUNTS);
 
 	accounts.push(AccountMeta::new_readonly(*whitelist, false));
 	accounts.push(AccountMeta::new(*authority, true));
@@ -598,7 +1110,7 @@ pub fn transfer_tokens(
 	accounts.push(AccountMeta::new_readonly(*token_program, false));
 	accounts.push(AccountMeta::new_readonly(system_program::id(), false));
 	accounts.push(AccountMeta::new_readonly(
-		spl_associated_token_account::id(),
+		account_order::ASSOCIATED_TOKEN_PROGRAM_ID,
 		false,
 	));
 
@@ -618,7 +1130,7 @@ pub fn withdraw_tokens(
 	amount: u64,
 	token_program: &Pubkey,
 ) -> Result<Instruction, ProgramError> {
-	let mut accounts = Vec::with_capacity(6);
+	let mut accounts = Vec::with_capacity(account_order::withdraw_tokens::ACCOUNTS);
 
 	accounts.push(AccountMeta::new_readonly(*whitelist, false));
 	accounts.push(AccountMeta::new(*authority, true));
@@ -644,9 +1156,9 @@ pub fn burn_ticket(
 	ticket_token_account: &Pubkey,
 	token_program: &Pubkey,
 ) -> Result<Instruction, ProgramError> {
-	let mut accounts = Vec::with_capacity(10);
+	let mut accounts = Vec::with_capacity(account_order::burn_ticket::ACCOUNTS);
 
-	accounts.push(AccountMeta::new_readonly(*whitelist, false));
+	accounts.push(AccountMeta::new(*whitelist, false));
 	accounts.push(AccountMeta::new(*authority, true));
 	accounts.push(AccountMeta::new_readonly(*mint, false));
 	accounts.push(AccountMeta::new(*treasury, false));
@@ -656,7 +1168,7 @@ pub fn burn_ticket(
 	accounts.push(AccountMeta::new_readonly(*token_program, false));
 	accounts.push(AccountMeta::new_readonly(system_program::id(), false));
 	accounts.push(AccountMeta::new_readonly(
-		spl_associated_token_account::id(),
+		account_order::ASSOCIATED_TOKEN_PROGRAM_ID,
 		false,
 	));
 	Ok(Instruction::new_with_borsh(
@@ -675,7 +1187,7 @@ pub fn terminate_whitelist(
 	recipient_token_account: &Pubkey,
 	token_program: &Pubkey,
 ) -> Result<Instruction, ProgramError> {
-	let mut accounts = Vec::with_capacity(8);
+	let mut accounts = Vec::with_capacity(account_order::terminate_whitelist::ACCOUNTS);
 
 	accounts.push(AccountMeta::new(*whitelist, false));
 	accounts.push(AccountMeta::new(*authority, true));
@@ -692,3 +1204,699 @@ pub fn terminate_whitelist(
 		accounts,
 	))
 }
+
+pub fn migrate_whitelist(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::migrate_whitelist::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new(*authority, true));
+	accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::MigrateWhitelist,
+		accounts,
+	))
+}
+
+pub fn set_ticket_note(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	user_ticket: &Pubkey,
+	note: &str,
+) -> Result<Instruction, ProgramError> {
+	let note = crate::state::Ticket::encode_note(note)?;
+	let mut accounts = Vec::with_capacity(account_order::set_ticket_note::ACCOUNTS);
+
+	accounts.push(AccountMeta::new_readonly(*whitelist, false));
+	accounts.push(AccountMeta::new_readonly(*authority, true));
+	accounts.push(AccountMeta::new(*user_ticket, false));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::SetTicketNote { note },
+		accounts,
+	))
+}
+
+pub fn deposit_for(
+	whitelist: &Pubkey,
+	vault: &Pubkey,
+	depositor_key: &Pubkey,
+	depositor_token_account_key: &Pubkey,
+	mint: &Pubkey,
+	depositor_ledger: &Pubkey,
+	amount: u64,
+	token_program: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::deposit_for::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new(*vault, false));
+	accounts.push(AccountMeta::new(*depositor_key, true));
+	accounts.push(AccountMeta::new(*depositor_token_account_key, false));
+	accounts.push(AccountMeta::new_readonly(*mint, false));
+	accounts.push(AccountMeta::new_readonly(*token_program, false));
+	accounts.push(AccountMeta::new(*depositor_ledger, false));
+	accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::DepositFor { amount },
+		accounts,
+	))
+}
+
+pub fn withdraw_deposit(
+	whitelist: &Pubkey,
+	vault: &Pubkey,
+	depositor_key: &Pubkey,
+	depositor_token_account_key: &Pubkey,
+	mint: &Pubkey,
+	depositor_ledger: &Pubkey,
+	amount: u64,
+	token_program: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::withdraw_deposit::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new(*vault, false));
+	accounts.push(AccountMeta::new(*depositor_key, true));
+	accounts.push(AccountMeta::new(*depositor_token_account_key, false));
+	accounts.push(AccountMeta::new_readonly(*mint, false));
+	accounts.push(AccountMeta::new_readonly(*token_program, false));
+	accounts.push(AccountMeta::new(*depositor_ledger, false));
+	accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::WithdrawDeposit { amount },
+		accounts,
+	))
+}
+
+pub fn set_paused(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	target: PauseTarget,
+	paused: bool,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::set_paused::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new_readonly(*authority, true));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::SetPaused { target, paused },
+		accounts,
+	))
+}
+
+pub fn register_with_referral(
+	whitelist: &Pubkey,
+	user: &Pubkey,
+	user_ticket: &Pubkey,
+	referrer: &Pubkey,
+	referrer_ticket: &Pubkey,
+	allocation: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::register_with_referral::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new(*user, true));
+	accounts.push(AccountMeta::new(*user_ticket, false));
+	accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+	accounts.push(AccountMeta::new(*referrer_ticket, false));
+	accounts.push(AccountMeta::new_readonly(*allocation, false));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::RegisterWithReferral {
+			referrer: *referrer,
+		},
+		accounts,
+	))
+}
+
+/// Creates a `RegisterWithReferral` instruction for a whitelist with `authority_pays_rent` set,
+/// the authority co-signs and pays the new ticket's rent
+pub fn sponsored_register_with_referral(
+	whitelist: &Pubkey,
+	user: &Pubkey,
+	user_ticket: &Pubkey,
+	referrer: &Pubkey,
+	referrer_ticket: &Pubkey,
+	allocation: &Pubkey,
+	authority: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+	let mut instruction = register_with_referral(
+		whitelist,
+		user,
+		user_ticket,
+		referrer,
+		referrer_ticket,
+		allocation,
+	)?;
+	instruction
+		.accounts
+		.push(AccountMeta::new(*authority, true));
+	Ok(instruction)
+}
+
+pub fn set_referral_bonus(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	mint: &Pubkey,
+	referral_bonus: u64,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::set_referral_bonus::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new_readonly(*authority, true));
+	accounts.push(AccountMeta::new_readonly(*mint, false));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::SetReferralBonus { referral_bonus },
+		accounts,
+	))
+}
+
+pub fn add_user_and_seed(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	mint: &Pubkey,
+	user: &Pubkey,
+	user_ticket: &Pubkey,
+	vault: &Pubkey,
+	ticket_token_account: &Pubkey,
+	token_program: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::add_user_and_seed::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new(*authority, true));
+	accounts.push(AccountMeta::new_readonly(*mint, false));
+	accounts.push(AccountMeta::new_readonly(*user, false));
+	accounts.push(AccountMeta::new(*user_ticket, false));
+	accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+	accounts.push(AccountMeta::new(*vault, false));
+	accounts.push(AccountMeta::new(*ticket_token_account, false));
+	accounts.push(AccountMeta::new_readonly(*token_program, false));
+	accounts.push(AccountMeta::new_readonly(
+		account_order::ASSOCIATED_TOKEN_PROGRAM_ID,
+		false,
+	));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::AddUserAndSeed,
+		accounts,
+	))
+}
+
+pub fn force_close_ticket(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	mint: &Pubkey,
+	treasury: &Pubkey,
+	ticket: &Pubkey,
+	ticket_token_account: &Pubkey,
+	token_program: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::force_close_ticket::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new_readonly(*authority, true));
+	accounts.push(AccountMeta::new_readonly(*mint, false));
+	accounts.push(AccountMeta::new(*treasury, false));
+	accounts.push(AccountMeta::new(*ticket, false));
+	accounts.push(AccountMeta::new(*ticket_token_account, false));
+	accounts.push(AccountMeta::new_readonly(*token_program, false));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::ForceCloseTicket,
+		accounts,
+	))
+}
+
+pub fn cleanup_token_account(
+	whitelist: &Pubkey,
+	owner: &Pubkey,
+	mint: &Pubkey,
+	token_account: &Pubkey,
+	token_program: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::cleanup_token_account::ACCOUNTS);
+
+	accounts.push(AccountMeta::new_readonly(*whitelist, false));
+	accounts.push(AccountMeta::new(*owner, true));
+	accounts.push(AccountMeta::new_readonly(*mint, false));
+	accounts.push(AccountMeta::new(*token_account, false));
+	accounts.push(AccountMeta::new_readonly(*token_program, false));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::CleanupTokenAccount,
+		accounts,
+	))
+}
+
+pub fn set_buy_cooldown(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	cooldown_secs: u64,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::set_buy_cooldown::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new_readonly(*authority, true));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::SetBuyCooldown { cooldown_secs },
+		accounts,
+	))
+}
+
+pub fn refund(
+	whitelist: &Pubkey,
+	vault: &Pubkey,
+	mint: &Pubkey,
+	owner: &Pubkey,
+	ticket: &Pubkey,
+	owner_token_account: &Pubkey,
+	amount: u64,
+	token_program: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::refund::ACCOUNTS);
+
+	accounts.push(AccountMeta::new_readonly(*whitelist, false));
+	accounts.push(AccountMeta::new(*vault, false));
+	accounts.push(AccountMeta::new_readonly(*mint, false));
+	accounts.push(AccountMeta::new(*owner, true));
+	accounts.push(AccountMeta::new(*ticket, false));
+	accounts.push(AccountMeta::new(*owner_token_account, false));
+	accounts.push(AccountMeta::new_readonly(*token_program, false));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::Refund { amount },
+		accounts,
+	))
+}
+
+pub fn claim_ticket_rent(
+	whitelist: &Pubkey,
+	owner: &Pubkey,
+	ticket: &Pubkey,
+	payer: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::claim_ticket_rent::ACCOUNTS);
+
+	accounts.push(AccountMeta::new_readonly(*whitelist, false));
+	accounts.push(AccountMeta::new(*owner, true));
+	accounts.push(AccountMeta::new(*ticket, false));
+	accounts.push(AccountMeta::new(*payer, false));
+	accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::ClaimTicketRent,
+		accounts,
+	))
+}
+
+pub fn set_allocation(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	mint: &Pubkey,
+	user: &Pubkey,
+	allocation: &Pubkey,
+	allowance: u64,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::set_allocation::ACCOUNTS);
+
+	accounts.push(AccountMeta::new_readonly(*whitelist, false));
+	accounts.push(AccountMeta::new(*authority, true));
+	accounts.push(AccountMeta::new_readonly(*mint, false));
+	accounts.push(AccountMeta::new_readonly(*user, false));
+	accounts.push(AccountMeta::new(*allocation, false));
+	accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::SetAllocation { allowance },
+		accounts,
+	))
+}
+
+pub fn set_schedule(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	registration_start: i64,
+	sale_start: i64,
+	sale_end: i64,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::set_schedule::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new_readonly(*authority, true));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::SetSchedule {
+			registration_start,
+			sale_start,
+			sale_end,
+		},
+		accounts,
+	))
+}
+
+/// Creates a `CloseTickets` instruction closing every `(ticket, ticket token account)` pair in
+/// `tickets`
+pub fn close_tickets(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	mint: &Pubkey,
+	treasury: &Pubkey,
+	tickets: &[(Pubkey, Pubkey)],
+	token_program: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts =
+		Vec::with_capacity(account_order::close_tickets::ACCOUNTS + tickets.len() * 2);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new_readonly(*authority, true));
+	accounts.push(AccountMeta::new_readonly(*mint, false));
+	accounts.push(AccountMeta::new(*treasury, false));
+	accounts.push(AccountMeta::new_readonly(*token_program, false));
+	for (ticket, ticket_token_account) in tickets {
+		accounts.push(AccountMeta::new(*ticket, false));
+		accounts.push(AccountMeta::new(*ticket_token_account, false));
+	}
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::CloseTickets,
+		accounts,
+	))
+}
+
+/// Creates a `WithdrawSol` instruction, withdrawing from `ticket` when given and from the
+/// whitelist account otherwise
+pub fn withdraw_sol(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	treasury: &Pubkey,
+	ticket: Option<&Pubkey>,
+	amount: u64,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::withdraw_sol::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new_readonly(*authority, true));
+	accounts.push(AccountMeta::new(*treasury, false));
+	if let Some(ticket) = ticket {
+		accounts.push(AccountMeta::new(*ticket, false));
+	}
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::WithdrawSol { amount },
+		accounts,
+	))
+}
+
+pub fn set_register_authority(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	register_authority: Option<&Pubkey>,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::set_register_authority::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new_readonly(*authority, true));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::SetRegisterAuthority {
+			register_authority: register_authority.copied(),
+		},
+		accounts,
+	))
+}
+
+/// Creates a `RegisterWithSignature` instruction, it must be sent straight after the
+/// `register_voucher` instruction carrying the voucher for the same `nonce`
+pub fn register_with_signature(
+	whitelist: &Pubkey,
+	user: &Pubkey,
+	user_ticket: &Pubkey,
+	allocation: &Pubkey,
+	nonce: u64,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::register_with_signature::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new(*user, true));
+	accounts.push(AccountMeta::new(*user_ticket, false));
+	accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+	accounts.push(AccountMeta::new_readonly(sysvar::instructions::id(), false));
+	accounts.push(AccountMeta::new_readonly(*allocation, false));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::RegisterWithSignature { nonce },
+		accounts,
+	))
+}
+
+/// Creates a `RegisterWithSignature` instruction for a whitelist with `authority_pays_rent` set,
+/// the authority co-signs and pays the new ticket's rent
+pub fn sponsored_register_with_signature(
+	whitelist: &Pubkey,
+	user: &Pubkey,
+	user_ticket: &Pubkey,
+	allocation: &Pubkey,
+	nonce: u64,
+	authority: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+	let mut instruction = register_with_signature(whitelist, user, user_ticket, allocation, nonce)?;
+	instruction
+		.accounts
+		.push(AccountMeta::new(*authority, true));
+	Ok(instruction)
+}
+
+pub fn withdraw_treasury(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	treasury: &Pubkey,
+	recipient: &Pubkey,
+	amount: u64,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::withdraw_treasury::ACCOUNTS);
+
+	accounts.push(AccountMeta::new_readonly(*whitelist, false));
+	accounts.push(AccountMeta::new_readonly(*authority, true));
+	accounts.push(AccountMeta::new(*treasury, false));
+	accounts.push(AccountMeta::new(*recipient, false));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::WithdrawTreasury { amount },
+		accounts,
+	))
+}
+
+pub fn change_authority(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	new_authority: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::change_authority::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new_readonly(*authority, true));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::ChangeAuthority {
+			new_authority: *new_authority,
+		},
+		accounts,
+	))
+}
+
+/// Creates an 'UpdatePrice' instruction
+pub fn update_price(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	token_price: u64,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::update_price::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new_readonly(*authority, true));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::UpdatePrice { token_price },
+		accounts,
+	))
+}
+
+/// Creates a 'CloseVault' instruction
+pub fn close_vault(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	vault: &Pubkey,
+	mint: &Pubkey,
+	recipient_token_account: &Pubkey,
+	token_program: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::close_vault::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new(*authority, true));
+	accounts.push(AccountMeta::new(*vault, false));
+	accounts.push(AccountMeta::new_readonly(*mint, false));
+	accounts.push(AccountMeta::new(*recipient_token_account, false));
+	accounts.push(AccountMeta::new_readonly(*token_program, false));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::CloseVault,
+		accounts,
+	))
+}
+
+/// Creates an 'UpdateBuyLimit' instruction
+pub fn update_buy_limit(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	mint: &Pubkey,
+	buy_limit: u64,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::update_buy_limit::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new_readonly(*authority, true));
+	accounts.push(AccountMeta::new_readonly(*mint, false));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::UpdateBuyLimit { buy_limit },
+		accounts,
+	))
+}
+
+/// Creates a 'SyncTicketAllowance' instruction
+pub fn sync_ticket_allowance(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	user_ticket: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::sync_ticket_allowance::ACCOUNTS);
+
+	accounts.push(AccountMeta::new_readonly(*whitelist, false));
+	accounts.push(AccountMeta::new_readonly(*authority, true));
+	accounts.push(AccountMeta::new(*user_ticket, false));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::SyncTicketAllowance,
+		accounts,
+	))
+}
+
+/// Creates a 'BatchAddUser' instruction, `users` pairs each user with its ticket address
+pub fn batch_add_user(
+	whitelist: &Pubkey,
+	authority: &Pubkey,
+	mint: &Pubkey,
+	users: &[(Pubkey, Pubkey)],
+) -> Result<Instruction, ProgramError> {
+	let mut accounts =
+		Vec::with_capacity(account_order::batch_add_user::ACCOUNTS + users.len() * 2);
+
+	accounts.push(AccountMeta::new(*whitelist, false));
+	accounts.push(AccountMeta::new(*authority, true));
+	accounts.push(AccountMeta::new_readonly(*mint, false));
+	accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+	for (user, user_ticket) in users {
+		accounts.push(AccountMeta::new_readonly(*user, false));
+		accounts.push(AccountMeta::new(*user_ticket, false));
+	}
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::BatchAddUser {
+			users: users.iter().map(|(user, _)| *user).collect(),
+		},
+		accounts,
+	))
+}
+
+/// Creates a 'MigrateTicket' instruction
+pub fn migrate_ticket(user_ticket: &Pubkey, payer: &Pubkey) -> Result<Instruction, ProgramError> {
+	let mut accounts = Vec::with_capacity(account_order::migrate_ticket::ACCOUNTS);
+
+	accounts.push(AccountMeta::new(*user_ticket, false));
+	accounts.push(AccountMeta::new(*payer, true));
+	accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+
+	Ok(Instruction::new_with_borsh(
+		crate::id(),
+		&WhitelistInstruction::MigrateTicket,
+		accounts,
+	))
+}
+
+/// Start of the Ed25519 program's data after the signature count and padding byte
+const ED25519_OFFSETS_START: usize = 2;
+/// Size of the Ed25519 program's offsets for a single signature
+const ED25519_OFFSETS_LEN: usize = 14;
+
+/// Creates the Ed25519 program instruction presenting `signature`, the register authority's
+/// voucher for `user` on `whitelist`, to a following `RegisterWithSignature`. The public key,
+/// signature and message are all carried in the instruction's own data
+pub fn register_voucher(
+	register_authority: &Pubkey,
+	signature: &[u8; 64],
+	user: &Pubkey,
+	whitelist: &Pubkey,
+	nonce: u64,
+) -> Instruction {
+	let message = crate::register_voucher_message(user, whitelist, nonce);
+	let public_key_offset = ED25519_OFFSETS_START + ED25519_OFFSETS_LEN;
+	let signature_offset = public_key_offset + 32;
+	let message_offset = signature_offset + signature.len();
+
+	let mut data = Vec::with_capacity(message_offset + message.len());
+	data.extend_from_slice(&[1, 0]);
+	// Every offset points into this instruction, which the Ed25519 program marks with u16::MAX
+	for offset in [
+		signature_offset,
+		u16::MAX as usize,
+		public_key_offset,
+		u16::MAX as usize,
+		message_offset,
+		message.len(),
+		u16::MAX as usize,
+	] {
+		data.extend_from_slice(&(offset as u16).to_le_bytes());
+	}
+	data.extend_from_slice(register_authority.as_ref());
+	data.extend_from_slice(signature);
+	data.extend_from_slice(&message);
+
+	Instruction::new_with_bytes(ed25519_program::id(), &data, vec![])
+}
@@ -1,17 +1,155 @@
+pub mod account_order;
 pub mod entrypoint;
 pub mod error;
+pub mod events;
 pub mod instructions;
 pub mod processor;
 pub mod state;
 
-use solana_program::{declare_id, pubkey::Pubkey};
+use {
+	crate::error::WhitelistError,
+	solana_program::{declare_id, pubkey::Pubkey},
+};
 
 const SEED: &[u8; 12] = b"___whitelist";
+const DEPOSITOR_SEED: &[u8; 9] = b"depositor";
+const ALLOCATION_SEED: &[u8; 10] = b"allocation";
+const TREASURY_SEED: &[u8; 8] = b"treasury";
+#[cfg(not(feature = "test-mode"))]
 declare_id!("3jyFQazJomtErMzsHrhNzj18aTJYiq3Xdr3H9J51CUzp");
+// A `test-mode` build answers to its own program id, so it cannot run at, or be mistaken for, the
+// deployed program
+#[cfg(feature = "test-mode")]
+declare_id!("5K9DypDodZiJgKF2QQnijKp6HcfyQLuRkW4T94L8B2rp");
+
+/// Set by the `test-mode` feature, for localnet testing only. Schedule times are not required to
+/// be in the future, so a timed sale can be set up part way through its windows without warping
+/// the clock
+pub const TEST_MODE: bool = cfg!(feature = "test-mode");
+
+/// Most users a single `BatchAddUser` instruction accepts, keeping the transaction within the
+/// packet size as every user is passed both in the instruction data and as two accounts
+pub const MAX_BATCH_USERS: usize = 8;
+
 pub fn get_whitelist_address(mint: &Pubkey) -> (Pubkey, u8) {
-	Pubkey::find_program_address(&[SEED, mint.as_ref()], &crate::id())
+	get_whitelist_address_with_program_id(mint, &crate::id())
 }
 
 pub fn get_user_ticket_address(user: &Pubkey, whitelist: &Pubkey) -> (Pubkey, u8) {
-	Pubkey::find_program_address(&[SEED, user.as_ref(), whitelist.as_ref()], &crate::id())
+	get_user_ticket_address_with_program_id(user, whitelist, &crate::id())
+}
+
+pub fn get_depositor_address(depositor: &Pubkey, whitelist: &Pubkey) -> (Pubkey, u8) {
+	get_depositor_address_with_program_id(depositor, whitelist, &crate::id())
+}
+
+pub fn get_allocation_address(user: &Pubkey, whitelist: &Pubkey) -> (Pubkey, u8) {
+	get_allocation_address_with_program_id(user, whitelist, &crate::id())
+}
+
+/// Program owned treasury of a whitelist initialised with `treasury_is_pda`, proceeds paid into it
+/// can only leave through `WithdrawTreasury`
+pub fn get_treasury_address(mint: &Pubkey) -> (Pubkey, u8) {
+	get_treasury_address_with_program_id(mint, &crate::id())
+}
+
+/// `get_whitelist_address` for a deployment of the program at `program_id` rather than the
+/// declared id, e.g. one deployed to a local validator
+pub fn get_whitelist_address_with_program_id(mint: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+	Pubkey::find_program_address(&[SEED, mint.as_ref()], program_id)
+}
+
+/// `get_user_ticket_address` for a deployment of the program at `program_id`
+pub fn get_user_ticket_address_with_program_id(
+	user: &Pubkey,
+	whitelist: &Pubkey,
+	program_id: &Pubkey,
+) -> (Pubkey, u8) {
+	Pubkey::find_program_address(&[SEED, user.as_ref(), whitelist.as_ref()], program_id)
+}
+
+/// `get_depositor_address` for a deployment of the program at `program_id`
+pub fn get_depositor_address_with_program_id(
+	depositor: &Pubkey,
+	whitelist: &Pubkey,
+	program_id: &Pubkey,
+) -> (Pubkey, u8) {
+	Pubkey::find_program_address(
+		&[SEED, DEPOSITOR_SEED, depositor.as_ref(), whitelist.as_ref()],
+		program_id,
+	)
+}
+
+/// `get_allocation_address` for a deployment of the program at `program_id`
+pub fn get_allocation_address_with_program_id(
+	user: &Pubkey,
+	whitelist: &Pubkey,
+	program_id: &Pubkey,
+) -> (Pubkey, u8) {
+	Pubkey::find_program_address(
+		&[SEED, ALLOCATION_SEED, user.as_ref(), whitelist.as_ref()],
+		program_id,
+	)
+}
+
+/// `get_treasury_address` for a deployment of the program at `program_id`
+pub fn get_treasury_address_with_program_id(mint: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+	Pubkey::find_program_address(&[SEED, TREASURY_SEED, mint.as_ref()], program_id)
+}
+
+/// Message a register authority signs to let `user` register on `whitelist` with
+/// `RegisterWithSignature`: the user, the whitelist and the little-endian `nonce`. The nonce is
+/// chosen by whoever issues the vouchers and is not tracked by the program
+pub fn register_voucher_message(user: &Pubkey, whitelist: &Pubkey, nonce: u64) -> [u8; 72] {
+	let mut message = [0u8; 72];
+	message[..32].copy_from_slice(user.as_ref());
+	message[32..64].copy_from_slice(whitelist.as_ref());
+	message[64..].copy_from_slice(&nonce.to_le_bytes());
+	message
+}
+
+/// Lamports charged for buying `amount_base_units` of a mint with `decimals` decimals, where
+/// `token_price` is the price of one whole token in lamports. Rounds up so that a fraction of a
+/// token is never free. `process_buy` charges exactly this amount
+pub fn buy_cost(
+	amount_base_units: u64,
+	token_price: u64,
+	decimals: u8,
+) -> Result<u64, WhitelistError> {
+	let one_token = 10u128
+		.checked_pow(decimals as u32)
+		.ok_or(WhitelistError::Overflow)?;
+	let cost = (amount_base_units as u128 * token_price as u128).div_ceil(one_token);
+	u64::try_from(cost).map_err(|_| WhitelistError::Overflow)
+}
+
+/// Parses a decimal token amount such as `"123.456789123"` into base units of a mint with
+/// `decimals` decimals. Unlike `ui_amount_to_amount` the digits are assembled as integers, so
+/// no precision is lost to `f64` on large balances. More fractional digits than the mint has are
+/// rejected rather than rounded
+pub fn parse_token_amount(amount: &str, decimals: u8) -> Result<u64, WhitelistError> {
+	let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+	if (whole.is_empty() && fraction.is_empty())
+		|| !whole.bytes().all(|b| b.is_ascii_digit())
+		|| !fraction.bytes().all(|b| b.is_ascii_digit())
+		|| fraction.len() > decimals as usize
+	{
+		return Err(WhitelistError::InvalidTokenAmount);
+	}
+
+	let one_token = 10u64
+		.checked_pow(decimals as u32)
+		.ok_or(WhitelistError::Overflow)?;
+	let parse = |digits: &str| match digits {
+		"" => Ok(0),
+		digits => digits.parse::<u64>().map_err(|_| WhitelistError::Overflow),
+	};
+	// The fraction is padded out to `decimals` digits, "5" of a 9 decimal mint is 500_000_000
+	let fraction = parse(&format!("{:0<1$}", fraction, decimals as usize))?;
+	let whole = parse(whole)?;
+
+	whole
+		.checked_mul(one_token)
+		.and_then(|whole| whole.checked_add(fraction))
+		.ok_or(WhitelistError::Overflow)
 }
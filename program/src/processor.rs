@@ -1,24 +1,46 @@
 use {
 	crate::{
+		account_order::{
+			account_at, add_user, add_user_and_seed, allow_register, amend_times,
+			amend_whitelist_size, batch_add_user, burn_ticket, buy, change_authority,
+			claim_ticket_rent, cleanup_token_account, close_tickets, close_vault, deposit_for,
+			deposit_tokens, force_close_ticket, initialise_whitelist, migrate_ticket,
+			migrate_whitelist, refund, register, register_with_referral, register_with_signature,
+			remove_user, set_allocation, set_buy_cooldown, set_paused, set_referral_bonus,
+			set_register_authority, set_schedule, set_ticket_note, start_registration,
+			start_token_sale, sync_ticket_allowance, terminate_whitelist, transfer_tokens,
+			unregister, update_buy_limit, update_price, withdraw_deposit, withdraw_sol,
+			withdraw_tokens, withdraw_treasury, ASSOCIATED_TOKEN_PROGRAM_ID,
+		},
+		buy_cost,
 		error::WhitelistError,
+		events::{SaleSchedule, WhitelistEvent},
+		get_allocation_address, get_depositor_address, get_treasury_address,
 		get_user_ticket_address, get_whitelist_address,
-		instructions::WhitelistInstruction,
-		state::{Ticket, Whitelist},
-		SEED,
+		instructions::{PauseTarget, WhitelistInstruction},
+		register_voucher_message,
+		state::{Allocation, Depositor, Ticket, TicketV0, Whitelist, WhitelistV0},
+		ALLOCATION_SEED, DEPOSITOR_SEED, MAX_BATCH_USERS, SEED, TREASURY_SEED,
 	},
 	borsh::{BorshDeserialize, BorshSerialize},
 	solana_program::{
-		account_info::{next_account_info, AccountInfo},
+		account_info::AccountInfo,
+		ed25519_program,
 		entrypoint::ProgramResult,
 		msg,
 		program::{invoke, invoke_signed},
 		program_error::ProgramError,
 		pubkey::Pubkey,
 		system_instruction, system_program,
-		sysvar::{clock::Clock, rent::Rent, Sysvar},
+		sysvar::{
+			clock::Clock,
+			instructions::{load_current_index_checked, load_instruction_at_checked},
+			rent::Rent,
+			Sysvar,
+		},
 	},
 	spl_token_2022::{
-		extension::StateWithExtensions,
+		extension::{ExtensionType, StateWithExtensions},
 		state::{Account, Mint},
 	},
 };
@@ -45,6 +67,11 @@ impl Processor {
 				registration_duration,
 				sale_start_timestamp,
 				sale_duration,
+				max_tickets,
+				refund_window_secs,
+				authority_pays_rent,
+				allow_free,
+				treasury_is_pda,
 			} => Self::process_init(
 				accounts,
 				&treasury,
@@ -56,6 +83,11 @@ impl Processor {
 				registration_duration,
 				sale_start_timestamp,
 				sale_duration,
+				max_tickets,
+				refund_window_secs,
+				authority_pays_rent,
+				allow_free,
+				treasury_is_pda,
 			),
 			WhitelistInstruction::AddUser => Self::process_add_user(accounts),
 			WhitelistInstruction::RemoveUser => Self::process_remove_user(accounts),
@@ -67,30 +99,105 @@ impl Processor {
 				registration_duration,
 				sale_start_timestamp,
 				sale_duration,
+				allow_overlap,
 			} => Self::process_amend_times(
 				accounts,
 				registration_start_timestamp,
 				registration_duration,
 				sale_start_timestamp,
 				sale_duration,
+				allow_overlap,
 			),
 			WhitelistInstruction::AllowRegister { allow_registration } => {
 				Self::process_allow_register(accounts, allow_registration)
 			}
-			WhitelistInstruction::Register => Self::process_register(accounts),
+			WhitelistInstruction::Register => Self::process_register(accounts, None, None),
 			WhitelistInstruction::Unregister => Self::process_unregister(accounts),
-			WhitelistInstruction::Buy { amount } => Self::process_buy(accounts, amount),
+			WhitelistInstruction::Buy {
+				amount,
+				max_price,
+				allow_partial,
+			} => Self::process_buy(accounts, amount, max_price, allow_partial),
 			WhitelistInstruction::DepositTokens { amount } => {
 				Self::process_deposit_tokens(accounts, amount)
 			}
 			WhitelistInstruction::StartRegistration => Self::process_start_registration(accounts),
-			WhitelistInstruction::StartTokenSale => Self::process_start_token_sale(accounts),
+			WhitelistInstruction::StartTokenSale {
+				require_fully_funded,
+			} => Self::process_start_token_sale(accounts, require_fully_funded),
 			WhitelistInstruction::TransferTokens => Self::process_transfer_tokens(accounts),
 			WhitelistInstruction::WithdrawTokens { amount } => {
 				Self::process_withdraw_tokens(accounts, amount)
 			}
 			WhitelistInstruction::BurnTicket => Self::process_burn_ticket(accounts),
 			WhitelistInstruction::TerminateWhitelist => Self::process_terminate_whitelist(accounts),
+			WhitelistInstruction::MigrateWhitelist => Self::process_migrate_whitelist(accounts),
+			WhitelistInstruction::SetTicketNote { note } => {
+				Self::process_set_ticket_note(accounts, note)
+			}
+			WhitelistInstruction::DepositFor { amount } => {
+				Self::process_deposit_for(accounts, amount)
+			}
+			WhitelistInstruction::WithdrawDeposit { amount } => {
+				Self::process_withdraw_deposit(accounts, amount)
+			}
+			WhitelistInstruction::SetPaused { target, paused } => {
+				Self::process_set_paused(accounts, target, paused)
+			}
+			WhitelistInstruction::RegisterWithReferral { referrer } => {
+				Self::process_register(accounts, Some(referrer), None)
+			}
+			WhitelistInstruction::SetReferralBonus { referral_bonus } => {
+				Self::process_set_referral_bonus(accounts, referral_bonus)
+			}
+			WhitelistInstruction::AddUserAndSeed => Self::process_add_user_and_seed(accounts),
+			WhitelistInstruction::ForceCloseTicket => Self::process_force_close_ticket(accounts),
+			WhitelistInstruction::CleanupTokenAccount => {
+				Self::process_cleanup_token_account(accounts)
+			}
+			WhitelistInstruction::SetBuyCooldown { cooldown_secs } => {
+				Self::process_set_buy_cooldown(accounts, cooldown_secs)
+			}
+			WhitelistInstruction::Refund { amount } => Self::process_refund(accounts, amount),
+			WhitelistInstruction::ClaimTicketRent => Self::process_claim_ticket_rent(accounts),
+			WhitelistInstruction::SetAllocation { allowance } => {
+				Self::process_set_allocation(accounts, allowance)
+			}
+			WhitelistInstruction::SetSchedule {
+				registration_start,
+				sale_start,
+				sale_end,
+			} => Self::process_set_schedule(accounts, registration_start, sale_start, sale_end),
+			WhitelistInstruction::CloseTickets => Self::process_close_tickets(accounts),
+			WhitelistInstruction::WithdrawSol { amount } => {
+				Self::process_withdraw_sol(accounts, amount)
+			}
+			WhitelistInstruction::SetRegisterAuthority { register_authority } => {
+				Self::process_set_register_authority(accounts, register_authority)
+			}
+			WhitelistInstruction::RegisterWithSignature { nonce } => {
+				Self::process_register(accounts, None, Some(nonce))
+			}
+			WhitelistInstruction::WithdrawTreasury { amount } => {
+				Self::process_withdraw_treasury(accounts, amount)
+			}
+			WhitelistInstruction::ChangeAuthority { new_authority } => {
+				Self::process_change_authority(accounts, new_authority)
+			}
+			WhitelistInstruction::UpdatePrice { token_price } => {
+				Self::process_update_price(accounts, token_price)
+			}
+			WhitelistInstruction::CloseVault => Self::process_close_vault(accounts),
+			WhitelistInstruction::UpdateBuyLimit { buy_limit } => {
+				Self::process_update_buy_limit(accounts, buy_limit)
+			}
+			WhitelistInstruction::SyncTicketAllowance => {
+				Self::process_sync_ticket_allowance(accounts)
+			}
+			WhitelistInstruction::BatchAddUser { users } => {
+				Self::process_batch_add_user(accounts, users)
+			}
+			WhitelistInstruction::MigrateTicket => Self::process_migrate_ticket(accounts),
 		}
 	}
 
@@ -105,23 +212,31 @@ impl Processor {
 		registration_duration: i64,
 		sale_start_timestamp: i64,
 		sale_duration: i64,
+		max_tickets: u64,
+		refund_window_secs: u64,
+		authority_pays_rent: bool,
+		allow_free: bool,
+		treasury_is_pda: bool,
 	) -> ProgramResult {
-		let accounts_iter = &mut accounts.iter();
-		let whitelist_account = next_account_info(accounts_iter)?;
-		let authority = next_account_info(accounts_iter)?;
-		let vault = next_account_info(accounts_iter)?;
-		let mint = next_account_info(accounts_iter)?;
-		let token_program = next_account_info(accounts_iter)?;
-		let system_program = next_account_info(accounts_iter)?;
-		let assc_token_program = next_account_info(accounts_iter)?;
+		let whitelist_account = account_at(accounts, initialise_whitelist::WHITELIST)?;
+		let authority = account_at(accounts, initialise_whitelist::AUTHORITY)?;
+		let vault = account_at(accounts, initialise_whitelist::VAULT)?;
+		let mint = account_at(accounts, initialise_whitelist::MINT)?;
+		let token_program = account_at(accounts, initialise_whitelist::TOKEN_PROGRAM)?;
+		let system_program = account_at(accounts, initialise_whitelist::SYSTEM_PROGRAM)?;
+		let assc_token_program =
+			account_at(accounts, initialise_whitelist::ASSOCIATED_TOKEN_PROGRAM)?;
 
 		let rent = Rent::get()?;
 
 		let (wl, bump) = crate::get_whitelist_address(mint.key);
-		let mint_decimals = {
+		let (mint_decimals, mint_freezable) = {
 			let borrowed_mint_data = mint.data.borrow();
 			let mint_data = StateWithExtensions::<Mint>::unpack(&borrowed_mint_data)?;
-			mint_data.base.decimals
+			(
+				mint_data.base.decimals,
+				mint_data.base.freeze_authority.is_some(),
+			)
 		};
 
 		// Safety dance
@@ -147,60 +262,148 @@ impl Processor {
 		}
 
 		if token_program.key != &spl_token_2022::id() && token_program.key != &spl_token::id() {
-			return Err(ProgramError::IncorrectProgramId);
+			return Err(WhitelistError::IncorrectTokenProgram.into());
 		}
 
 		if system_program.key != &system_program::id() {
-			return Err(ProgramError::IncorrectProgramId);
+			return Err(WhitelistError::IncorrectSystemProgram.into());
 		}
 
-		if assc_token_program.key != &spl_associated_token_account::id() {
-			return Err(ProgramError::IncorrectProgramId);
+		if assc_token_program.key != &ASSOCIATED_TOKEN_PROGRAM_ID {
+			return Err(WhitelistError::IncorrectAssociatedTokenProgram.into());
 		}
 
-		if whitelist_account.owner != &crate::id() {
-			msg!("Initialising whitelist account");
+		let create_vault = || {
 			invoke_signed(
-				&system_instruction::create_account(
+				&spl_associated_token_account::instruction::create_associated_token_account(
 					authority.key,
 					&whitelist_account.key,
-					rent.minimum_balance(Whitelist::LEN)
-						.max(1)
-						.saturating_sub(whitelist_account.lamports()),
-					Whitelist::LEN as u64,
-					&crate::id(),
+					mint.key,
+					token_program.key,
 				),
 				&[
 					authority.clone(),
+					vault.clone(),
 					whitelist_account.clone(),
+					mint.clone(),
 					system_program.clone(),
+					token_program.clone(),
+					assc_token_program.clone(),
 				],
 				&[&[SEED, mint.key.as_ref(), &[bump]]],
-			)?;
+			)
+		};
 
-			msg!("Initialising vault");
+		if whitelist_account.owner != &crate::id() {
+			// A frozen vault would strand every deposit and purchase, refuse mints that could
+			// freeze it rather than find out mid-sale
+			if mint_freezable {
+				msg!("Mint {} has a freeze authority", mint.key);
+				return Err(WhitelistError::FreezableMint.into());
+			}
+
+			// Buyers would pay nothing, only go ahead when a free sale was asked for
+			if token_price == 0 && !allow_free {
+				msg!("Initialise rejected: token price is 0 and the sale was not marked as free");
+				return Err(WhitelistError::ZeroPrice.into());
+			}
+
+			// Without the flag the PDA would never be created, and nothing could spend from it
+			let (treasury_pda, treasury_bump) = get_treasury_address(mint.key);
+			if treasury_is_pda != (treasury == &treasury_pda) {
+				msg!(
+					"Initialise rejected: treasury {} with treasury_is_pda {}",
+					treasury,
+					treasury_is_pda
+				);
+				return Err(WhitelistError::IncorrectTreasuryAddress.into());
+			}
+
+			msg!("Initialising whitelist account");
 			invoke_signed(
-				&spl_associated_token_account::instruction::create_associated_token_account(
+				&system_instruction::create_account(
 					authority.key,
 					&whitelist_account.key,
-					mint.key,
-					token_program.key,
+					rent.minimum_balance(Whitelist::space())
+						.max(1)
+						.saturating_sub(whitelist_account.lamports()),
+					Whitelist::space() as u64,
+					&crate::id(),
 				),
 				&[
 					authority.clone(),
-					vault.clone(),
 					whitelist_account.clone(),
-					mint.clone(),
 					system_program.clone(),
-					token_program.clone(),
-					assc_token_program.clone(),
 				],
 				&[&[SEED, mint.key.as_ref(), &[bump]]],
 			)?;
 
+			msg!("Initialising vault");
+			create_vault()?;
+
+			if treasury_is_pda {
+				let treasury_account = account_at(accounts, initialise_whitelist::TREASURY)?;
+				if treasury_account.key != &treasury_pda {
+					return Err(WhitelistError::IncorrectTreasuryAddress.into());
+				}
+
+				// Owned by the program and without data, so only `WithdrawTreasury` can debit it
+				msg!("Initialising treasury");
+				let treasury_seeds: &[&[u8]] =
+					&[SEED, TREASURY_SEED, mint.key.as_ref(), &[treasury_bump]];
+				let treasury_rent = rent.minimum_balance(0).max(1);
+				if treasury_account.lamports() == 0 {
+					invoke_signed(
+						&system_instruction::create_account(
+							authority.key,
+							treasury_account.key,
+							treasury_rent,
+							0,
+							&crate::id(),
+						),
+						&[
+							authority.clone(),
+							treasury_account.clone(),
+							system_program.clone(),
+						],
+						&[treasury_seeds],
+					)?;
+				} else {
+					// `create_account` refuses an address that already holds lamports, so anyone
+					// could block initialisation by sending the PDA lamports. Top it up to rent
+					// and claim it instead
+					let shortfall = treasury_rent.saturating_sub(treasury_account.lamports());
+					if shortfall > 0 {
+						invoke(
+							&system_instruction::transfer(
+								authority.key,
+								treasury_account.key,
+								shortfall,
+							),
+							&[
+								authority.clone(),
+								treasury_account.clone(),
+								system_program.clone(),
+							],
+						)?;
+					}
+					invoke_signed(
+						&system_instruction::allocate(treasury_account.key, 0),
+						&[treasury_account.clone(), system_program.clone()],
+						&[treasury_seeds],
+					)?;
+					invoke_signed(
+						&system_instruction::assign(treasury_account.key, &crate::id()),
+						&[treasury_account.clone(), system_program.clone()],
+						&[treasury_seeds],
+					)?;
+				}
+			}
+
 			let buy_limit = spl_token_2022::ui_amount_to_amount(buy_limit as f64, mint_decimals);
 
 			let whitelist_state = Whitelist {
+				version: Whitelist::VERSION,
 				bump,
 				authority: *authority.key,
 				vault: *vault.key,
@@ -215,6 +418,15 @@ impl Processor {
 				registration_duration,
 				sale_timestamp: sale_start_timestamp,
 				sale_duration,
+				registered: 0,
+				registration_paused: false,
+				sale_paused: false,
+				referral_bonus: 0,
+				max_tickets,
+				cooldown_secs: 0,
+				refund_window_secs,
+				authority_pays_rent,
+				register_authority: Pubkey::default(),
 			};
 
 			whitelist_state.check_times()?;
@@ -224,22 +436,43 @@ impl Processor {
 
 			Ok(())
 		} else {
-			return Err(WhitelistError::WhitelistAlreadyInitialized.into());
+			// A whitelist without its vault is left behind by an init that failed part way, or by
+			// `CloseVault`, finish it by creating the vault and keep the state that was already
+			// written
+			let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+			let vault_missing = vault.owner == &system_program::id() && vault.data_is_empty();
+			let vault_closed = wl_data.vault == Pubkey::default();
+			if !vault_missing || (vault.key != &wl_data.vault && !vault_closed) {
+				return Err(WhitelistError::WhitelistAlreadyInitialized.into());
+			}
+
+			if authority.key != &wl_data.authority {
+				return Err(WhitelistError::Unauthorised.into());
+			}
+
+			msg!("Whitelist exists without a vault, initialising vault");
+			create_vault()?;
+			if vault_closed {
+				wl_data.vault = *vault.key;
+				wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
+			}
+			msg!("Vault initialised");
+
+			Ok(())
 		}
 	}
 
 	fn process_add_user(accounts: &[AccountInfo]) -> ProgramResult {
-		let accounts_iter = &mut accounts.iter();
-		let whitelist_account = next_account_info(accounts_iter)?;
-		let authority = next_account_info(accounts_iter)?;
-		let mint = next_account_info(accounts_iter)?;
-		let user_account = next_account_info(accounts_iter)?;
-		let user_ticket_account = next_account_info(accounts_iter)?;
-		let system_program = next_account_info(accounts_iter)?;
+		let whitelist_account = account_at(accounts, add_user::WHITELIST)?;
+		let authority = account_at(accounts, add_user::AUTHORITY)?;
+		let mint = account_at(accounts, add_user::MINT)?;
+		let user_account = account_at(accounts, add_user::USER)?;
+		let user_ticket_account = account_at(accounts, add_user::USER_TICKET)?;
+		let system_program = account_at(accounts, add_user::SYSTEM_PROGRAM)?;
 
 		let rent = Rent::get()?;
 
-		let wl_data = Whitelist::try_from_slice(&whitelist_account.data.borrow()[..])?;
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
 
 		let (wl, _bump) = crate::get_whitelist_address(mint.key);
 		let (user_ticket, user_bump) = crate::get_user_ticket_address(user_account.key, &wl);
@@ -261,45 +494,68 @@ impl Processor {
 		}
 
 		if system_program.key != &system_program::id() {
-			return Err(ProgramError::IncorrectProgramId);
+			return Err(WhitelistError::IncorrectSystemProgram.into());
 		}
 
-		if user_ticket_account.owner != &crate::id() {
-			msg!("Creating user whitelist account");
-			invoke_signed(
-				&system_instruction::create_account(
-					authority.key,
-					&user_ticket,
-					rent.minimum_balance(Ticket::LEN)
-						.max(1)
-						.saturating_sub(user_ticket_account.lamports()),
-					Ticket::LEN as u64,
-					&crate::id(),
-				),
-				&[
-					authority.clone(),
-					user_ticket_account.clone(),
-					system_program.clone(),
-				],
-				&[&[
-					SEED,
-					user_account.key.as_ref(),
-					whitelist_account.key.as_ref(),
-					&[user_bump],
-				]],
-			)?;
+		// Overwriting a live ticket would reset its purchases and hand its rent to a new payer
+		if user_ticket_account.owner == &crate::id() {
+			msg!(
+				"Add user rejected: user {} is already registered",
+				user_account.key
+			);
+			return Err(WhitelistError::WhitelistAlreadyInitialized.into());
 		}
 
+		wl_data.register_ticket().map_err(|err| {
+			msg!(
+				"Add user rejected: {} tickets already registered, size {}, ceiling {}",
+				wl_data.registered,
+				wl_data.whitelist_size,
+				wl_data.max_tickets
+			);
+			err
+		})?;
+
+		msg!("Creating user whitelist account");
+		invoke_signed(
+			&system_instruction::create_account(
+				authority.key,
+				&user_ticket,
+				rent.minimum_balance(Ticket::space())
+					.max(1)
+					.saturating_sub(user_ticket_account.lamports()),
+				Ticket::space() as u64,
+				&crate::id(),
+			),
+			&[
+				authority.clone(),
+				user_ticket_account.clone(),
+				system_program.clone(),
+			],
+			&[&[
+				SEED,
+				user_account.key.as_ref(),
+				whitelist_account.key.as_ref(),
+				&[user_bump],
+			]],
+		)?;
+
 		let ticket_data = Ticket {
+			version: Ticket::VERSION,
 			bump: user_bump,
 			whitelist: *whitelist_account.key,
 			owner: *user_account.key,
 			allowance: wl_data.buy_limit,
 			payer: *authority.key,
 			amount_bought: 0,
+			claimed: 0,
+			note: [0; Ticket::NOTE_LEN],
+			referrer: Pubkey::default(),
+			last_purchase_ts: 0,
 		};
 
 		ticket_data.serialize(&mut &mut user_ticket_account.data.borrow_mut()[..])?;
+		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
 
 		msg!("User initialised");
 
@@ -308,19 +564,19 @@ impl Processor {
 
 	fn process_remove_user(accounts: &[AccountInfo]) -> ProgramResult {
 		msg!("Process: Remove user");
-		let accounts_iter = &mut accounts.iter();
-		let whitelist_account = next_account_info(accounts_iter)?;
-		let authority = next_account_info(accounts_iter)?;
-		let mint = next_account_info(accounts_iter)?;
-		let user_account = next_account_info(accounts_iter)?;
-		let user_ticket_account = next_account_info(accounts_iter)?;
-		let system_program = next_account_info(accounts_iter)?;
+		let whitelist_account = account_at(accounts, remove_user::WHITELIST)?;
+		let authority = account_at(accounts, remove_user::AUTHORITY)?;
+		let vault = account_at(accounts, remove_user::VAULT)?;
+		let mint = account_at(accounts, remove_user::MINT)?;
+		let user_account = account_at(accounts, remove_user::USER)?;
+		let user_ticket_account = account_at(accounts, remove_user::USER_TICKET)?;
+		let ticket_token_account = account_at(accounts, remove_user::TICKET_TOKEN)?;
+		let token_program = account_at(accounts, remove_user::TOKEN_PROGRAM)?;
+		let system_program = account_at(accounts, remove_user::SYSTEM_PROGRAM)?;
 
 		let (wl, _bump) = get_whitelist_address(&mint.key);
-		let (user_ticket, user_bump) =
-			get_user_ticket_address(&user_account.key, &whitelist_account.key);
-		let wl_data = Whitelist::try_from_slice(&whitelist_account.data.borrow()[..])?;
-		let ticket_data = Ticket::try_from_slice(&user_ticket_account.data.borrow()[..])?;
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+		let ticket_data = Ticket::unpack(&user_ticket_account.data.borrow())?;
 
 		if !authority.is_signer || authority.key != &wl_data.authority {
 			return Err(WhitelistError::Unauthorised.into());
@@ -330,51 +586,100 @@ impl Processor {
 			return Err(WhitelistError::InvalidWhitelistAddress.into());
 		}
 
-		if user_ticket_account.key != &user_ticket || user_bump != ticket_data.bump {
-			return Err(WhitelistError::IncorrectUserAccount.into());
-		}
+		Ticket::derive_and_verify(
+			user_account.key,
+			whitelist_account.key,
+			user_ticket_account.key,
+			ticket_data.bump,
+		)?;
 
+		if token_program.key != &spl_token_2022::id() && token_program.key != &spl_token::id() {
+			msg!(
+				"Remove user rejected: {} is not a token program",
+				token_program.key
+			);
+			return Err(WhitelistError::IncorrectTokenProgram.into());
+		}
 		if system_program.key != &system_program::id() {
-			return Err(ProgramError::IncorrectProgramId);
+			return Err(WhitelistError::IncorrectSystemProgram.into());
+		}
+
+		let ticket_ata = spl_associated_token_account::get_associated_token_address_with_program_id(
+			user_ticket_account.key,
+			mint.key,
+			token_program.key,
+		);
+		if ticket_token_account.key != &ticket_ata {
+			msg!(
+				"Remove user rejected: ticket token account {} is not the ticket's ATA {}",
+				ticket_token_account.key,
+				ticket_ata
+			);
+			return Err(WhitelistError::IncorrectUserAccount.into());
 		}
 
-		let user_lamports = user_ticket_account.lamports();
+		// Tokens seeded into the ticket can only go back to an open vault, with the vault closed
+		// they would be stranded so the ticket is kept
+		if ticket_token_amount(ticket_token_account, token_program)? > 0 {
+			if wl_data.vault == Pubkey::default() {
+				msg!("Remove user rejected: the ticket holds tokens and the vault is closed");
+				return Err(WhitelistError::TicketNotEmpty.into());
+			}
+			if vault.key != &wl_data.vault {
+				msg!(
+					"Remove user rejected: vault {} does not match whitelist vault {}",
+					vault.key,
+					wl_data.vault
+				);
+				return Err(WhitelistError::IncorrectVaultAddress.into());
+			}
+			check_distinct(ticket_token_account, vault)?;
+		}
 
-		user_ticket_account.assign(&system_program::id());
-		user_ticket_account.realloc(0, false)?;
-		invoke_signed(
-			&system_instruction::transfer(user_ticket_account.key, authority.key, user_lamports),
-			&[
-				user_ticket_account.clone(),
-				authority.clone(),
-				system_program.clone(),
-			],
-			&[&[
-				SEED,
-				user_account.key.as_ref(),
-				whitelist_account.key.as_ref(),
-				&[user_bump],
-			]],
+		let token_amount = return_ticket_tokens(
+			whitelist_account.key,
+			&wl_data,
+			&ticket_data,
+			user_ticket_account,
+			ticket_token_account,
+			vault,
+			mint,
+			authority,
+			token_program,
 		)?;
+		if token_amount > 0 {
+			msg!("Returned {} tokens to the vault", token_amount);
+		}
+
+		wl_data.unregister_ticket();
+		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
+
+		let user_lamports = close_program_account(user_ticket_account, authority)?;
 
 		msg!("User unregistered reclaimed: {} lamports", user_lamports);
 		Ok(())
 	}
 
 	fn process_amend_whitelist_size(accounts: &[AccountInfo], size: u64) -> ProgramResult {
-		let accounts_iter = &mut accounts.iter();
-		let whitelist_account = next_account_info(accounts_iter)?;
-		let authority = next_account_info(accounts_iter)?;
+		let whitelist_account = account_at(accounts, amend_whitelist_size::WHITELIST)?;
+		let authority = account_at(accounts, amend_whitelist_size::AUTHORITY)?;
 
-		let mut wl_data = Whitelist::try_from_slice(&whitelist_account.data.borrow()[..])?;
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
 
 		if !authority.is_signer || authority.key != &wl_data.authority {
 			return Err(WhitelistError::Unauthorised.into());
 		}
 
+		let old_size = wl_data.whitelist_size;
 		wl_data.whitelist_size = size;
 		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
-		Ok(())
+
+		WhitelistEvent::WhitelistSizeAmended {
+			whitelist: *whitelist_account.key,
+			old_size,
+			new_size: size,
+		}
+		.emit()
 	}
 
 	fn process_amend_times(
@@ -383,59 +688,157 @@ impl Processor {
 		registration_duration: Option<i64>,
 		sale_timestamp: Option<i64>,
 		sale_duration: Option<i64>,
+		allow_overlap: bool,
 	) -> ProgramResult {
-		let accounts_iter = &mut accounts.iter();
-		let whitelist_account = next_account_info(accounts_iter)?;
-		let authority = next_account_info(accounts_iter)?;
+		let whitelist_account = account_at(accounts, amend_times::WHITELIST)?;
+		let authority = account_at(accounts, amend_times::AUTHORITY)?;
 
 		let clock = Clock::get()?;
 
-		let mut wl_data = Whitelist::try_from_slice(&whitelist_account.data.borrow()[..])?;
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
 
 		if !authority.is_signer || authority.key != &wl_data.authority {
 			return Err(WhitelistError::Unauthorised.into());
 		}
 
-		if registration_timestamp.is_some() && wl_data.registration_timestamp > clock.unix_timestamp
+		// Abort if registration has already started
+		if registration_timestamp.is_some()
+			&& wl_data.registration_timestamp > 0
+			&& wl_data.registration_timestamp <= clock.unix_timestamp
 		{
-			// Abort if registration has already started
 			return Err(WhitelistError::RegistrationStarted.into());
 		}
 
 		// The same safety check as above for the sale
-		if sale_timestamp.is_some() && wl_data.sale_timestamp > clock.unix_timestamp {
+		if sale_timestamp.is_some()
+			&& wl_data.sale_timestamp > 0
+			&& wl_data.sale_timestamp <= clock.unix_timestamp
+		{
 			return Err(WhitelistError::SaleStarted.into());
 		}
 
-		// safe to unwrap
-		if registration_timestamp.is_some() {
-			wl_data.registration_timestamp = registration_timestamp.unwrap();
+		let old_schedule = SaleSchedule::from(&wl_data);
+
+		// Fields that are not being amended keep their stored values, the whole schedule is
+		// then validated so amending a single field cannot leave the windows out of order
+		wl_data.registration_timestamp =
+			registration_timestamp.unwrap_or(wl_data.registration_timestamp);
+		wl_data.registration_duration =
+			registration_duration.unwrap_or(wl_data.registration_duration);
+		wl_data.sale_timestamp = sale_timestamp.unwrap_or(wl_data.sale_timestamp);
+		wl_data.sale_duration = sale_duration.unwrap_or(wl_data.sale_duration);
+
+		wl_data.check_times()?;
+
+		// Registration running into the sale lets users register, and unregister, while others
+		// are already buying, so it has to be asked for explicitly
+		if !allow_overlap && wl_data.registration_overlaps_sale() {
+			msg!(
+				"Amend rejected: registration ends at {}, after the sale starts at {}",
+				wl_data.registration_timestamp + wl_data.registration_duration,
+				wl_data.sale_timestamp
+			);
+			return Err(WhitelistError::WindowsOverlap.into());
+		}
+
+		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
+
+		WhitelistEvent::TimesAmended {
+			whitelist: *whitelist_account.key,
+			old: old_schedule,
+			new: SaleSchedule::from(&wl_data),
+		}
+		.emit()
+	}
+
+	fn process_set_schedule(
+		accounts: &[AccountInfo],
+		registration_start: i64,
+		sale_start: i64,
+		sale_end: i64,
+	) -> ProgramResult {
+		msg!("Process: Set schedule");
+		let whitelist_account = account_at(accounts, set_schedule::WHITELIST)?;
+		let authority = account_at(accounts, set_schedule::AUTHORITY)?;
+
+		let clock = Clock::get()?;
+
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+		if !authority.is_signer || authority.key != &wl_data.authority {
+			return Err(WhitelistError::Unauthorised.into());
+		}
+
+		if wl_data.registration_timestamp > 0
+			&& wl_data.registration_timestamp <= clock.unix_timestamp
+		{
+			return Err(WhitelistError::RegistrationStarted.into());
 		}
 
-		if registration_duration.is_some() {
-			wl_data.registration_duration = registration_duration.unwrap();
+		if wl_data.sale_timestamp > 0 && wl_data.sale_timestamp <= clock.unix_timestamp {
+			return Err(WhitelistError::SaleStarted.into());
 		}
 
-		if sale_timestamp.is_some() {
-			wl_data.sale_timestamp = sale_timestamp.unwrap();
+		// A timestamp of 0 means unscheduled elsewhere, every point of a schedule must be set
+		if registration_start <= 0
+			|| (!crate::TEST_MODE && registration_start <= clock.unix_timestamp)
+		{
+			msg!(
+				"Set schedule rejected: registration start {} is not after the current time {}",
+				registration_start,
+				clock.unix_timestamp
+			);
+			return Err(WhitelistError::InvalidRegistrationStartTime.into());
 		}
 
-		if sale_duration.is_some() {
-			wl_data.sale_duration = sale_duration.unwrap();
+		if sale_start <= registration_start {
+			msg!(
+				"Set schedule rejected: sale start {} is not after registration start {}",
+				sale_start,
+				registration_start
+			);
+			return Err(WhitelistError::SaleBeforeRegistration.into());
 		}
 
-		wl_data.check_times()?;
+		if sale_end <= sale_start {
+			msg!(
+				"Set schedule rejected: sale end {} is not after sale start {}",
+				sale_end,
+				sale_start
+			);
+			return Err(WhitelistError::InvalidTimestamp.into());
+		}
+
+		let old_schedule = SaleSchedule::from(&wl_data);
 
+		// Registration closes as the sale opens, so the windows never overlap
+		wl_data.registration_timestamp = registration_start;
+		wl_data.registration_duration = sale_start - registration_start;
+		wl_data.sale_timestamp = sale_start;
+		wl_data.sale_duration = sale_end - sale_start;
+
+		wl_data.check_times()?;
 		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
-		Ok(())
+
+		msg!(
+			"Schedule set: registration from {}, sale from {} until {}",
+			registration_start,
+			sale_start,
+			sale_end
+		);
+		WhitelistEvent::TimesAmended {
+			whitelist: *whitelist_account.key,
+			old: old_schedule,
+			new: SaleSchedule::from(&wl_data),
+		}
+		.emit()
 	}
 
 	fn process_allow_register(accounts: &[AccountInfo], allow_registration: bool) -> ProgramResult {
-		let accounts_iter = &mut accounts.iter();
-		let whitelist_account = next_account_info(accounts_iter)?;
-		let authority = next_account_info(accounts_iter)?;
+		let whitelist_account = account_at(accounts, allow_register::WHITELIST)?;
+		let authority = account_at(accounts, allow_register::AUTHORITY)?;
 
-		let mut wl_data = Whitelist::try_from_slice(&whitelist_account.data.borrow()[..])?;
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
 
 		if !authority.is_signer || authority.key != &wl_data.authority {
 			return Err(WhitelistError::Unauthorised.into());
@@ -449,253 +852,440 @@ impl Processor {
 		Ok(())
 	}
 
-	fn process_register(accounts: &[AccountInfo]) -> ProgramResult {
+	fn process_register(
+		accounts: &[AccountInfo],
+		referrer: Option<Pubkey>,
+		nonce: Option<u64>,
+	) -> ProgramResult {
 		msg!("Process: Register");
-		let accounts_iter = &mut accounts.iter();
-		let whitelist_account = next_account_info(accounts_iter)?;
-		let user_account = next_account_info(accounts_iter)?;
-		let user_ticket_account = next_account_info(accounts_iter)?;
-		let system_program = next_account_info(accounts_iter)?;
+		let whitelist_account = account_at(accounts, register::WHITELIST)?;
+		let user_account = account_at(accounts, register::USER)?;
+		let user_ticket_account = account_at(accounts, register::USER_TICKET)?;
+		let system_program = account_at(accounts, register::SYSTEM_PROGRAM)?;
 
 		let clock = Clock::get()?;
 
-		let wl_data = Whitelist::try_from_slice(&whitelist_account.data.borrow()[..])?;
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
 		let (_user_ticket, user_bump) =
 			get_user_ticket_address(&user_account.key, &whitelist_account.key);
 
 		if wl_data.registration_timestamp > 0
 			&& wl_data.registration_timestamp > clock.unix_timestamp
 		{
+			msg!(
+				"Register rejected: registration starts at {}, current time {}",
+				wl_data.registration_timestamp,
+				clock.unix_timestamp
+			);
 			return Err(WhitelistError::RegistrationNotStarted.into());
 		}
 
+		// A `registration_duration` of 0 leaves registration open indefinitely
 		if wl_data.registration_timestamp > 0
-			&& wl_data.registration_timestamp + wl_data.registration_duration > clock.unix_timestamp
+			&& wl_data.registration_duration > 0
+			&& wl_data.registration_timestamp + wl_data.registration_duration
+				<= clock.unix_timestamp
 		{
+			msg!(
+				"Register rejected: registration ended at {}, current time {}",
+				wl_data.registration_timestamp + wl_data.registration_duration,
+				clock.unix_timestamp
+			);
 			return Err(WhitelistError::RegistrationFinished.into());
 		}
 
-		if user_ticket_account.owner != &crate::id() {
-			let rent = Rent::get()?;
-			invoke_signed(
-				&system_instruction::create_account(
-					user_account.key,
-					user_ticket_account.key,
-					rent.minimum_balance(Ticket::LEN)
-						.max(1)
-						.saturating_sub(user_ticket_account.lamports()),
-					Ticket::LEN as u64,
-					&crate::id(),
-				),
-				&[
-					user_account.clone(),
-					user_ticket_account.clone(),
-					system_program.clone(),
-				],
-				&[&[
-					SEED,
-					user_account.key.as_ref(),
-					whitelist_account.key.as_ref(),
-					&[user_bump],
-				]],
-			)?;
-		}
-
-		let ticket_data = Ticket {
-			bump: user_bump,
-			whitelist: *whitelist_account.key,
-			owner: *user_account.key,
-			allowance: wl_data.buy_limit,
-			payer: *user_account.key,
-			amount_bought: 0,
-		};
-
-		ticket_data.serialize(&mut &mut user_ticket_account.data.borrow_mut()[..])?;
-
-		msg!("Registration successful");
-		Ok(())
-	}
-
-	fn process_unregister(accounts: &[AccountInfo]) -> ProgramResult {
-		msg!("Process: Unregister");
-		let accounts_iter = &mut accounts.iter();
-		let whitelist_account = next_account_info(accounts_iter)?;
-		let authority = next_account_info(accounts_iter)?;
-		let vault = next_account_info(accounts_iter)?;
-		let mint = next_account_info(accounts_iter)?;
-		let user_account = next_account_info(accounts_iter)?;
-		let user_ticket_account = next_account_info(accounts_iter)?;
-		let ticket_token_account = next_account_info(accounts_iter)?;
-		let token_program = next_account_info(accounts_iter)?;
-		let system_program = next_account_info(accounts_iter)?;
-
-		let clock = Clock::get()?;
-
-		let (user_ticket, user_bump) =
-			get_user_ticket_address(&user_account.key, &whitelist_account.key);
-
-		let wl_data = Whitelist::try_from_slice(&whitelist_account.data.borrow()[..])?;
-		let ticket_data = Ticket::try_from_slice(&user_ticket_account.data.borrow()[..])?;
-
-		if authority.key != &wl_data.authority {
-			return Err(WhitelistError::AccountMismatch.into());
+		if wl_data.registration_paused {
+			msg!("Register rejected: registration is paused");
+			return Err(WhitelistError::RegistrationPaused.into());
 		}
 
-		if vault.key != &wl_data.vault {
-			return Err(WhitelistError::IncorrectVaultAddress.into());
+		if !user_account.is_signer {
+			msg!("Register rejected: user {} did not sign", user_account.key);
+			return Err(WhitelistError::SignerError.into());
 		}
 
-		if mint.key != &wl_data.mint {
-			return Err(WhitelistError::IncorrectMintAddress.into());
+		// Registering again would overwrite a live ticket, resetting its purchases, cooldown,
+		// payer and any bonus or allocation it holds
+		if user_ticket_account.owner == &crate::id() {
+			msg!(
+				"Register rejected: user {} is already registered",
+				user_account.key
+			);
+			return Err(WhitelistError::WhitelistAlreadyInitialized.into());
 		}
 
-		if user_account.key != &ticket_data.owner {
-			return Err(WhitelistError::Unauthorised.into());
+		// A gated whitelist only registers users presenting a voucher from its register authority
+		if let Some(register_authority) = wl_data.register_authority() {
+			let nonce = match nonce {
+				Some(nonce) => nonce,
+				None => {
+					msg!(
+						"Register rejected: registration requires a voucher signed by {}",
+						register_authority
+					);
+					return Err(WhitelistError::SignatureRequired.into());
+				}
+			};
+			check_register_voucher(
+				account_at(accounts, register_with_signature::INSTRUCTIONS_SYSVAR)?,
+				&register_authority,
+				&register_voucher_message(user_account.key, whitelist_account.key, nonce),
+			)?;
 		}
 
-		let payer_account = if &ticket_data.payer == authority.key {
+		// The authority co-signs registrations on whitelists where it pays the ticket's rent
+		let rent_payer = if wl_data.authority_pays_rent {
+			let authority = account_at(
+				accounts,
+				match (referrer, nonce) {
+					(Some(_), _) => register_with_referral::AUTHORITY,
+					(None, Some(_)) => register_with_signature::AUTHORITY,
+					(None, None) => register::AUTHORITY,
+				},
+			)?;
+			if !authority.is_signer || authority.key != &wl_data.authority {
+				msg!("Register rejected: rent is paid by the authority, which must sign");
+				return Err(WhitelistError::Unauthorised.into());
+			}
+			authority
+		} else {
+			user_account
+		};
+
+		wl_data.register_ticket().map_err(|err| {
+			msg!(
+				"Register rejected: {} tickets already registered, size {}, ceiling {}",
+				wl_data.registered,
+				wl_data.whitelist_size,
+				wl_data.max_tickets
+			);
+			err
+		})?;
+
+		let rent = Rent::get()?;
+		invoke_signed(
+			&system_instruction::create_account(
+				rent_payer.key,
+				user_ticket_account.key,
+				rent.minimum_balance(Ticket::space())
+					.max(1)
+					.saturating_sub(user_ticket_account.lamports()),
+				Ticket::space() as u64,
+				&crate::id(),
+			),
+			&[
+				rent_payer.clone(),
+				user_ticket_account.clone(),
+				system_program.clone(),
+			],
+			&[&[
+				SEED,
+				user_account.key.as_ref(),
+				whitelist_account.key.as_ref(),
+				&[user_bump],
+			]],
+		)?;
+
+		// An allocation set by the authority ahead of registration overrides `buy_limit`. The
+		// account is required and checked against the derived address so a user cannot leave
+		// out a lower allocation to register with the full `buy_limit`
+		let allocation_account = account_at(
+			accounts,
+			match (referrer, nonce) {
+				(Some(_), _) => register_with_referral::ALLOCATION,
+				(None, Some(_)) => register_with_signature::ALLOCATION,
+				(None, None) => register::ALLOCATION,
+			},
+		)?;
+		let (allocation, _) = get_allocation_address(user_account.key, whitelist_account.key);
+		if allocation_account.key != &allocation {
+			msg!(
+				"Register rejected: {} does not match derived allocation {}",
+				allocation_account.key,
+				allocation
+			);
+			return Err(WhitelistError::AccountMismatch.into());
+		}
+		let allowance = if allocation_account.owner == &crate::id() {
+			Allocation::unpack(&allocation_account.data.borrow())?.allowance
+		} else {
+			wl_data.buy_limit
+		};
+
+		let ticket_data = Ticket {
+			version: Ticket::VERSION,
+			bump: user_bump,
+			whitelist: *whitelist_account.key,
+			owner: *user_account.key,
+			allowance,
+			payer: *rent_payer.key,
+			amount_bought: 0,
+			claimed: 0,
+			note: [0; Ticket::NOTE_LEN],
+			referrer: referrer.unwrap_or_default(),
+			last_purchase_ts: 0,
+		};
+
+		if let Some(referrer) = referrer {
+			let referrer_ticket_account =
+				account_at(accounts, register_with_referral::REFERRER_TICKET)?;
+			let (referrer_ticket, _) = get_user_ticket_address(&referrer, whitelist_account.key);
+
+			if referrer == *user_account.key
+				|| referrer_ticket_account.key != &referrer_ticket
+				|| referrer_ticket_account.owner != &crate::id()
+			{
+				msg!(
+					"Register rejected: referrer {} has no ticket on this whitelist",
+					referrer
+				);
+				return Err(WhitelistError::InvalidReferrer.into());
+			}
+
+			if wl_data.referral_bonus > 0 {
+				let mut referrer_data = Ticket::unpack(&referrer_ticket_account.data.borrow())?;
+				referrer_data.allowance = referrer_data
+					.allowance
+					.checked_add(wl_data.referral_bonus)
+					.ok_or(WhitelistError::Overflow)?;
+				referrer_data.serialize(&mut &mut referrer_ticket_account.data.borrow_mut()[..])?;
+				msg!(
+					"Referrer {} credited {} allowance",
+					referrer,
+					wl_data.referral_bonus
+				);
+			}
+		}
+
+		ticket_data.serialize(&mut &mut user_ticket_account.data.borrow_mut()[..])?;
+		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
+
+		msg!("Registration successful");
+		Ok(())
+	}
+
+	fn process_unregister(accounts: &[AccountInfo]) -> ProgramResult {
+		msg!("Process: Unregister");
+		let whitelist_account = account_at(accounts, unregister::WHITELIST)?;
+		let authority = account_at(accounts, unregister::AUTHORITY)?;
+		let vault = account_at(accounts, unregister::VAULT)?;
+		let mint = account_at(accounts, unregister::MINT)?;
+		let user_account = account_at(accounts, unregister::USER)?;
+		let user_ticket_account = account_at(accounts, unregister::USER_TICKET)?;
+		let ticket_token_account = account_at(accounts, unregister::TICKET_TOKEN)?;
+		let token_program = account_at(accounts, unregister::TOKEN_PROGRAM)?;
+		let system_program = account_at(accounts, unregister::SYSTEM_PROGRAM)?;
+
+		let clock = Clock::get()?;
+
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+		let ticket_data = Ticket::unpack(&user_ticket_account.data.borrow())?;
+
+		if authority.key != &wl_data.authority {
+			msg!(
+				"Unregister rejected: authority {} does not match whitelist authority {}",
+				authority.key,
+				wl_data.authority
+			);
+			return Err(WhitelistError::AccountMismatch.into());
+		}
+
+		if vault.key != &wl_data.vault {
+			msg!(
+				"Unregister rejected: vault {} does not match whitelist vault {}",
+				vault.key,
+				wl_data.vault
+			);
+			return Err(WhitelistError::IncorrectVaultAddress.into());
+		}
+		check_distinct(ticket_token_account, vault)?;
+
+		if mint.key != &wl_data.mint {
+			msg!(
+				"Unregister rejected: mint {} does not match whitelist mint {}",
+				mint.key,
+				wl_data.mint
+			);
+			return Err(WhitelistError::IncorrectMintAddress.into());
+		}
+
+		if user_account.key != &ticket_data.owner {
+			msg!(
+				"Unregister rejected: user {} is not the ticket owner {}",
+				user_account.key,
+				ticket_data.owner
+			);
+			return Err(WhitelistError::Unauthorised.into());
+		}
+		if !user_account.is_signer {
+			msg!(
+				"Unregister rejected: user {} did not sign",
+				user_account.key
+			);
+			return Err(WhitelistError::SignerError.into());
+		}
+
+		let payer_account = if &ticket_data.payer == authority.key {
 			authority
 		} else if &ticket_data.payer == user_account.key {
 			user_account
 		} else {
+			msg!(
+				"Unregister rejected: ticket payer {} is neither the authority nor the user",
+				ticket_data.payer
+			);
 			return Err(WhitelistError::IncorrectPayer.into());
 		};
 
-		if user_ticket_account.key != &user_ticket || user_bump != ticket_data.bump {
-			return Err(WhitelistError::IncorrectUserAccount.into());
-		}
+		Ticket::derive_and_verify(
+			user_account.key,
+			whitelist_account.key,
+			user_ticket_account.key,
+			ticket_data.bump,
+		)?;
 
 		if token_program.key != &spl_token_2022::id() && token_program.key != &spl_token::id() {
-			return Err(ProgramError::IncorrectProgramId);
+			msg!("Unregister rejected: {} is not a token program", token_program.key);
+			return Err(WhitelistError::IncorrectTokenProgram.into());
 		}
 		if system_program.key != &system_program::id() {
-			return Err(ProgramError::IncorrectProgramId);
+			msg!("Unregister rejected: {} is not the system program", system_program.key);
+			return Err(WhitelistError::IncorrectSystemProgram.into());
 		}
 
 		// As this PDA is expected to hold funds, and registration spaces are limited, a user
 		// should only be able to unregister during the registration period, if the registration
 		// period is occuring in parallel to the the sale period then a user should not be able to
-		// unregister, we could check for lamports in excess of the minimum balance, but it is
-		// simpler to not permit the user to unregister once a token sale has begun.
-		if (wl_data.registration_timestamp > 0
+		// unregister. A sale without a start time is live from initialisation, so its tickets can
+		// never be unregistered
+		if wl_data.registration_timestamp > clock.unix_timestamp {
+			msg!(
+				"Unregister rejected: registration starts at {}, current time {}",
+				wl_data.registration_timestamp,
+				clock.unix_timestamp
+			);
+			return Err(WhitelistError::CannotUnregister.into());
+		}
+		if wl_data.registration_timestamp > 0
+			&& wl_data.registration_duration > 0
 			&& wl_data.registration_timestamp + wl_data.registration_duration
-				> clock.unix_timestamp)
-			|| wl_data.registration_duration == 0
-			|| wl_data.sale_duration == 0
+				<= clock.unix_timestamp
 		{
+			msg!(
+				"Unregister rejected: registration ended at {}, current time {}",
+				wl_data.registration_timestamp + wl_data.registration_duration,
+				clock.unix_timestamp
+			);
+			return Err(WhitelistError::CannotUnregister.into());
+		}
+		if wl_data.sale_timestamp <= clock.unix_timestamp {
+			msg!(
+				"Unregister rejected: the sale started at {}, current time {}",
+				wl_data.sale_timestamp,
+				clock.unix_timestamp
+			);
 			return Err(WhitelistError::CannotUnregister.into());
 		}
 
-		// Check if the ticket token account exists if it does, we will transfer all the tokens
-		// back to the vault and then lamports back to the authority
-		if ticket_token_account.owner != &spl_token_2022::id()
-			&& ticket_token_account.owner != &spl_token::id()
-		{
-			let borrowed_ticket_token_account_data = ticket_token_account.data.borrow();
-
-			let ticket_token_account_data =
-				StateWithExtensions::<Account>::unpack(&borrowed_ticket_token_account_data)?;
-			let borrowed_mint_data = mint.data.borrow();
-			let mint_data = StateWithExtensions::<Mint>::unpack(&borrowed_mint_data)?;
+		// The payer is only owed the rent, purchase SOL above it belongs to the sale
+		let proceeds = user_ticket_account
+			.lamports()
+			.saturating_sub(Rent::get()?.minimum_balance(user_ticket_account.data_len()));
+		if proceeds > 0 {
+			msg!(
+				"Unregister rejected: the ticket holds {} lamports of purchase SOL",
+				proceeds
+			);
+			return Err(WhitelistError::TicketHoldsProceeds.into());
+		}
 
-			if ticket_token_account_data.base.amount > 0 {
-				//Transfer tokens
-				//TODO: CHECK
-				invoke_signed(
-					&spl_token_2022::instruction::transfer_checked(
-						&spl_token_2022::id(),
-						ticket_token_account.key,
-						mint.key,
-						vault.key,
-						whitelist_account.key,
-						&[whitelist_account.key],
-						ticket_token_account_data.base.amount,
-						mint_data.base.decimals,
-					)?,
-					&[
-						ticket_token_account.clone(),
-						mint.clone(),
-						vault.clone(),
-						whitelist_account.clone(),
-						token_program.clone(),
-					],
-					&[&[SEED, mint.key.as_ref(), &[wl_data.bump]]],
-				)?;
-			}
+		let ticket_ata = spl_associated_token_account::get_associated_token_address_with_program_id(
+			user_ticket_account.key,
+			mint.key,
+			token_program.key,
+		);
+		if ticket_token_account.key != &ticket_ata {
+			msg!(
+				"Unregister rejected: ticket token account {} is not the ticket's ATA {}",
+				ticket_token_account.key,
+				ticket_ata
+			);
+			return Err(WhitelistError::IncorrectUserAccount.into());
+		}
 
-			// Close the account
-			invoke_signed(
-				&spl_token_2022::instruction::close_account(
-					&spl_token_2022::id(),
-					ticket_token_account.key,
-					authority.key,
-					user_ticket_account.key,
-					&[whitelist_account.key],
-				)?,
-				&[
-					ticket_token_account.clone(),
-					authority.clone(),
-					user_ticket_account.clone(),
-					token_program.clone(),
-				],
-				&[&[SEED, mint.key.as_ref(), &[wl_data.bump]]],
-			)?;
+		// The ticket's tokens go back to the vault and its token account's rent to the authority
+		let token_amount = return_ticket_tokens(
+			whitelist_account.key,
+			&wl_data,
+			&ticket_data,
+			user_ticket_account,
+			ticket_token_account,
+			vault,
+			mint,
+			authority,
+			token_program,
+		)?;
+		if token_amount > 0 {
+			msg!("Returned {} tokens to the vault", token_amount);
 		}
 
-		let user_lamports = user_ticket_account.lamports();
+		wl_data.unregister_ticket();
+		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
 
-		user_ticket_account.assign(&system_program::id());
-		user_ticket_account.realloc(0, false)?;
-		invoke_signed(
-			&system_instruction::transfer(
-				user_ticket_account.key,
-				payer_account.key,
-				user_lamports,
-			),
-			&[
-				user_ticket_account.clone(),
-				payer_account.clone(),
-				system_program.clone(),
-			],
-			&[&[
-				SEED,
-				user_account.key.as_ref(),
-				whitelist_account.key.as_ref(),
-				&[user_bump],
-			]],
-		)?;
+		let user_lamports = close_program_account(user_ticket_account, payer_account)?;
 
 		msg!("User unregistered reclaimed: {} lamports", user_lamports);
 		Ok(())
 	}
 
-	fn process_buy(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+	fn process_buy(
+		accounts: &[AccountInfo],
+		amount: u64,
+		max_price: Option<u64>,
+		allow_partial: bool,
+	) -> ProgramResult {
 		msg!("Process: Buy");
-		let accounts_iter = &mut accounts.iter();
-		let whitelist_account = next_account_info(accounts_iter)?;
-		let vault = next_account_info(accounts_iter)?;
-		let mint = next_account_info(accounts_iter)?;
-		let user_account = next_account_info(accounts_iter)?;
-		let user_ticket_account = next_account_info(accounts_iter)?;
-		let ticket_token_account = next_account_info(accounts_iter)?;
-		let user_token_account = next_account_info(accounts_iter)?;
-		let token_program = next_account_info(accounts_iter)?;
-		let system_program = next_account_info(accounts_iter)?;
-		let assc_token_program = next_account_info(accounts_iter)?;
+		let whitelist_account = account_at(accounts, buy::WHITELIST)?;
+		let vault = account_at(accounts, buy::VAULT)?;
+		let mint = account_at(accounts, buy::MINT)?;
+		let user_account = account_at(accounts, buy::USER)?;
+		let user_ticket_account = account_at(accounts, buy::USER_TICKET)?;
+		let ticket_token_account = account_at(accounts, buy::TICKET_TOKEN)?;
+		let user_token_account = account_at(accounts, buy::USER_TOKEN)?;
+		let token_program = account_at(accounts, buy::TOKEN_PROGRAM)?;
+		let system_program = account_at(accounts, buy::SYSTEM_PROGRAM)?;
+		let assc_token_program = account_at(accounts, buy::ASSOCIATED_TOKEN_PROGRAM)?;
 
 		let clock = Clock::get()?;
 
-		let wl_data = Whitelist::try_from_slice(&whitelist_account.data.borrow()[..])?;
-		let mut ticket_data = Ticket::try_from_slice(&user_ticket_account.data.borrow()[..])?;
+		let wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+		let mut ticket_data = Ticket::unpack(&user_ticket_account.data.borrow())?;
 
 		if vault.key != &wl_data.vault {
+			msg!(
+				"Buy rejected: vault {} does not match whitelist vault {}",
+				vault.key,
+				wl_data.vault
+			);
 			return Err(WhitelistError::IncorrectVaultAddress.into());
 		}
 
 		if mint.key != &wl_data.mint {
+			msg!(
+				"Buy rejected: mint {} does not match whitelist mint {}",
+				mint.key,
+				wl_data.mint
+			);
 			return Err(WhitelistError::IncorrectMintAddress.into());
 		}
 
+		Ticket::derive_and_verify(
+			user_account.key,
+			whitelist_account.key,
+			user_ticket_account.key,
+			ticket_data.bump,
+		)?;
+
 		let ticket_account_token_amount = {
 			if ticket_token_account.owner == &spl_token_2022::id()
 				|| ticket_token_account.owner == &spl_token::id()
@@ -709,50 +1299,145 @@ impl Processor {
 			}
 		};
 
-		let (mint_decimals, token_amount) = {
-			let borrowed_mint_data = mint.data.borrow();
-			let mint_data = StateWithExtensions::<Mint>::unpack(&borrowed_mint_data)?;
-			(
-				mint_data.base.decimals,
-				spl_token_2022::ui_amount_to_amount(amount as f64, mint_data.base.decimals),
-			)
-		};
+		let mint_decimals = whitelist_mint_decimals(mint, &wl_data)?;
+		let mut token_amount = spl_token_2022::ui_amount_to_amount(amount as f64, mint_decimals);
+
+		// Nothing would change hands, but an empty user token account could still be created
+		if token_amount == 0 {
+			msg!("Buy rejected: amount is zero");
+			return Err(WhitelistError::ZeroAmount.into());
+		}
 
 		if !user_account.is_signer {
+			msg!("Buy rejected: user {} did not sign", user_account.key);
 			return Err(WhitelistError::SignerError.into());
 		}
 
-		{
-			let borrowed_vault_data = vault.data.borrow();
-			let vault_data = StateWithExtensions::<Account>::unpack(&borrowed_vault_data)?;
-			if vault_data.base.amount < token_amount {
-				return Err(WhitelistError::InsufficientFunds.into());
+		if let Some(max_price) = max_price {
+			if wl_data.token_price > max_price {
+				msg!(
+					"Buy rejected: price {} > maximum price {}",
+					wl_data.token_price,
+					max_price
+				);
+				return Err(WhitelistError::PriceExceeded.into());
 			}
 		}
 
-		let sol_amount = match token_amount.checked_mul(wl_data.token_price) {
-			Some(x) => x,
-			None => return Err(WhitelistError::Overflow.into()),
-		};
+		// The remaining checks run in a fixed order so that a buy failing several of them always
+		// reports the same error: paused, then sale phase, then a frozen vault, then the ticket's
+		// cooldown and allowance, then the vault balance and finally the buyer's SOL
+		if wl_data.sale_paused {
+			msg!("Buy rejected: the sale is paused");
+			return Err(WhitelistError::SalePaused.into());
+		}
 
 		if wl_data.sale_timestamp > 0 && wl_data.sale_timestamp > clock.unix_timestamp {
+			msg!(
+				"Buy rejected: sale starts at {}, current time {}",
+				wl_data.sale_timestamp,
+				clock.unix_timestamp
+			);
 			return Err(WhitelistError::SaleNotStarted.into());
 		}
 
-		if wl_data.sale_timestamp > 0
-			&& wl_data.sale_timestamp + wl_data.sale_duration > clock.unix_timestamp
-		{
+		if wl_data.sale_ended(clock.unix_timestamp) {
+			msg!(
+				"Buy rejected: sale ended at {}, current time {}",
+				wl_data.sale_timestamp + wl_data.sale_duration,
+				clock.unix_timestamp
+			);
 			return Err(WhitelistError::SaleEnded.into());
 		}
 
-		if ticket_data.allowance - ticket_data.amount_bought < token_amount {
+		check_vault_not_frozen(vault)?;
+
+		if let Some(until) =
+			wl_data.cooldown_until(ticket_data.last_purchase_ts, clock.unix_timestamp)
+		{
+			msg!(
+				"Buy rejected: ticket is cooling down until {}, current time {}",
+				until,
+				clock.unix_timestamp
+			);
+			return Err(WhitelistError::Cooldown.into());
+		}
+
+		let remaining_allowance = ticket_data.allowance.saturating_sub(ticket_data.amount_bought);
+		if remaining_allowance < token_amount {
+			msg!(
+				"Buy rejected: remaining allowance {} < requested {}",
+				remaining_allowance,
+				token_amount
+			);
 			return Err(WhitelistError::BuyLimitExceeded.into());
 		}
 
-		// We'll check for a `user_token_account` and create one if it doesn't exist
-		if user_token_account.owner != &spl_token_2022::id()
-			&& user_token_account.owner != &spl_token::id()
 		{
+			let borrowed_vault_data = vault.data.borrow();
+			let vault_data = StateWithExtensions::<Account>::unpack(&borrowed_vault_data)?;
+			// Tokens already moved to the ticket with `TransferTokens` are delivered first, the
+			// vault only has to cover the rest
+			let available = vault_data
+				.base
+				.amount
+				.saturating_add(ticket_account_token_amount);
+			// A partial fill takes whatever is available, nothing available still fails
+			if allow_partial && available > 0 && available < token_amount {
+				msg!("Partial fill: {} of {} requested", available, token_amount);
+				token_amount = available;
+			}
+			if available < token_amount {
+				msg!(
+					"Buy rejected: vault balance {} + ticket balance {} < requested {}",
+					vault_data.base.amount,
+					ticket_account_token_amount,
+					token_amount
+				);
+				return Err(WhitelistError::InsufficientFunds.into());
+			}
+		}
+
+		let sol_amount = match buy_cost(token_amount, wl_data.token_price, mint_decimals) {
+			Ok(x) => x,
+			Err(err) => {
+				msg!(
+					"Buy rejected: price {} * amount {} overflows",
+					wl_data.token_price,
+					token_amount
+				);
+				return Err(err.into());
+			}
+		};
+
+		let create_user_token_account = user_token_account.owner != &spl_token_2022::id()
+			&& user_token_account.owner != &spl_token::id();
+
+		// Check the buyer can pay up front rather than failing inside the system transfer, the
+		// buffer covers the rent of a newly created token account (sized for Token-2022 which is
+		// the larger of the two)
+		let fee_buffer = if create_user_token_account {
+			Rent::get()?.minimum_balance(ExtensionType::try_calculate_account_len::<Account>(&[
+				ExtensionType::ImmutableOwner,
+			])?)
+		} else {
+			0
+		};
+		let required_lamports = match sol_amount.checked_add(fee_buffer) {
+			Some(x) => x,
+			None => return Err(WhitelistError::Overflow.into()),
+		};
+		if user_account.lamports() < required_lamports {
+			msg!(
+				"Buy rejected: buyer balance {} < required {} lamports",
+				user_account.lamports(),
+				required_lamports
+			);
+			return Err(WhitelistError::InsufficientSol.into());
+		}
+
+		// We'll check for a `user_token_account` and create one if it doesn't exist
+		if create_user_token_account {
 			invoke(
 				&spl_associated_token_account::instruction::create_associated_token_account(
 					user_account.key,
@@ -778,26 +1463,27 @@ impl Processor {
 			&[user_account.clone(), user_ticket_account.clone()],
 		)?;
 
-		// We check to see if the tokens already exist in the ticket token account
-		// if they do we transfer from that account to the user's token account, if they don't
-		// we must transfer from the vault
-		if ticket_account_token_amount > 0 {
+		// Tokens already held by the ticket token account are delivered first, the vault only
+		// sends what they fall short of so the user receives exactly `token_amount`
+		let from_ticket = ticket_account_token_amount.min(token_amount);
+		let from_vault = token_amount - from_ticket;
+		if from_ticket > 0 {
 			invoke_signed(
 				&spl_token_2022::instruction::transfer_checked(
 					token_program.key,
 					ticket_token_account.key,
 					mint.key,
 					user_token_account.key,
-					whitelist_account.key,
+					user_ticket_account.key,
 					&[],
-					token_amount,
+					from_ticket,
 					mint_decimals,
 				)?,
 				&[
 					ticket_token_account.clone(),
 					mint.clone(),
 					user_token_account.clone(),
-					whitelist_account.clone(),
+					user_ticket_account.clone(),
 				],
 				&[&[
 					SEED,
@@ -807,66 +1493,103 @@ impl Processor {
 				]],
 			)?;
 		}
-		invoke_signed(
-			&spl_token_2022::instruction::transfer_checked(
-				token_program.key,
-				vault.key,
-				mint.key,
-				user_token_account.key,
-				whitelist_account.key,
-				&[],
-				token_amount,
-				mint_decimals,
-			)?,
-			&[
-				vault.clone(),
-				mint.clone(),
-				user_token_account.clone(),
-				whitelist_account.clone(),
-			],
-			&[&[SEED, mint.key.as_ref(), &[wl_data.bump]]],
-		)?;
+		if from_vault > 0 {
+			invoke_signed(
+				&spl_token_2022::instruction::transfer_checked(
+					token_program.key,
+					vault.key,
+					mint.key,
+					user_token_account.key,
+					whitelist_account.key,
+					&[],
+					from_vault,
+					mint_decimals,
+				)?,
+				&[
+					vault.clone(),
+					mint.clone(),
+					user_token_account.clone(),
+					whitelist_account.clone(),
+				],
+				&[&[SEED, mint.key.as_ref(), &[wl_data.bump]]],
+			)?;
+		}
 
 		ticket_data.amount_bought = match ticket_data.amount_bought.checked_add(token_amount) {
 			Some(x) => x,
 			None => return Err(WhitelistError::Overflow.into()),
 		};
+		// Tokens are delivered as part of the purchase so they are released immediately
+		ticket_data.record_claim(token_amount)?;
+		ticket_data.last_purchase_ts = clock.unix_timestamp;
 		ticket_data.serialize(&mut &mut user_ticket_account.data.borrow_mut()[..])?;
-		msg!("Bought: {}", amount);
+		msg!(
+			"Bought: {} ({} from ticket, {} from vault)",
+			token_amount,
+			from_ticket,
+			from_vault
+		);
 		Ok(())
 	}
 
 	fn process_deposit_tokens(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
 		msg!("Process: Deposit");
-		let accounts_iter = &mut accounts.iter();
-		let whitelist_account = next_account_info(accounts_iter)?;
-		let vault = next_account_info(accounts_iter)?;
-		let depositor_account = next_account_info(accounts_iter)?;
-		let depositor_token_account = next_account_info(accounts_iter)?;
-		let mint = next_account_info(accounts_iter)?;
-		let token_program = next_account_info(accounts_iter)?;
+		Self::deposit(accounts, amount)?;
+		Ok(())
+	}
 
-		let mut wl_data = Whitelist::try_from_slice(&whitelist_account.data.borrow()[..])?;
+	// Shared by `DepositTokens` and `DepositFor`, returns the amount actually deposited after
+	// any excess over what is needed to fill every ticket has been trimmed
+	fn deposit(accounts: &[AccountInfo], amount: u64) -> Result<u64, ProgramError> {
+		let whitelist_account = account_at(accounts, deposit_tokens::WHITELIST)?;
+		let vault = account_at(accounts, deposit_tokens::VAULT)?;
+		let depositor_account = account_at(accounts, deposit_tokens::DEPOSITOR)?;
+		let depositor_token_account = account_at(accounts, deposit_tokens::DEPOSITOR_TOKEN)?;
+		let mint = account_at(accounts, deposit_tokens::MINT)?;
+		let token_program = account_at(accounts, deposit_tokens::TOKEN_PROGRAM)?;
 
-		let (mint_decimals, mut token_amount) = {
-			let borrowed_mint_data = mint.data.borrow();
-			let mint_data = StateWithExtensions::<Mint>::unpack(&borrowed_mint_data)?;
-			(
-				mint_data.base.decimals,
-				spl_token_2022::ui_amount_to_amount(amount as f64, mint_data.base.decimals),
-			)
-		};
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+		if mint.key != &wl_data.mint {
+			msg!(
+				"Deposit rejected: mint {} does not match whitelist mint {}",
+				mint.key,
+				wl_data.mint
+			);
+			return Err(WhitelistError::IncorrectMintAddress.into());
+		}
+
+		let mint_decimals = whitelist_mint_decimals(mint, &wl_data)?;
+		let mut token_amount = spl_token_2022::ui_amount_to_amount(amount as f64, mint_decimals);
 
 		let (wl, wl_bump) = get_whitelist_address(mint.key);
 
 		if whitelist_account.key != &wl || wl_bump != wl_data.bump {
+			msg!(
+				"Deposit rejected: whitelist {} does not match derived whitelist {}",
+				whitelist_account.key,
+				wl
+			);
 			return Err(WhitelistError::InvalidWhitelistAddress.into());
 		}
 
 		if !depositor_account.is_signer {
+			msg!("Deposit rejected: depositor {} did not sign", depositor_account.key);
 			return Err(WhitelistError::SignerError.into());
 		}
 
+		// Tokens deposited into a finished sale could only leave through `WithdrawTokens` or
+		// `TerminateWhitelist`, so a late deposit is almost certainly a mistake
+		let clock = Clock::get()?;
+		if wl_data.sale_ended(clock.unix_timestamp) {
+			msg!(
+				"Deposit rejected: sale ended at {}, current time {}",
+				wl_data.sale_timestamp + wl_data.sale_duration,
+				clock.unix_timestamp
+			);
+			return Err(WhitelistError::SaleEnded.into());
+		}
+
 		if vault.key != &wl_data.vault
 			|| vault.key
 				!= &spl_associated_token_account::get_associated_token_address_with_program_id(
@@ -874,15 +1597,17 @@ impl Processor {
 					mint.key,
 					token_program.key,
 				) {
+			msg!(
+				"Deposit rejected: vault {} does not match whitelist vault {}",
+				vault.key,
+				wl_data.vault
+			);
 			return Err(WhitelistError::IncorrectVaultAddress.into());
 		}
 
-		if mint.key != &wl_data.mint {
-			return Err(WhitelistError::IncorrectMintAddress.into());
-		}
-
-		if token_program.key != &spl_token_2022::id() && token_program.key != &spl_token::id() {
-			return Err(ProgramError::IncorrectProgramId);
+		if token_program.key != &spl_token_2022::id() && token_program.key != &spl_token::id() {
+			msg!("Deposit rejected: {} is not a token program", token_program.key);
+			return Err(WhitelistError::IncorrectTokenProgram.into());
 		}
 
 		// Checks if the deposited amount will exceed the amount of tokens necessary to fulfil all
@@ -944,18 +1669,195 @@ automatically setting the deposited token amount to fulfill the maximum required
 		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
 
 		msg!("Deposited: {}", token_amount);
+		Ok(token_amount)
+	}
+
+	fn process_deposit_for(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+		msg!("Process: Deposit for");
+		let whitelist_account = account_at(accounts, deposit_for::WHITELIST)?;
+		let depositor_account = account_at(accounts, deposit_for::DEPOSITOR)?;
+		let ledger_account = account_at(accounts, deposit_for::DEPOSITOR_LEDGER)?;
+		let system_program = account_at(accounts, deposit_for::SYSTEM_PROGRAM)?;
+
+		let (ledger, ledger_bump) =
+			get_depositor_address(depositor_account.key, whitelist_account.key);
+		if ledger_account.key != &ledger {
+			msg!(
+				"Deposit rejected: ledger {} does not match derived ledger {}",
+				ledger_account.key,
+				ledger
+			);
+			return Err(WhitelistError::AccountMismatch.into());
+		}
+
+		if system_program.key != &system_program::id() {
+			msg!("Deposit rejected: {} is not the system program", system_program.key);
+			return Err(WhitelistError::IncorrectSystemProgram.into());
+		}
+
+		let mut ledger_data = if ledger_account.owner == &crate::id() {
+			Depositor::unpack(&ledger_account.data.borrow())?
+		} else {
+			let rent = Rent::get()?;
+			invoke_signed(
+				&system_instruction::create_account(
+					depositor_account.key,
+					ledger_account.key,
+					rent.minimum_balance(Depositor::space())
+						.max(1)
+						.saturating_sub(ledger_account.lamports()),
+					Depositor::space() as u64,
+					&crate::id(),
+				),
+				&[
+					depositor_account.clone(),
+					ledger_account.clone(),
+					system_program.clone(),
+				],
+				&[&[
+					SEED,
+					DEPOSITOR_SEED,
+					depositor_account.key.as_ref(),
+					whitelist_account.key.as_ref(),
+					&[ledger_bump],
+				]],
+			)?;
+
+			Depositor {
+				version: Depositor::VERSION,
+				bump: ledger_bump,
+				whitelist: *whitelist_account.key,
+				owner: *depositor_account.key,
+				amount: 0,
+			}
+		};
+
+		let token_amount = Self::deposit(accounts, amount)?;
+
+		ledger_data.amount = match ledger_data.amount.checked_add(token_amount) {
+			Some(x) => x,
+			None => return Err(WhitelistError::Overflow.into()),
+		};
+		ledger_data.serialize(&mut &mut ledger_account.data.borrow_mut()[..])?;
+
+		msg!("Credited {} to depositor {}", token_amount, depositor_account.key);
+		Ok(())
+	}
+
+	fn process_withdraw_deposit(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+		msg!("Process: Withdraw deposit");
+		let whitelist_account = account_at(accounts, withdraw_deposit::WHITELIST)?;
+		let vault = account_at(accounts, withdraw_deposit::VAULT)?;
+		let depositor_account = account_at(accounts, withdraw_deposit::DEPOSITOR)?;
+		let depositor_token_account = account_at(accounts, withdraw_deposit::DEPOSITOR_TOKEN)?;
+		let mint = account_at(accounts, withdraw_deposit::MINT)?;
+		let token_program = account_at(accounts, withdraw_deposit::TOKEN_PROGRAM)?;
+		let ledger_account = account_at(accounts, withdraw_deposit::DEPOSITOR_LEDGER)?;
+		let system_program = account_at(accounts, withdraw_deposit::SYSTEM_PROGRAM)?;
+
+		let clock = Clock::get()?;
+
+		if whitelist_account.owner != &crate::id() {
+			return Err(WhitelistError::InvalidWhitelistAddress.into());
+		}
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+		if !depositor_account.is_signer {
+			msg!("Withdraw deposit rejected: depositor {} did not sign", depositor_account.key);
+			return Err(WhitelistError::SignerError.into());
+		}
+
+		let (ledger, _) = get_depositor_address(depositor_account.key, whitelist_account.key);
+		if ledger_account.key != &ledger || ledger_account.owner != &crate::id() {
+			msg!(
+				"Withdraw deposit rejected: {} is not the ledger of depositor {}",
+				ledger_account.key,
+				depositor_account.key
+			);
+			return Err(WhitelistError::AccountMismatch.into());
+		}
+		let mut ledger_data = Depositor::unpack(&ledger_account.data.borrow())?;
+
+		if vault.key != &wl_data.vault {
+			return Err(WhitelistError::IncorrectVaultAddress.into());
+		}
+
+		if mint.key != &wl_data.mint {
+			return Err(WhitelistError::IncorrectMintAddress.into());
+		}
+
+		if token_program.key != &spl_token_2022::id() && token_program.key != &spl_token::id() {
+			return Err(WhitelistError::IncorrectTokenProgram.into());
+		}
+
+		if system_program.key != &system_program::id() {
+			return Err(WhitelistError::IncorrectSystemProgram.into());
+		}
+
+		// Deposits are only refundable while none of them can have been sold, a sale timestamp
+		// of 0 means the sale opened at initialisation
+		if wl_data.sale_timestamp == 0 || wl_data.sale_timestamp <= clock.unix_timestamp {
+			msg!("Withdraw deposit rejected: the sale has started");
+			return Err(WhitelistError::SaleStarted.into());
+		}
+
+		let mint_decimals = whitelist_mint_decimals(mint, &wl_data)?;
+		let token_amount = spl_token_2022::ui_amount_to_amount(amount as f64, mint_decimals);
+
+		if token_amount > ledger_data.amount {
+			msg!(
+				"Withdraw deposit rejected: requested {} > deposited {}",
+				token_amount,
+				ledger_data.amount
+			);
+			return Err(WhitelistError::WithdrawalExceedsDeposit.into());
+		}
+
+		invoke_signed(
+			&spl_token_2022::instruction::transfer_checked(
+				token_program.key,
+				vault.key,
+				mint.key,
+				depositor_token_account.key,
+				whitelist_account.key,
+				&[],
+				token_amount,
+				mint_decimals,
+			)?,
+			&[
+				vault.clone(),
+				mint.clone(),
+				depositor_token_account.clone(),
+				whitelist_account.clone(),
+			],
+			&[&[SEED, mint.key.as_ref(), &[wl_data.bump]]],
+		)?;
+
+		wl_data.deposited = match wl_data.deposited.checked_sub(token_amount) {
+			Some(x) => x,
+			None => return Err(WhitelistError::Overflow.into()),
+		};
+		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
+
+		ledger_data.amount -= token_amount;
+		if ledger_data.amount > 0 {
+			ledger_data.serialize(&mut &mut ledger_account.data.borrow_mut()[..])?;
+		} else {
+			close_program_account(ledger_account, depositor_account)?;
+		}
+
+		msg!("Withdrawn deposit: {}", token_amount);
 		Ok(())
 	}
 
 	fn process_start_registration(accounts: &[AccountInfo]) -> ProgramResult {
 		msg!("Process: Start registration");
-		let accounts_iter = &mut accounts.iter();
-		let whitelist_account = next_account_info(accounts_iter)?;
-		let authority = next_account_info(accounts_iter)?;
+		let whitelist_account = account_at(accounts, start_registration::WHITELIST)?;
+		let authority = account_at(accounts, start_registration::AUTHORITY)?;
 
 		let clock = Clock::get()?;
 
-		let mut wl_data = Whitelist::try_from_slice(&whitelist_account.data.borrow()[..])?;
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
 		if !authority.is_signer || authority.key != &wl_data.authority {
 			return Err(WhitelistError::Unauthorised.into());
 		}
@@ -971,19 +1873,54 @@ automatically setting the deposited token amount to fulfill the maximum required
 		Ok(())
 	}
 
-	fn process_start_token_sale(accounts: &[AccountInfo]) -> ProgramResult {
+	fn process_start_token_sale(
+		accounts: &[AccountInfo],
+		require_fully_funded: bool,
+	) -> ProgramResult {
 		msg!("Process: Start token sale");
-		let accounts_iter = &mut accounts.iter();
-		let whitelist_account = next_account_info(accounts_iter)?;
-		let authority = next_account_info(accounts_iter)?;
+		let whitelist_account = account_at(accounts, start_token_sale::WHITELIST)?;
+		let authority = account_at(accounts, start_token_sale::AUTHORITY)?;
+		let vault = account_at(accounts, start_token_sale::VAULT)?;
 
 		let clock = Clock::get()?;
-		let mut wl_data = Whitelist::try_from_slice(&whitelist_account.data.borrow()[..])?;
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
 
 		if !authority.is_signer || authority.key != &wl_data.authority {
 			return Err(WhitelistError::Unauthorised.into());
 		}
 
+		if vault.key != &wl_data.vault {
+			return Err(WhitelistError::IncorrectVaultAddress.into());
+		}
+
+		if require_fully_funded {
+			let vault_balance = {
+				let borrowed_vault_data = vault.data.borrow();
+				StateWithExtensions::<Account>::unpack(&borrowed_vault_data)?
+					.base
+					.amount
+			};
+
+			// An unbounded whitelist can never be fully funded, require at least one full ticket
+			let required = if wl_data.whitelist_size > 0 {
+				match wl_data.whitelist_size.checked_mul(wl_data.buy_limit) {
+					Some(x) => x,
+					None => return Err(WhitelistError::Overflow.into()),
+				}
+			} else {
+				wl_data.buy_limit
+			};
+
+			if vault_balance < required {
+				msg!(
+					"Start sale rejected: vault balance {} < required {}",
+					vault_balance,
+					required
+				);
+				return Err(WhitelistError::Undersubscribed.into());
+			}
+		}
+
 		wl_data.sale_timestamp = clock.unix_timestamp;
 
 		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
@@ -993,28 +1930,20 @@ automatically setting the deposited token amount to fulfill the maximum required
 	}
 
 	fn process_transfer_tokens(accounts: &[AccountInfo]) -> ProgramResult {
-		let transfer_amount: u64;
-		let accounts_iter = &mut accounts.iter();
-		let whitelist_account = next_account_info(accounts_iter)?;
-		let authority = next_account_info(accounts_iter)?;
-		let vault = next_account_info(accounts_iter)?;
-		let mint = next_account_info(accounts_iter)?;
-		let user_account = next_account_info(accounts_iter)?;
-		let ticket_account = next_account_info(accounts_iter)?;
-		let ticket_token_account = next_account_info(accounts_iter)?;
-		let token_program = next_account_info(accounts_iter)?;
-		let system_program = next_account_info(accounts_iter)?;
-		let assc_token_program = next_account_info(accounts_iter)?;
-
-		let (ticket_addr, bump) =
-			get_user_ticket_address(&user_account.key, &whitelist_account.key);
-
-		let wl_data = Whitelist::try_from_slice(&whitelist_account.data.borrow()[..])?;
-		let borrowed_mint_data = mint.data.borrow();
-		let mint_data = StateWithExtensions::<Mint>::unpack(&borrowed_mint_data)?;
-		let borrowed_ticket_token_account_data = ticket_token_account.data.borrow();
-		let ticket_token_account_data =
-			StateWithExtensions::<Account>::unpack(&borrowed_ticket_token_account_data)?;
+		let whitelist_account = account_at(accounts, transfer_tokens::WHITELIST)?;
+		let authority = account_at(accounts, transfer_tokens::AUTHORITY)?;
+		let vault = account_at(accounts, transfer_tokens::VAULT)?;
+		let mint = account_at(accounts, transfer_tokens::MINT)?;
+		let user_account = account_at(accounts, transfer_tokens::USER)?;
+		let ticket_account = account_at(accounts, transfer_tokens::TICKET)?;
+		let ticket_token_account = account_at(accounts, transfer_tokens::TICKET_TOKEN)?;
+		let token_program = account_at(accounts, transfer_tokens::TOKEN_PROGRAM)?;
+		let system_program = account_at(accounts, transfer_tokens::SYSTEM_PROGRAM)?;
+		let assc_token_program = account_at(accounts, transfer_tokens::ASSOCIATED_TOKEN_PROGRAM)?;
+
+		let wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+		let ticket_data = Ticket::unpack(&ticket_account.data.borrow())?;
+		let ticket_bump = ticket_data.bump;
 
 		if whitelist_account.owner != &crate::id() {
 			return Err(WhitelistError::InvalidWhitelistAddress.into());
@@ -1023,15 +1952,17 @@ automatically setting the deposited token amount to fulfill the maximum required
 		if !authority.is_signer || authority.key != &wl_data.authority {
 			return Err(WhitelistError::Unauthorised.into());
 		}
-		if mint.key != &wl_data.mint {
-			return Err(WhitelistError::IncorrectMintAddress.into());
-		}
+		let mint_decimals = whitelist_mint_decimals(mint, &wl_data)?;
 		if vault.key != &wl_data.vault {
 			return Err(WhitelistError::IncorrectVaultAddress.into());
 		}
-		if ticket_account.key != &ticket_addr {
-			return Err(WhitelistError::IncorrectUserAccount.into());
-		}
+		check_distinct(ticket_token_account, vault)?;
+		Ticket::derive_and_verify(
+			user_account.key,
+			whitelist_account.key,
+			ticket_account.key,
+			ticket_bump,
+		)?;
 
 		//Check to see if the `ticket_token_account` is initialised intialise it if not
 		if ticket_token_account.owner != &spl_token_2022::id()
@@ -1057,21 +1988,20 @@ automatically setting the deposited token amount to fulfill the maximum required
 					SEED,
 					user_account.key.as_ref(),
 					whitelist_account.key.as_ref(),
-					&[bump],
+					&[ticket_bump],
 				]],
 			)?;
 		}
 
-		if ticket_token_account_data.base.amount > 0 {
-			transfer_amount = match wl_data
-				.buy_limit
-				.checked_sub(ticket_token_account_data.base.amount)
-			{
-				Some(x) => x,
-				None => return Err(WhitelistError::Overflow.into()),
-			};
-		} else {
-			transfer_amount = wl_data.buy_limit;
+		// Top the ticket up to what it can still buy, tokens it already holds count towards it
+		let held = ticket_token_amount(ticket_token_account, token_program)?;
+		let transfer_amount = ticket_data
+			.allowance
+			.saturating_sub(ticket_data.amount_bought)
+			.saturating_sub(held);
+		if transfer_amount == 0 {
+			msg!("Ticket token account already holds {} tokens", held);
+			return Ok(());
 		}
 
 		invoke_signed(
@@ -1080,10 +2010,10 @@ automatically setting the deposited token amount to fulfill the maximum required
 				vault.key,
 				mint.key,
 				ticket_token_account.key,
-				authority.key,
+				whitelist_account.key,
 				&[],
 				transfer_amount,
-				mint_data.base.decimals,
+				mint_decimals,
 			)?,
 			&[
 				vault.clone(),
@@ -1099,15 +2029,14 @@ automatically setting the deposited token amount to fulfill the maximum required
 
 	// Only withdraws tokens from the vault, to withdraw tokens from ticket PDAs we terminate them
 	fn process_withdraw_tokens(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
-		let accounts_iter = &mut accounts.iter();
-		let whitelist_account = next_account_info(accounts_iter)?;
-		let authority = next_account_info(accounts_iter)?;
-		let vault = next_account_info(accounts_iter)?;
-		let mint = next_account_info(accounts_iter)?;
-		let recipient_token_account = next_account_info(accounts_iter)?;
-		let token_program = next_account_info(accounts_iter)?;
-
-		let wl_data = Whitelist::try_from_slice(&whitelist_account.data.borrow()[..])?;
+		let whitelist_account = account_at(accounts, withdraw_tokens::WHITELIST)?;
+		let authority = account_at(accounts, withdraw_tokens::AUTHORITY)?;
+		let vault = account_at(accounts, withdraw_tokens::VAULT)?;
+		let mint = account_at(accounts, withdraw_tokens::MINT)?;
+		let recipient_token_account = account_at(accounts, withdraw_tokens::RECIPIENT_TOKEN)?;
+		let token_program = account_at(accounts, withdraw_tokens::TOKEN_PROGRAM)?;
+
+		let wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
 		wl_data.check_sale_time()?;
 
 		if whitelist_account.owner != &crate::id() {
@@ -1127,13 +2056,14 @@ automatically setting the deposited token amount to fulfill the maximum required
 		}
 
 		if token_program.key != &spl_token_2022::id() && token_program.key != &spl_token::id() {
-			return Err(ProgramError::IncorrectProgramId);
+			return Err(WhitelistError::IncorrectTokenProgram.into());
 		}
 
-		let borrowed_mint_data = mint.data.borrow();
-		let mint_data = StateWithExtensions::<Mint>::unpack(&borrowed_mint_data)?;
-		let token_amount =
-			spl_token_2022::ui_amount_to_amount(amount as f64, mint_data.base.decimals);
+		check_distinct(recipient_token_account, vault)?;
+		check_vault_not_frozen(vault)?;
+
+		let mint_decimals = whitelist_mint_decimals(mint, &wl_data)?;
+		let token_amount = spl_token_2022::ui_amount_to_amount(amount as f64, mint_decimals);
 
 		invoke_signed(
 			&spl_token_2022::instruction::transfer_checked(
@@ -1144,7 +2074,7 @@ automatically setting the deposited token amount to fulfill the maximum required
 				whitelist_account.key,
 				&[],
 				token_amount,
-				mint_data.base.decimals,
+				mint_decimals,
 			)?,
 			&[
 				vault.clone(),
@@ -1160,67 +2090,83 @@ automatically setting the deposited token amount to fulfill the maximum required
 	}
 
 	fn process_burn_ticket(accounts: &[AccountInfo]) -> ProgramResult {
-		let accounts_iter = &mut accounts.iter();
-		let whitelist_account = next_account_info(accounts_iter)?;
-		let authority = next_account_info(accounts_iter)?;
-		let mint = next_account_info(accounts_iter)?;
-		let treasury = next_account_info(accounts_iter)?;
-		let treasury_token_account = next_account_info(accounts_iter)?;
-		let ticket_account = next_account_info(accounts_iter)?;
-		let ticket_token_account = next_account_info(accounts_iter)?;
-		let token_program = next_account_info(accounts_iter)?;
-		let system_program = next_account_info(accounts_iter)?;
-		let assc_token_program = next_account_info(accounts_iter)?;
-
-		let wl_data = Whitelist::try_from_slice(&whitelist_account.data.borrow()[..])?;
-		let ticket_data = Ticket::try_from_slice(&ticket_account.data.borrow()[..])?;
+		let whitelist_account = account_at(accounts, burn_ticket::WHITELIST)?;
+		let authority = account_at(accounts, burn_ticket::AUTHORITY)?;
+		let mint = account_at(accounts, burn_ticket::MINT)?;
+		let treasury = account_at(accounts, burn_ticket::TREASURY)?;
+		let treasury_token_account = account_at(accounts, burn_ticket::TREASURY_TOKEN)?;
+		let ticket_account = account_at(accounts, burn_ticket::TICKET)?;
+		let ticket_token_account = account_at(accounts, burn_ticket::TICKET_TOKEN)?;
+		let token_program = account_at(accounts, burn_ticket::TOKEN_PROGRAM)?;
+		let system_program = account_at(accounts, burn_ticket::SYSTEM_PROGRAM)?;
+		let assc_token_program = account_at(accounts, burn_ticket::ASSOCIATED_TOKEN_PROGRAM)?;
+
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+		let ticket_data = Ticket::unpack(&ticket_account.data.borrow())?;
 		let token_amount = {
 			let borrowed_ticket_token_data = ticket_token_account.data.borrow();
 			let ticket_data = StateWithExtensions::<Account>::unpack(&borrowed_ticket_token_data)?;
 			ticket_data.base.amount
 		};
-		let mint_decimals = {
-			let borrowed_mint_data = mint.data.borrow();
-			let mint_data = StateWithExtensions::<Mint>::unpack(&borrowed_mint_data)?;
-			mint_data.base.decimals
-		};
 
 		// Safety dance
 		if !authority.is_signer || authority.key != &wl_data.authority {
 			return Err(WhitelistError::Unauthorised.into());
 		}
 
-		if mint.key != &wl_data.mint {
-			return Err(WhitelistError::IncorrectMintAddress.into());
-		}
+		let mint_decimals = whitelist_mint_decimals(mint, &wl_data)?;
 
 		if treasury.key != &wl_data.treasury {
 			return Err(WhitelistError::IncorrectTreasuryAddress.into());
 		}
 
 		if token_program.key != &spl_token_2022::id() && token_program.key != &spl_token::id() {
-			return Err(ProgramError::IncorrectProgramId);
+			return Err(WhitelistError::IncorrectTokenProgram.into());
 		}
 
 		if system_program.key != &system_program::id() {
-			return Err(ProgramError::IncorrectProgramId);
+			return Err(WhitelistError::IncorrectSystemProgram.into());
 		}
 
-		if assc_token_program.key != &spl_associated_token_account::id() {
-			return Err(ProgramError::IncorrectProgramId);
+		if assc_token_program.key != &ASSOCIATED_TOKEN_PROGRAM_ID {
+			return Err(WhitelistError::IncorrectAssociatedTokenProgram.into());
+		}
+
+		// The ticket must be its owner's ticket on this whitelist and the token account that
+		// ticket's ATA, otherwise the rent of an unrelated account would be swept to the treasury
+		Ticket::derive_and_verify(
+			&ticket_data.owner,
+			whitelist_account.key,
+			ticket_account.key,
+			ticket_data.bump,
+		)?;
+
+		let ticket_ata = spl_associated_token_account::get_associated_token_address_with_program_id(
+			ticket_account.key,
+			mint.key,
+			token_program.key,
+		);
+		if ticket_token_account.key != &ticket_ata {
+			msg!(
+				"Burn rejected: ticket token account {} is not the ticket's ATA {}",
+				ticket_token_account.key,
+				ticket_ata
+			);
+			return Err(WhitelistError::IncorrectUserAccount.into());
 		}
+		check_distinct(treasury_token_account, ticket_token_account)?;
+
 		let ticket_token_lamports = ticket_token_account.lamports();
-		let ticket_lamports = ticket_account.lamports();
 
 		if token_amount > 0 {
 			// Create the treasury token account if it doesn't exist
 			if treasury_token_account.owner != &spl_token_2022::id()
 				&& treasury_token_account.owner != &spl_token::id()
 			{
-				invoke_signed(
+				invoke(
 					&spl_associated_token_account::instruction::create_associated_token_account(
 						&authority.key,
-						&treasury_token_account.key,
+						&treasury.key,
 						&mint.key,
 						&token_program.key,
 					),
@@ -1233,17 +2179,16 @@ automatically setting the deposited token amount to fulfill the maximum required
 						token_program.clone(),
 						assc_token_program.clone(),
 					],
-					&[&[SEED, whitelist_account.key.as_ref(), &[wl_data.bump]]],
 				)?
 			}
-			// Transfer tokens from the ticket token account
+			// Transfer tokens from the ticket token account, which the ticket owns
 			invoke_signed(
 				&spl_token_2022::instruction::transfer_checked(
 					&token_program.key,
 					&ticket_token_account.key,
 					&mint.key,
 					&treasury_token_account.key,
-					&whitelist_account.key,
+					&ticket_account.key,
 					&[],
 					token_amount,
 					mint_decimals,
@@ -1252,7 +2197,7 @@ automatically setting the deposited token amount to fulfill the maximum required
 					ticket_token_account.clone(),
 					mint.clone(),
 					treasury_token_account.clone(),
-					whitelist_account.clone(),
+					ticket_account.clone(),
 				],
 				&[&[
 					SEED,
@@ -1285,25 +2230,11 @@ automatically setting the deposited token amount to fulfill the maximum required
 			]],
 		)?;
 
-		// Zero ticket data
-		ticket_account.assign(&system_program::id());
-		ticket_account.realloc(0, false)?;
+		wl_data.unregister_ticket();
+		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
 
-		// Transfer SOL from the ticket
-		invoke_signed(
-			&system_instruction::transfer(ticket_account.key, treasury.key, ticket_lamports),
-			&[
-				ticket_account.clone(),
-				treasury.clone(),
-				system_program.clone(),
-			],
-			&[&[
-				SEED,
-				ticket_data.owner.as_ref(),
-				whitelist_account.key.as_ref(),
-				&[ticket_data.bump],
-			]],
-		)?;
+		// Close the ticket, its SOL goes to the treasury
+		let ticket_lamports = close_program_account(ticket_account, treasury)?;
 
 		msg!(
 			"Ticket burned. {} tokens & {} lamports transferred to: {}",
@@ -1315,564 +2246,9273 @@ automatically setting the deposited token amount to fulfill the maximum required
 	}
 
 	fn process_terminate_whitelist(accounts: &[AccountInfo]) -> ProgramResult {
-		let accounts_iter = &mut accounts.iter();
-		let whitelist_account = next_account_info(accounts_iter)?;
-		let authority = next_account_info(accounts_iter)?;
-		let vault = next_account_info(accounts_iter)?;
-		let mint = next_account_info(accounts_iter)?;
-		let recipient_account = next_account_info(accounts_iter)?;
-		let recipient_token_account = next_account_info(accounts_iter)?;
-		let token_program = next_account_info(accounts_iter)?;
-		let system_program = next_account_info(accounts_iter)?;
-
-		let whitelist_lamports = whitelist_account.lamports();
-		let vault_lamports = vault.lamports();
-		let borrowed_vault_data = vault.data.borrow();
-		let vault_data = StateWithExtensions::<Account>::unpack(&borrowed_vault_data)?;
-		let borrowed_mint_data = mint.data.borrow();
-		let mint_data = StateWithExtensions::<Mint>::unpack(&borrowed_mint_data)?;
+		let whitelist_account = account_at(accounts, terminate_whitelist::WHITELIST)?;
+		let authority = account_at(accounts, terminate_whitelist::AUTHORITY)?;
+		let vault = account_at(accounts, terminate_whitelist::VAULT)?;
+		let mint = account_at(accounts, terminate_whitelist::MINT)?;
+		let recipient_token_account = account_at(accounts, terminate_whitelist::RECIPIENT_TOKEN)?;
+		let token_program = account_at(accounts, terminate_whitelist::TOKEN_PROGRAM)?;
 
-		let wl_data = Whitelist::try_from_slice(&whitelist_account.data.borrow()[..])?;
-		wl_data.check_sale_time()?;
+		if whitelist_account.owner != &crate::id() {
+			return Err(WhitelistError::InvalidWhitelistAddress.into());
+		}
+		let wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
 
-		// Transfer remaining tokens out of the vault
-		if vault_data.base.amount != 0 {
-			invoke_signed(
-				&spl_token_2022::instruction::transfer_checked(
-					token_program.key,
-					vault.key,
-					mint.key,
-					recipient_token_account.key,
-					whitelist_account.key,
-					&[],
-					vault_data.base.amount,
-					mint_data.base.decimals,
-				)?,
-				&[
-					vault.clone(),
-					mint.clone(),
-					recipient_token_account.clone(),
-					whitelist_account.clone(),
-				],
-				&[&[SEED, mint.key.as_ref(), &[wl_data.bump]]],
-			)?;
+		if !authority.is_signer || authority.key != &wl_data.authority {
+			return Err(WhitelistError::Unauthorised.into());
 		}
 
-		invoke_signed(
-			&spl_token_2022::instruction::close_account(
-				token_program.key,
-				vault.key,
-				authority.key,
-				whitelist_account.key,
-				&[],
-			)?,
-			&[vault.clone(), authority.clone(), whitelist_account.clone()],
-			&[&[SEED, mint.key.as_ref(), &[wl_data.bump]]],
-		)?;
+		wl_data.check_sale_time()?;
+		let mint_decimals = whitelist_mint_decimals(mint, &wl_data)?;
+
+		// Proceeds left in a treasury PDA could never be withdrawn once the whitelist is gone
+		let (treasury_pda, _) = get_treasury_address(mint.key);
+		if wl_data.treasury == treasury_pda {
+			let treasury = account_at(accounts, terminate_whitelist::TREASURY)?;
+			if treasury.key != &treasury_pda {
+				return Err(WhitelistError::IncorrectTreasuryAddress.into());
+			}
+			let rent = Rent::get()?.minimum_balance(treasury.data_len());
+			if treasury.lamports() > rent {
+				msg!(
+					"Terminate rejected: treasury {} holds {} lamports, withdraw them first",
+					treasury.key,
+					treasury.lamports() - rent
+				);
+				return Err(WhitelistError::TreasuryNotEmpty.into());
+			}
+		}
 
-		// Close whitelist and reclaim lamports
-		whitelist_account.assign(&system_program::id());
-		whitelist_account.realloc(0, false)?;
-		invoke_signed(
-			&system_instruction::transfer(whitelist_account.key, authority.key, whitelist_lamports),
-			&[
-				whitelist_account.clone(),
-				recipient_account.clone(),
-				system_program.clone(),
-			],
-			&[&[SEED, mint.key.as_ref(), &[wl_data.bump]]],
-		)?;
+		// A vault closed with `CloseVault` has already been emptied and its rent reclaimed
+		let vault_rent = if wl_data.vault == Pubkey::default() {
+			msg!("Vault already closed, closing whitelist");
+			0
+		} else {
+			if vault.key != &wl_data.vault {
+				return Err(WhitelistError::IncorrectVaultAddress.into());
+			}
+			// The vault is emptied into the recipient before it is closed, both must be distinct
+			check_distinct(recipient_token_account, vault)?;
+			check_vault_not_frozen(vault)?;
+
+			let (_, vault_rent) = empty_and_close_vault(
+				whitelist_account,
+				authority,
+				vault,
+				mint,
+				recipient_token_account,
+				token_program,
+				mint_decimals,
+				wl_data.bump,
+			)?;
+			vault_rent
+		};
+
+		// Close whitelist and reclaim every lamport left in it to the authority
+		let whitelist_rent = close_program_account(whitelist_account, authority)?;
 
 		msg!(
-			"Terminated whitelist reclaimed sol: {} lamports",
-			whitelist_lamports + vault_lamports
+			"Terminated whitelist, reclaimed {} lamports to {}: vault rent {}, whitelist rent {}",
+			vault_rent + whitelist_rent,
+			authority.key,
+			vault_rent,
+			whitelist_rent
 		);
 		Ok(())
 	}
-}
 
-#[cfg(test)]
-mod tests {
-	use {
-		super::*,
-		//chrono::NaiveDateTime,
-		solana_program_test::*,
-		solana_sdk::{
-			hash::Hash, signature::Signer, signer::keypair::Keypair, transaction::Transaction,
-		},
-		test_case::test_case,
-	};
+	fn process_migrate_whitelist(accounts: &[AccountInfo]) -> ProgramResult {
+		msg!("Process: Migrate whitelist");
+		let whitelist_account = account_at(accounts, migrate_whitelist::WHITELIST)?;
+		let authority = account_at(accounts, migrate_whitelist::AUTHORITY)?;
+		let system_program = account_at(accounts, migrate_whitelist::SYSTEM_PROGRAM)?;
 
-	//let datetime = NaiveDateTime::parse_from_str(date_string.as_str(), "%Y-%m-%s %H:%M:%S")?;
+		if whitelist_account.owner != &crate::id() {
+			return Err(WhitelistError::WhitelistNotInitialized.into());
+		}
 
-	async fn setup_test_environment() -> (BanksClient, Keypair, Hash) {
-		let mut program_test =
-			ProgramTest::new("stuk_wl", crate::id(), processor!(Processor::process));
+		// Accounts already on the current layout are sized to `Whitelist::space`, only the legacy
+		// layout is accepted so that the migration can only ever run once
+		if whitelist_account.data_len() != WhitelistV0::LEN {
+			return Err(WhitelistError::WhitelistAlreadyMigrated.into());
+		}
 
-		program_test.add_program(
-			"spl_token_2022",
-			spl_token_2022::id(),
-			processor!(spl_token_2022::processor::Processor::process),
-		);
-		program_test.add_program(
-			"spl_token",
-			spl_token::id(),
-			processor!(spl_token::processor::Processor::process),
-		);
+		let legacy_data = WhitelistV0::try_from_slice(&whitelist_account.data.borrow()[..])?;
+		let (wl, _bump) = get_whitelist_address(&legacy_data.mint);
 
-		program_test.start().await
-	}
+		if whitelist_account.key != &wl {
+			return Err(WhitelistError::InvalidWhitelistAddress.into());
+		}
 
-	async fn create_mint(
-		banks_client: &mut BanksClient,
+		if !authority.is_signer || authority.key != &legacy_data.authority {
+			return Err(WhitelistError::Unauthorised.into());
+		}
+
+		if system_program.key != &system_program::id() {
+			return Err(WhitelistError::IncorrectSystemProgram.into());
+		}
+
+		let rent = Rent::get()?;
+		let rent_difference = rent
+			.minimum_balance(Whitelist::space())
+			.saturating_sub(whitelist_account.lamports());
+
+		if rent_difference > 0 {
+			invoke(
+				&system_instruction::transfer(authority.key, whitelist_account.key, rent_difference),
+				&[
+					authority.clone(),
+					whitelist_account.clone(),
+					system_program.clone(),
+				],
+			)?;
+		}
+
+		whitelist_account.realloc(Whitelist::space(), true)?;
+
+		let wl_data = Whitelist::from(legacy_data);
+		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
+
+		msg!("Whitelist migrated to version: {}", wl_data.version);
+		Ok(())
+	}
+
+	fn process_set_ticket_note(accounts: &[AccountInfo], note: [u8; 32]) -> ProgramResult {
+		msg!("Process: Set ticket note");
+		let whitelist_account = account_at(accounts, set_ticket_note::WHITELIST)?;
+		let authority = account_at(accounts, set_ticket_note::AUTHORITY)?;
+		let user_ticket_account = account_at(accounts, set_ticket_note::USER_TICKET)?;
+
+		let wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+		let mut ticket_data = Ticket::unpack(&user_ticket_account.data.borrow())?;
+
+		if !authority.is_signer || authority.key != &wl_data.authority {
+			return Err(WhitelistError::Unauthorised.into());
+		}
+
+		if whitelist_account.key != &get_whitelist_address(&wl_data.mint).0 {
+			return Err(WhitelistError::InvalidWhitelistAddress.into());
+		}
+
+		if user_ticket_account.owner != &crate::id()
+			|| ticket_data.whitelist != *whitelist_account.key
+		{
+			return Err(WhitelistError::IncorrectUserAccount.into());
+		}
+		Ticket::derive_and_verify(
+			&ticket_data.owner,
+			whitelist_account.key,
+			user_ticket_account.key,
+			ticket_data.bump,
+		)?;
+
+		ticket_data.note = note;
+		ticket_data.serialize(&mut &mut user_ticket_account.data.borrow_mut()[..])?;
+
+		msg!("Ticket note set: {}", ticket_data.note_string());
+		Ok(())
+	}
+
+	fn process_set_paused(
+		accounts: &[AccountInfo],
+		target: PauseTarget,
+		paused: bool,
+	) -> ProgramResult {
+		msg!("Process: Set paused");
+		let whitelist_account = account_at(accounts, set_paused::WHITELIST)?;
+		let authority = account_at(accounts, set_paused::AUTHORITY)?;
+
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+		if !authority.is_signer || authority.key != &wl_data.authority {
+			return Err(WhitelistError::Unauthorised.into());
+		}
+
+		let flag = match target {
+			PauseTarget::Registration => &mut wl_data.registration_paused,
+			PauseTarget::Sale => &mut wl_data.sale_paused,
+		};
+		let old_paused = *flag;
+		*flag = paused;
+		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
+
+		msg!("{:?} paused: {}", target, paused);
+		WhitelistEvent::PausedSet {
+			whitelist: *whitelist_account.key,
+			target,
+			old_paused,
+			new_paused: paused,
+		}
+		.emit()
+	}
+
+	fn process_set_referral_bonus(accounts: &[AccountInfo], referral_bonus: u64) -> ProgramResult {
+		msg!("Process: Set referral bonus");
+		let whitelist_account = account_at(accounts, set_referral_bonus::WHITELIST)?;
+		let authority = account_at(accounts, set_referral_bonus::AUTHORITY)?;
+		let mint = account_at(accounts, set_referral_bonus::MINT)?;
+
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+		if !authority.is_signer || authority.key != &wl_data.authority {
+			return Err(WhitelistError::Unauthorised.into());
+		}
+
+		let mint_decimals = whitelist_mint_decimals(mint, &wl_data)?;
+		wl_data.referral_bonus =
+			spl_token_2022::ui_amount_to_amount(referral_bonus as f64, mint_decimals);
+		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
+
+		msg!("Referral bonus set to: {}", wl_data.referral_bonus);
+		Ok(())
+	}
+
+	fn process_set_register_authority(
+		accounts: &[AccountInfo],
+		register_authority: Option<Pubkey>,
+	) -> ProgramResult {
+		msg!("Process: Set register authority");
+		let whitelist_account = account_at(accounts, set_register_authority::WHITELIST)?;
+		let authority = account_at(accounts, set_register_authority::AUTHORITY)?;
+
+		if whitelist_account.owner != &crate::id() {
+			return Err(WhitelistError::InvalidWhitelistAddress.into());
+		}
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+		if !authority.is_signer || authority.key != &wl_data.authority {
+			return Err(WhitelistError::Unauthorised.into());
+		}
+
+		wl_data.register_authority = register_authority.unwrap_or_default();
+		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
+
+		match register_authority {
+			Some(register_authority) => msg!("Register authority set to: {}", register_authority),
+			None => msg!("Register authority cleared, registration is no longer gated"),
+		}
+		Ok(())
+	}
+
+	fn process_change_authority(accounts: &[AccountInfo], new_authority: Pubkey) -> ProgramResult {
+		msg!("Process: Change authority");
+		let whitelist_account = account_at(accounts, change_authority::WHITELIST)?;
+		let authority = account_at(accounts, change_authority::AUTHORITY)?;
+
+		if whitelist_account.owner != &crate::id() {
+			return Err(WhitelistError::InvalidWhitelistAddress.into());
+		}
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+		if !authority.is_signer || authority.key != &wl_data.authority {
+			return Err(WhitelistError::Unauthorised.into());
+		}
+
+		// Nobody holds the key to the default pubkey, the whitelist would be left without an
+		// authority
+		if new_authority == Pubkey::default() {
+			msg!("Change authority rejected: new authority is the default pubkey");
+			return Err(WhitelistError::InvalidAuthority.into());
+		}
+
+		let old_authority = wl_data.authority;
+		wl_data.authority = new_authority;
+		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
+
+		WhitelistEvent::AuthorityChanged {
+			whitelist: *whitelist_account.key,
+			old_authority,
+			new_authority,
+		}
+		.emit()
+	}
+
+	fn process_update_buy_limit(accounts: &[AccountInfo], buy_limit: u64) -> ProgramResult {
+		msg!("Process: Update buy limit");
+		let whitelist_account = account_at(accounts, update_buy_limit::WHITELIST)?;
+		let authority = account_at(accounts, update_buy_limit::AUTHORITY)?;
+		let mint = account_at(accounts, update_buy_limit::MINT)?;
+
+		if whitelist_account.owner != &crate::id() {
+			return Err(WhitelistError::InvalidWhitelistAddress.into());
+		}
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+		if !authority.is_signer || authority.key != &wl_data.authority {
+			return Err(WhitelistError::Unauthorised.into());
+		}
+
+		let mint_decimals = whitelist_mint_decimals(mint, &wl_data)?;
+
+		let old_buy_limit = wl_data.buy_limit;
+		wl_data.buy_limit = spl_token_2022::ui_amount_to_amount(buy_limit as f64, mint_decimals);
+		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
+
+		WhitelistEvent::BuyLimitUpdated {
+			whitelist: *whitelist_account.key,
+			old_buy_limit,
+			new_buy_limit: wl_data.buy_limit,
+		}
+		.emit()
+	}
+
+	fn process_sync_ticket_allowance(accounts: &[AccountInfo]) -> ProgramResult {
+		msg!("Process: Sync ticket allowance");
+		let whitelist_account = account_at(accounts, sync_ticket_allowance::WHITELIST)?;
+		let authority = account_at(accounts, sync_ticket_allowance::AUTHORITY)?;
+		let user_ticket_account = account_at(accounts, sync_ticket_allowance::USER_TICKET)?;
+
+		if whitelist_account.owner != &crate::id() {
+			return Err(WhitelistError::InvalidWhitelistAddress.into());
+		}
+		let wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+		if !authority.is_signer || authority.key != &wl_data.authority {
+			return Err(WhitelistError::Unauthorised.into());
+		}
+
+		if user_ticket_account.owner != &crate::id() {
+			return Err(WhitelistError::IncorrectUserAccount.into());
+		}
+		let mut ticket_data = Ticket::unpack(&user_ticket_account.data.borrow())?;
+		if ticket_data.whitelist != *whitelist_account.key {
+			return Err(WhitelistError::IncorrectUserAccount.into());
+		}
+		Ticket::derive_and_verify(
+			&ticket_data.owner,
+			whitelist_account.key,
+			user_ticket_account.key,
+			ticket_data.bump,
+		)?;
+
+		// Allowances are only ever raised, what the ticket was granted beyond the limit is kept
+		if ticket_data.allowance >= wl_data.buy_limit {
+			msg!(
+				"Ticket allowance {} already covers buy limit {}",
+				ticket_data.allowance,
+				wl_data.buy_limit
+			);
+			return Ok(());
+		}
+
+		let old_allowance = ticket_data.allowance;
+		ticket_data.allowance = wl_data.buy_limit;
+		ticket_data.serialize(&mut &mut user_ticket_account.data.borrow_mut()[..])?;
+
+		msg!(
+			"Ticket allowance raised from {} to {}",
+			old_allowance,
+			ticket_data.allowance
+		);
+		Ok(())
+	}
+
+	fn process_batch_add_user(accounts: &[AccountInfo], users: Vec<Pubkey>) -> ProgramResult {
+		msg!("Process: Batch add user");
+		let whitelist_account = account_at(accounts, batch_add_user::WHITELIST)?;
+		let authority = account_at(accounts, batch_add_user::AUTHORITY)?;
+		let mint = account_at(accounts, batch_add_user::MINT)?;
+		let system_program = account_at(accounts, batch_add_user::SYSTEM_PROGRAM)?;
+		let user_accounts = accounts.get(batch_add_user::USERS..).unwrap_or_default();
+
+		if users.is_empty() || users.len() > MAX_BATCH_USERS {
+			msg!(
+				"Batch add user rejected: got {} users, expected 1 to {}",
+				users.len(),
+				MAX_BATCH_USERS
+			);
+			return Err(WhitelistError::InvalidBatchSize.into());
+		}
+
+		if user_accounts.len() != users.len() * 2 {
+			msg!(
+				"Batch add user rejected: expected {} user and ticket account pairs, got {} accounts",
+				users.len(),
+				user_accounts.len()
+			);
+			return Err(WhitelistError::BatchAccountsMismatch.into());
+		}
+
+		for (user, pair) in users.iter().zip(user_accounts.chunks_exact(2)) {
+			if pair[0].key != user {
+				msg!(
+					"Batch add user rejected: expected user {}, got {}",
+					user,
+					pair[0].key
+				);
+				return Err(WhitelistError::BatchAccountsMismatch.into());
+			}
+
+			// `AddUser` validates the whitelist, authority, mint and ticket for each user
+			Self::process_add_user(&[
+				whitelist_account.clone(),
+				authority.clone(),
+				mint.clone(),
+				pair[0].clone(),
+				pair[1].clone(),
+				system_program.clone(),
+			])?;
+		}
+
+		msg!("{} users initialised", users.len());
+
+		Ok(())
+	}
+
+	fn process_migrate_ticket(accounts: &[AccountInfo]) -> ProgramResult {
+		msg!("Process: Migrate ticket");
+		let user_ticket_account = account_at(accounts, migrate_ticket::USER_TICKET)?;
+		let payer = account_at(accounts, migrate_ticket::PAYER)?;
+		let system_program = account_at(accounts, migrate_ticket::SYSTEM_PROGRAM)?;
+
+		if user_ticket_account.owner != &crate::id() {
+			return Err(WhitelistError::IncorrectUserAccount.into());
+		}
+
+		// As with whitelists only the legacy length is accepted, so a ticket migrates once
+		if user_ticket_account.data_len() != TicketV0::LEN {
+			return Err(WhitelistError::TicketAlreadyMigrated.into());
+		}
+
+		let legacy_data = TicketV0::try_from_slice(&user_ticket_account.data.borrow()[..])?;
+		Ticket::derive_and_verify(
+			&legacy_data.owner,
+			&legacy_data.whitelist,
+			user_ticket_account.key,
+			legacy_data.bump,
+		)?;
+
+		if !payer.is_signer {
+			return Err(WhitelistError::SignerError.into());
+		}
+
+		if system_program.key != &system_program::id() {
+			return Err(WhitelistError::IncorrectSystemProgram.into());
+		}
+
+		let rent = Rent::get()?;
+		let rent_difference = rent
+			.minimum_balance(Ticket::space())
+			.saturating_sub(user_ticket_account.lamports());
+
+		if rent_difference > 0 {
+			invoke(
+				&system_instruction::transfer(payer.key, user_ticket_account.key, rent_difference),
+				&[
+					payer.clone(),
+					user_ticket_account.clone(),
+					system_program.clone(),
+				],
+			)?;
+		}
+
+		user_ticket_account.realloc(Ticket::space(), true)?;
+
+		let ticket_data = Ticket::from(legacy_data);
+		ticket_data.serialize(&mut &mut user_ticket_account.data.borrow_mut()[..])?;
+
+		msg!("Ticket migrated to version: {}", ticket_data.version);
+		Ok(())
+	}
+
+	fn process_close_vault(accounts: &[AccountInfo]) -> ProgramResult {
+		msg!("Process: Close vault");
+		let whitelist_account = account_at(accounts, close_vault::WHITELIST)?;
+		let authority = account_at(accounts, close_vault::AUTHORITY)?;
+		let vault = account_at(accounts, close_vault::VAULT)?;
+		let mint = account_at(accounts, close_vault::MINT)?;
+		let recipient_token_account = account_at(accounts, close_vault::RECIPIENT_TOKEN)?;
+		let token_program = account_at(accounts, close_vault::TOKEN_PROGRAM)?;
+
+		let clock = Clock::get()?;
+
+		if whitelist_account.owner != &crate::id() {
+			return Err(WhitelistError::InvalidWhitelistAddress.into());
+		}
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+		if !authority.is_signer || authority.key != &wl_data.authority {
+			return Err(WhitelistError::Unauthorised.into());
+		}
+
+		// Also rejects a vault that is already closed, whose stored address is the default pubkey
+		if vault.key != &wl_data.vault || wl_data.vault == Pubkey::default() {
+			return Err(WhitelistError::IncorrectVaultAddress.into());
+		}
+
+		if token_program.key != &spl_token_2022::id() && token_program.key != &spl_token::id() {
+			return Err(WhitelistError::IncorrectTokenProgram.into());
+		}
+
+		if !wl_data.sale_ended(clock.unix_timestamp) {
+			msg!("Close vault rejected: the sale has not ended");
+			return Err(WhitelistError::SaleOngoing.into());
+		}
+
+		let mint_decimals = whitelist_mint_decimals(mint, &wl_data)?;
+
+		check_distinct(recipient_token_account, vault)?;
+		check_vault_not_frozen(vault)?;
+
+		let (vault_amount, vault_rent) = empty_and_close_vault(
+			whitelist_account,
+			authority,
+			vault,
+			mint,
+			recipient_token_account,
+			token_program,
+			mint_decimals,
+			wl_data.bump,
+		)?;
+
+		wl_data.vault = Pubkey::default();
+		wl_data.deposited = 0;
+		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
+
+		msg!(
+			"Vault closed, {} tokens sent to {}, reclaimed {} lamports to {}",
+			vault_amount,
+			recipient_token_account.key,
+			vault_rent,
+			authority.key
+		);
+		Ok(())
+	}
+
+	fn process_update_price(accounts: &[AccountInfo], token_price: u64) -> ProgramResult {
+		msg!("Process: Update price");
+		let whitelist_account = account_at(accounts, update_price::WHITELIST)?;
+		let authority = account_at(accounts, update_price::AUTHORITY)?;
+
+		let clock = Clock::get()?;
+
+		if whitelist_account.owner != &crate::id() {
+			return Err(WhitelistError::InvalidWhitelistAddress.into());
+		}
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+		if !authority.is_signer || authority.key != &wl_data.authority {
+			return Err(WhitelistError::Unauthorised.into());
+		}
+
+		// A sale timestamp of 0 means the sale opened at initialisation
+		if wl_data.sale_timestamp == 0 || wl_data.sale_timestamp <= clock.unix_timestamp {
+			msg!("Update price rejected: the sale has started");
+			return Err(WhitelistError::SaleStarted.into());
+		}
+
+		if token_price == 0 {
+			msg!("Update price rejected: token price is 0");
+			return Err(WhitelistError::ZeroPrice.into());
+		}
+
+		let old_price = wl_data.token_price;
+		wl_data.token_price = token_price;
+		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
+
+		WhitelistEvent::PriceUpdated {
+			whitelist: *whitelist_account.key,
+			old_price,
+			new_price: token_price,
+		}
+		.emit()
+	}
+
+	fn process_set_allocation(accounts: &[AccountInfo], allowance: u64) -> ProgramResult {
+		msg!("Process: Set allocation");
+		let whitelist_account = account_at(accounts, set_allocation::WHITELIST)?;
+		let authority = account_at(accounts, set_allocation::AUTHORITY)?;
+		let mint = account_at(accounts, set_allocation::MINT)?;
+		let user_account = account_at(accounts, set_allocation::USER)?;
+		let allocation_account = account_at(accounts, set_allocation::ALLOCATION)?;
+		let system_program = account_at(accounts, set_allocation::SYSTEM_PROGRAM)?;
+
+		if whitelist_account.owner != &crate::id() {
+			return Err(WhitelistError::InvalidWhitelistAddress.into());
+		}
+		let wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+		if !authority.is_signer || authority.key != &wl_data.authority {
+			return Err(WhitelistError::Unauthorised.into());
+		}
+
+		if mint.key != &wl_data.mint {
+			return Err(WhitelistError::IncorrectMintAddress.into());
+		}
+
+		let (allocation, allocation_bump) =
+			get_allocation_address(user_account.key, whitelist_account.key);
+		if allocation_account.key != &allocation {
+			msg!(
+				"Set allocation rejected: {} does not match derived allocation {}",
+				allocation_account.key,
+				allocation
+			);
+			return Err(WhitelistError::AccountMismatch.into());
+		}
+
+		if system_program.key != &system_program::id() {
+			msg!(
+				"Set allocation rejected: {} is not the system program",
+				system_program.key
+			);
+			return Err(WhitelistError::IncorrectSystemProgram.into());
+		}
+
+		let mut allocation_data = if allocation_account.owner == &crate::id() {
+			Allocation::unpack(&allocation_account.data.borrow())?
+		} else {
+			let rent = Rent::get()?;
+			invoke_signed(
+				&system_instruction::create_account(
+					authority.key,
+					allocation_account.key,
+					rent.minimum_balance(Allocation::space())
+						.max(1)
+						.saturating_sub(allocation_account.lamports()),
+					Allocation::space() as u64,
+					&crate::id(),
+				),
+				&[
+					authority.clone(),
+					allocation_account.clone(),
+					system_program.clone(),
+				],
+				&[&[
+					SEED,
+					ALLOCATION_SEED,
+					user_account.key.as_ref(),
+					whitelist_account.key.as_ref(),
+					&[allocation_bump],
+				]],
+			)?;
+
+			Allocation {
+				version: Allocation::VERSION,
+				bump: allocation_bump,
+				whitelist: *whitelist_account.key,
+				user: *user_account.key,
+				allowance: 0,
+			}
+		};
+
+		let mint_decimals = whitelist_mint_decimals(mint, &wl_data)?;
+		allocation_data.allowance =
+			spl_token_2022::ui_amount_to_amount(allowance as f64, mint_decimals);
+		allocation_data.serialize(&mut &mut allocation_account.data.borrow_mut()[..])?;
+
+		msg!(
+			"Allocation of {} set for user {}",
+			allocation_data.allowance,
+			user_account.key
+		);
+		Ok(())
+	}
+
+	fn process_set_buy_cooldown(accounts: &[AccountInfo], cooldown_secs: u64) -> ProgramResult {
+		msg!("Process: Set buy cooldown");
+		let whitelist_account = account_at(accounts, set_buy_cooldown::WHITELIST)?;
+		let authority = account_at(accounts, set_buy_cooldown::AUTHORITY)?;
+
+		let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+		if !authority.is_signer || authority.key != &wl_data.authority {
+			return Err(WhitelistError::Unauthorised.into());
+		}
+
+		wl_data.cooldown_secs = cooldown_secs;
+		wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
+
+		msg!("Buy cooldown set to: {} seconds", wl_data.cooldown_secs);
+		Ok(())
+	}
+
+	fn process_refund(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+		msg!("Process: Refund");
+		let whitelist_account = account_at(accounts, refund::WHITELIST)?;
+		let vault = account_at(accounts, refund::VAULT)?;
+		let mint = account_at(accounts, refund::MINT)?;
+		let owner = account_at(accounts, refund::OWNER)?;
+		let ticket_account = account_at(accounts, refund::TICKET)?;
+		let owner_token_account = account_at(accounts, refund::OWNER_TOKEN)?;
+		let token_program = account_at(accounts, refund::TOKEN_PROGRAM)?;
+
+		let clock = Clock::get()?;
+
+		if whitelist_account.owner != &crate::id() {
+			return Err(WhitelistError::InvalidWhitelistAddress.into());
+		}
+		let wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+		let mut ticket_data = Ticket::unpack(&ticket_account.data.borrow())?;
+
+		if !owner.is_signer {
+			msg!("Refund rejected: owner {} did not sign", owner.key);
+			return Err(WhitelistError::SignerError.into());
+		}
+
+		if vault.key != &wl_data.vault {
+			return Err(WhitelistError::IncorrectVaultAddress.into());
+		}
+
+		if mint.key != &wl_data.mint {
+			return Err(WhitelistError::IncorrectMintAddress.into());
+		}
+
+		Ticket::derive_and_verify(
+			owner.key,
+			whitelist_account.key,
+			ticket_account.key,
+			ticket_data.bump,
+		)?;
+
+		if token_program.key != &spl_token_2022::id() && token_program.key != &spl_token::id() {
+			return Err(WhitelistError::IncorrectTokenProgram.into());
+		}
+
+		check_distinct(owner_token_account, vault)?;
+
+		if !wl_data.refund_open(ticket_data.last_purchase_ts, clock.unix_timestamp) {
+			msg!(
+				"Refund rejected: last purchase at {}, refund window {} seconds, current time {}",
+				ticket_data.last_purchase_ts,
+				wl_data.refund_window_secs,
+				clock.unix_timestamp
+			);
+			return Err(WhitelistError::RefundWindowClosed.into());
+		}
+
+		let mint_decimals = whitelist_mint_decimals(mint, &wl_data)?;
+		let token_amount = spl_token_2022::ui_amount_to_amount(amount as f64, mint_decimals);
+		if token_amount == 0 {
+			msg!("Refund rejected: amount is zero");
+			return Err(WhitelistError::ZeroAmount.into());
+		}
+
+		ticket_data.record_refund(token_amount)?;
+
+		// Refunded at the current price, paid from the purchase SOL the ticket holds above its
+		// rent exemption
+		let sol_amount = buy_cost(token_amount, wl_data.token_price, mint_decimals)?;
+		let refundable = ticket_account
+			.lamports()
+			.saturating_sub(Rent::get()?.minimum_balance(ticket_account.data_len()));
+		if sol_amount > refundable {
+			msg!(
+				"Refund rejected: refund {} > {} lamports held by the ticket",
+				sol_amount,
+				refundable
+			);
+			return Err(WhitelistError::InsufficientFunds.into());
+		}
+
+		invoke(
+			&spl_token_2022::instruction::transfer_checked(
+				token_program.key,
+				owner_token_account.key,
+				mint.key,
+				vault.key,
+				owner.key,
+				&[],
+				token_amount,
+				mint_decimals,
+			)?,
+			&[
+				owner_token_account.clone(),
+				mint.clone(),
+				vault.clone(),
+				owner.clone(),
+			],
+		)?;
+
+		// The ticket belongs to the program so its lamports are moved directly
+		**ticket_account.lamports.borrow_mut() -= sol_amount;
+		**owner.lamports.borrow_mut() = owner
+			.lamports()
+			.checked_add(sol_amount)
+			.ok_or(WhitelistError::Overflow)?;
+
+		ticket_data.serialize(&mut &mut ticket_account.data.borrow_mut()[..])?;
+
+		msg!("Refunded: {} tokens for {} lamports", amount, sol_amount);
+		Ok(())
+	}
+
+	fn process_claim_ticket_rent(accounts: &[AccountInfo]) -> ProgramResult {
+		msg!("Process: Claim ticket rent");
+		let whitelist_account = account_at(accounts, claim_ticket_rent::WHITELIST)?;
+		let owner = account_at(accounts, claim_ticket_rent::OWNER)?;
+		let ticket_account = account_at(accounts, claim_ticket_rent::TICKET)?;
+		let payer = account_at(accounts, claim_ticket_rent::PAYER)?;
+		let system_program = account_at(accounts, claim_ticket_rent::SYSTEM_PROGRAM)?;
+
+		if whitelist_account.owner != &crate::id() {
+			return Err(WhitelistError::InvalidWhitelistAddress.into());
+		}
+		let mut ticket_data = Ticket::unpack(&ticket_account.data.borrow())?;
+
+		if !owner.is_signer {
+			msg!(
+				"Claim ticket rent rejected: owner {} did not sign",
+				owner.key
+			);
+			return Err(WhitelistError::SignerError.into());
+		}
+
+		Ticket::derive_and_verify(
+			owner.key,
+			whitelist_account.key,
+			ticket_account.key,
+			ticket_data.bump,
+		)?;
+
+		if system_program.key != &system_program::id() {
+			return Err(WhitelistError::IncorrectSystemProgram.into());
+		}
+
+		if ticket_data.payer == *owner.key {
+			msg!("Claim ticket rent rejected: the ticket is already paid for by its owner");
+			return Err(WhitelistError::IncorrectPayer.into());
+		}
+		if payer.key != &ticket_data.payer {
+			msg!(
+				"Claim ticket rent rejected: payer {} is not the ticket payer {}",
+				payer.key,
+				ticket_data.payer
+			);
+			return Err(WhitelistError::IncorrectPayer.into());
+		}
+
+		// Only the rent is reimbursed, any purchase SOL held by the ticket stays with it
+		let rent = Rent::get()?.minimum_balance(ticket_account.data_len());
+		invoke(
+			&system_instruction::transfer(owner.key, payer.key, rent),
+			&[owner.clone(), payer.clone(), system_program.clone()],
+		)?;
+
+		ticket_data.payer = *owner.key;
+		ticket_data.serialize(&mut &mut ticket_account.data.borrow_mut()[..])?;
+
+		msg!(
+			"Ticket rent of {} lamports reimbursed to {}",
+			rent,
+			payer.key
+		);
+		Ok(())
+	}
+
+	fn process_add_user_and_seed(accounts: &[AccountInfo]) -> ProgramResult {
+		msg!("Process: Add user and seed");
+		// Validates the whitelist, authority, mint and ticket, and opens the ticket if needed
+		Self::process_add_user(accounts)?;
+
+		let whitelist_account = account_at(accounts, add_user_and_seed::WHITELIST)?;
+		let authority = account_at(accounts, add_user_and_seed::AUTHORITY)?;
+		let mint = account_at(accounts, add_user_and_seed::MINT)?;
+		let user_ticket_account = account_at(accounts, add_user_and_seed::USER_TICKET)?;
+		let system_program = account_at(accounts, add_user_and_seed::SYSTEM_PROGRAM)?;
+		let vault = account_at(accounts, add_user_and_seed::VAULT)?;
+		let ticket_token_account = account_at(accounts, add_user_and_seed::TICKET_TOKEN)?;
+		let token_program = account_at(accounts, add_user_and_seed::TOKEN_PROGRAM)?;
+		let assc_token_program = account_at(accounts, add_user_and_seed::ASSOCIATED_TOKEN_PROGRAM)?;
+
+		let wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+		if vault.key != &wl_data.vault {
+			return Err(WhitelistError::IncorrectVaultAddress.into());
+		}
+
+		if token_program.key != &spl_token_2022::id() && token_program.key != &spl_token::id() {
+			return Err(WhitelistError::IncorrectTokenProgram.into());
+		}
+
+		if assc_token_program.key != &ASSOCIATED_TOKEN_PROGRAM_ID {
+			return Err(WhitelistError::IncorrectAssociatedTokenProgram.into());
+		}
+
+		let ticket_ata = spl_associated_token_account::get_associated_token_address_with_program_id(
+			user_ticket_account.key,
+			mint.key,
+			token_program.key,
+		);
+		if ticket_token_account.key != &ticket_ata {
+			msg!(
+				"Add user rejected: ticket token account {} is not the ticket's ATA {}",
+				ticket_token_account.key,
+				ticket_ata
+			);
+			return Err(WhitelistError::IncorrectUserAccount.into());
+		}
+
+		invoke(
+			&spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+				authority.key,
+				user_ticket_account.key,
+				mint.key,
+				token_program.key,
+			),
+			&[
+				authority.clone(),
+				ticket_token_account.clone(),
+				user_ticket_account.clone(),
+				mint.clone(),
+				system_program.clone(),
+				token_program.clone(),
+				assc_token_program.clone(),
+			],
+		)?;
+
+		let ticket_balance = {
+			let borrowed_ticket_token_data = ticket_token_account.data.borrow();
+			StateWithExtensions::<Account>::unpack(&borrowed_ticket_token_data)?
+				.base
+				.amount
+		};
+		let mint_decimals = whitelist_mint_decimals(mint, &wl_data)?;
+
+		// Top the token account up to the buy limit, one already at or above it is left as it is
+		let seed_amount = wl_data.buy_limit.saturating_sub(ticket_balance);
+		if seed_amount > 0 {
+			invoke_signed(
+				&spl_token_2022::instruction::transfer_checked(
+					token_program.key,
+					vault.key,
+					mint.key,
+					ticket_token_account.key,
+					whitelist_account.key,
+					&[],
+					seed_amount,
+					mint_decimals,
+				)?,
+				&[
+					vault.clone(),
+					mint.clone(),
+					ticket_token_account.clone(),
+					whitelist_account.clone(),
+				],
+				&[&[SEED, mint.key.as_ref(), &[wl_data.bump]]],
+			)?;
+		}
+
+		msg!("Seeded ticket with {} tokens", seed_amount);
+		Ok(())
+	}
+
+	fn process_force_close_ticket(accounts: &[AccountInfo]) -> ProgramResult {
+		msg!("Process: Force close ticket");
+		let whitelist_account = account_at(accounts, force_close_ticket::WHITELIST)?;
+		let authority = account_at(accounts, force_close_ticket::AUTHORITY)?;
+		let mint = account_at(accounts, force_close_ticket::MINT)?;
+		let treasury = account_at(accounts, force_close_ticket::TREASURY)?;
+		let ticket_account = account_at(accounts, force_close_ticket::TICKET)?;
+		let ticket_token_account = account_at(accounts, force_close_ticket::TICKET_TOKEN)?;
+		let token_program = account_at(accounts, force_close_ticket::TOKEN_PROGRAM)?;
+
+		if ticket_account.owner != &crate::id() {
+			return Err(WhitelistError::IncorrectUserAccount.into());
+		}
+		let ticket_data = Ticket::unpack(&ticket_account.data.borrow())?;
+
+		// The whitelist may be gone, so tie everything back to the mint by derivation
+		let (whitelist, _) = get_whitelist_address(mint.key);
+		if whitelist_account.key != &whitelist || ticket_data.whitelist != whitelist {
+			return Err(WhitelistError::InvalidWhitelistAddress.into());
+		}
+
+		Ticket::derive_and_verify(
+			&ticket_data.owner,
+			&whitelist,
+			ticket_account.key,
+			ticket_data.bump,
+		)?;
+
+		if token_program.key != &spl_token_2022::id() && token_program.key != &spl_token::id() {
+			return Err(WhitelistError::IncorrectTokenProgram.into());
+		}
+
+		let ticket_ata = spl_associated_token_account::get_associated_token_address_with_program_id(
+			ticket_account.key,
+			mint.key,
+			token_program.key,
+		);
+		if ticket_token_account.key != &ticket_ata {
+			msg!(
+				"Force close rejected: ticket token account {} is not the ticket's ATA {}",
+				ticket_token_account.key,
+				ticket_ata
+			);
+			return Err(WhitelistError::IncorrectUserAccount.into());
+		}
+
+		let whitelist_closed =
+			whitelist_account.owner == &system_program::id() && whitelist_account.data_is_empty();
+		if whitelist_closed {
+			// Nothing is left to read the authority from, only whoever funded the ticket may
+			// reclaim it
+			if !authority.is_signer || authority.key != &ticket_data.payer {
+				return Err(WhitelistError::Unauthorised.into());
+			}
+
+			// The payer is only owed the rent, purchase SOL above it belongs to the sale
+			let proceeds = ticket_account
+				.lamports()
+				.saturating_sub(Rent::get()?.minimum_balance(ticket_account.data_len()));
+			if proceeds > 0 {
+				msg!(
+					"Force close rejected: the ticket holds {} lamports of purchase SOL",
+					proceeds
+				);
+				return Err(WhitelistError::TicketHoldsProceeds.into());
+			}
+		} else {
+			let mut wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+			if !authority.is_signer || authority.key != &wl_data.authority {
+				return Err(WhitelistError::Unauthorised.into());
+			}
+
+			if treasury.key != &wl_data.treasury {
+				return Err(WhitelistError::IncorrectTreasuryAddress.into());
+			}
+
+			wl_data.unregister_ticket();
+			wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
+		}
+
+		// Tokens left with the ticket would be stranded once it is gone, `BurnTicket` moves them
+		// to the treasury while the whitelist is live
+		if ticket_token_amount(ticket_token_account, token_program)? > 0 {
+			return Err(WhitelistError::TicketNotEmpty.into());
+		}
+		let lamports = close_ticket(
+			&whitelist,
+			&ticket_data,
+			ticket_account,
+			ticket_token_account,
+			treasury,
+			token_program,
+		)?;
+
+		msg!(
+			"Ticket force closed. {} lamports transferred to: {}",
+			lamports,
+			treasury.key
+		);
+		Ok(())
+	}
+
+	fn process_close_tickets(accounts: &[AccountInfo]) -> ProgramResult {
+		msg!("Process: Close tickets");
+		let whitelist_account = account_at(accounts, close_tickets::WHITELIST)?;
+		let authority = account_at(accounts, close_tickets::AUTHORITY)?;
+		let mint = account_at(accounts, close_tickets::MINT)?;
+		let treasury = account_at(accounts, close_tickets::TREASURY)?;
+		let token_program = account_at(accounts, close_tickets::TOKEN_PROGRAM)?;
+		let ticket_accounts = accounts.get(close_tickets::TICKETS..).unwrap_or_default();
+
+		if ticket_accounts.is_empty() || ticket_accounts.len() % 2 != 0 {
+			msg!(
+				"Close tickets rejected: expected ticket and ticket token account pairs, got {} accounts",
+				ticket_accounts.len()
+			);
+			return Err(ProgramError::NotEnoughAccountKeys);
+		}
+
+		// The whitelist may be gone, so tie everything back to the mint by derivation
+		let (whitelist, _) = get_whitelist_address(mint.key);
+		if whitelist_account.key != &whitelist {
+			return Err(WhitelistError::InvalidWhitelistAddress.into());
+		}
+
+		if token_program.key != &spl_token_2022::id() && token_program.key != &spl_token::id() {
+			return Err(WhitelistError::IncorrectTokenProgram.into());
+		}
+
+		if !authority.is_signer {
+			return Err(WhitelistError::Unauthorised.into());
+		}
+
+		let whitelist_closed =
+			whitelist_account.owner == &system_program::id() && whitelist_account.data_is_empty();
+		let mut wl_data = if whitelist_closed {
+			None
+		} else {
+			let wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+			if authority.key != &wl_data.authority {
+				return Err(WhitelistError::Unauthorised.into());
+			}
+
+			if treasury.key != &wl_data.treasury {
+				return Err(WhitelistError::IncorrectTreasuryAddress.into());
+			}
+
+			let clock = Clock::get()?;
+			if !wl_data.sale_ended(clock.unix_timestamp) {
+				msg!("Close tickets rejected: the sale has not ended");
+				return Err(WhitelistError::SaleOngoing.into());
+			}
+
+			Some(wl_data)
+		};
+
+		let rent = Rent::get()?;
+		let mut closed = 0;
+		let mut lamports = 0u64;
+		for pair in ticket_accounts.chunks_exact(2) {
+			let (ticket_account, ticket_token_account) = (&pair[0], &pair[1]);
+
+			if ticket_account.owner != &crate::id() {
+				return Err(WhitelistError::IncorrectUserAccount.into());
+			}
+			let ticket_data = Ticket::unpack(&ticket_account.data.borrow())?;
+			if ticket_data.whitelist != whitelist {
+				return Err(WhitelistError::InvalidWhitelistAddress.into());
+			}
+
+			Ticket::derive_and_verify(
+				&ticket_data.owner,
+				&whitelist,
+				ticket_account.key,
+				ticket_data.bump,
+			)?;
+
+			let ticket_ata =
+				spl_associated_token_account::get_associated_token_address_with_program_id(
+					ticket_account.key,
+					mint.key,
+					token_program.key,
+				);
+			if ticket_token_account.key != &ticket_ata {
+				msg!(
+					"Close tickets rejected: ticket token account {} is not the ATA {} of ticket {}",
+					ticket_token_account.key,
+					ticket_ata,
+					ticket_account.key
+				);
+				return Err(WhitelistError::IncorrectUserAccount.into());
+			}
+
+			// Nothing is left to read the authority from, only whoever funded the ticket may
+			// reclaim it
+			if whitelist_closed && authority.key != &ticket_data.payer {
+				return Err(WhitelistError::Unauthorised.into());
+			}
+
+			let token_amount = ticket_token_amount(ticket_token_account, token_program)?;
+			if token_amount > 0 {
+				msg!(
+					"Skipping ticket {}: its token account holds {} tokens",
+					ticket_account.key,
+					token_amount
+				);
+				continue;
+			}
+
+			// The payer is only owed the rent, purchase SOL above it belongs to the sale
+			if whitelist_closed {
+				let proceeds = ticket_account
+					.lamports()
+					.saturating_sub(rent.minimum_balance(ticket_account.data_len()));
+				if proceeds > 0 {
+					msg!(
+						"Skipping ticket {}: it holds {} lamports of purchase SOL",
+						ticket_account.key,
+						proceeds
+					);
+					continue;
+				}
+			}
+
+			let ticket_lamports = close_ticket(
+				&whitelist,
+				&ticket_data,
+				ticket_account,
+				ticket_token_account,
+				treasury,
+				token_program,
+			)?;
+			lamports = lamports
+				.checked_add(ticket_lamports)
+				.ok_or(WhitelistError::Overflow)?;
+			closed += 1;
+
+			if let Some(wl_data) = wl_data.as_mut() {
+				wl_data.unregister_ticket();
+			}
+		}
+
+		if let Some(wl_data) = wl_data {
+			wl_data.serialize(&mut &mut whitelist_account.data.borrow_mut()[..])?;
+		}
+
+		msg!(
+			"{} tickets closed. {} lamports transferred to: {}",
+			closed,
+			lamports,
+			treasury.key
+		);
+		Ok(())
+	}
+
+	fn process_withdraw_sol(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+		msg!("Process: Withdraw SOL");
+		let whitelist_account = account_at(accounts, withdraw_sol::WHITELIST)?;
+		let authority = account_at(accounts, withdraw_sol::AUTHORITY)?;
+		let treasury = account_at(accounts, withdraw_sol::TREASURY)?;
+		let ticket_account = accounts.get(withdraw_sol::TICKET);
+
+		if whitelist_account.owner != &crate::id() {
+			return Err(WhitelistError::WhitelistNotInitialized.into());
+		}
+		let wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+		if !authority.is_signer || authority.key != &wl_data.authority {
+			return Err(WhitelistError::Unauthorised.into());
+		}
+
+		if treasury.key != &wl_data.treasury {
+			return Err(WhitelistError::IncorrectTreasuryAddress.into());
+		}
+
+		if amount == 0 {
+			return Err(WhitelistError::ZeroAmount.into());
+		}
+
+		let source = match ticket_account {
+			Some(ticket_account) => {
+				if ticket_account.owner != &crate::id() {
+					return Err(WhitelistError::IncorrectUserAccount.into());
+				}
+				let ticket_data = Ticket::unpack(&ticket_account.data.borrow())?;
+				if ticket_data.whitelist != *whitelist_account.key {
+					return Err(WhitelistError::InvalidWhitelistAddress.into());
+				}
+
+				// Refunds are paid from the SOL the ticket holds, leave it until they close
+				let clock = Clock::get()?;
+				if wl_data.refund_open(ticket_data.last_purchase_ts, clock.unix_timestamp) {
+					msg!(
+						"Withdraw SOL rejected: ticket {} bought at {} and can be refunded for {} seconds",
+						ticket_account.key,
+						ticket_data.last_purchase_ts,
+						wl_data.refund_window_secs
+					);
+					return Err(WhitelistError::RefundWindowOpen.into());
+				}
+				ticket_account
+			}
+			None => whitelist_account,
+		};
+
+		check_distinct(source, treasury)?;
+
+		let rent = Rent::get()?.minimum_balance(source.data_len());
+		let available = source.lamports().saturating_sub(rent);
+		if amount > available {
+			msg!(
+				"Withdraw SOL rejected: {} lamports requested, {} available above rent",
+				amount,
+				available
+			);
+			return Err(WhitelistError::InsufficientFunds.into());
+		}
+
+		let treasury_lamports = treasury
+			.lamports()
+			.checked_add(amount)
+			.ok_or(WhitelistError::Overflow)?;
+		**source.lamports.borrow_mut() -= amount;
+		**treasury.lamports.borrow_mut() = treasury_lamports;
+
+		msg!(
+			"Withdrew {} lamports from {} to: {}",
+			amount,
+			source.key,
+			treasury.key
+		);
+		Ok(())
+	}
+
+	fn process_withdraw_treasury(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+		msg!("Process: Withdraw treasury");
+		let whitelist_account = account_at(accounts, withdraw_treasury::WHITELIST)?;
+		let authority = account_at(accounts, withdraw_treasury::AUTHORITY)?;
+		let treasury = account_at(accounts, withdraw_treasury::TREASURY)?;
+		let recipient = account_at(accounts, withdraw_treasury::RECIPIENT)?;
+
+		if whitelist_account.owner != &crate::id() {
+			return Err(WhitelistError::WhitelistNotInitialized.into());
+		}
+		let wl_data = Whitelist::unpack(&whitelist_account.data.borrow())?;
+
+		if !authority.is_signer || authority.key != &wl_data.authority {
+			return Err(WhitelistError::Unauthorised.into());
+		}
+
+		// Only a whitelist initialised with `treasury_is_pda` has a treasury the program holds
+		let (treasury_pda, _) = get_treasury_address(&wl_data.mint);
+		if treasury.key != &treasury_pda
+			|| treasury.key != &wl_data.treasury
+			|| treasury.owner != &crate::id()
+		{
+			msg!(
+				"Withdraw treasury rejected: {} is not the whitelist's treasury PDA",
+				treasury.key
+			);
+			return Err(WhitelistError::IncorrectTreasuryAddress.into());
+		}
+
+		if amount == 0 {
+			return Err(WhitelistError::ZeroAmount.into());
+		}
+
+		check_distinct(treasury, recipient)?;
+
+		let rent = Rent::get()?.minimum_balance(treasury.data_len());
+		let available = treasury.lamports().saturating_sub(rent);
+		if amount > available {
+			msg!(
+				"Withdraw treasury rejected: {} lamports requested, {} available above rent",
+				amount,
+				available
+			);
+			return Err(WhitelistError::InsufficientFunds.into());
+		}
+
+		let recipient_lamports = recipient
+			.lamports()
+			.checked_add(amount)
+			.ok_or(WhitelistError::Overflow)?;
+		**treasury.lamports.borrow_mut() -= amount;
+		**recipient.lamports.borrow_mut() = recipient_lamports;
+
+		msg!(
+			"Withdrew {} lamports from treasury {} to: {}",
+			amount,
+			treasury.key,
+			recipient.key
+		);
+		Ok(())
+	}
+
+	fn process_cleanup_token_account(accounts: &[AccountInfo]) -> ProgramResult {
+		let whitelist_account = account_at(accounts, cleanup_token_account::WHITELIST)?;
+		let owner = account_at(accounts, cleanup_token_account::OWNER)?;
+		let mint = account_at(accounts, cleanup_token_account::MINT)?;
+		let token_account = account_at(accounts, cleanup_token_account::TOKEN_ACCOUNT)?;
+		let token_program = account_at(accounts, cleanup_token_account::TOKEN_PROGRAM)?;
+
+		if !owner.is_signer {
+			return Err(WhitelistError::SignerError.into());
+		}
+
+		// Only the derivation is checked so the account can still be cleaned up once the
+		// whitelist has been terminated
+		if whitelist_account.key != &get_whitelist_address(mint.key).0 {
+			return Err(WhitelistError::InvalidWhitelistAddress.into());
+		}
+
+		if token_program.key != &spl_token_2022::id() && token_program.key != &spl_token::id() {
+			return Err(WhitelistError::IncorrectTokenProgram.into());
+		}
+
+		let owner_ata = spl_associated_token_account::get_associated_token_address_with_program_id(
+			owner.key,
+			mint.key,
+			token_program.key,
+		);
+		if token_account.key != &owner_ata || token_account.owner != token_program.key {
+			msg!(
+				"Cleanup rejected: token account {} is not the owner's ATA {}",
+				token_account.key,
+				owner_ata
+			);
+			return Err(WhitelistError::AccountMismatch.into());
+		}
+
+		let token_amount = {
+			let borrowed_token_account_data = token_account.data.borrow();
+			StateWithExtensions::<Account>::unpack(&borrowed_token_account_data)?
+				.base
+				.amount
+		};
+		if token_amount > 0 {
+			msg!(
+				"Cleanup rejected: token account holds {} tokens",
+				token_amount
+			);
+			return Err(WhitelistError::TokenAccountNotEmpty.into());
+		}
+
+		let owner_lamports = owner.lamports();
+		invoke(
+			&spl_token_2022::instruction::close_account(
+				token_program.key,
+				token_account.key,
+				owner.key,
+				owner.key,
+				&[],
+			)?,
+			&[token_account.clone(), owner.clone(), owner.clone()],
+		)?;
+
+		msg!(
+			"Token account closed, reclaimed {} lamports to {}",
+			owner.lamports().saturating_sub(owner_lamports),
+			owner.key
+		);
+		Ok(())
+	}
+}
+
+/// Closes an account owned by this program, moving every lamport it holds to `destination`
+/// before emptying its data and handing it back to the system program. The lamports are moved
+/// directly rather than through a system transfer, which the program could not sign for once the
+/// account is no longer its own. Returns the lamports reclaimed
+fn close_program_account(
+	account: &AccountInfo,
+	destination: &AccountInfo,
+) -> Result<u64, ProgramError> {
+	check_distinct(account, destination)?;
+
+	let lamports = account.lamports();
+	let destination_lamports = destination
+		.lamports()
+		.checked_add(lamports)
+		.ok_or(WhitelistError::Overflow)?;
+
+	**destination.lamports.borrow_mut() = destination_lamports;
+	**account.lamports.borrow_mut() = 0;
+	account.realloc(0, false)?;
+	account.assign(&system_program::id());
+
+	Ok(lamports)
+}
+
+/// Checks that the instruction before the current one is an Ed25519 program instruction
+/// verifying a single signature by `register_authority` over `message`. The runtime has already
+/// verified the signature by the time the program runs, so only what was signed is checked here.
+/// Every offset must point into the Ed25519 instruction itself, so the key and message read here
+/// are the ones that were verified
+fn check_register_voucher(
+	instructions_sysvar: &AccountInfo,
+	register_authority: &Pubkey,
+	message: &[u8],
+) -> ProgramResult {
+	let current = load_current_index_checked(instructions_sysvar)?;
+	let voucher = match current.checked_sub(1) {
+		Some(index) => load_instruction_at_checked(index as usize, instructions_sysvar)?,
+		None => {
+			msg!("Register rejected: no Ed25519 instruction precedes the registration");
+			return Err(WhitelistError::SignatureRequired.into());
+		}
+	};
+	if voucher.program_id != ed25519_program::id() {
+		msg!("Register rejected: no Ed25519 instruction precedes the registration");
+		return Err(WhitelistError::SignatureRequired.into());
+	}
+
+	let signed = match ed25519_offsets(&voucher.data) {
+		Some([_, THIS_INSTRUCTION, key, THIS_INSTRUCTION, offset, len, THIS_INSTRUCTION]) => {
+			let data = &voucher.data;
+			data.get(key..key + 32) == Some(register_authority.as_ref())
+				&& data.get(offset..offset + len) == Some(message)
+		}
+		_ => false,
+	};
+	if !signed {
+		msg!(
+			"Register rejected: voucher was not signed by register authority {} for this user",
+			register_authority
+		);
+		return Err(WhitelistError::InvalidSignature.into());
+	}
+	Ok(())
+}
+
+/// Instruction index the Ed25519 program reads as the Ed25519 instruction itself
+const THIS_INSTRUCTION: usize = u16::MAX as usize;
+
+/// Offsets of the only signature an Ed25519 program instruction verifies, in order: the
+/// signature, its instruction, the public key, its instruction, the message, the message size
+/// and its instruction. `None` unless exactly one signature is verified
+fn ed25519_offsets(data: &[u8]) -> Option<[usize; 7]> {
+	if data.first() != Some(&1) {
+		return None;
+	}
+	let mut offsets = [0; 7];
+	// The offsets follow the signature count and a padding byte
+	for (field, offset) in offsets.iter_mut().enumerate() {
+		let at = 2 + field * 2;
+		let bytes = data.get(at..at + 2)?;
+		*offset = u16::from_le_bytes([bytes[0], bytes[1]]) as usize;
+	}
+	Some(offsets)
+}
+
+/// Tokens held by a ticket's token account, 0 when the account was never initialised
+fn ticket_token_amount(
+	ticket_token_account: &AccountInfo,
+	token_program: &AccountInfo,
+) -> Result<u64, ProgramError> {
+	if ticket_token_account.owner != token_program.key {
+		return Ok(0);
+	}
+
+	let borrowed_ticket_token_data = ticket_token_account.data.borrow();
+	let amount = StateWithExtensions::<Account>::unpack(&borrowed_ticket_token_data)?
+		.base
+		.amount;
+	Ok(amount)
+}
+
+/// Closes an empty ticket together with its token account when one was created, moving the rent
+/// of both to `treasury`. The caller verifies the ticket and that its token account holds no
+/// tokens. Returns the lamports reclaimed
+fn close_ticket<'a>(
+	whitelist: &Pubkey,
+	ticket_data: &Ticket,
+	ticket_account: &AccountInfo<'a>,
+	ticket_token_account: &AccountInfo<'a>,
+	treasury: &AccountInfo<'a>,
+	token_program: &AccountInfo<'a>,
+) -> Result<u64, ProgramError> {
+	let mut ticket_token_lamports = 0;
+	if ticket_token_account.owner == token_program.key {
+		ticket_token_lamports = ticket_token_account.lamports();
+		invoke_signed(
+			&spl_token_2022::instruction::close_account(
+				token_program.key,
+				ticket_token_account.key,
+				treasury.key,
+				ticket_account.key,
+				&[],
+			)?,
+			&[
+				ticket_token_account.clone(),
+				treasury.clone(),
+				ticket_account.clone(),
+			],
+			&[&[
+				SEED,
+				ticket_data.owner.as_ref(),
+				whitelist.as_ref(),
+				&[ticket_data.bump],
+			]],
+		)?;
+	}
+
+	let ticket_lamports = close_program_account(ticket_account, treasury)?;
+
+	Ok(ticket_lamports
+		.checked_add(ticket_token_lamports)
+		.ok_or(WhitelistError::Overflow)?)
+}
+
+/// Returns every token held by a ticket's token account to the vault and closes the token
+/// account, its rent going to `destination`, the ticket signing for both. Does nothing when the
+/// token account was never created. The caller verifies the accounts. Returns the tokens returned
+fn return_ticket_tokens<'a>(
+	whitelist: &Pubkey,
+	wl_data: &Whitelist,
+	ticket_data: &Ticket,
+	ticket_account: &AccountInfo<'a>,
+	ticket_token_account: &AccountInfo<'a>,
+	vault: &AccountInfo<'a>,
+	mint: &AccountInfo<'a>,
+	destination: &AccountInfo<'a>,
+	token_program: &AccountInfo<'a>,
+) -> Result<u64, ProgramError> {
+	if ticket_token_account.owner != token_program.key {
+		return Ok(0);
+	}
+
+	let token_amount = ticket_token_amount(ticket_token_account, token_program)?;
+	let ticket_seeds: &[&[u8]] = &[
+		SEED,
+		ticket_data.owner.as_ref(),
+		whitelist.as_ref(),
+		&[ticket_data.bump],
+	];
+
+	if token_amount > 0 {
+		let mint_decimals = whitelist_mint_decimals(mint, wl_data)?;
+		invoke_signed(
+			&spl_token_2022::instruction::transfer_checked(
+				token_program.key,
+				ticket_token_account.key,
+				mint.key,
+				vault.key,
+				ticket_account.key,
+				&[],
+				token_amount,
+				mint_decimals,
+			)?,
+			&[
+				ticket_token_account.clone(),
+				mint.clone(),
+				vault.clone(),
+				ticket_account.clone(),
+				token_program.clone(),
+			],
+			&[ticket_seeds],
+		)?;
+	}
+
+	invoke_signed(
+		&spl_token_2022::instruction::close_account(
+			token_program.key,
+			ticket_token_account.key,
+			destination.key,
+			ticket_account.key,
+			&[],
+		)?,
+		&[
+			ticket_token_account.clone(),
+			destination.clone(),
+			ticket_account.clone(),
+			token_program.clone(),
+		],
+		&[ticket_seeds],
+	)?;
+
+	Ok(token_amount)
+}
+
+/// Fails with `AccountMismatch` when the same account is passed for two slots that must differ,
+/// e.g. a transfer whose source is also its destination
+fn check_distinct(account: &AccountInfo, other: &AccountInfo) -> ProgramResult {
+	if account.key == other.key {
+		msg!(
+			"Account {} was passed for two distinct accounts",
+			account.key
+		);
+		return Err(WhitelistError::AccountMismatch.into());
+	}
+
+	Ok(())
+}
+
+/// Reads the decimals of the whitelist's mint, failing with `IncorrectMintAddress` when `mint` is
+/// any other mint. Amounts are only converted with decimals read through here, so a mint with
+/// different decimals cannot be passed to skew a conversion or transfer
+fn whitelist_mint_decimals(mint: &AccountInfo, wl_data: &Whitelist) -> Result<u8, ProgramError> {
+	if mint.key != &wl_data.mint {
+		msg!(
+			"Mint {} does not match whitelist mint {}",
+			mint.key,
+			wl_data.mint
+		);
+		return Err(WhitelistError::IncorrectMintAddress.into());
+	}
+
+	let borrowed_mint_data = mint.data.borrow();
+	Ok(StateWithExtensions::<Mint>::unpack(&borrowed_mint_data)?
+		.base
+		.decimals)
+}
+
+/// Fails with `VaultFrozen` when the freeze authority of the vault's mint has frozen it, so a
+/// stuck vault is reported as such instead of as an opaque token program error
+fn check_vault_not_frozen(vault: &AccountInfo) -> ProgramResult {
+	let borrowed_vault_data = vault.data.borrow();
+	if StateWithExtensions::<Account>::unpack(&borrowed_vault_data)?
+		.base
+		.is_frozen()
+	{
+		msg!("Vault {} is frozen", vault.key);
+		return Err(WhitelistError::VaultFrozen.into());
+	}
+
+	Ok(())
+}
+
+/// Transfers every token left in the vault to `recipient_token_account` and closes the vault,
+/// its rent going to `authority`. The caller verifies the accounts. Returns the tokens moved and
+/// the rent reclaimed, the rent measured on the authority so that only what actually moved is
+/// reported
+fn empty_and_close_vault<'a>(
+	whitelist_account: &AccountInfo<'a>,
+	authority: &AccountInfo<'a>,
+	vault: &AccountInfo<'a>,
+	mint: &AccountInfo<'a>,
+	recipient_token_account: &AccountInfo<'a>,
+	token_program: &AccountInfo<'a>,
+	mint_decimals: u8,
+	bump: u8,
+) -> Result<(u64, u64), ProgramError> {
+	let vault_amount = {
+		let borrowed_vault_data = vault.data.borrow();
+		StateWithExtensions::<Account>::unpack(&borrowed_vault_data)?
+			.base
+			.amount
+	};
+
+	// An already drained vault skips straight to closing it
+	if vault_amount == 0 {
+		msg!("Vault empty, closing accounts");
+	} else {
+		invoke_signed(
+			&spl_token_2022::instruction::transfer_checked(
+				token_program.key,
+				vault.key,
+				mint.key,
+				recipient_token_account.key,
+				whitelist_account.key,
+				&[],
+				vault_amount,
+				mint_decimals,
+			)?,
+			&[
+				vault.clone(),
+				mint.clone(),
+				recipient_token_account.clone(),
+				whitelist_account.clone(),
+			],
+			&[&[SEED, mint.key.as_ref(), &[bump]]],
+		)?;
+	}
+
+	let authority_lamports = authority.lamports();
+	invoke_signed(
+		&spl_token_2022::instruction::close_account(
+			token_program.key,
+			vault.key,
+			authority.key,
+			whitelist_account.key,
+			&[],
+		)?,
+		&[vault.clone(), authority.clone(), whitelist_account.clone()],
+		&[&[SEED, mint.key.as_ref(), &[bump]]],
+	)?;
+
+	Ok((
+		vault_amount,
+		authority.lamports().saturating_sub(authority_lamports),
+	))
+}
+
+#[cfg(test)]
+mod tests {
+	use {
+		super::*,
+		//chrono::NaiveDateTime,
+		solana_program_test::*,
+		solana_sdk::{
+			hash::Hash, instruction::AccountMeta, signature::Signer, signer::keypair::Keypair,
+			transaction::Transaction,
+		},
+		test_case::test_case,
+	};
+
+	//let datetime = NaiveDateTime::parse_from_str(date_string.as_str(), "%Y-%m-%s %H:%M:%S")?;
+
+	fn program_test() -> ProgramTest {
+		let mut program_test =
+			ProgramTest::new("stuk_wl", crate::id(), processor!(Processor::process));
+
+		program_test.add_program(
+			"spl_token_2022",
+			spl_token_2022::id(),
+			processor!(spl_token_2022::processor::Processor::process),
+		);
+		program_test.add_program(
+			"spl_token",
+			spl_token::id(),
+			processor!(spl_token::processor::Processor::process),
+		);
+
+		program_test
+	}
+
+	async fn setup_test_environment() -> (BanksClient, Keypair, Hash) {
+		program_test().start().await
+	}
+
+	/// Registration, sale and refund windows used to initialise a whitelist, all zero by
+	/// default so that every phase is open immediately and refunds are disabled. Users pay
+	/// their own ticket rent unless `authority_pays_rent` is set
+	#[derive(Clone, Copy, Default)]
+	struct SaleTimes {
+		registration_start: i64,
+		registration_duration: i64,
+		sale_start: i64,
+		sale_duration: i64,
+		refund_window_secs: u64,
+		authority_pays_rent: bool,
+	}
+
+	/// Advances the bank a slot and sets its clock to `unix_timestamp`, so time gated paths can
+	/// be exercised without waiting on the wall clock. Returns a blockhash from the new bank for
+	/// signing subsequent transactions
+	async fn warp_to_timestamp(context: &mut ProgramTestContext, unix_timestamp: i64) -> Hash {
+		let slot = context
+			.banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.slot;
+		context.warp_to_slot(slot + 1).unwrap();
+
+		let mut clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+		clock.unix_timestamp = unix_timestamp;
+		context.set_sysvar(&clock);
+
+		context.last_blockhash = context.banks_client.get_latest_blockhash().await.unwrap();
+		context.last_blockhash
+	}
+
+	/// Asserts that a closed account holds no lamports, no data and belongs to the system
+	/// program. The runtime drops accounts left with zero lamports, so a missing account passes
+	async fn assert_account_closed(banks_client: &mut BanksClient, address: Pubkey) {
+		if let Some(account) = banks_client.get_account(address).await.unwrap() {
+			assert_eq!(account.lamports, 0);
+			assert!(account.data.is_empty());
+			assert_eq!(account.owner, system_program::id());
+		}
+	}
+
+	async fn assert_whitelist_error(
+		banks_client: &mut BanksClient,
+		transaction: Transaction,
+		error: WhitelistError,
+	) {
+		let err = banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap_err()
+			.unwrap();
+		assert_eq!(
+			err,
+			solana_sdk::transaction::TransactionError::InstructionError(
+				0,
+				solana_sdk::instruction::InstructionError::Custom(error as u32)
+			)
+		);
+	}
+
+	async fn create_mint(
+		banks_client: &mut BanksClient,
+		payer: &Keypair,
+		recent_blockhash: &Hash,
+		mint_keypair: &Keypair,
+		token_program_id: &Pubkey,
+		decimals: u8,
+	) {
+		create_mint_with_freeze_authority(
+			banks_client,
+			payer,
+			recent_blockhash,
+			mint_keypair,
+			token_program_id,
+			decimals,
+			None,
+		)
+		.await;
+	}
+
+	async fn create_mint_with_freeze_authority(
+		banks_client: &mut BanksClient,
 		payer: &Keypair,
 		recent_blockhash: &Hash,
 		mint_keypair: &Keypair,
 		token_program_id: &Pubkey,
 		decimals: u8,
+		freeze_authority: Option<&Pubkey>,
 	) {
 		let space =
 			spl_token_2022::extension::ExtensionType::try_calculate_account_len::<Mint>(&[])
 				.unwrap();
-		let mint_rent = banks_client
-			.get_rent()
+		let mint_rent = banks_client
+			.get_rent()
+			.await
+			.unwrap()
+			.minimum_balance(space);
+
+		let init_mint = spl_token_2022::instruction::initialize_mint(
+			&token_program_id,
+			&mint_keypair.pubkey(),
+			&payer.pubkey(),
+			freeze_authority,
+			decimals,
+		);
+
+		let instructions = [
+			system_instruction::create_account(
+				&payer.pubkey(),
+				&mint_keypair.pubkey(),
+				mint_rent,
+				space as u64,
+				&token_program_id,
+			),
+			init_mint.unwrap(),
+		];
+
+		let mut transaction = Transaction::new_with_payer(&instructions, Some(&payer.pubkey()));
+		transaction.sign(&[payer, &mint_keypair], *recent_blockhash);
+
+		banks_client.process_transaction(transaction).await.unwrap();
+		let mint_test = banks_client
+			.get_account(mint_keypair.pubkey())
+			.await
+			.expect("get mint account")
+			.expect("mint account is none");
+		assert_eq!(mint_test.data.len(), space);
+
+		println!("Mint created");
+	}
+
+	async fn create_default_whitelist(
+		banks_client: &mut BanksClient,
+		payer: &Keypair,
+		recent_blockhash: &Hash,
+		token_program_id: &Pubkey,
+	) -> (Pubkey, Pubkey, Keypair, Keypair) {
+		create_timed_whitelist(
+			banks_client,
+			payer,
+			recent_blockhash,
+			token_program_id,
+			SaleTimes::default(),
+		)
+		.await
+	}
+
+	async fn create_timed_whitelist(
+		banks_client: &mut BanksClient,
+		payer: &Keypair,
+		recent_blockhash: &Hash,
+		token_program_id: &Pubkey,
+		times: SaleTimes,
+	) -> (Pubkey, Pubkey, Keypair, Keypair) {
+		let treasury = Keypair::new();
+		let mint_keypair = Keypair::new();
+		let (whitelist, _) = get_whitelist_address(&mint_keypair.pubkey());
+		create_mint(
+			banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint_keypair,
+			token_program_id,
+			9,
+		)
+		.await;
+		let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
+			&whitelist,
+			&mint_keypair.pubkey(),
+			token_program_id,
+		);
+
+		let token_price = 1;
+		let buy_limit = 10;
+		let whitelist_size = 5;
+		let allow_registration = true;
+		let ix = crate::instructions::init_whitelist(
+			&whitelist,
+			&payer.pubkey(),
+			&vault,
+			&mint_keypair.pubkey(),
+			&treasury.pubkey(),
+			token_price,
+			buy_limit,
+			whitelist_size,
+			allow_registration,
+			times.registration_start,
+			times.registration_duration,
+			times.sale_start,
+			times.sale_duration,
+			0,
+			times.refund_window_secs,
+			times.authority_pays_rent,
+			false,
+			false,
+			token_program_id,
+		)
+		.unwrap();
+
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[payer], *recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		println!("Whitelist initialised");
+		(whitelist, vault, mint_keypair, treasury)
+	}
+
+	async fn mint_tokens(
+		banks_client: &mut BanksClient,
+		payer: &Keypair,
+		recent_blockhash: &Hash,
+		mint: &Pubkey,
+		owner: &Pubkey,
+		amount: u64,
+		token_program_id: &Pubkey,
+	) -> Pubkey {
+		let token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				owner,
+				mint,
+				token_program_id,
+			);
+
+		let instructions = [
+			spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+				&payer.pubkey(),
+				owner,
+				mint,
+				token_program_id,
+			),
+			spl_token_2022::instruction::mint_to(
+				token_program_id,
+				mint,
+				&token_account,
+				&payer.pubkey(),
+				&[],
+				amount,
+			)
+			.unwrap(),
+		];
+
+		let mut transaction = Transaction::new_with_payer(&instructions, Some(&payer.pubkey()));
+		transaction.sign(&[payer], *recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		token_account
+	}
+
+	async fn fund_vault(
+		banks_client: &mut BanksClient,
+		payer: &Keypair,
+		recent_blockhash: &Hash,
+		whitelist: &Pubkey,
+		vault: &Pubkey,
+		mint: &Pubkey,
+		amount: u64,
+		token_program_id: &Pubkey,
+	) {
+		let payer_token_account = mint_tokens(
+			banks_client,
+			payer,
+			recent_blockhash,
+			mint,
+			&payer.pubkey(),
+			amount * 10u64.pow(9),
+			token_program_id,
+		)
+		.await;
+
+		let ix = crate::instructions::deposit_tokens(
+			whitelist,
+			vault,
+			&payer.pubkey(),
+			&payer_token_account,
+			mint,
+			amount,
+			token_program_id,
+		)
+		.unwrap();
+
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[payer], *recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+	}
+
+	async fn token_balance(banks_client: &mut BanksClient, token_account: Pubkey) -> u64 {
+		let account = banks_client
+			.get_account(token_account)
+			.await
+			.unwrap()
+			.unwrap();
+		StateWithExtensions::<Account>::unpack(&account.data)
+			.unwrap()
+			.base
+			.amount
+	}
+
+	async fn register_user(
+		banks_client: &mut BanksClient,
+		user: &Keypair,
+		recent_blockhash: &Hash,
+		whitelist: &Pubkey,
+	) -> Pubkey {
+		let (ticket, _) = get_user_ticket_address(&user.pubkey(), whitelist);
+		let ix = crate::instructions::register(
+			whitelist,
+			&user.pubkey(),
+			&ticket,
+			&get_allocation_address(&user.pubkey(), whitelist).0,
+		)
+		.unwrap();
+
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&user.pubkey()));
+		transaction.sign(&[user], *recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		ticket
+	}
+
+	/// Adds `user` with `AddUserAndSeed`, returning their ticket and its token account holding
+	/// the buy limit
+	async fn seed_user(
+		banks_client: &mut BanksClient,
+		payer: &Keypair,
+		recent_blockhash: &Hash,
+		whitelist: &Pubkey,
+		vault: &Pubkey,
+		mint: &Pubkey,
+		user: &Pubkey,
+		token_program_id: &Pubkey,
+	) -> (Pubkey, Pubkey) {
+		let (ticket, _) = get_user_ticket_address(user, whitelist);
+		let ticket_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&ticket,
+				mint,
+				token_program_id,
+			);
+		let ix = crate::instructions::add_user_and_seed(
+			whitelist,
+			&payer.pubkey(),
+			mint,
+			user,
+			&ticket,
+			vault,
+			&ticket_token_account,
+			token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[payer], *recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		(ticket, ticket_token_account)
+	}
+
+	fn buy_instruction(
+		whitelist: &Pubkey,
+		vault: &Pubkey,
+		mint: &Pubkey,
+		user: &Pubkey,
+		amount: u64,
+		max_price: Option<u64>,
+		token_program_id: &Pubkey,
+	) -> solana_program::instruction::Instruction {
+		let (ticket, _) = get_user_ticket_address(user, whitelist);
+		let ticket_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&ticket,
+				mint,
+				token_program_id,
+			);
+		let user_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				user,
+				mint,
+				token_program_id,
+			);
+
+		crate::instructions::buy_tokens(
+			whitelist,
+			vault,
+			mint,
+			user,
+			&ticket,
+			&ticket_token_account,
+			&user_token_account,
+			amount,
+			max_price,
+			false,
+			token_program_id,
+		)
+		.unwrap()
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_init_whitelist(token_program_id: Pubkey) {
+		let treasury_keypair = Keypair::new();
+		let mint_keypair = Keypair::new();
+
+		let (whitelist, _) = get_whitelist_address(&mint_keypair.pubkey());
+
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+
+		create_mint(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint_keypair,
+			&token_program_id,
+			9,
+		)
+		.await;
+
+		let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
+			&whitelist,
+			&mint_keypair.pubkey(),
+			&token_program_id,
+		);
+
+		let token_price = 1;
+		let buy_limit = 10;
+		let whitelist_size = 5;
+		let allow_registration = true;
+		let registration_start_timestamp = 0;
+		let registration_duration = 0;
+		let sale_start_timestamp = 0;
+		let sale_duration = 0;
+
+		let ix = crate::instructions::init_whitelist(
+			&whitelist,
+			&payer.pubkey(),
+			&vault,
+			&mint_keypair.pubkey(),
+			&treasury_keypair.pubkey(),
+			token_price,
+			buy_limit,
+			whitelist_size,
+			allow_registration,
+			registration_start_timestamp,
+			registration_duration,
+			sale_start_timestamp,
+			sale_duration,
+			0,
+			0,
+			false,
+			false,
+			false,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+		let whitelist_account = banks_client
+			.get_account(whitelist)
+			.await
+			.expect("get_account")
+			.expect("whitelist account not none");
+		let rent = banks_client.get_rent().await.unwrap();
+		assert_eq!(whitelist_account.data.len(), Whitelist::LEN);
+		assert_eq!(whitelist_account.owner, crate::id());
+		assert_eq!(
+			whitelist_account.lamports,
+			rent.minimum_balance(Whitelist::LEN)
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_init_recovers_missing_vault(token_program_id: Pubkey) {
+		let authority = Keypair::new();
+		let mint_keypair = Keypair::new();
+		let (whitelist, bump) = get_whitelist_address(&mint_keypair.pubkey());
+		let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
+			&whitelist,
+			&mint_keypair.pubkey(),
+			&token_program_id,
+		);
+
+		// A whitelist that was written without its vault ever being created
+		let stranded = Whitelist {
+			bump,
+			authority: authority.pubkey(),
+			vault,
+			mint: mint_keypair.pubkey(),
+			..sample_whitelist()
+		};
+		let mut program_test = program_test();
+		program_test.add_account(
+			whitelist,
+			solana_sdk::account::Account {
+				lamports: Rent::default().minimum_balance(Whitelist::LEN),
+				data: borsh::to_vec(&stranded).unwrap(),
+				owner: crate::id(),
+				executable: false,
+				rent_epoch: 0,
+			},
+		);
+		program_test.add_account(
+			authority.pubkey(),
+			solana_sdk::account::Account::new(1_000_000_000, 0, &system_program::id()),
+		);
+		let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+		create_mint(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint_keypair,
+			&token_program_id,
+			9,
+		)
+		.await;
+
+		let init_ix = || {
+			crate::instructions::init_whitelist(
+				&whitelist,
+				&authority.pubkey(),
+				&vault,
+				&mint_keypair.pubkey(),
+				&Pubkey::new_unique(),
+				2,
+				20,
+				10,
+				false,
+				0,
+				0,
+				0,
+				0,
+				0,
+				0,
+				false,
+				false,
+				false,
+				&token_program_id,
+			)
+			.unwrap()
+		};
+		let mut transaction = Transaction::new_with_payer(&[init_ix()], Some(&payer.pubkey()));
+		transaction.sign(&[&payer, &authority], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let vault_account = banks_client.get_account(vault).await.unwrap().unwrap();
+		assert_eq!(vault_account.owner, token_program_id);
+		// The state already written is kept rather than replaced by the new arguments
+		let whitelist_account = banks_client.get_account(whitelist).await.unwrap().unwrap();
+		assert_eq!(
+			Whitelist::unpack(&whitelist_account.data).unwrap(),
+			stranded
+		);
+
+		// With the vault in place the whitelist is fully initialised
+		let recent_blockhash = banks_client
+			.get_new_latest_blockhash(&recent_blockhash)
+			.await
+			.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[init_ix()], Some(&payer.pubkey()));
+		transaction.sign(&[&payer, &authority], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::WhitelistAlreadyInitialized,
+		)
+		.await;
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_wrong_program_errors(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let mint_keypair = Keypair::new();
+		let (whitelist, _) = get_whitelist_address(&mint_keypair.pubkey());
+		let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
+			&whitelist,
+			&mint_keypair.pubkey(),
+			&token_program_id,
+		);
+		create_mint(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint_keypair,
+			&token_program_id,
+			9,
+		)
+		.await;
+
+		// Each program account is swapped for an arbitrary key in turn
+		for (index, error) in [
+			(
+				initialise_whitelist::SYSTEM_PROGRAM,
+				WhitelistError::IncorrectSystemProgram,
+			),
+			(
+				initialise_whitelist::ASSOCIATED_TOKEN_PROGRAM,
+				WhitelistError::IncorrectAssociatedTokenProgram,
+			),
+		] {
+			let mut ix = crate::instructions::init_whitelist(
+				&whitelist,
+				&payer.pubkey(),
+				&vault,
+				&mint_keypair.pubkey(),
+				&Pubkey::new_unique(),
+				2,
+				20,
+				10,
+				false,
+				0,
+				0,
+				0,
+				0,
+				0,
+				0,
+				false,
+				false,
+				false,
+				&token_program_id,
+			)
+			.unwrap();
+			ix.accounts[index].pubkey = Pubkey::new_unique();
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer], recent_blockhash);
+			assert_whitelist_error(&mut banks_client, transaction, error).await;
+		}
+
+		// The vault is derived from the token program, so a wrong one is caught after init. The
+		// sale has not started, so the vault may be withdrawn from
+		let clock = banks_client.get_sysvar::<Clock>().await.unwrap();
+		let (whitelist, vault, mint, _treasury) = create_timed_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+			SaleTimes {
+				sale_start: clock.unix_timestamp + 3600,
+				sale_duration: 3600,
+				..SaleTimes::default()
+			},
+		)
+		.await;
+		let mut ix = crate::instructions::withdraw_tokens(
+			&whitelist,
+			&payer.pubkey(),
+			&vault,
+			&mint.pubkey(),
+			&Pubkey::new_unique(),
+			1,
+			&token_program_id,
+		)
+		.unwrap();
+		ix.accounts[withdraw_tokens::TOKEN_PROGRAM].pubkey = Pubkey::new_unique();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::IncorrectTokenProgram,
+		)
+		.await;
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_init_rejects_freezable_mint(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let mint_keypair = Keypair::new();
+		create_mint_with_freeze_authority(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint_keypair,
+			&token_program_id,
+			9,
+			Some(&payer.pubkey()),
+		)
+		.await;
+
+		let (whitelist, _) = get_whitelist_address(&mint_keypair.pubkey());
+		let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
+			&whitelist,
+			&mint_keypair.pubkey(),
+			&token_program_id,
+		);
+		let ix = crate::instructions::init_whitelist(
+			&whitelist,
+			&payer.pubkey(),
+			&vault,
+			&mint_keypair.pubkey(),
+			&Pubkey::new_unique(),
+			2,
+			20,
+			10,
+			false,
+			0,
+			0,
+			0,
+			0,
+			0,
+			0,
+			false,
+			false,
+			false,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::FreezableMint,
+		)
+		.await;
+
+		assert!(banks_client.get_account(whitelist).await.unwrap().is_none());
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_init_zero_price(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let mint_keypair = Keypair::new();
+		create_mint(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint_keypair,
+			&token_program_id,
+			9,
+		)
+		.await;
+
+		let (whitelist, _) = get_whitelist_address(&mint_keypair.pubkey());
+		let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
+			&whitelist,
+			&mint_keypair.pubkey(),
+			&token_program_id,
+		);
+		let init_ix = |allow_free: bool| {
+			crate::instructions::init_whitelist(
+				&whitelist,
+				&payer.pubkey(),
+				&vault,
+				&mint_keypair.pubkey(),
+				&Pubkey::new_unique(),
+				0,
+				20,
+				10,
+				false,
+				0,
+				0,
+				0,
+				0,
+				0,
+				0,
+				false,
+				allow_free,
+				false,
+				&token_program_id,
+			)
+			.unwrap()
+		};
+
+		// A zero price gives the supply away unless the sale is explicitly free
+		let mut transaction = Transaction::new_with_payer(&[init_ix(false)], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(&mut banks_client, transaction, WhitelistError::ZeroPrice).await;
+		assert!(banks_client.get_account(whitelist).await.unwrap().is_none());
+
+		let mut transaction = Transaction::new_with_payer(&[init_ix(true)], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let whitelist_account = banks_client
+			.get_account(whitelist)
+			.await
+			.unwrap()
+			.expect("free sale initialised");
+		let wl_data = Whitelist::unpack(&whitelist_account.data).unwrap();
+		assert_eq!(wl_data.token_price, 0);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_frozen_vault(token_program_id: Pubkey) {
+		let authority = Keypair::new();
+		let mint_keypair = Keypair::new();
+		let (whitelist, bump) = get_whitelist_address(&mint_keypair.pubkey());
+		let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
+			&whitelist,
+			&mint_keypair.pubkey(),
+			&token_program_id,
+		);
+
+		// A whitelist over a freezable mint, as could have been created before such mints were
+		// refused, with the sale still to come
+		let mut program_test = program_test();
+		program_test.add_account(
+			whitelist,
+			solana_sdk::account::Account {
+				lamports: Rent::default().minimum_balance(Whitelist::LEN),
+				data: borsh::to_vec(&Whitelist {
+					bump,
+					authority: authority.pubkey(),
+					vault,
+					mint: mint_keypair.pubkey(),
+					sale_timestamp: i64::MAX,
+					..sample_whitelist()
+				})
+				.unwrap(),
+				owner: crate::id(),
+				executable: false,
+				rent_epoch: 0,
+			},
+		);
+		program_test.add_account(
+			authority.pubkey(),
+			solana_sdk::account::Account::new(1_000_000_000, 0, &system_program::id()),
+		);
+		let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+		create_mint_with_freeze_authority(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint_keypair,
+			&token_program_id,
+			9,
+			Some(&payer.pubkey()),
+		)
+		.await;
+		mint_tokens(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint_keypair.pubkey(),
+			&whitelist,
+			10_000_000_000,
+			&token_program_id,
+		)
+		.await;
+
+		let freeze_ix = spl_token_2022::instruction::freeze_account(
+			&token_program_id,
+			&vault,
+			&mint_keypair.pubkey(),
+			&payer.pubkey(),
+			&[],
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[freeze_ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let recipient_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&authority.pubkey(),
+				&mint_keypair.pubkey(),
+				&token_program_id,
+			);
+		let withdraw_ix = crate::instructions::withdraw_tokens(
+			&whitelist,
+			&authority.pubkey(),
+			&vault,
+			&mint_keypair.pubkey(),
+			&recipient_token_account,
+			1,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[withdraw_ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer, &authority], recent_blockhash);
+		assert_whitelist_error(&mut banks_client, transaction, WhitelistError::VaultFrozen).await;
+
+		let terminate_ix = crate::instructions::terminate_whitelist(
+			&whitelist,
+			&authority.pubkey(),
+			&vault,
+			&mint_keypair.pubkey(),
+			&authority.pubkey(),
+			&recipient_token_account,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[terminate_ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer, &authority], recent_blockhash);
+		assert_whitelist_error(&mut banks_client, transaction, WhitelistError::VaultFrozen).await;
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_wrong_decimals_mint_is_rejected(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		// Before the sale, when the vault may be withdrawn from and the whitelist terminated
+		let clock = banks_client.get_sysvar::<Clock>().await.unwrap();
+		let (whitelist, vault, mint, _treasury) = create_timed_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+			SaleTimes {
+				sale_start: clock.unix_timestamp + 3600,
+				sale_duration: 3600,
+				..SaleTimes::default()
+			},
+		)
+		.await;
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			10,
+			&token_program_id,
+		)
+		.await;
+
+		// A 6 decimal mint in place of the whitelist's 9 decimal mint would scale every amount
+		// by 1000 if its decimals were read
+		let wrong_mint = Keypair::new();
+		create_mint(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&wrong_mint,
+			&token_program_id,
+			6,
+		)
+		.await;
+		let wrong_token_account = mint_tokens(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&wrong_mint.pubkey(),
+			&payer.pubkey(),
+			10_000_000,
+			&token_program_id,
+		)
+		.await;
+
+		let instructions = [
+			crate::instructions::deposit_tokens(
+				&whitelist,
+				&vault,
+				&payer.pubkey(),
+				&wrong_token_account,
+				&wrong_mint.pubkey(),
+				1,
+				&token_program_id,
+			)
+			.unwrap(),
+			crate::instructions::withdraw_tokens(
+				&whitelist,
+				&payer.pubkey(),
+				&vault,
+				&wrong_mint.pubkey(),
+				&wrong_token_account,
+				1,
+				&token_program_id,
+			)
+			.unwrap(),
+			crate::instructions::set_referral_bonus(
+				&whitelist,
+				&payer.pubkey(),
+				&wrong_mint.pubkey(),
+				1,
+			)
+			.unwrap(),
+			crate::instructions::terminate_whitelist(
+				&whitelist,
+				&payer.pubkey(),
+				&vault,
+				&wrong_mint.pubkey(),
+				&payer.pubkey(),
+				&wrong_token_account,
+				&token_program_id,
+			)
+			.unwrap(),
+		];
+		for ix in instructions {
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer], recent_blockhash);
+			assert_whitelist_error(
+				&mut banks_client,
+				transaction,
+				WhitelistError::IncorrectMintAddress,
+			)
+			.await;
+		}
+
+		// Nothing was converted or moved
+		let wl_data = banks_client
+			.get_account_data_with_borsh::<Whitelist>(whitelist)
+			.await
+			.unwrap();
+		assert_eq!(wl_data.deposited, 10 * 10u64.pow(9));
+		assert_eq!(wl_data.referral_bonus, 0);
+		assert_eq!(
+			token_balance(&mut banks_client, vault).await,
+			10 * 10u64.pow(9)
+		);
+		assert_eq!(
+			token_balance(&mut banks_client, wrong_token_account).await,
+			10_000_000
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_vault_as_recipient_is_rejected(token_program_id: Pubkey) {
+		let authority = Keypair::new();
+		let mint_keypair = Keypair::new();
+		let (whitelist, bump) = get_whitelist_address(&mint_keypair.pubkey());
+		let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
+			&whitelist,
+			&mint_keypair.pubkey(),
+			&token_program_id,
+		);
+
+		let mut program_test = program_test();
+		program_test.add_account(
+			whitelist,
+			solana_sdk::account::Account {
+				lamports: Rent::default().minimum_balance(Whitelist::LEN),
+				data: borsh::to_vec(&Whitelist {
+					bump,
+					authority: authority.pubkey(),
+					vault,
+					mint: mint_keypair.pubkey(),
+					sale_timestamp: i64::MAX,
+					..sample_whitelist()
+				})
+				.unwrap(),
+				owner: crate::id(),
+				executable: false,
+				rent_epoch: 0,
+			},
+		);
+		program_test.add_account(
+			authority.pubkey(),
+			solana_sdk::account::Account::new(1_000_000_000, 0, &system_program::id()),
+		);
+		let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+		create_mint(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint_keypair,
+			&token_program_id,
+			9,
+		)
+		.await;
+		mint_tokens(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint_keypair.pubkey(),
+			&whitelist,
+			10_000_000_000,
+			&token_program_id,
+		)
+		.await;
+
+		// The vault passed as the recipient of its own tokens
+		let withdraw_ix = crate::instructions::withdraw_tokens(
+			&whitelist,
+			&authority.pubkey(),
+			&vault,
+			&mint_keypair.pubkey(),
+			&vault,
+			1,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[withdraw_ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer, &authority], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::AccountMismatch,
+		)
+		.await;
+
+		let terminate_ix = crate::instructions::terminate_whitelist(
+			&whitelist,
+			&authority.pubkey(),
+			&vault,
+			&mint_keypair.pubkey(),
+			&authority.pubkey(),
+			&vault,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[terminate_ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer, &authority], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::AccountMismatch,
+		)
+		.await;
+
+		let vault_account = banks_client.get_account(vault).await.unwrap().unwrap();
+		let vault_data = StateWithExtensions::<Account>::unpack(&vault_account.data).unwrap();
+		assert_eq!(vault_data.base.amount, 10_000_000_000);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_add_user(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _, mint, _) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let user_keypair = Keypair::new();
+		let (user_ticket, _) = get_user_ticket_address(&user_keypair.pubkey(), &whitelist);
+		let ix = crate::instructions::add_user(
+			&whitelist,
+			&payer.pubkey(),
+			&mint.pubkey(),
+			&user_keypair.pubkey(),
+			&user_ticket,
+		)
+		.unwrap();
+
+		let mut transaction = Transaction::new_with_payer(&[ix.clone()], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		// Adding the user again would overwrite their live ticket
+		let recent_blockhash = banks_client
+			.get_new_latest_blockhash(&recent_blockhash)
+			.await
+			.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::WhitelistAlreadyInitialized,
+		)
+		.await;
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_batch_add_user(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _, mint, _) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let users: Vec<(Pubkey, Pubkey)> = (0..3)
+			.map(|_| {
+				let user = Pubkey::new_unique();
+				(user, get_user_ticket_address(&user, &whitelist).0)
+			})
+			.collect();
+		let batch = |users: &[(Pubkey, Pubkey)]| {
+			crate::instructions::batch_add_user(&whitelist, &payer.pubkey(), &mint.pubkey(), users)
+				.unwrap()
+		};
+
+		// Every user needs its own account pair
+		let mut ix = batch(&users);
+		ix.accounts.truncate(ix.accounts.len() - 2);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::BatchAccountsMismatch,
+		)
+		.await;
+
+		// Pairs must follow the order of the users in the instruction data
+		let mut ix = batch(&users);
+		ix.accounts
+			.swap(batch_add_user::USERS, batch_add_user::USERS + 2);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::BatchAccountsMismatch,
+		)
+		.await;
+
+		let oversized: Vec<(Pubkey, Pubkey)> = (0..=MAX_BATCH_USERS)
+			.map(|_| (Pubkey::new_unique(), Pubkey::new_unique()))
+			.collect();
+		let mut transaction =
+			Transaction::new_with_payer(&[batch(&oversized)], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::InvalidBatchSize,
+		)
+		.await;
+
+		let mut transaction = Transaction::new_with_payer(&[batch(&users)], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let wl_data = banks_client
+			.get_account_data_with_borsh::<Whitelist>(whitelist)
+			.await
+			.unwrap();
+		assert_eq!(wl_data.registered, 3);
+		for (user, user_ticket) in &users {
+			let ticket = banks_client
+				.get_account_data_with_borsh::<Ticket>(*user_ticket)
+				.await
+				.unwrap();
+			assert_eq!(ticket.owner, *user);
+			assert_eq!(ticket.whitelist, whitelist);
+			assert_eq!(ticket.allowance, wl_data.buy_limit);
+			assert_eq!(ticket.payer, payer.pubkey());
+		}
+
+		// A batch naming a user who already has a ticket fails as a whole
+		let new_user = Pubkey::new_unique();
+		let mixed = [
+			(new_user, get_user_ticket_address(&new_user, &whitelist).0),
+			users[0],
+		];
+		let mut transaction = Transaction::new_with_payer(&[batch(&mixed)], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::WhitelistAlreadyInitialized,
+		)
+		.await;
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_claim_ticket_rent(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _, mint, _) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let user = Keypair::new();
+		let (user_ticket, _) = get_user_ticket_address(&user.pubkey(), &whitelist);
+		let add_ix = crate::instructions::add_user(
+			&whitelist,
+			&payer.pubkey(),
+			&mint.pubkey(),
+			&user.pubkey(),
+			&user_ticket,
+		)
+		.unwrap();
+		let fund_ix = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 1_000_000_000);
+		let mut transaction =
+			Transaction::new_with_payer(&[add_ix, fund_ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let authority_lamports = banks_client.get_balance(payer.pubkey()).await.unwrap();
+		let claim = || {
+			let ix = crate::instructions::claim_ticket_rent(
+				&whitelist,
+				&user.pubkey(),
+				&user_ticket,
+				&payer.pubkey(),
+			)
+			.unwrap();
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&user.pubkey()));
+			transaction.sign(&[&user], recent_blockhash);
+			transaction
+		};
+		banks_client.process_transaction(claim()).await.unwrap();
+
+		// The user now funds the ticket and the authority has its rent back
+		let ticket_account = banks_client
+			.get_account(user_ticket)
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(
+			Ticket::unpack(&ticket_account.data).unwrap().payer,
+			user.pubkey()
+		);
+		let rent = banks_client.get_rent().await.unwrap();
+		assert_eq!(
+			banks_client.get_balance(payer.pubkey()).await.unwrap(),
+			authority_lamports + rent.minimum_balance(Ticket::space())
+		);
+
+		// Nothing is left to reimburse
+		let ix = crate::instructions::claim_ticket_rent(
+			&whitelist,
+			&user.pubkey(),
+			&user_ticket,
+			&user.pubkey(),
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&user.pubkey()));
+		transaction.sign(&[&user], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::IncorrectPayer,
+		)
+		.await;
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_set_ticket_note(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _, mint, _) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let user_keypair = Keypair::new();
+		let (user_ticket, _) = get_user_ticket_address(&user_keypair.pubkey(), &whitelist);
+		let add_ix = crate::instructions::add_user(
+			&whitelist,
+			&payer.pubkey(),
+			&mint.pubkey(),
+			&user_keypair.pubkey(),
+			&user_ticket,
+		)
+		.unwrap();
+		let note_ix = crate::instructions::set_ticket_note(
+			&whitelist,
+			&payer.pubkey(),
+			&user_ticket,
+			"partner allocation",
+		)
+		.unwrap();
+		let mut transaction =
+			Transaction::new_with_payer(&[add_ix, note_ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let ticket_account = banks_client
+			.get_account(user_ticket)
+			.await
+			.unwrap()
+			.unwrap();
+		let ticket_data = Ticket::unpack(&ticket_account.data).unwrap();
+		assert_eq!(ticket_data.note_string(), "partner allocation");
+
+		assert_eq!(
+			crate::instructions::set_ticket_note(
+				&whitelist,
+				&payer.pubkey(),
+				&user_ticket,
+				&"x".repeat(Ticket::NOTE_LEN + 1),
+			)
+			.unwrap_err(),
+			WhitelistError::NoteTooLong.into()
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_add_user_whitelist_full(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _, mint, _) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let mut instructions =
+			vec![crate::instructions::amend_whitelist_size(&whitelist, &payer.pubkey(), 2).unwrap()];
+		for _ in 0..2 {
+			let user = Pubkey::new_unique();
+			let (user_ticket, _) = get_user_ticket_address(&user, &whitelist);
+			instructions.push(
+				crate::instructions::add_user(
+					&whitelist,
+					&payer.pubkey(),
+					&mint.pubkey(),
+					&user,
+					&user_ticket,
+				)
+				.unwrap(),
+			);
+		}
+		let mut transaction = Transaction::new_with_payer(&instructions, Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let whitelist_account = banks_client.get_account(whitelist).await.unwrap().unwrap();
+		let wl_data = Whitelist::unpack(&whitelist_account.data).unwrap();
+		assert_eq!(wl_data.registered, 2);
+
+		let user = Pubkey::new_unique();
+		let (user_ticket, _) = get_user_ticket_address(&user, &whitelist);
+		let ix = crate::instructions::add_user(
+			&whitelist,
+			&payer.pubkey(),
+			&mint.pubkey(),
+			&user,
+			&user_ticket,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		let err = banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap_err()
+			.unwrap();
+		assert_eq!(
+			err,
+			solana_sdk::transaction::TransactionError::InstructionError(
+				0,
+				solana_sdk::instruction::InstructionError::Custom(
+					WhitelistError::WhitelistFull as u32
+				)
+			)
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_register_max_tickets(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let mint_keypair = Keypair::new();
+		let treasury = Keypair::new();
+		let (whitelist, _) = get_whitelist_address(&mint_keypair.pubkey());
+		create_mint(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint_keypair,
+			&token_program_id,
+			9,
+		)
+		.await;
+		let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
+			&whitelist,
+			&mint_keypair.pubkey(),
+			&token_program_id,
+		);
+
+		// An unlimited whitelist still stops at `max_tickets`
+		let ix = crate::instructions::init_whitelist(
+			&whitelist,
+			&payer.pubkey(),
+			&vault,
+			&mint_keypair.pubkey(),
+			&treasury.pubkey(),
+			1,
+			10,
+			0,
+			true,
+			0,
+			0,
+			0,
+			0,
+			2,
+			0,
+			false,
+			false,
+			false,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let users = [Keypair::new(), Keypair::new(), Keypair::new()];
+		let instructions: Vec<_> = users
+			.iter()
+			.map(|user| {
+				system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 1_000_000_000)
+			})
+			.collect();
+		let mut transaction = Transaction::new_with_payer(&instructions, Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		for user in &users[..2] {
+			register_user(&mut banks_client, user, &recent_blockhash, &whitelist).await;
+		}
+
+		let whitelist_account = banks_client.get_account(whitelist).await.unwrap().unwrap();
+		let wl_data = Whitelist::unpack(&whitelist_account.data).unwrap();
+		assert_eq!(wl_data.whitelist_size, 0);
+		assert_eq!(wl_data.max_tickets, 2);
+		assert_eq!(wl_data.registered, 2);
+
+		let (user_ticket, _) = get_user_ticket_address(&users[2].pubkey(), &whitelist);
+		let ix = crate::instructions::register(
+			&whitelist,
+			&users[2].pubkey(),
+			&user_ticket,
+			&get_allocation_address(&users[2].pubkey(), &whitelist).0,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&users[2].pubkey()));
+		transaction.sign(&[&users[2]], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::TicketLimitReached,
+		)
+		.await;
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_add_user_and_seed(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			50,
+			&token_program_id,
+		)
+		.await;
+
+		let user = Pubkey::new_unique();
+		let (user_ticket, _) = get_user_ticket_address(&user, &whitelist);
+		let ticket_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&user_ticket,
+				&mint.pubkey(),
+				&token_program_id,
+			);
+		let ix = crate::instructions::add_user_and_seed(
+			&whitelist,
+			&payer.pubkey(),
+			&mint.pubkey(),
+			&user,
+			&user_ticket,
+			&vault,
+			&ticket_token_account,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let ticket_account = banks_client
+			.get_account(user_ticket)
+			.await
+			.unwrap()
+			.unwrap();
+		let ticket_data = Ticket::unpack(&ticket_account.data).unwrap();
+		assert_eq!(ticket_data.owner, user);
+
+		// The default whitelist has a buy limit of 10 tokens
+		let token_account = banks_client
+			.get_account(ticket_token_account)
+			.await
+			.unwrap()
+			.unwrap();
+		let token_data = StateWithExtensions::<Account>::unpack(&token_account.data).unwrap();
+		assert_eq!(token_data.base.amount, 10 * 10u64.pow(9));
+		let vault_account = banks_client.get_account(vault).await.unwrap().unwrap();
+		let vault_data = StateWithExtensions::<Account>::unpack(&vault_account.data).unwrap();
+		assert_eq!(vault_data.base.amount, 40 * 10u64.pow(9));
+
+		let whitelist_account = banks_client.get_account(whitelist).await.unwrap().unwrap();
+		let wl_data = Whitelist::unpack(&whitelist_account.data).unwrap();
+		assert_eq!(wl_data.registered, 1);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_remove_user(token_program_id: Pubkey) {
+		let user_keypair = Keypair::new();
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let (user_ticket, _) = get_user_ticket_address(&user_keypair.pubkey(), &whitelist);
+		let ticket_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&user_ticket,
+				&mint.pubkey(),
+				&token_program_id,
+			);
+
+		let add_ix = crate::instructions::add_user(
+			&whitelist,
+			&payer.pubkey(),
+			&mint.pubkey(),
+			&user_keypair.pubkey(),
+			&user_ticket,
+		)
+		.unwrap();
+
+		let mut transaction = Transaction::new_with_payer(&[add_ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let remove_ix = crate::instructions::remove_user(
+			&whitelist,
+			&payer.pubkey(),
+			&vault,
+			&mint.pubkey(),
+			&user_keypair.pubkey(),
+			&user_ticket,
+			&ticket_token_account,
+			&token_program_id,
+		)
+		.unwrap();
+
+		let ticket_lamports = banks_client.get_balance(user_ticket).await.unwrap();
+		let authority_lamports = banks_client.get_balance(payer.pubkey()).await.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[remove_ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		let fee = banks_client
+			.get_fee_for_message(transaction.message.clone())
+			.await
+			.unwrap()
+			.unwrap();
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		assert_account_closed(&mut banks_client, user_ticket).await;
+		assert_eq!(
+			banks_client.get_balance(payer.pubkey()).await.unwrap(),
+			authority_lamports + ticket_lamports - fee
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_remove_seeded_user(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			50,
+			&token_program_id,
+		)
+		.await;
+		let user = Pubkey::new_unique();
+		let (ticket, ticket_token_account) = seed_user(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			&user,
+			&token_program_id,
+		)
+		.await;
+		let vault_balance = token_balance(&mut banks_client, vault).await;
+
+		let ix = crate::instructions::remove_user(
+			&whitelist,
+			&payer.pubkey(),
+			&vault,
+			&mint.pubkey(),
+			&user,
+			&ticket,
+			&ticket_token_account,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		// The seeded tokens are back in the vault rather than stranded in a closed ticket
+		assert_account_closed(&mut banks_client, ticket).await;
+		assert_account_closed(&mut banks_client, ticket_token_account).await;
+		assert_eq!(
+			token_balance(&mut banks_client, vault).await,
+			vault_balance + 10 * 10u64.pow(9)
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_amend_whitelist_size(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let ix =
+			crate::instructions::amend_whitelist_size(&whitelist, &payer.pubkey(), 42).unwrap();
+
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[payer], recent_blockhash);
+		let result = banks_client
+			.process_transaction_with_metadata(transaction)
+			.await
+			.unwrap();
+		assert!(result.result.is_ok());
+
+		// The change is recorded with the size it replaced
+		let events: Vec<WhitelistEvent> = result
+			.metadata
+			.unwrap()
+			.log_messages
+			.iter()
+			.filter_map(|log| WhitelistEvent::from_log(log))
+			.collect();
+		assert_eq!(
+			events,
+			[WhitelistEvent::WhitelistSizeAmended {
+				whitelist,
+				old_size: 5,
+				new_size: 42,
+			}]
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_change_authority(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+		let new_authority = Keypair::new();
+
+		let change = |authority: &Keypair, new_authority: &Pubkey| {
+			let ix = crate::instructions::change_authority(
+				&whitelist,
+				&authority.pubkey(),
+				new_authority,
+			)
+			.unwrap();
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer, authority], recent_blockhash);
+			transaction
+		};
+		let amend_size = |authority: &Keypair, size| {
+			let ix =
+				crate::instructions::amend_whitelist_size(&whitelist, &authority.pubkey(), size)
+					.unwrap();
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer, authority], recent_blockhash);
+			transaction
+		};
+
+		assert_whitelist_error(
+			&mut banks_client,
+			change(&new_authority, &new_authority.pubkey()),
+			WhitelistError::Unauthorised,
+		)
+		.await;
+		assert_whitelist_error(
+			&mut banks_client,
+			change(&payer, &Pubkey::default()),
+			WhitelistError::InvalidAuthority,
+		)
+		.await;
+
+		let result = banks_client
+			.process_transaction_with_metadata(change(&payer, &new_authority.pubkey()))
+			.await
+			.unwrap();
+		assert!(result.result.is_ok());
+		let events: Vec<WhitelistEvent> = result
+			.metadata
+			.unwrap()
+			.log_messages
+			.iter()
+			.filter_map(|log| WhitelistEvent::from_log(log))
+			.collect();
+		assert_eq!(
+			events,
+			[WhitelistEvent::AuthorityChanged {
+				whitelist,
+				old_authority: payer.pubkey(),
+				new_authority: new_authority.pubkey(),
+			}]
+		);
+
+		// The old authority is locked out, the new one takes over
+		assert_whitelist_error(
+			&mut banks_client,
+			amend_size(&payer, 42),
+			WhitelistError::Unauthorised,
+		)
+		.await;
+		banks_client
+			.process_transaction(amend_size(&new_authority, 42))
+			.await
+			.unwrap();
+		let wl_data = banks_client
+			.get_account_data_with_borsh::<Whitelist>(whitelist)
+			.await
+			.unwrap();
+		assert_eq!(wl_data.authority, new_authority.pubkey());
+		assert_eq!(wl_data.whitelist_size, 42);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_update_price(token_program_id: Pubkey) {
+		let mut context = program_test().start_with_context().await;
+		let now = context
+			.banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
+		let times = SaleTimes {
+			sale_start: now + 100,
+			sale_duration: 100,
+			..SaleTimes::default()
+		};
+		let recent_blockhash = context.last_blockhash;
+		let (whitelist, _vault, _mint, _treasury) = create_timed_whitelist(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&token_program_id,
+			times,
+		)
+		.await;
+		let authority = context.payer.pubkey();
+
+		let stranger = Keypair::new();
+		let ix = crate::instructions::update_price(&whitelist, &stranger.pubkey(), 5).unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&authority));
+		transaction.sign(&[&context.payer, &stranger], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::Unauthorised,
+		)
+		.await;
+
+		let ix = crate::instructions::update_price(&whitelist, &authority, 0).unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&authority));
+		transaction.sign(&[&context.payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::ZeroPrice,
+		)
+		.await;
+
+		// Repricing before the sale opens goes through
+		let ix = crate::instructions::update_price(&whitelist, &authority, 5).unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&authority));
+		transaction.sign(&[&context.payer], recent_blockhash);
+		let result = context
+			.banks_client
+			.process_transaction_with_metadata(transaction)
+			.await
+			.unwrap();
+		assert!(result.result.is_ok());
+		let events: Vec<WhitelistEvent> = result
+			.metadata
+			.unwrap()
+			.log_messages
+			.iter()
+			.filter_map(|log| WhitelistEvent::from_log(log))
+			.collect();
+		assert_eq!(
+			events,
+			[WhitelistEvent::PriceUpdated {
+				whitelist,
+				old_price: 1,
+				new_price: 5,
+			}]
+		);
+
+		// Once the sale has opened the price is fixed
+		let recent_blockhash = warp_to_timestamp(&mut context, times.sale_start).await;
+		let ix = crate::instructions::update_price(&whitelist, &authority, 7).unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&authority));
+		transaction.sign(&[&context.payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::SaleStarted,
+		)
+		.await;
+
+		let wl_data = context
+			.banks_client
+			.get_account_data_with_borsh::<Whitelist>(whitelist)
+			.await
+			.unwrap();
+		assert_eq!(wl_data.token_price, 5);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_close_vault(token_program_id: Pubkey) {
+		let mut context = program_test().start_with_context().await;
+		let now = context
+			.banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
+		let times = SaleTimes {
+			sale_start: now + 100,
+			sale_duration: 100,
+			..SaleTimes::default()
+		};
+		let recent_blockhash = context.last_blockhash;
+		let (whitelist, vault, mint, _treasury) = create_timed_whitelist(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&token_program_id,
+			times,
+		)
+		.await;
+		fund_vault(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			10,
+			&token_program_id,
+		)
+		.await;
+		let authority = context.payer.pubkey();
+		let recipient_token_account = mint_tokens(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&mint.pubkey(),
+			&Pubkey::new_unique(),
+			0,
+			&token_program_id,
+		)
+		.await;
+		let close_vault_ix = || {
+			crate::instructions::close_vault(
+				&whitelist,
+				&authority,
+				&vault,
+				&mint.pubkey(),
+				&recipient_token_account,
+				&token_program_id,
+			)
+			.unwrap()
+		};
+
+		let mut transaction = Transaction::new_with_payer(&[close_vault_ix()], Some(&authority));
+		transaction.sign(&[&context.payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::SaleOngoing,
+		)
+		.await;
+
+		let recent_blockhash =
+			warp_to_timestamp(&mut context, times.sale_start + times.sale_duration).await;
+		let vault_rent = context
+			.banks_client
+			.get_account(vault)
+			.await
+			.unwrap()
+			.unwrap()
+			.lamports;
+		let mut transaction = Transaction::new_with_payer(&[close_vault_ix()], Some(&authority));
+		transaction.sign(&[&context.payer], recent_blockhash);
+		let result = context
+			.banks_client
+			.process_transaction_with_metadata(transaction)
+			.await
+			.unwrap();
+		assert!(result.result.is_ok());
+		let expected_log = format!(
+			"Vault closed, {} tokens sent to {}, reclaimed {} lamports to {}",
+			10 * 10u64.pow(9),
+			recipient_token_account,
+			vault_rent,
+			authority
+		);
+		assert!(result
+			.metadata
+			.unwrap()
+			.log_messages
+			.iter()
+			.any(|log| log.contains(&expected_log)));
+
+		// The whitelist outlives its vault
+		assert_account_closed(&mut context.banks_client, vault).await;
+		assert_eq!(
+			token_balance(&mut context.banks_client, recipient_token_account).await,
+			10 * 10u64.pow(9)
+		);
+		let wl_data = context
+			.banks_client
+			.get_account_data_with_borsh::<Whitelist>(whitelist)
+			.await
+			.unwrap();
+		assert_eq!(wl_data.vault, Pubkey::default());
+		assert_eq!(wl_data.deposited, 0);
+
+		let recent_blockhash = context
+			.banks_client
+			.get_new_latest_blockhash(&recent_blockhash)
+			.await
+			.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[close_vault_ix()], Some(&authority));
+		transaction.sign(&[&context.payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::IncorrectVaultAddress,
+		)
+		.await;
+
+		// Re-running init reopens the vault and keeps the rest of the state
+		let ix = crate::instructions::init_whitelist(
+			&whitelist,
+			&authority,
+			&vault,
+			&mint.pubkey(),
+			&Pubkey::new_unique(),
+			2,
+			20,
+			10,
+			false,
+			0,
+			0,
+			0,
+			0,
+			0,
+			0,
+			false,
+			false,
+			false,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&authority));
+		transaction.sign(&[&context.payer], recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+		let vault_account = context
+			.banks_client
+			.get_account(vault)
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(vault_account.owner, token_program_id);
+		let reopened = context
+			.banks_client
+			.get_account_data_with_borsh::<Whitelist>(whitelist)
+			.await
+			.unwrap();
+		assert_eq!(reopened, Whitelist { vault, ..wl_data });
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_update_buy_limit(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			50,
+			&token_program_id,
+		)
+		.await;
+		// Registered under the default buy limit of 10 tokens
+		let ticket = register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+		let buy = |amount| {
+			let ix = buy_instruction(
+				&whitelist,
+				&vault,
+				&mint.pubkey(),
+				&payer.pubkey(),
+				amount,
+				None,
+				&token_program_id,
+			);
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer], recent_blockhash);
+			transaction
+		};
+		let sync = |buy_limit| {
+			let ixs = [
+				crate::instructions::update_buy_limit(
+					&whitelist,
+					&payer.pubkey(),
+					&mint.pubkey(),
+					buy_limit,
+				)
+				.unwrap(),
+				crate::instructions::sync_ticket_allowance(&whitelist, &payer.pubkey(), &ticket)
+					.unwrap(),
+			];
+			let mut transaction = Transaction::new_with_payer(&ixs, Some(&payer.pubkey()));
+			transaction.sign(&[&payer], recent_blockhash);
+			transaction
+		};
+
+		let ix =
+			crate::instructions::update_buy_limit(&whitelist, &payer.pubkey(), &mint.pubkey(), 25)
+				.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		let result = banks_client
+			.process_transaction_with_metadata(transaction)
+			.await
+			.unwrap();
+		assert!(result.result.is_ok());
+		let events: Vec<WhitelistEvent> = result
+			.metadata
+			.unwrap()
+			.log_messages
+			.iter()
+			.filter_map(|log| WhitelistEvent::from_log(log))
+			.collect();
+		assert_eq!(
+			events,
+			[WhitelistEvent::BuyLimitUpdated {
+				whitelist,
+				old_buy_limit: 10 * 10u64.pow(9),
+				new_buy_limit: 25 * 10u64.pow(9),
+			}]
+		);
+
+		// The existing ticket keeps its allowance until it is synced
+		assert_whitelist_error(&mut banks_client, buy(11), WhitelistError::BuyLimitExceeded).await;
+
+		let stranger = Keypair::new();
+		let ix =
+			crate::instructions::sync_ticket_allowance(&whitelist, &stranger.pubkey(), &ticket)
+				.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer, &stranger], recent_blockhash);
+		assert_whitelist_error(&mut banks_client, transaction, WhitelistError::Unauthorised).await;
+
+		banks_client.process_transaction(sync(25)).await.unwrap();
+		let ticket_data = banks_client
+			.get_account_data_with_borsh::<Ticket>(ticket)
+			.await
+			.unwrap();
+		assert_eq!(ticket_data.allowance, 25 * 10u64.pow(9));
+		assert_eq!(ticket_data.amount_bought, 0);
+
+		banks_client.process_transaction(buy(25)).await.unwrap();
+
+		// Lowering the limit never takes allowance back from a ticket
+		banks_client.process_transaction(sync(5)).await.unwrap();
+		let ticket_data = banks_client
+			.get_account_data_with_borsh::<Ticket>(ticket)
+			.await
+			.unwrap();
+		assert_eq!(ticket_data.allowance, 25 * 10u64.pow(9));
+		assert_eq!(ticket_data.amount_bought, 25 * 10u64.pow(9));
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_amend_times(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let ix = crate::instructions::amend_times(
+			&whitelist,
+			&payer.pubkey(),
+			None,
+			Some(259200000),
+			None,
+			Some(604800000),
+			false,
+		)
+		.unwrap();
+
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_amend_sale_before_registration(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let now = banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
+		let (whitelist, _vault, _mint, _treasury) = create_timed_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+			SaleTimes {
+				registration_start: now + 200,
+				registration_duration: 100,
+				sale_start: now + 300,
+				sale_duration: 100,
+				..SaleTimes::default()
+			},
+		)
+		.await;
+
+		// Only the sale start is amended, it must still be checked against the stored
+		// registration start
+		let ix = crate::instructions::amend_times(
+			&whitelist,
+			&payer.pubkey(),
+			None,
+			None,
+			Some(now + 100),
+			None,
+			false,
+		)
+		.unwrap();
+
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::SaleBeforeRegistration,
+		)
+		.await;
+	}
+
+	#[cfg(feature = "test-mode")]
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_timed_sale_in_test_mode(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let now = banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
+
+		// Registration opened an hour ago and the sale half an hour ago, both still running
+		let times = SaleTimes {
+			registration_start: now - 3600,
+			registration_duration: 7200,
+			sale_start: now - 1800,
+			sale_duration: 3600,
+			..SaleTimes::default()
+		};
+		let (whitelist, vault, mint, _treasury) = create_timed_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+			times,
+		)
+		.await;
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			10,
+			&token_program_id,
+		)
+		.await;
+		register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+		let ix = buy_instruction(
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			2,
+			None,
+			&token_program_id,
+		);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+		assert_eq!(
+			token_balance(&mut banks_client, vault).await,
+			8 * 10u64.pow(9)
+		);
+
+		let recipient_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&payer.pubkey(),
+				&mint.pubkey(),
+				&token_program_id,
+			);
+		let ix = crate::instructions::withdraw_tokens(
+			&whitelist,
+			&payer.pubkey(),
+			&vault,
+			&mint.pubkey(),
+			&recipient_token_account,
+			1,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(&mut banks_client, transaction, WhitelistError::SaleOngoing).await;
+
+		// A sale that finished half an hour ago is closed to registration and deposits, and can
+		// be terminated
+		let times = SaleTimes {
+			registration_start: now - 7200,
+			registration_duration: 3600,
+			sale_start: now - 3600,
+			sale_duration: 1800,
+			..SaleTimes::default()
+		};
+		let (whitelist, vault, mint, _treasury) = create_timed_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+			times,
+		)
+		.await;
+		let (ticket, _) = get_user_ticket_address(&payer.pubkey(), &whitelist);
+		let ix = crate::instructions::register(
+			&whitelist,
+			&payer.pubkey(),
+			&ticket,
+			&get_allocation_address(&payer.pubkey(), &whitelist).0,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::RegistrationFinished,
+		)
+		.await;
+
+		let payer_token_account = mint_tokens(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			10u64.pow(9),
+			&token_program_id,
+		)
+		.await;
+		let ix = crate::instructions::deposit_tokens(
+			&whitelist,
+			&vault,
+			&payer.pubkey(),
+			&payer_token_account,
+			&mint.pubkey(),
+			1,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(&mut banks_client, transaction, WhitelistError::SaleEnded).await;
+
+		let ix = crate::instructions::terminate_whitelist(
+			&whitelist,
+			&payer.pubkey(),
+			&vault,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			&payer_token_account,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+		assert!(banks_client.get_account(whitelist).await.unwrap().is_none());
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_set_schedule(token_program_id: Pubkey) {
+		let mut context = program_test().start_with_context().await;
+		let now = context
+			.banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
+		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+			&mut context.banks_client,
+			&context.payer,
+			&context.last_blockhash,
+			&token_program_id,
+		)
+		.await;
+		let authority = context.payer.pubkey();
+
+		let mut invalid = vec![
+			// Registration opening in the past
+			(
+				now - 100,
+				now + 200,
+				now + 300,
+				WhitelistError::InvalidRegistrationStartTime,
+			),
+			// Sale opening before, or with, registration
+			(
+				now + 200,
+				now + 100,
+				now + 300,
+				WhitelistError::SaleBeforeRegistration,
+			),
+			(
+				now + 200,
+				now + 200,
+				now + 300,
+				WhitelistError::SaleBeforeRegistration,
+			),
+			// Sale ending before it opens
+			(
+				now + 100,
+				now + 200,
+				now + 150,
+				WhitelistError::InvalidTimestamp,
+			),
+			(
+				now + 100,
+				now + 200,
+				now + 200,
+				WhitelistError::InvalidTimestamp,
+			),
+		];
+		// `test-mode` builds accept a registration opening in the past
+		if crate::TEST_MODE {
+			invalid.remove(0);
+		}
+		for (registration_start, sale_start, sale_end, err) in invalid {
+			let ix = crate::instructions::set_schedule(
+				&whitelist,
+				&authority,
+				registration_start,
+				sale_start,
+				sale_end,
+			)
+			.unwrap();
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&authority));
+			transaction.sign(&[&context.payer], context.last_blockhash);
+			assert_whitelist_error(&mut context.banks_client, transaction, err).await;
+		}
+
+		let ix = crate::instructions::set_schedule(
+			&whitelist,
+			&authority,
+			now + 100,
+			now + 250,
+			now + 400,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&authority));
+		transaction.sign(&[&context.payer], context.last_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+
+		let wl_data = context
+			.banks_client
+			.get_account_data_with_borsh::<Whitelist>(whitelist)
+			.await
+			.unwrap();
+		assert_eq!(wl_data.registration_timestamp, now + 100);
+		assert_eq!(wl_data.registration_duration, 150);
+		assert_eq!(wl_data.sale_timestamp, now + 250);
+		assert_eq!(wl_data.sale_duration, 150);
+		assert!(!wl_data.registration_overlaps_sale());
+
+		// Once registration has opened the schedule is fixed
+		let recent_blockhash = warp_to_timestamp(&mut context, now + 100).await;
+		let ix = crate::instructions::set_schedule(
+			&whitelist,
+			&authority,
+			now + 500,
+			now + 600,
+			now + 700,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&authority));
+		transaction.sign(&[&context.payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::RegistrationStarted,
+		)
+		.await;
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_amend_times_overlap(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let now = banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
+		let (whitelist, _vault, _mint, _treasury) = create_timed_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+			SaleTimes {
+				registration_start: now + 200,
+				registration_duration: 100,
+				sale_start: now + 300,
+				sale_duration: 100,
+				..SaleTimes::default()
+			},
+		)
+		.await;
+
+		// Only the duration changes, but registration would now close after the sale opens
+		let ix = crate::instructions::amend_times(
+			&whitelist,
+			&payer.pubkey(),
+			None,
+			Some(200),
+			None,
+			None,
+			false,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::WindowsOverlap,
+		)
+		.await;
+
+		// Ending registration exactly as the sale starts does not overlap
+		let ix = crate::instructions::amend_times(
+			&whitelist,
+			&payer.pubkey(),
+			None,
+			Some(100),
+			Some(now + 300),
+			Some(200),
+			false,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		// The overlap is accepted once it is asked for
+		let ix = crate::instructions::amend_times(
+			&whitelist,
+			&payer.pubkey(),
+			None,
+			Some(200),
+			None,
+			None,
+			true,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let whitelist_account = banks_client.get_account(whitelist).await.unwrap().unwrap();
+		let wl_data = Whitelist::unpack(&whitelist_account.data).unwrap();
+		assert_eq!(wl_data.registration_duration, 200);
+		assert_eq!(wl_data.sale_duration, 200);
+		assert!(wl_data.registration_overlaps_sale());
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_allow_registration_false(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let ix_false =
+			crate::instructions::allow_registration(&whitelist, &payer.pubkey(), false).unwrap();
+
+		let mut transaction = Transaction::new_with_payer(&[ix_false], Some(&payer.pubkey()));
+		transaction.sign(&[payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let whitelist_account = banks_client
+			.get_account(whitelist)
+			.await
+			.expect("get_account")
+			.expect("whitelist account not none");
+		let wl_data = Whitelist::try_from_slice(&whitelist_account.data[..]).unwrap();
+		assert_eq!(wl_data.allow_registration, false);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_allow_registration_true(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let ix_true =
+			crate::instructions::allow_registration(&whitelist, &payer.pubkey(), true).unwrap();
+
+		let mut transaction = Transaction::new_with_payer(&[ix_true], Some(&payer.pubkey()));
+		transaction.sign(&[payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let whitelist_account = banks_client
+			.get_account(whitelist)
+			.await
+			.expect("get_account")
+			.expect("whitelist account not none");
+		let wl_data = Whitelist::try_from_slice(&whitelist_account.data[..]).unwrap();
+		assert_eq!(wl_data.allow_registration, true);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_register(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let (ticket, _) = get_user_ticket_address(&payer.pubkey(), &whitelist);
+
+		let ix = crate::instructions::register(
+			&whitelist,
+			&payer.pubkey(),
+			&ticket,
+			&get_allocation_address(&payer.pubkey(), &whitelist).0,
+		)
+		.unwrap();
+
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let ticket_account = banks_client
+			.get_account(ticket)
+			.await
+			.expect("get_account")
+			.expect("associated_account not none");
+
+		let rent = banks_client.get_rent().await.unwrap();
+		assert_eq!(ticket_account.data.len(), Ticket::LEN);
+		assert_eq!(ticket_account.owner, crate::id());
+		assert_eq!(ticket_account.lamports, rent.minimum_balance(Ticket::LEN));
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_register_existing_ticket(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let user = Keypair::new();
+		let ix = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 1_000_000_000);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let ticket = register_user(&mut banks_client, &user, &recent_blockhash, &whitelist).await;
+		let ix =
+			crate::instructions::set_ticket_note(&whitelist, &payer.pubkey(), &ticket, "partner")
+				.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+		let before = banks_client
+			.get_account(ticket)
+			.await
+			.unwrap()
+			.unwrap()
+			.data;
+
+		// Anyone registering on the user's behalf is turned away before the ticket is touched
+		let mut ix = crate::instructions::register(
+			&whitelist,
+			&user.pubkey(),
+			&ticket,
+			&get_allocation_address(&user.pubkey(), &whitelist).0,
+		)
+		.unwrap();
+		ix.accounts[register::USER].is_signer = false;
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(&mut banks_client, transaction, WhitelistError::SignerError).await;
+
+		let recent_blockhash = banks_client
+			.get_new_latest_blockhash(&recent_blockhash)
+			.await
+			.unwrap();
+		let ix = crate::instructions::register(
+			&whitelist,
+			&user.pubkey(),
+			&ticket,
+			&get_allocation_address(&user.pubkey(), &whitelist).0,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&user.pubkey()));
+		transaction.sign(&[&user], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::WhitelistAlreadyInitialized,
+		)
+		.await;
+
+		let after = banks_client
+			.get_account(ticket)
+			.await
+			.unwrap()
+			.unwrap()
+			.data;
+		assert_eq!(after, before);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_register_rent_payer_policy(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let rent = banks_client.get_rent().await.unwrap();
+
+		// By default the registering user pays for their ticket
+		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+		let user = Keypair::new();
+		let fund_ix = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 1_000_000_000);
+		let mut transaction = Transaction::new_with_payer(&[fund_ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let ticket = register_user(&mut banks_client, &user, &recent_blockhash, &whitelist).await;
+		let ticket_data = Ticket::unpack(
+			&banks_client
+				.get_account(ticket)
+				.await
+				.unwrap()
+				.unwrap()
+				.data,
+		)
+		.unwrap();
+		assert_eq!(ticket_data.payer, user.pubkey());
+
+		// A sponsored whitelist charges the authority, who must co-sign
+		let (whitelist, _vault, _mint, _treasury) = create_timed_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+			SaleTimes {
+				authority_pays_rent: true,
+				..SaleTimes::default()
+			},
+		)
+		.await;
+		let wl_data = Whitelist::unpack(
+			&banks_client
+				.get_account(whitelist)
+				.await
+				.unwrap()
+				.unwrap()
+				.data,
+		)
+		.unwrap();
+		assert!(wl_data.authority_pays_rent);
+
+		let user = Keypair::new();
+		let (ticket, _) = get_user_ticket_address(&user.pubkey(), &whitelist);
+
+		let impostor = Keypair::new();
+		let ix = crate::instructions::sponsored_register(
+			&whitelist,
+			&user.pubkey(),
+			&ticket,
+			&get_allocation_address(&user.pubkey(), &whitelist).0,
+			&impostor.pubkey(),
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer, &user, &impostor], recent_blockhash);
+		assert_whitelist_error(&mut banks_client, transaction, WhitelistError::Unauthorised).await;
+
+		let ix = crate::instructions::sponsored_register(
+			&whitelist,
+			&user.pubkey(),
+			&ticket,
+			&get_allocation_address(&user.pubkey(), &whitelist).0,
+			&payer.pubkey(),
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer, &user], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let ticket_account = banks_client.get_account(ticket).await.unwrap().unwrap();
+		assert_eq!(ticket_account.lamports, rent.minimum_balance(Ticket::LEN));
+		assert_eq!(
+			Ticket::unpack(&ticket_account.data).unwrap().payer,
+			payer.pubkey()
+		);
+		// The user signed without holding any SOL
+		assert!(banks_client
+			.get_account(user.pubkey())
+			.await
+			.unwrap()
+			.is_none());
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_register_honours_allocation(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _vault, mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let user = Keypair::new();
+		let other_user = Keypair::new();
+		let mut ixs = Vec::new();
+		for user in [&user, &other_user] {
+			ixs.push(system_instruction::transfer(
+				&payer.pubkey(),
+				&user.pubkey(),
+				1_000_000_000,
+			));
+		}
+		let mut transaction = Transaction::new_with_payer(&ixs, Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let (allocation, _) = crate::get_allocation_address(&user.pubkey(), &whitelist);
+
+		// Only the authority may reserve an allocation
+		let ix = crate::instructions::set_allocation(
+			&whitelist,
+			&user.pubkey(),
+			&mint.pubkey(),
+			&user.pubkey(),
+			&allocation,
+			100,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&user.pubkey()));
+		transaction.sign(&[&user], recent_blockhash);
+		assert_whitelist_error(&mut banks_client, transaction, WhitelistError::Unauthorised).await;
+
+		// A second call overwrites the first
+		for allowance in [100, 25] {
+			let ix = crate::instructions::set_allocation(
+				&whitelist,
+				&payer.pubkey(),
+				&mint.pubkey(),
+				&user.pubkey(),
+				&allocation,
+				allowance,
+			)
+			.unwrap();
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer], recent_blockhash);
+			banks_client.process_transaction(transaction).await.unwrap();
+		}
+		let allocation_data = banks_client
+			.get_account_data_with_borsh::<Allocation>(allocation)
+			.await
+			.unwrap();
+		assert_eq!(allocation_data.user, user.pubkey());
+		assert_eq!(allocation_data.allowance, 25 * 10u64.pow(9));
+
+		let (ticket, _) = get_user_ticket_address(&user.pubkey(), &whitelist);
+		let ix = crate::instructions::register(&whitelist, &user.pubkey(), &ticket, &allocation)
+			.unwrap();
+
+		// Swapping in another account or leaving it out cannot dodge the lower allocation
+		let mut swapped = ix.clone();
+		swapped.accounts[register::ALLOCATION].pubkey = Pubkey::new_unique();
+		let mut transaction = Transaction::new_with_payer(&[swapped], Some(&user.pubkey()));
+		transaction.sign(&[&user], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::AccountMismatch,
+		)
+		.await;
+
+		let mut missing = ix.clone();
+		missing.accounts.truncate(register::ALLOCATION);
+		let mut transaction = Transaction::new_with_payer(&[missing], Some(&user.pubkey()));
+		transaction.sign(&[&user], recent_blockhash);
+		assert!(banks_client.process_transaction(transaction).await.is_err());
+		assert!(banks_client.get_account(ticket).await.unwrap().is_none());
+
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&user.pubkey()));
+		transaction.sign(&[&user], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let ticket_data = banks_client
+			.get_account_data_with_borsh::<Ticket>(ticket)
+			.await
+			.unwrap();
+		assert_eq!(ticket_data.allowance, 25 * 10u64.pow(9));
+
+		// Without an allocation the ticket gets the whitelist's buy limit
+		let other_ticket = register_user(
+			&mut banks_client,
+			&other_user,
+			&recent_blockhash,
+			&whitelist,
+		)
+		.await;
+		let other_data = banks_client
+			.get_account_data_with_borsh::<Ticket>(other_ticket)
+			.await
+			.unwrap();
+		assert_eq!(other_data.allowance, 10 * 10u64.pow(9));
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_register_with_referral(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _vault, mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let referrer_ticket =
+			register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+		let ix =
+			crate::instructions::set_referral_bonus(&whitelist, &payer.pubkey(), &mint.pubkey(), 2)
+				.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let user = Keypair::new();
+		let ix = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 1_000_000_000);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		// Referring yourself is rejected
+		let (user_ticket, _) = get_user_ticket_address(&user.pubkey(), &whitelist);
+		let ix = crate::instructions::register_with_referral(
+			&whitelist,
+			&user.pubkey(),
+			&user_ticket,
+			&user.pubkey(),
+			&user_ticket,
+			&get_allocation_address(&user.pubkey(), &whitelist).0,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&user.pubkey()));
+		transaction.sign(&[&user], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::InvalidReferrer,
+		)
+		.await;
+
+		let ix = crate::instructions::register_with_referral(
+			&whitelist,
+			&user.pubkey(),
+			&user_ticket,
+			&payer.pubkey(),
+			&referrer_ticket,
+			&get_allocation_address(&user.pubkey(), &whitelist).0,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&user.pubkey()));
+		transaction.sign(&[&user], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let user_data = banks_client
+			.get_account_data_with_borsh::<Ticket>(user_ticket)
+			.await
+			.unwrap();
+		assert_eq!(user_data.referrer(), Some(payer.pubkey()));
+		assert_eq!(user_data.allowance, 10 * 10u64.pow(9));
+
+		// The referrer's own ticket is credited the bonus on top of the buy limit
+		let referrer_data = banks_client
+			.get_account_data_with_borsh::<Ticket>(referrer_ticket)
+			.await
+			.unwrap();
+		assert_eq!(referrer_data.referrer(), None);
+		assert_eq!(referrer_data.allowance, 12 * 10u64.pow(9));
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_register_with_signature(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let register_authority = Keypair::new();
+		let ix = crate::instructions::set_register_authority(
+			&whitelist,
+			&payer.pubkey(),
+			Some(&register_authority.pubkey()),
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let user = Keypair::new();
+		let ix = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 1_000_000_000);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+		let (user_ticket, _) = get_user_ticket_address(&user.pubkey(), &whitelist);
+
+		// Registering without a voucher is rejected once registration is gated
+		let ix = crate::instructions::register(
+			&whitelist,
+			&user.pubkey(),
+			&user_ticket,
+			&get_allocation_address(&user.pubkey(), &whitelist).0,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&user.pubkey()));
+		transaction.sign(&[&user], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::SignatureRequired,
+		)
+		.await;
+
+		// `signer` signs a voucher for `voucher_user`, presented as a voucher from `claimed`
+		let voucher = |signer: &Keypair, claimed: &Pubkey, voucher_user: &Pubkey, nonce: u64| {
+			let message = register_voucher_message(voucher_user, &whitelist, nonce);
+			let signature: [u8; 64] = signer.sign_message(&message).as_ref().try_into().unwrap();
+			let mut transaction = Transaction::new_with_payer(
+				&[
+					crate::instructions::register_voucher(
+						claimed,
+						&signature,
+						voucher_user,
+						&whitelist,
+						nonce,
+					),
+					crate::instructions::register_with_signature(
+						&whitelist,
+						&user.pubkey(),
+						&user_ticket,
+						&get_allocation_address(&user.pubkey(), &whitelist).0,
+						nonce,
+					)
+					.unwrap(),
+				],
+				Some(&user.pubkey()),
+			);
+			transaction.sign(&[&user], recent_blockhash);
+			transaction
+		};
+
+		// A valid signature by a key other than the register authority
+		let forger = Keypair::new();
+		assert_whitelist_error(
+			&mut banks_client,
+			voucher(&forger, &forger.pubkey(), &user.pubkey(), 1),
+			WhitelistError::InvalidSignature,
+		)
+		.await;
+		// A genuine voucher issued to another user
+		assert_whitelist_error(
+			&mut banks_client,
+			voucher(
+				&register_authority,
+				&register_authority.pubkey(),
+				&Pubkey::new_unique(),
+				2,
+			),
+			WhitelistError::InvalidSignature,
+		)
+		.await;
+		// A forged signature claiming to be from the register authority fails verification
+		// before the program runs
+		assert!(banks_client
+			.process_transaction(voucher(
+				&forger,
+				&register_authority.pubkey(),
+				&user.pubkey(),
+				3
+			))
+			.await
+			.is_err());
+		assert!(banks_client
+			.get_account(user_ticket)
+			.await
+			.unwrap()
+			.is_none());
+
+		banks_client
+			.process_transaction(voucher(
+				&register_authority,
+				&register_authority.pubkey(),
+				&user.pubkey(),
+				4,
+			))
+			.await
+			.unwrap();
+		let ticket_data = banks_client
+			.get_account_data_with_borsh::<Ticket>(user_ticket)
+			.await
+			.unwrap();
+		assert_eq!(ticket_data.owner, user.pubkey());
+
+		// Clearing the register authority opens registration again
+		let ix =
+			crate::instructions::set_register_authority(&whitelist, &payer.pubkey(), None).unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+		register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+
+		let wl_data = banks_client
+			.get_account_data_with_borsh::<Whitelist>(whitelist)
+			.await
+			.unwrap();
+		assert_eq!(wl_data.register_authority(), None);
+		assert_eq!(wl_data.registered, 2);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_unregister(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let (ticket, _) = get_user_ticket_address(&payer.pubkey(), &whitelist);
+
+		let ix_true = crate::instructions::register(
+			&whitelist,
+			&payer.pubkey(),
+			&ticket,
+			&get_allocation_address(&payer.pubkey(), &whitelist).0,
+		)
+		.unwrap();
+
+		let mut transaction = Transaction::new_with_payer(&[ix_true], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_unregister_closes_ticket(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let clock = banks_client.get_sysvar::<Clock>().await.unwrap();
+		let (whitelist, vault, mint, _treasury) = create_timed_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+			SaleTimes {
+				registration_duration: 3600,
+				sale_start: clock.unix_timestamp + 3600,
+				sale_duration: 3600,
+				..SaleTimes::default()
+			},
+		)
+		.await;
+
+		let ticket = register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+		let ticket_token_account = mint_tokens(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint.pubkey(),
+			&ticket,
+			2 * 10u64.pow(9),
+			&token_program_id,
+		)
+		.await;
+
+		let ticket_lamports = banks_client.get_balance(ticket).await.unwrap();
+		let ticket_token_lamports = banks_client
+			.get_balance(ticket_token_account)
+			.await
+			.unwrap();
+		let vault_balance = token_balance(&mut banks_client, vault).await;
+		let user_lamports = banks_client.get_balance(payer.pubkey()).await.unwrap();
+		let ix = crate::instructions::unregister(
+			&whitelist,
+			&payer.pubkey(),
+			&vault,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			&ticket,
+			&ticket_token_account,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		let fee = banks_client
+			.get_fee_for_message(transaction.message.clone())
+			.await
+			.unwrap()
+			.unwrap();
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		// The ticket's tokens are back in the vault and both accounts' rent with the payer, who
+		// is also the authority here
+		assert_account_closed(&mut banks_client, ticket).await;
+		assert_account_closed(&mut banks_client, ticket_token_account).await;
+		assert_eq!(
+			token_balance(&mut banks_client, vault).await,
+			vault_balance + 2 * 10u64.pow(9)
+		);
+		assert_eq!(
+			banks_client.get_balance(payer.pubkey()).await.unwrap(),
+			user_lamports + ticket_lamports + ticket_token_lamports - fee
+		);
+		let whitelist_account = banks_client.get_account(whitelist).await.unwrap().unwrap();
+		let wl_data = Whitelist::unpack(&whitelist_account.data).unwrap();
+		assert_eq!(wl_data.registered, 0);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_unregister_rejected(token_program_id: Pubkey) {
+		let mut context = program_test().start_with_context().await;
+		let now = context
+			.banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
+		let times = SaleTimes {
+			registration_duration: 3600,
+			sale_start: now + 3600,
+			sale_duration: 3600,
+			..SaleTimes::default()
+		};
+		let recent_blockhash = context.last_blockhash;
+		let (whitelist, vault, mint, _treasury) = create_timed_whitelist(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&token_program_id,
+			times,
+		)
+		.await;
+		let payer = context.payer.insecure_clone();
+
+		// A user added by the authority, whose ticket a third party tries to close
+		let user = Pubkey::new_unique();
+		let (ticket, _) = get_user_ticket_address(&user, &whitelist);
+		let ix = crate::instructions::add_user(
+			&whitelist,
+			&payer.pubkey(),
+			&mint.pubkey(),
+			&user,
+			&ticket,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+
+		let unregister_ix = |user: &Pubkey, ticket: &Pubkey| {
+			crate::instructions::unregister(
+				&whitelist,
+				&payer.pubkey(),
+				&vault,
+				&mint.pubkey(),
+				user,
+				ticket,
+				&spl_associated_token_account::get_associated_token_address_with_program_id(
+					ticket,
+					&mint.pubkey(),
+					&token_program_id,
+				),
+				&token_program_id,
+			)
+			.unwrap()
+		};
+
+		// Without the user's signature
+		let mut ix = unregister_ix(&user, &ticket);
+		ix.accounts[unregister::USER].is_signer = false;
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::SignerError,
+		)
+		.await;
+
+		// Lamports above the ticket's rent are not the payer's to reclaim
+		let payer_ticket = register_user(
+			&mut context.banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+		)
+		.await;
+		let ixs = [
+			system_instruction::transfer(&payer.pubkey(), &payer_ticket, 1_000_000),
+			unregister_ix(&payer.pubkey(), &payer_ticket),
+		];
+		let mut transaction = Transaction::new_with_payer(&ixs, Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::TicketHoldsProceeds,
+		)
+		.await;
+
+		// Once the sale has started, even with registration still open
+		let recent_blockhash = warp_to_timestamp(&mut context, times.sale_start + 10).await;
+		let mut transaction = Transaction::new_with_payer(
+			&[unregister_ix(&payer.pubkey(), &payer_ticket)],
+			Some(&payer.pubkey()),
+		);
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::CannotUnregister,
+		)
+		.await;
+
+		let ticket_account = context
+			.banks_client
+			.get_account(payer_ticket)
+			.await
+			.unwrap()
+			.expect("ticket kept");
+		assert_eq!(ticket_account.owner, crate::id());
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_start_registration(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let ix = crate::instructions::start_registration(&whitelist, &payer.pubkey()).unwrap();
+
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_start_token_sale(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, vault, _mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let ix = crate::instructions::start_token_sale(&whitelist, &payer.pubkey(), &vault, false)
+			.unwrap();
+
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_start_token_sale_fully_funded(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		// Default whitelist has 5 tickets of 10 tokens each
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			50,
+			&token_program_id,
+		)
+		.await;
+
+		let ix = crate::instructions::start_token_sale(&whitelist, &payer.pubkey(), &vault, true)
+			.unwrap();
+
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_start_token_sale_undersubscribed(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			20,
+			&token_program_id,
+		)
+		.await;
+
+		let ix = crate::instructions::start_token_sale(&whitelist, &payer.pubkey(), &vault, true)
+			.unwrap();
+
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		let err = banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap_err()
+			.unwrap();
+		assert_eq!(
+			err,
+			solana_sdk::transaction::TransactionError::InstructionError(
+				0,
+				solana_sdk::instruction::InstructionError::Custom(
+					WhitelistError::Undersubscribed as u32
+				)
+			)
+		);
+	}
+
+	#[tokio::test]
+	async fn test_migrate_whitelist() {
+		let authority = Keypair::new();
+		let mint = Pubkey::new_unique();
+		let (whitelist, bump) = get_whitelist_address(&mint);
+
+		let legacy = WhitelistV0 {
+			bump,
+			authority: authority.pubkey(),
+			vault: Pubkey::new_unique(),
+			treasury: Pubkey::new_unique(),
+			mint,
+			token_price: 1,
+			buy_limit: 10,
+			deposited: 0,
+			whitelist_size: 5,
+			allow_registration: true,
+			registration_timestamp: 0,
+			registration_duration: 0,
+			sale_timestamp: 0,
+			sale_duration: 0,
+		};
+		let legacy_data = borsh::to_vec(&legacy).unwrap();
+		assert_eq!(legacy_data.len(), WhitelistV0::LEN);
+
+		let mut program_test = program_test();
+		program_test.add_account(
+			whitelist,
+			solana_sdk::account::Account {
+				lamports: Rent::default().minimum_balance(WhitelistV0::LEN),
+				data: legacy_data,
+				owner: crate::id(),
+				executable: false,
+				rent_epoch: 0,
+			},
+		);
+		program_test.add_account(
+			authority.pubkey(),
+			solana_sdk::account::Account::new(1_000_000_000, 0, &system_program::id()),
+		);
+		let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+		let ix = crate::instructions::migrate_whitelist(&whitelist, &authority.pubkey()).unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer, &authority], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let whitelist_account = banks_client
+			.get_account(whitelist)
+			.await
+			.expect("get_account")
+			.expect("whitelist account not none");
+		let rent = banks_client.get_rent().await.unwrap();
+		assert_eq!(whitelist_account.data.len(), Whitelist::LEN);
+		assert_eq!(
+			whitelist_account.lamports,
+			rent.minimum_balance(Whitelist::LEN)
+		);
+
+		let wl_data = Whitelist::try_from_slice(&whitelist_account.data[..]).unwrap();
+		assert_eq!(wl_data, Whitelist::from(legacy));
+		assert_eq!(wl_data.version, Whitelist::VERSION);
+
+		// A second migration must be rejected
+		let recent_blockhash = banks_client
+			.get_new_latest_blockhash(&recent_blockhash)
+			.await
+			.unwrap();
+		let ix = crate::instructions::migrate_whitelist(&whitelist, &authority.pubkey()).unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer, &authority], recent_blockhash);
+		assert!(banks_client.process_transaction(transaction).await.is_err());
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_migrate_ticket(token_program_id: Pubkey) {
+		let mut context = program_test().start_with_context().await;
+		let recent_blockhash = context.last_blockhash;
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+		let payer = context.payer.insecure_clone();
+
+		let user = Pubkey::new_unique();
+		let (ticket, bump) = get_user_ticket_address(&user, &whitelist);
+		let legacy = TicketV0 {
+			bump,
+			whitelist,
+			owner: user,
+			payer: payer.pubkey(),
+			allowance: 10 * 10u64.pow(9),
+			amount_bought: 3 * 10u64.pow(9),
+		};
+		let legacy_data = borsh::to_vec(&legacy).unwrap();
+		assert_eq!(legacy_data.len(), TicketV0::LEN);
+		context.set_account(
+			&ticket,
+			&solana_sdk::account::Account {
+				lamports: Rent::default().minimum_balance(TicketV0::LEN),
+				data: legacy_data,
+				owner: crate::id(),
+				executable: false,
+				rent_epoch: 0,
+			}
+			.into(),
+		);
+
+		let ix = crate::instructions::migrate_ticket(&ticket, &payer.pubkey()).unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+
+		let ticket_account = context
+			.banks_client
+			.get_account(ticket)
+			.await
+			.unwrap()
+			.unwrap();
+		let rent = context.banks_client.get_rent().await.unwrap();
+		assert_eq!(ticket_account.data.len(), Ticket::LEN);
+		assert_eq!(ticket_account.lamports, rent.minimum_balance(Ticket::LEN));
+		let ticket_data = Ticket::unpack(&ticket_account.data).unwrap();
+		assert_eq!(ticket_data, Ticket::from(legacy));
+		assert_eq!(ticket_data.claimable(), 0);
+
+		// A second migration must be rejected
+		let recent_blockhash = context
+			.banks_client
+			.get_new_latest_blockhash(&recent_blockhash)
+			.await
+			.unwrap();
+		let ix = crate::instructions::migrate_ticket(&ticket, &payer.pubkey()).unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::TicketAlreadyMigrated,
+		)
+		.await;
+
+		// The migrated ticket can be closed like any other
+		let ix = crate::instructions::remove_user(
+			&whitelist,
+			&payer.pubkey(),
+			&vault,
+			&mint.pubkey(),
+			&user,
+			&ticket,
+			&spl_associated_token_account::get_associated_token_address_with_program_id(
+				&ticket,
+				&mint.pubkey(),
+				&token_program_id,
+			),
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+		assert_account_closed(&mut context.banks_client, ticket).await;
+	}
+
+	fn sample_whitelist() -> Whitelist {
+		let mint = Pubkey::new_unique();
+		let (_, bump) = get_whitelist_address(&mint);
+		Whitelist {
+			version: Whitelist::VERSION,
+			bump,
+			authority: Pubkey::new_unique(),
+			vault: Pubkey::new_unique(),
+			treasury: Pubkey::new_unique(),
+			mint,
+			token_price: 1,
+			buy_limit: 10,
+			deposited: 0,
+			whitelist_size: 5,
+			allow_registration: true,
+			registration_timestamp: 0,
+			registration_duration: 0,
+			sale_timestamp: 0,
+			sale_duration: 0,
+			registered: 0,
+			registration_paused: false,
+			sale_paused: false,
+			referral_bonus: 0,
+			max_tickets: 0,
+			cooldown_secs: 0,
+			refund_window_secs: 0,
+			authority_pays_rent: false,
+			register_authority: Pubkey::default(),
+		}
+	}
+
+	fn sample_ticket() -> Ticket {
+		Ticket {
+			version: Ticket::VERSION,
+			bump: 255,
+			whitelist: Pubkey::new_unique(),
+			owner: Pubkey::new_unique(),
+			payer: Pubkey::new_unique(),
+			allowance: 10,
+			amount_bought: 0,
+			claimed: 0,
+			note: Ticket::encode_note("team").unwrap(),
+			referrer: Pubkey::new_unique(),
+			last_purchase_ts: 0,
+		}
+	}
+
+	#[test]
+	fn test_unpack_current_version() {
+		let wl_data = sample_whitelist();
+		let data = borsh::to_vec(&wl_data).unwrap();
+		assert_eq!(Whitelist::unpack(&data).unwrap(), wl_data);
+
+		// Every field the processor reads must survive a round trip, with the timestamps and size
+		// set so a field dropped or reordered in `state.rs` cannot hide behind a zero
+		let wl_data = Whitelist {
+			whitelist_size: 42,
+			registration_timestamp: 1_700_000_000,
+			registration_duration: 3_600,
+			sale_timestamp: 1_700_003_600,
+			sale_duration: 7_200,
+			..sample_whitelist()
+		};
+		let data = borsh::to_vec(&wl_data).unwrap();
+		assert_eq!(data.len(), Whitelist::LEN);
+		let unpacked = Whitelist::unpack(&data).unwrap();
+		assert_eq!(unpacked, wl_data);
+		assert_eq!(unpacked.whitelist_size, 42);
+		assert_eq!(unpacked.registration_timestamp, 1_700_000_000);
+		assert_eq!(unpacked.sale_timestamp, 1_700_003_600);
+
+		let ticket_data = sample_ticket();
+		let data = borsh::to_vec(&ticket_data).unwrap();
+		assert_eq!(Ticket::unpack(&data).unwrap(), ticket_data);
+	}
+
+	#[test]
+	fn test_space_fits_serialized_accounts() {
+		assert_eq!(
+			borsh::to_vec(&sample_whitelist()).unwrap().len(),
+			Whitelist::space()
+		);
+		assert_eq!(
+			borsh::to_vec(&sample_ticket()).unwrap().len(),
+			Ticket::space()
+		);
+		// Accounts are allocated with `LEN`, pinned here as changing it changes the layout of
+		// every live account and needs a migration
+		assert_eq!(Whitelist::LEN, 270);
+		assert_eq!(Ticket::LEN, 194);
+
+		let depositor = Depositor {
+			version: Depositor::VERSION,
+			bump: 255,
+			whitelist: Pubkey::new_unique(),
+			owner: Pubkey::new_unique(),
+			amount: 1,
+		};
+		assert_eq!(borsh::to_vec(&depositor).unwrap().len(), Depositor::space());
+
+		let allocation = Allocation {
+			version: Allocation::VERSION,
+			bump: 255,
+			whitelist: Pubkey::new_unique(),
+			user: Pubkey::new_unique(),
+			allowance: 1,
+		};
+		assert_eq!(
+			borsh::to_vec(&allocation).unwrap().len(),
+			Allocation::space()
+		);
+	}
+
+	#[test]
+	fn test_record_claim() {
+		let mut ticket_data = sample_ticket();
+		ticket_data.amount_bought = 10;
+
+		ticket_data.record_claim(4).unwrap();
+		assert_eq!(ticket_data.claimed, 4);
+		assert_eq!(ticket_data.claimable(), 6);
+
+		ticket_data.record_claim(6).unwrap();
+		assert_eq!(ticket_data.claimed, 10);
+		assert_eq!(ticket_data.claimable(), 0);
+
+		assert_eq!(
+			ticket_data.record_claim(1).unwrap_err(),
+			WhitelistError::ClaimExceedsPurchase.into()
+		);
+		assert_eq!(ticket_data.claimed, 10);
+	}
+
+	#[test]
+	fn test_cooldown_until() {
+		let mut wl_data = sample_whitelist();
+		assert_eq!(wl_data.cooldown_until(100, 100), None);
+
+		wl_data.cooldown_secs = 60;
+		// A ticket that has never bought is not cooling down
+		assert_eq!(wl_data.cooldown_until(0, 100), None);
+		assert_eq!(wl_data.cooldown_until(100, 159), Some(160));
+		assert_eq!(wl_data.cooldown_until(100, 160), None);
+
+		wl_data.cooldown_secs = u64::MAX;
+		assert_eq!(wl_data.cooldown_until(100, i64::MAX - 1), Some(i64::MAX));
+	}
+
+	#[test]
+	fn test_refund_open() {
+		let mut wl_data = sample_whitelist();
+		assert!(!wl_data.refund_open(100, 100));
+
+		wl_data.refund_window_secs = 60;
+		// A ticket that has never bought has nothing to refund
+		assert!(!wl_data.refund_open(0, 100));
+		assert!(wl_data.refund_open(100, 159));
+		assert!(!wl_data.refund_open(100, 160));
+
+		wl_data.refund_window_secs = u64::MAX;
+		assert!(wl_data.refund_open(100, i64::MAX - 1));
+	}
+
+	#[test]
+	fn test_ticket_derive_and_verify() {
+		let user = Pubkey::new_unique();
+		let whitelist = Pubkey::new_unique();
+		let (ticket, bump) = get_user_ticket_address(&user, &whitelist);
+
+		assert_eq!(
+			Ticket::derive_and_verify(&user, &whitelist, &ticket, bump),
+			Ok(())
+		);
+		// Another user's ticket on the same whitelist
+		let (other_ticket, other_bump) = get_user_ticket_address(&Pubkey::new_unique(), &whitelist);
+		assert_eq!(
+			Ticket::derive_and_verify(&user, &whitelist, &other_ticket, other_bump),
+			Err(WhitelistError::IncorrectUserAccount.into())
+		);
+		// The right address with a bump that was not the one it was derived with
+		assert_eq!(
+			Ticket::derive_and_verify(&user, &whitelist, &ticket, bump.wrapping_sub(1)),
+			Err(WhitelistError::IncorrectUserAccount.into())
+		);
+	}
+
+	#[test]
+	fn test_sale_window_closed() {
+		let wl_data = Whitelist {
+			sale_timestamp: 1_000,
+			sale_duration: 100,
+			..sample_whitelist()
+		};
+		// Pre-sale
+		assert!(wl_data.sale_window_closed(999));
+		// Mid-sale, from the instant buying opens until the last second of the window
+		assert!(!wl_data.sale_window_closed(1_000));
+		assert!(!wl_data.sale_window_closed(1_050));
+		assert!(!wl_data.sale_window_closed(1_099));
+		// Post-sale
+		assert!(wl_data.sale_window_closed(1_100));
+		assert!(wl_data.sale_window_closed(i64::MAX));
+
+		// Without a duration a started sale never ends by time
+		let wl_data = Whitelist {
+			sale_timestamp: 1_000,
+			sale_duration: 0,
+			..sample_whitelist()
+		};
+		assert!(wl_data.sale_window_closed(999));
+		assert!(!wl_data.sale_window_closed(i64::MAX));
+
+		// Without a start the sale is open from initialisation and never ends by time
+		let wl_data = Whitelist {
+			sale_timestamp: 0,
+			sale_duration: 100,
+			..sample_whitelist()
+		};
+		assert!(!wl_data.sale_window_closed(0));
+		assert!(!wl_data.sale_window_closed(1_050));
+		assert!(!wl_data.sale_window_closed(i64::MAX));
+	}
+
+	#[test]
+	fn test_buy_cost() {
+		// `token_price` is per whole token, so a 9 decimal token at 1 SOL costs 1 SOL
+		assert_eq!(buy_cost(1_000_000_000, 1_000_000_000, 9), Ok(1_000_000_000));
+		assert_eq!(buy_cost(2_500_000, 1_000_000, 6), Ok(2_500_000));
+		assert_eq!(buy_cost(3, 7, 0), Ok(21));
+		assert_eq!(buy_cost(0, 1_000_000_000, 9), Ok(0));
+
+		// Fractions of a lamport are rounded up rather than given away
+		assert_eq!(buy_cost(1, 1, 9), Ok(1));
+		assert_eq!(buy_cost(1_500_000_000, 3, 9), Ok(5));
+
+		assert_eq!(buy_cost(u64::MAX, 2, 0), Err(WhitelistError::Overflow));
+		assert_eq!(buy_cost(u64::MAX, u64::MAX, 9), Err(WhitelistError::Overflow));
+		assert_eq!(buy_cost(u64::MAX, u64::MAX, 38), Ok(4));
+		assert_eq!(buy_cost(1, 1, 39), Err(WhitelistError::Overflow));
+	}
+
+	#[test]
+	fn test_parse_token_amount() {
+		use crate::parse_token_amount;
+
+		// Every digit of a 9 decimal amount survives, `f64` would round these
+		assert_eq!(parse_token_amount("123.456789123", 9), Ok(123_456_789_123));
+		assert_eq!(parse_token_amount("18446744073.709551615", 9), Ok(u64::MAX));
+		assert_eq!(
+			parse_token_amount("9007199.254740993", 9),
+			Ok(9_007_199_254_740_993)
+		);
+		assert_eq!(parse_token_amount("42", 9), Ok(42_000_000_000));
+		assert_eq!(parse_token_amount("0.5", 9), Ok(500_000_000));
+		assert_eq!(parse_token_amount(".5", 6), Ok(500_000));
+		assert_eq!(parse_token_amount("7.", 0), Ok(7));
+
+		// More precision than the mint supports is rejected, not rounded
+		assert_eq!(
+			parse_token_amount("1.0000000001", 9),
+			Err(WhitelistError::InvalidTokenAmount)
+		);
+		assert_eq!(
+			parse_token_amount("1.5", 0),
+			Err(WhitelistError::InvalidTokenAmount)
+		);
+		for invalid in ["", ".", "-1", "1.2.3", "1e9", " 1", "abc"] {
+			assert_eq!(
+				parse_token_amount(invalid, 9),
+				Err(WhitelistError::InvalidTokenAmount),
+				"{:?}",
+				invalid
+			);
+		}
+
+		assert_eq!(
+			parse_token_amount("18446744073.709551616", 9),
+			Err(WhitelistError::Overflow)
+		);
+		assert_eq!(parse_token_amount("1", 20), Err(WhitelistError::Overflow));
+	}
+
+	#[test]
+	fn test_unpack_rejects_future_version() {
+		let mut data = borsh::to_vec(&sample_whitelist()).unwrap();
+		data[0] = Whitelist::VERSION + 1;
+		assert_eq!(
+			Whitelist::unpack(&data).unwrap_err(),
+			WhitelistError::UnsupportedVersion.into()
+		);
+
+		let mut data = borsh::to_vec(&sample_ticket()).unwrap();
+		data[0] = Ticket::VERSION + 1;
+		assert_eq!(
+			Ticket::unpack(&data).unwrap_err(),
+			WhitelistError::UnsupportedVersion.into()
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_terminate_empty_whitelist(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let mint_keypair = Keypair::new();
+		let treasury = Keypair::new();
+		let (whitelist, _) = get_whitelist_address(&mint_keypair.pubkey());
+		create_mint(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint_keypair,
+			&token_program_id,
+			9,
+		)
+		.await;
+		let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
+			&whitelist,
+			&mint_keypair.pubkey(),
+			&token_program_id,
+		);
+		let recipient_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&payer.pubkey(),
+				&mint_keypair.pubkey(),
+				&token_program_id,
+			);
+
+		let clock = banks_client.get_sysvar::<Clock>().await.unwrap();
+		let ix = crate::instructions::init_whitelist(
+			&whitelist,
+			&payer.pubkey(),
+			&vault,
+			&mint_keypair.pubkey(),
+			&treasury.pubkey(),
+			1,
+			10,
+			5,
+			true,
+			0,
+			0,
+			clock.unix_timestamp + 3600,
+			3600,
+			0,
+			0,
+			false,
+			false,
+			false,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let whitelist_lamports = banks_client
+			.get_account(whitelist)
+			.await
+			.unwrap()
+			.unwrap()
+			.lamports;
+		let vault_lamports = banks_client
+			.get_account(vault)
+			.await
+			.unwrap()
+			.unwrap()
+			.lamports;
+		let authority_lamports = banks_client.get_balance(payer.pubkey()).await.unwrap();
+
+		let ix = crate::instructions::terminate_whitelist(
+			&whitelist,
+			&payer.pubkey(),
+			&vault,
+			&mint_keypair.pubkey(),
+			&payer.pubkey(),
+			&recipient_token_account,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		let fee = banks_client
+			.get_fee_for_message(transaction.message.clone())
+			.await
+			.unwrap()
+			.unwrap();
+		let result = banks_client
+			.process_transaction_with_metadata(transaction)
+			.await
+			.unwrap();
+		assert!(result.result.is_ok());
+
+		// Only the vault close CPI should run, no mint unpack or token transfer
+		let metadata = result.metadata.unwrap();
+		assert!(metadata
+			.log_messages
+			.iter()
+			.any(|log| log.contains("Vault empty, closing accounts")));
+		assert!(metadata.compute_units_consumed < 25_000);
+
+		assert_account_closed(&mut banks_client, whitelist).await;
+		assert!(banks_client.get_account(vault).await.unwrap().is_none());
+		assert_eq!(
+			banks_client.get_balance(payer.pubkey()).await.unwrap(),
+			authority_lamports + whitelist_lamports + vault_lamports - fee
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_terminate_rejects_non_authority(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let clock = banks_client.get_sysvar::<Clock>().await.unwrap();
+		let (whitelist, vault, mint, _treasury) = create_timed_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+			SaleTimes {
+				sale_start: clock.unix_timestamp + 3600,
+				sale_duration: 3600,
+				..SaleTimes::default()
+			},
+		)
+		.await;
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			50,
+			&token_program_id,
+		)
+		.await;
+
+		// A stranger signing for themselves cannot close the whitelist and take the vault
+		let stranger = Keypair::new();
+		let stranger_token_account = mint_tokens(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint.pubkey(),
+			&stranger.pubkey(),
+			0,
+			&token_program_id,
+		)
+		.await;
+		let ix = crate::instructions::terminate_whitelist(
+			&whitelist,
+			&stranger.pubkey(),
+			&vault,
+			&mint.pubkey(),
+			&stranger.pubkey(),
+			&stranger_token_account,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer, &stranger], recent_blockhash);
+		assert_whitelist_error(&mut banks_client, transaction, WhitelistError::Unauthorised).await;
+
+		assert!(banks_client.get_account(whitelist).await.unwrap().is_some());
+		assert_eq!(
+			token_balance(&mut banks_client, vault).await,
+			50 * 10u64.pow(9)
+		);
+		assert_eq!(
+			token_balance(&mut banks_client, stranger_token_account).await,
+			0
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_terminate_reclaims_rent_to_authority(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let clock = banks_client.get_sysvar::<Clock>().await.unwrap();
+		let (whitelist, vault, mint, _treasury) = create_timed_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+			SaleTimes {
+				sale_start: clock.unix_timestamp + 3600,
+				sale_duration: 3600,
+				..SaleTimes::default()
+			},
+		)
+		.await;
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			50,
+			&token_program_id,
+		)
+		.await;
+
+		// Tokens go to a separate recipient, the rent of both closed accounts goes to the
+		// authority
+		let recipient = Keypair::new();
+		let recipient_token_account = mint_tokens(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint.pubkey(),
+			&recipient.pubkey(),
+			0,
+			&token_program_id,
+		)
+		.await;
+
+		let whitelist_rent = banks_client
+			.get_account(whitelist)
+			.await
+			.unwrap()
+			.unwrap()
+			.lamports;
+		let vault_rent = banks_client
+			.get_account(vault)
+			.await
+			.unwrap()
+			.unwrap()
+			.lamports;
+		let authority_lamports = banks_client.get_balance(payer.pubkey()).await.unwrap();
+
+		let ix = crate::instructions::terminate_whitelist(
+			&whitelist,
+			&payer.pubkey(),
+			&vault,
+			&mint.pubkey(),
+			&recipient.pubkey(),
+			&recipient_token_account,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		let fee = banks_client
+			.get_fee_for_message(transaction.message.clone())
+			.await
+			.unwrap()
+			.unwrap();
+		let result = banks_client
+			.process_transaction_with_metadata(transaction)
+			.await
+			.unwrap();
+		assert!(result.result.is_ok());
+
+		let expected_log = format!(
+			"Terminated whitelist, reclaimed {} lamports to {}: vault rent {}, whitelist rent {}",
+			whitelist_rent + vault_rent,
+			payer.pubkey(),
+			vault_rent,
+			whitelist_rent
+		);
+		assert!(result
+			.metadata
+			.unwrap()
+			.log_messages
+			.iter()
+			.any(|log| log.contains(&expected_log)));
+
+		assert_account_closed(&mut banks_client, whitelist).await;
+		assert_eq!(
+			banks_client.get_balance(payer.pubkey()).await.unwrap(),
+			authority_lamports + whitelist_rent + vault_rent - fee
+		);
+		assert_eq!(
+			banks_client.get_balance(recipient.pubkey()).await.unwrap(),
+			0
+		);
+		let recipient_token_data = banks_client
+			.get_account(recipient_token_account)
+			.await
+			.unwrap()
+			.unwrap()
+			.data;
+		assert_eq!(
+			StateWithExtensions::<Account>::unpack(&recipient_token_data)
+				.unwrap()
+				.base
+				.amount,
+			50 * 10u64.pow(9)
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_burn_ticket_reclaims_lamports_to_treasury(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _vault, mint, treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+		let ticket = register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+		let ticket_token_account = mint_tokens(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint.pubkey(),
+			&ticket,
+			0,
+			&token_program_id,
+		)
+		.await;
+		let treasury_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&treasury.pubkey(),
+				&mint.pubkey(),
+				&token_program_id,
+			);
+
+		let ticket_lamports = banks_client.get_balance(ticket).await.unwrap();
+		let ticket_token_lamports = banks_client
+			.get_balance(ticket_token_account)
+			.await
+			.unwrap();
+		let treasury_lamports = banks_client.get_balance(treasury.pubkey()).await.unwrap();
+
+		let ix = crate::instructions::burn_ticket(
+			&whitelist,
+			&payer.pubkey(),
+			&mint.pubkey(),
+			&treasury.pubkey(),
+			&treasury_token_account,
+			&ticket,
+			&ticket_token_account,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		assert_account_closed(&mut banks_client, ticket).await;
+		assert!(banks_client
+			.get_account(ticket_token_account)
+			.await
+			.unwrap()
+			.is_none());
+		assert_eq!(
+			banks_client.get_balance(treasury.pubkey()).await.unwrap(),
+			treasury_lamports + ticket_lamports + ticket_token_lamports
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_burn_seeded_ticket(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, vault, mint, treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			50,
+			&token_program_id,
+		)
+		.await;
+		let (ticket, ticket_token_account) = seed_user(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			&Pubkey::new_unique(),
+			&token_program_id,
+		)
+		.await;
+		let treasury_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&treasury.pubkey(),
+				&mint.pubkey(),
+				&token_program_id,
+			);
+
+		// The ticket's tokens move to the treasury, whose token account is opened on the way
+		let ix = crate::instructions::burn_ticket(
+			&whitelist,
+			&payer.pubkey(),
+			&mint.pubkey(),
+			&treasury.pubkey(),
+			&treasury_token_account,
+			&ticket,
+			&ticket_token_account,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		assert_account_closed(&mut banks_client, ticket).await;
+		assert!(banks_client
+			.get_account(ticket_token_account)
+			.await
+			.unwrap()
+			.is_none());
+		assert_eq!(
+			token_balance(&mut banks_client, treasury_token_account).await,
+			10 * 10u64.pow(9)
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_force_close_orphaned_ticket(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let clock = banks_client.get_sysvar::<Clock>().await.unwrap();
+		let (whitelist, vault, mint, treasury) = create_timed_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+			SaleTimes {
+				registration_duration: 3600,
+				sale_start: clock.unix_timestamp + 3600,
+				sale_duration: 3600,
+				..SaleTimes::default()
+			},
+		)
+		.await;
+		let ticket = register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+		let ticket_token_account = mint_tokens(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint.pubkey(),
+			&ticket,
+			0,
+			&token_program_id,
+		)
+		.await;
+		let recipient_token_account = mint_tokens(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			0,
+			&token_program_id,
+		)
+		.await;
+
+		// Terminate the whitelist while the ticket is still open, leaving it orphaned
+		let ix = crate::instructions::terminate_whitelist(
+			&whitelist,
+			&payer.pubkey(),
+			&vault,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			&recipient_token_account,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+		assert_account_closed(&mut banks_client, whitelist).await;
+
+		// With the whitelist gone only the ticket's payer may reclaim it
+		let stranger = Keypair::new();
+		let ix = crate::instructions::force_close_ticket(
+			&whitelist,
+			&stranger.pubkey(),
+			&mint.pubkey(),
+			&treasury.pubkey(),
+			&ticket,
+			&ticket_token_account,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer, &stranger], recent_blockhash);
+		assert_whitelist_error(&mut banks_client, transaction, WhitelistError::Unauthorised).await;
+
+		let ticket_lamports = banks_client.get_balance(ticket).await.unwrap();
+		let ticket_token_lamports = banks_client
+			.get_balance(ticket_token_account)
+			.await
+			.unwrap();
+		let treasury_lamports = banks_client.get_balance(treasury.pubkey()).await.unwrap();
+
+		let ix = crate::instructions::force_close_ticket(
+			&whitelist,
+			&payer.pubkey(),
+			&mint.pubkey(),
+			&treasury.pubkey(),
+			&ticket,
+			&ticket_token_account,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		assert_account_closed(&mut banks_client, ticket).await;
+		assert!(banks_client
+			.get_account(ticket_token_account)
+			.await
+			.unwrap()
+			.is_none());
+		assert_eq!(
+			banks_client.get_balance(treasury.pubkey()).await.unwrap(),
+			treasury_lamports + ticket_lamports + ticket_token_lamports
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_orphaned_ticket_keeps_proceeds(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let clock = banks_client.get_sysvar::<Clock>().await.unwrap();
+		let (whitelist, vault, mint, treasury) = create_timed_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+			SaleTimes {
+				registration_duration: 3600,
+				sale_start: clock.unix_timestamp + 3600,
+				sale_duration: 3600,
+				..SaleTimes::default()
+			},
+		)
+		.await;
+		let ticket = register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+		let ticket_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&ticket,
+				&mint.pubkey(),
+				&token_program_id,
+			);
+		let recipient_token_account = mint_tokens(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			0,
+			&token_program_id,
+		)
+		.await;
+
+		// Purchase SOL the authority never withdrew, left with the ticket as the whitelist is
+		// terminated
+		let ixs = [
+			system_instruction::transfer(&payer.pubkey(), &ticket, 1_000_000),
+			crate::instructions::terminate_whitelist(
+				&whitelist,
+				&payer.pubkey(),
+				&vault,
+				&mint.pubkey(),
+				&payer.pubkey(),
+				&recipient_token_account,
+				&token_program_id,
+			)
+			.unwrap(),
+		];
+		let mut transaction = Transaction::new_with_payer(&ixs, Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+		assert_account_closed(&mut banks_client, whitelist).await;
+		let ticket_lamports = banks_client.get_balance(ticket).await.unwrap();
+
+		// The payer is only owed the rent, so neither instruction hands them the proceeds
+		let ix = crate::instructions::force_close_ticket(
+			&whitelist,
+			&payer.pubkey(),
+			&mint.pubkey(),
+			&payer.pubkey(),
+			&ticket,
+			&ticket_token_account,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::TicketHoldsProceeds,
+		)
+		.await;
+
+		let ix = crate::instructions::close_tickets(
+			&whitelist,
+			&payer.pubkey(),
+			&mint.pubkey(),
+			&treasury.pubkey(),
+			&[(ticket, ticket_token_account)],
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let ticket_account = banks_client
+			.get_account(ticket)
+			.await
+			.unwrap()
+			.expect("ticket with proceeds kept");
+		assert_eq!(ticket_account.owner, crate::id());
+		assert_eq!(ticket_account.lamports, ticket_lamports);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_close_tickets(token_program_id: Pubkey) {
+		let mut context = program_test().start_with_context().await;
+		let now = context
+			.banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
+		let (whitelist, _vault, mint, treasury) = create_timed_whitelist(
+			&mut context.banks_client,
+			&context.payer,
+			&context.last_blockhash,
+			&token_program_id,
+			SaleTimes {
+				registration_duration: 3600,
+				sale_start: now + 3600,
+				sale_duration: 3600,
+				..SaleTimes::default()
+			},
+		)
+		.await;
+		let payer = context.payer.insecure_clone();
+		let authority = payer.pubkey();
+
+		let users: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+		let mut tickets = Vec::with_capacity(users.len());
+		let mut add_ixs = Vec::with_capacity(users.len());
+		for user in &users {
+			let (ticket, _) = get_user_ticket_address(user, &whitelist);
+			let ticket_token_account =
+				spl_associated_token_account::get_associated_token_address_with_program_id(
+					&ticket,
+					&mint.pubkey(),
+					&token_program_id,
+				);
+			add_ixs.push(
+				crate::instructions::add_user(
+					&whitelist,
+					&authority,
+					&mint.pubkey(),
+					user,
+					&ticket,
+				)
+				.unwrap(),
+			);
+			tickets.push((ticket, ticket_token_account));
+		}
+		let mut transaction = Transaction::new_with_payer(&add_ixs, Some(&authority));
+		transaction.sign(&[&context.payer], context.last_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+
+		// The first ticket has an empty token account, the second never had one and the third
+		// still holds tokens
+		mint_tokens(
+			&mut context.banks_client,
+			&context.payer,
+			&context.last_blockhash,
+			&mint.pubkey(),
+			&tickets[0].0,
+			0,
+			&token_program_id,
+		)
+		.await;
+		mint_tokens(
+			&mut context.banks_client,
+			&context.payer,
+			&context.last_blockhash,
+			&mint.pubkey(),
+			&tickets[2].0,
+			1,
+			&token_program_id,
+		)
+		.await;
+
+		let close = |recent_blockhash: Hash| {
+			let ix = crate::instructions::close_tickets(
+				&whitelist,
+				&authority,
+				&mint.pubkey(),
+				&treasury.pubkey(),
+				&tickets,
+				&token_program_id,
+			)
+			.unwrap();
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&authority));
+			transaction.sign(&[&payer], recent_blockhash);
+			transaction
+		};
+
+		// Tickets stay open until the sale has ended
+		let transaction = close(context.last_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::SaleOngoing,
+		)
+		.await;
+
+		let recent_blockhash = warp_to_timestamp(&mut context, now + 7200).await;
+		let mut reclaimed = 0;
+		for address in [tickets[0].0, tickets[0].1, tickets[1].0] {
+			reclaimed += context.banks_client.get_balance(address).await.unwrap();
+		}
+		let treasury_lamports = context
+			.banks_client
+			.get_balance(treasury.pubkey())
+			.await
+			.unwrap();
+
+		let transaction = close(recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+
+		assert_account_closed(&mut context.banks_client, tickets[0].0).await;
+		assert_account_closed(&mut context.banks_client, tickets[1].0).await;
+		assert!(context
+			.banks_client
+			.get_account(tickets[0].1)
+			.await
+			.unwrap()
+			.is_none());
+		assert_eq!(
+			context
+				.banks_client
+				.get_balance(treasury.pubkey())
+				.await
+				.unwrap(),
+			treasury_lamports + reclaimed
+		);
+
+		// The ticket still holding tokens is left for `BurnTicket`
+		let ticket_account = context
+			.banks_client
+			.get_account(tickets[2].0)
+			.await
+			.unwrap()
+			.expect("ticket with tokens kept");
+		assert_eq!(ticket_account.owner, crate::id());
+
+		let whitelist_account = context
+			.banks_client
+			.get_account(whitelist)
+			.await
+			.unwrap()
+			.unwrap();
+		let wl_data = Whitelist::unpack(&whitelist_account.data).unwrap();
+		assert_eq!(wl_data.registered, 1);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_burn_ticket_rejects_foreign_token_account(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _vault, mint, treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+		let ticket = register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+		let ticket_token_account = mint_tokens(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint.pubkey(),
+			&ticket,
+			0,
+			&token_program_id,
+		)
+		.await;
+		// A token account of the same mint that does not belong to the ticket
+		let foreign_token_account = mint_tokens(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			0,
+			&token_program_id,
+		)
+		.await;
+		let treasury_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&treasury.pubkey(),
+				&mint.pubkey(),
+				&token_program_id,
+			);
+
+		let burn = |ticket_token_account: &Pubkey| {
+			crate::instructions::burn_ticket(
+				&whitelist,
+				&payer.pubkey(),
+				&mint.pubkey(),
+				&treasury.pubkey(),
+				&treasury_token_account,
+				&ticket,
+				ticket_token_account,
+				&token_program_id,
+			)
+			.unwrap()
+		};
+
+		let mut transaction =
+			Transaction::new_with_payer(&[burn(&foreign_token_account)], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::IncorrectUserAccount,
+		)
+		.await;
+		assert!(banks_client
+			.get_account(foreign_token_account)
+			.await
+			.unwrap()
+			.is_some());
+
+		let mut transaction =
+			Transaction::new_with_payer(&[burn(&ticket_token_account)], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+		assert!(banks_client
+			.get_account(ticket_token_account)
+			.await
+			.unwrap()
+			.is_none());
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_burn_ticket_rejects_aliased_treasury_token_account(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, _vault, mint, treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+		let ticket = register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+		let ticket_token_account = mint_tokens(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint.pubkey(),
+			&ticket,
+			1_000_000_000,
+			&token_program_id,
+		)
+		.await;
+
+		// The ticket's token account passed as the treasury's, its tokens would go nowhere
+		let burn_ix = crate::instructions::burn_ticket(
+			&whitelist,
+			&payer.pubkey(),
+			&mint.pubkey(),
+			&treasury.pubkey(),
+			&ticket_token_account,
+			&ticket,
+			&ticket_token_account,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[burn_ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::AccountMismatch,
+		)
+		.await;
+		assert!(banks_client.get_account(ticket).await.unwrap().is_some());
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_buy_insufficient_sol(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			50,
+			&token_program_id,
+		)
+		.await;
+
+		// Enough to register but not to also cover the token account the purchase creates, at
+		// 1 lamport per token the price itself is negligible
+		let user = Keypair::new();
+		let ix = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 3_000_000);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+		register_user(&mut banks_client, &user, &recent_blockhash, &whitelist).await;
+
+		let ix = buy_instruction(
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			&user.pubkey(),
+			1,
+			None,
+			&token_program_id,
+		);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&user.pubkey()));
+		transaction.sign(&[&user], recent_blockhash);
+		let err = banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap_err()
+			.unwrap();
+		assert_eq!(
+			err,
+			solana_sdk::transaction::TransactionError::InstructionError(
+				0,
+				solana_sdk::instruction::InstructionError::Custom(
+					WhitelistError::InsufficientSol as u32
+				)
+			)
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_buy_limit_exceeded_logs_reason(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			50,
+			&token_program_id,
+		)
+		.await;
+		register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+
+		// The default whitelist has a buy limit of 10 tokens
+		let ix = buy_instruction(
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			11,
+			None,
+			&token_program_id,
+		);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		let result = banks_client
+			.process_transaction_with_metadata(transaction)
+			.await
+			.unwrap();
+
+		assert!(result.result.is_err());
+		let logs = result.metadata.unwrap().log_messages;
+		assert!(logs.iter().any(|log| log
+			.contains("Buy rejected: remaining allowance 10000000000 < requested 11000000000")));
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_register_window(token_program_id: Pubkey) {
+		let mut context = program_test().start_with_context().await;
+		let now = context
+			.banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
+		let times = SaleTimes {
+			registration_start: now + 100,
+			registration_duration: 100,
+			sale_start: now + 300,
+			sale_duration: 100,
+			..SaleTimes::default()
+		};
+		let (whitelist, _vault, _mint, _treasury) = create_timed_whitelist(
+			&mut context.banks_client,
+			&context.payer,
+			&context.last_blockhash,
+			&token_program_id,
+			times,
+		)
+		.await;
+
+		let (ticket, _) = get_user_ticket_address(&context.payer.pubkey(), &whitelist);
+		let ix = crate::instructions::register(
+			&whitelist,
+			&context.payer.pubkey(),
+			&ticket,
+			&get_allocation_address(&context.payer.pubkey(), &whitelist).0,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&context.payer.pubkey()));
+		transaction.sign(&[&context.payer], context.last_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::RegistrationNotStarted,
+		)
+		.await;
+
+		let recent_blockhash = warp_to_timestamp(&mut context, times.registration_start + 50).await;
+		register_user(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&whitelist,
+		)
+		.await;
+
+		// Registration closes as soon as its duration has elapsed
+		let recent_blockhash = warp_to_timestamp(
+			&mut context,
+			times.registration_start + times.registration_duration,
+		)
+		.await;
+		let user = Keypair::new();
+		let (ticket, _) = get_user_ticket_address(&user.pubkey(), &whitelist);
+		let ix = crate::instructions::register(
+			&whitelist,
+			&user.pubkey(),
+			&ticket,
+			&get_allocation_address(&user.pubkey(), &whitelist).0,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&context.payer.pubkey()));
+		transaction.sign(&[&context.payer, &user], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::RegistrationFinished,
+		)
+		.await;
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_deposit_after_sale_ended(token_program_id: Pubkey) {
+		let mut context = program_test().start_with_context().await;
+		let now = context
+			.banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
+		let times = SaleTimes {
+			sale_start: now + 100,
+			sale_duration: 100,
+			..SaleTimes::default()
+		};
+		let recent_blockhash = context.last_blockhash;
+		let (whitelist, vault, mint, _treasury) = create_timed_whitelist(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&token_program_id,
+			times,
+		)
+		.await;
+
+		// Deposits are accepted before and during the sale
+		fund_vault(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			10,
+			&token_program_id,
+		)
+		.await;
+		let recent_blockhash = warp_to_timestamp(&mut context, times.sale_start + 50).await;
+		fund_vault(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			10,
+			&token_program_id,
+		)
+		.await;
+
+		let recent_blockhash =
+			warp_to_timestamp(&mut context, times.sale_start + times.sale_duration).await;
+		let payer_token_account = mint_tokens(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&mint.pubkey(),
+			&context.payer.pubkey(),
+			10 * 10u64.pow(9),
+			&token_program_id,
+		)
+		.await;
+		let ix = crate::instructions::deposit_tokens(
+			&whitelist,
+			&vault,
+			&context.payer.pubkey(),
+			&payer_token_account,
+			&mint.pubkey(),
+			10,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&context.payer.pubkey()));
+		transaction.sign(&[&context.payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::SaleEnded,
+		)
+		.await;
+
+		let wl_data = context
+			.banks_client
+			.get_account_data_with_borsh::<Whitelist>(whitelist)
+			.await
+			.unwrap();
+		assert_eq!(wl_data.deposited, 20 * 10u64.pow(9));
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_buy_window(token_program_id: Pubkey) {
+		let mut context = program_test().start_with_context().await;
+		let now = context
+			.banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
+		let times = SaleTimes {
+			sale_start: now + 100,
+			sale_duration: 100,
+			..SaleTimes::default()
+		};
+		let recent_blockhash = context.last_blockhash;
+		let (whitelist, vault, mint, _treasury) = create_timed_whitelist(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&token_program_id,
+			times,
+		)
+		.await;
+		fund_vault(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			50,
+			&token_program_id,
+		)
+		.await;
+		let ticket = register_user(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&whitelist,
+		)
+		.await;
+
+		let ix = buy_instruction(
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			&context.payer.pubkey(),
+			1,
+			None,
+			&token_program_id,
+		);
+
+		let mut transaction =
+			Transaction::new_with_payer(&[ix.clone()], Some(&context.payer.pubkey()));
+		transaction.sign(&[&context.payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::SaleNotStarted,
+		)
+		.await;
+
+		let recent_blockhash = warp_to_timestamp(&mut context, times.sale_start + 50).await;
+		let mut transaction =
+			Transaction::new_with_payer(&[ix.clone()], Some(&context.payer.pubkey()));
+		transaction.sign(&[&context.payer], recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+		let ticket_account = context
+			.banks_client
+			.get_account(ticket)
+			.await
+			.unwrap()
+			.unwrap();
+		let ticket_data = Ticket::unpack(&ticket_account.data).unwrap();
+		assert_eq!(ticket_data.amount_bought, 10u64.pow(9));
+
+		// The sale closes as soon as its duration has elapsed
+		let recent_blockhash =
+			warp_to_timestamp(&mut context, times.sale_start + times.sale_duration).await;
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&context.payer.pubkey()));
+		transaction.sign(&[&context.payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::SaleEnded,
+		)
+		.await;
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_buy_check_precedence(token_program_id: Pubkey) {
+		let mut context = program_test().start_with_context().await;
+		let now = context
+			.banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
+		let times = SaleTimes {
+			sale_start: now + 100,
+			sale_duration: 100,
+			..SaleTimes::default()
+		};
+		let recent_blockhash = context.last_blockhash;
+		let (whitelist, vault, mint, _treasury) = create_timed_whitelist(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&token_program_id,
+			times,
+		)
+		.await;
+		let payer = context.payer.insecure_clone();
+
+		// A buyer that cannot cover the token account a purchase would create, against a vault
+		// that was never funded
+		let user = Keypair::new();
+		let ix = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 3_000_000);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+		register_user(
+			&mut context.banks_client,
+			&user,
+			&recent_blockhash,
+			&whitelist,
+		)
+		.await;
+
+		let set_paused = |paused, blockhash| {
+			let ix = crate::instructions::set_paused(
+				&whitelist,
+				&payer.pubkey(),
+				PauseTarget::Sale,
+				paused,
+			)
+			.unwrap();
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer], blockhash);
+			transaction
+		};
+		// The default buy limit is 10 tokens, 11 exceeds the allowance as well
+		let buy = |amount, blockhash| {
+			let ix = buy_instruction(
+				&whitelist,
+				&vault,
+				&mint.pubkey(),
+				&user.pubkey(),
+				amount,
+				None,
+				&token_program_id,
+			);
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer, &user], blockhash);
+			transaction
+		};
+
+		// Paused comes before the sale not having started
+		context
+			.banks_client
+			.process_transaction(set_paused(true, recent_blockhash))
+			.await
+			.unwrap();
+		assert_whitelist_error(
+			&mut context.banks_client,
+			buy(11, recent_blockhash),
+			WhitelistError::SalePaused,
+		)
+		.await;
+
+		// Not started comes before the allowance, vault balance and buyer SOL
+		context
+			.banks_client
+			.process_transaction(set_paused(false, recent_blockhash))
+			.await
+			.unwrap();
+		let recent_blockhash = context
+			.banks_client
+			.get_new_latest_blockhash(&recent_blockhash)
+			.await
+			.unwrap();
+		assert_whitelist_error(
+			&mut context.banks_client,
+			buy(11, recent_blockhash),
+			WhitelistError::SaleNotStarted,
+		)
+		.await;
+
+		// During the sale the allowance comes before the vault balance, which comes before the
+		// buyer's SOL
+		let recent_blockhash = warp_to_timestamp(&mut context, times.sale_start + 50).await;
+		assert_whitelist_error(
+			&mut context.banks_client,
+			buy(11, recent_blockhash),
+			WhitelistError::BuyLimitExceeded,
+		)
+		.await;
+		assert_whitelist_error(
+			&mut context.banks_client,
+			buy(1, recent_blockhash),
+			WhitelistError::InsufficientFunds,
+		)
+		.await;
+
+		// Ended comes before the vault balance and buyer SOL, and paused before ended
+		let recent_blockhash =
+			warp_to_timestamp(&mut context, times.sale_start + times.sale_duration).await;
+		assert_whitelist_error(
+			&mut context.banks_client,
+			buy(1, recent_blockhash),
+			WhitelistError::SaleEnded,
+		)
+		.await;
+		context
+			.banks_client
+			.process_transaction(set_paused(true, recent_blockhash))
+			.await
+			.unwrap();
+		let recent_blockhash = context
+			.banks_client
+			.get_new_latest_blockhash(&recent_blockhash)
+			.await
+			.unwrap();
+		assert_whitelist_error(
+			&mut context.banks_client,
+			buy(1, recent_blockhash),
+			WhitelistError::SalePaused,
+		)
+		.await;
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_buy_cooldown(token_program_id: Pubkey) {
+		let mut context = program_test().start_with_context().await;
+		let now = context
+			.banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
+		let recent_blockhash = context.last_blockhash;
+		let (whitelist, vault, mint, _treasury) = create_timed_whitelist(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&token_program_id,
+			SaleTimes::default(),
+		)
+		.await;
+		fund_vault(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			50,
+			&token_program_id,
+		)
+		.await;
+		let ticket = register_user(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&whitelist,
+		)
+		.await;
+
+		let ix =
+			crate::instructions::set_buy_cooldown(&whitelist, &context.payer.pubkey(), 60).unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&context.payer.pubkey()));
+		transaction.sign(&[&context.payer], recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+
+		let payer = context.payer.insecure_clone();
+		let buy = |amount, blockhash| {
+			let ix = buy_instruction(
+				&whitelist,
+				&vault,
+				&mint.pubkey(),
+				&payer.pubkey(),
+				amount,
+				None,
+				&token_program_id,
+			);
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer], blockhash);
+			transaction
+		};
+
+		// The first purchase is never held back
+		let recent_blockhash = warp_to_timestamp(&mut context, now + 10).await;
+		let transaction = buy(1, recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+		let ticket_data = context
+			.banks_client
+			.get_account_data_with_borsh::<Ticket>(ticket)
+			.await
+			.unwrap();
+		assert_eq!(ticket_data.last_purchase_ts, now + 10);
+
+		// Registering again cannot reset the ticket to dodge the cooldown
+		let recent_blockhash = warp_to_timestamp(&mut context, now + 11).await;
+		let ix = crate::instructions::register(
+			&whitelist,
+			&payer.pubkey(),
+			&ticket,
+			&get_allocation_address(&payer.pubkey(), &whitelist).0,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::WhitelistAlreadyInitialized,
+		)
+		.await;
+		let transaction = buy(1, recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::Cooldown,
+		)
+		.await;
+
+		let recent_blockhash = warp_to_timestamp(&mut context, now + 69).await;
+		let transaction = buy(1, recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::Cooldown,
+		)
+		.await;
+
+		let recent_blockhash = warp_to_timestamp(&mut context, now + 70).await;
+		let transaction = buy(1, recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+		let ticket_data = context
+			.banks_client
+			.get_account_data_with_borsh::<Ticket>(ticket)
+			.await
+			.unwrap();
+		assert_eq!(ticket_data.amount_bought, 2 * 10u64.pow(9));
+		assert_eq!(ticket_data.last_purchase_ts, now + 70);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_refund_window(token_program_id: Pubkey) {
+		let mut context = program_test().start_with_context().await;
+		let now = context
+			.banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
+		let recent_blockhash = context.last_blockhash;
+		let (whitelist, vault, mint, _treasury) = create_timed_whitelist(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&token_program_id,
+			SaleTimes {
+				refund_window_secs: 60,
+				..SaleTimes::default()
+			},
+		)
+		.await;
+		fund_vault(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			50,
+			&token_program_id,
+		)
+		.await;
+		let ticket = register_user(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&whitelist,
+		)
+		.await;
+
+		let payer = context.payer.insecure_clone();
+		let user_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&payer.pubkey(),
+				&mint.pubkey(),
+				&token_program_id,
+			);
+		let refund = |amount, blockhash| {
+			let ix = crate::instructions::refund(
+				&whitelist,
+				&vault,
+				&mint.pubkey(),
+				&payer.pubkey(),
+				&ticket,
+				&user_token_account,
+				amount,
+				&token_program_id,
+			)
+			.unwrap();
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer], blockhash);
+			transaction
+		};
+
+		let recent_blockhash = warp_to_timestamp(&mut context, now + 10).await;
+		let ix = buy_instruction(
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			2,
+			None,
+			&token_program_id,
+		);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+		let ticket_lamports = context.banks_client.get_balance(ticket).await.unwrap();
+
+		// Within the window part of the purchase is returned for its price
+		let recent_blockhash = warp_to_timestamp(&mut context, now + 69).await;
+		let transaction = refund(1, recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+		let ticket_data = context
+			.banks_client
+			.get_account_data_with_borsh::<Ticket>(ticket)
+			.await
+			.unwrap();
+		assert_eq!(ticket_data.amount_bought, 10u64.pow(9));
+		assert_eq!(ticket_data.claimed, 10u64.pow(9));
+		assert_eq!(
+			context.banks_client.get_balance(ticket).await.unwrap(),
+			ticket_lamports - 1
+		);
+		let vault_account = context
+			.banks_client
+			.get_account(vault)
+			.await
+			.unwrap()
+			.unwrap();
+		let vault_data = StateWithExtensions::<Account>::unpack(&vault_account.data).unwrap();
+		assert_eq!(vault_data.base.amount, 49 * 10u64.pow(9));
+
+		let transaction = refund(2, recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::RefundExceedsPurchase,
+		)
+		.await;
+
+		// Once the window has passed the sale is final
+		let recent_blockhash = warp_to_timestamp(&mut context, now + 70).await;
+		let transaction = refund(1, recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::RefundWindowClosed,
+		)
+		.await;
+	}
+
+	/// Minimal xorshift generator so that randomised tests replay identically from a seed
+	struct XorShift(u64);
+
+	impl XorShift {
+		fn below(&mut self, bound: u64) -> u64 {
+			self.0 ^= self.0 << 13;
+			self.0 ^= self.0 >> 7;
+			self.0 ^= self.0 << 17;
+			self.0 % bound
+		}
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_sale_reconciliation(token_program_id: Pubkey) {
+		for seed in [1, 7, 42] {
+			reconcile_random_sale(token_program_id, seed).await;
+		}
+	}
+
+	/// Runs a random sequence of deposits and buys against a fresh sale, checking every
+	/// outcome against a model and that the tokens deposited are all accounted for at the end
+	async fn reconcile_random_sale(token_program_id: Pubkey, seed: u64) {
+		let mut rng = XorShift(seed);
+		let mut context = program_test().start_with_context().await;
+		let payer = context.payer.insecure_clone();
+		let now = context
+			.banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
+		let recent_blockhash = context.last_blockhash;
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
+			&mut context.banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		let users: Vec<Keypair> = (0..3).map(|_| Keypair::new()).collect();
+		for user in &users {
+			let ix = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 1_000_000_000);
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer], recent_blockhash);
+			context
+				.banks_client
+				.process_transaction(transaction)
+				.await
+				.unwrap();
+			register_user(
+				&mut context.banks_client,
+				user,
+				&recent_blockhash,
+				&whitelist,
+			)
+			.await;
+		}
+
+		// The default whitelist allows 10 tokens per ticket
+		let unit = 10u64.pow(9);
+		let buy_limit = 10 * unit;
+		let mut deposited = 0;
+		let mut vault_balance = 0;
+		let mut bought = vec![0; users.len()];
+
+		for step in 1..=16 {
+			// Each step runs in a new bank so repeated operations are never deduplicated
+			let recent_blockhash = warp_to_timestamp(&mut context, now + step).await;
+			if rng.below(3) == 0 {
+				let amount = 1 + rng.below(8);
+				fund_vault(
+					&mut context.banks_client,
+					&payer,
+					&recent_blockhash,
+					&whitelist,
+					&vault,
+					&mint.pubkey(),
+					amount,
+					&token_program_id,
+				)
+				.await;
+				deposited += amount * unit;
+				vault_balance += amount * unit;
+				continue;
+			}
+
+			let index = rng.below(users.len() as u64) as usize;
+			let user = &users[index];
+			let amount = 1 + rng.below(4);
+			let ix = buy_instruction(
+				&whitelist,
+				&vault,
+				&mint.pubkey(),
+				&user.pubkey(),
+				amount,
+				None,
+				&token_program_id,
+			);
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer, user], recent_blockhash);
+
+			let expected_error = if bought[index] + amount * unit > buy_limit {
+				Some(WhitelistError::BuyLimitExceeded)
+			} else if vault_balance < amount * unit {
+				Some(WhitelistError::InsufficientFunds)
+			} else {
+				None
+			};
+			match expected_error {
+				Some(error) => {
+					assert_whitelist_error(&mut context.banks_client, transaction, error).await
+				}
+				None => {
+					context
+						.banks_client
+						.process_transaction(transaction)
+						.await
+						.unwrap();
+					bought[index] += amount * unit;
+					vault_balance -= amount * unit;
+				}
+			}
+		}
+
+		let wl_data = context
+			.banks_client
+			.get_account_data_with_borsh::<Whitelist>(whitelist)
+			.await
+			.unwrap();
+		let vault_account = context
+			.banks_client
+			.get_account(vault)
+			.await
+			.unwrap()
+			.unwrap();
+		let on_chain_vault = StateWithExtensions::<Account>::unpack(&vault_account.data)
+			.unwrap()
+			.base
+			.amount;
+
+		let mut delivered = 0;
+		for (user, bought) in users.iter().zip(&bought) {
+			let user_token_account =
+				spl_associated_token_account::get_associated_token_address_with_program_id(
+					&user.pubkey(),
+					&mint.pubkey(),
+					&token_program_id,
+				);
+			let balance = match context
+				.banks_client
+				.get_account(user_token_account)
+				.await
+				.unwrap()
+			{
+				Some(account) => {
+					StateWithExtensions::<Account>::unpack(&account.data)
+						.unwrap()
+						.base
+						.amount
+				}
+				None => 0,
+			};
+			assert_eq!(balance, *bought, "seed {}", seed);
+
+			let (ticket, _) = get_user_ticket_address(&user.pubkey(), &whitelist);
+			let ticket_data = context
+				.banks_client
+				.get_account_data_with_borsh::<Ticket>(ticket)
+				.await
+				.unwrap();
+			assert_eq!(ticket_data.amount_bought, *bought, "seed {}", seed);
+			delivered += balance;
+		}
+
+		assert_eq!(wl_data.deposited, deposited, "seed {}", seed);
+		assert_eq!(on_chain_vault, vault_balance, "seed {}", seed);
+		assert_eq!(
+			wl_data.deposited,
+			on_chain_vault + delivered,
+			"seed {}: deposited tokens are not all accounted for",
+			seed
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_withdraw_window(token_program_id: Pubkey) {
+		let mut context = program_test().start_with_context().await;
+		let now = context
+			.banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
+		let times = SaleTimes {
+			sale_start: now + 100,
+			sale_duration: 100,
+			..SaleTimes::default()
+		};
+		let recent_blockhash = context.last_blockhash;
+		let (whitelist, vault, mint, _treasury) = create_timed_whitelist(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&token_program_id,
+			times,
+		)
+		.await;
+		fund_vault(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			10,
+			&token_program_id,
+		)
+		.await;
+		let recipient_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&context.payer.pubkey(),
+				&mint.pubkey(),
+				&token_program_id,
+			);
+
+		let ix = crate::instructions::withdraw_tokens(
+			&whitelist,
+			&context.payer.pubkey(),
+			&vault,
+			&mint.pubkey(),
+			&recipient_token_account,
+			1,
+			&token_program_id,
+		)
+		.unwrap();
+
+		let mut transaction =
+			Transaction::new_with_payer(&[ix.clone()], Some(&context.payer.pubkey()));
+		transaction.sign(&[&context.payer], recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+		let vault_account = context
+			.banks_client
+			.get_account(vault)
+			.await
+			.unwrap()
+			.unwrap();
+		let vault_data = StateWithExtensions::<Account>::unpack(&vault_account.data).unwrap();
+		assert_eq!(vault_data.base.amount, 9 * 10u64.pow(9));
+
+		let recent_blockhash = warp_to_timestamp(&mut context, times.sale_start + 50).await;
+		let mut transaction =
+			Transaction::new_with_payer(&[ix.clone()], Some(&context.payer.pubkey()));
+		transaction.sign(&[&context.payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::SaleOngoing,
+		)
+		.await;
+
+		// Once the sale has ended the vault can be withdrawn from again
+		let recent_blockhash =
+			warp_to_timestamp(&mut context, times.sale_start + times.sale_duration + 1).await;
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&context.payer.pubkey()));
+		transaction.sign(&[&context.payer], recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+		assert_eq!(
+			token_balance(&mut context.banks_client, vault).await,
+			8 * 10u64.pow(9)
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_withdraw_rejected_in_unscheduled_sale(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			10,
+			&token_program_id,
+		)
+		.await;
+		register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+
+		// Without a sale start the sale is live from initialisation, so buying goes through
+		let wl_data = banks_client
+			.get_account_data_with_borsh::<Whitelist>(whitelist)
+			.await
+			.unwrap();
+		assert_eq!(wl_data.sale_timestamp, 0);
+		let ix = buy_instruction(
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			10u64.pow(9),
+			None,
+			&token_program_id,
+		);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		// And the vault cannot be emptied from under it
+		let recipient_token_account = mint_tokens(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			0,
+			&token_program_id,
+		)
+		.await;
+		let instructions = [
+			crate::instructions::withdraw_tokens(
+				&whitelist,
+				&payer.pubkey(),
+				&vault,
+				&mint.pubkey(),
+				&recipient_token_account,
+				1,
+				&token_program_id,
+			)
+			.unwrap(),
+			crate::instructions::terminate_whitelist(
+				&whitelist,
+				&payer.pubkey(),
+				&vault,
+				&mint.pubkey(),
+				&payer.pubkey(),
+				&recipient_token_account,
+				&token_program_id,
+			)
+			.unwrap(),
+		];
+		for ix in instructions {
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer], recent_blockhash);
+			assert_whitelist_error(&mut banks_client, transaction, WhitelistError::SaleOngoing)
+				.await;
+		}
+		assert_eq!(
+			token_balance(&mut banks_client, vault).await,
+			9 * 10u64.pow(9)
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_withdraw_deposit(token_program_id: Pubkey) {
+		let mut context = program_test().start_with_context().await;
+		let now = context
+			.banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
+		let times = SaleTimes {
+			sale_start: now + 100,
+			sale_duration: 100,
+			..SaleTimes::default()
+		};
+		let recent_blockhash = context.last_blockhash;
+		let (whitelist, vault, mint, _treasury) = create_timed_whitelist(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&token_program_id,
+			times,
+		)
+		.await;
+
+		let depositor = Keypair::new();
+		let ix = system_instruction::transfer(
+			&context.payer.pubkey(),
+			&depositor.pubkey(),
+			1_000_000_000,
+		);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&context.payer.pubkey()));
+		transaction.sign(&[&context.payer], recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+		let depositor_token_account = mint_tokens(
+			&mut context.banks_client,
+			&context.payer,
+			&recent_blockhash,
+			&mint.pubkey(),
+			&depositor.pubkey(),
+			10 * 10u64.pow(9),
+			&token_program_id,
+		)
+		.await;
+		let (ledger, _) = get_depositor_address(&depositor.pubkey(), &whitelist);
+
+		let deposit_ix = crate::instructions::deposit_for(
+			&whitelist,
+			&vault,
+			&depositor.pubkey(),
+			&depositor_token_account,
+			&mint.pubkey(),
+			&ledger,
+			10,
+			&token_program_id,
+		)
+		.unwrap();
+		let withdraw_ix = |amount| {
+			crate::instructions::withdraw_deposit(
+				&whitelist,
+				&vault,
+				&depositor.pubkey(),
+				&depositor_token_account,
+				&mint.pubkey(),
+				&ledger,
+				amount,
+				&token_program_id,
+			)
+			.unwrap()
+		};
+
+		let mut transaction = Transaction::new_with_payer(
+			&[deposit_ix.clone(), withdraw_ix(4)],
+			Some(&depositor.pubkey()),
+		);
+		transaction.sign(&[&depositor], recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+
+		let ledger_account = context
+			.banks_client
+			.get_account(ledger)
+			.await
+			.unwrap()
+			.unwrap();
+		let ledger_data = Depositor::unpack(&ledger_account.data).unwrap();
+		assert_eq!(ledger_data.owner, depositor.pubkey());
+		assert_eq!(ledger_data.amount, 6 * 10u64.pow(9));
+
+		// Only the depositor's own net contribution can be reclaimed
+		let mut transaction =
+			Transaction::new_with_payer(&[withdraw_ix(7)], Some(&depositor.pubkey()));
+		transaction.sign(&[&depositor], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::WithdrawalExceedsDeposit,
+		)
+		.await;
+
+		let ledger_lamports = context.banks_client.get_balance(ledger).await.unwrap();
+		let depositor_lamports = context
+			.banks_client
+			.get_balance(depositor.pubkey())
+			.await
+			.unwrap();
+		let mut transaction =
+			Transaction::new_with_payer(&[withdraw_ix(6)], Some(&depositor.pubkey()));
+		transaction.sign(&[&depositor], recent_blockhash);
+		let fee = context
+			.banks_client
+			.get_fee_for_message(transaction.message.clone())
+			.await
+			.unwrap()
+			.unwrap();
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+
+		// Closing the emptied ledger returns exactly its lamports to the depositor
+		assert_account_closed(&mut context.banks_client, ledger).await;
+		assert_eq!(
+			context
+				.banks_client
+				.get_balance(depositor.pubkey())
+				.await
+				.unwrap(),
+			depositor_lamports + ledger_lamports - fee
+		);
+		let token_account = context
+			.banks_client
+			.get_account(depositor_token_account)
+			.await
+			.unwrap()
+			.unwrap();
+		let token_data = StateWithExtensions::<Account>::unpack(&token_account.data).unwrap();
+		assert_eq!(token_data.base.amount, 10 * 10u64.pow(9));
+		let whitelist_account = context
+			.banks_client
+			.get_account(whitelist)
+			.await
+			.unwrap()
+			.unwrap();
+		let wl_data = Whitelist::unpack(&whitelist_account.data).unwrap();
+		assert_eq!(wl_data.deposited, 0);
+
+		// Once the sale starts deposits are committed
+		let recent_blockhash = warp_to_timestamp(&mut context, times.sale_start).await;
+		let mut transaction = Transaction::new_with_payer(&[deposit_ix], Some(&depositor.pubkey()));
+		transaction.sign(&[&depositor], recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+
+		let mut transaction =
+			Transaction::new_with_payer(&[withdraw_ix(1)], Some(&depositor.pubkey()));
+		transaction.sign(&[&depositor], recent_blockhash);
+		assert_whitelist_error(
+			&mut context.banks_client,
+			transaction,
+			WhitelistError::SaleStarted,
+		)
+		.await;
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_buy_price_exceeded(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			50,
+			&token_program_id,
+		)
+		.await;
+		register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+
+		// The default whitelist sells at a price of 1, a buyer quoted 0 sees the price raised
+		let ix = buy_instruction(
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			1,
+			Some(0),
+			&token_program_id,
+		);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::PriceExceeded,
+		)
+		.await;
+
+		let ix = buy_instruction(
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			1,
+			Some(1),
+			&token_program_id,
+		);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_buy_partial_fill(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			3,
+			&token_program_id,
+		)
+		.await;
+		let ticket = register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+		let ticket_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&ticket,
+				&mint.pubkey(),
+				&token_program_id,
+			);
+		let user_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&payer.pubkey(),
+				&mint.pubkey(),
+				&token_program_id,
+			);
+		let buy = |allow_partial| {
+			crate::instructions::buy_tokens(
+				&whitelist,
+				&vault,
+				&mint.pubkey(),
+				&payer.pubkey(),
+				&ticket,
+				&ticket_token_account,
+				&user_token_account,
+				5,
+				None,
+				allow_partial,
+				&token_program_id,
+			)
+			.unwrap()
+		};
+
+		// Without opting in a buy the vault cannot cover fails outright
+		let mut transaction = Transaction::new_with_payer(&[buy(false)], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::InsufficientFunds,
+		)
+		.await;
+
+		let ticket_lamports = banks_client.get_balance(ticket).await.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[buy(true)], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		let result = banks_client
+			.process_transaction_with_metadata(transaction)
+			.await
+			.unwrap();
+		assert!(result.result.is_ok());
+		let logs = result.metadata.unwrap().log_messages;
+		assert!(logs
+			.iter()
+			.any(|log| log.contains("Partial fill: 3000000000 of 5000000000 requested")));
+
+		// Only the 3 tokens the vault held are delivered and paid for
+		let filled = 3 * 10u64.pow(9);
+		let token_account = banks_client
+			.get_account(user_token_account)
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(
+			StateWithExtensions::<Account>::unpack(&token_account.data)
+				.unwrap()
+				.base
+				.amount,
+			filled
+		);
+		assert_eq!(
+			banks_client.get_balance(ticket).await.unwrap() - ticket_lamports,
+			buy_cost(filled, 1, 9).unwrap()
+		);
+		let ticket_data = banks_client
+			.get_account_data_with_borsh::<Ticket>(ticket)
+			.await
+			.unwrap();
+		assert_eq!(ticket_data.amount_bought, filled);
+
+		// An empty vault has nothing to fill
+		let recent_blockhash = banks_client
+			.get_new_latest_blockhash(&recent_blockhash)
+			.await
+			.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[buy(true)], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::InsufficientFunds,
+		)
+		.await;
+	}
+
+	#[test_case(spl_token::id(), 0 ; "Token Program, 0 decimals")]
+	#[test_case(spl_token::id(), 6 ; "Token Program, 6 decimals")]
+	#[test_case(spl_token::id(), 9 ; "Token Program, 9 decimals")]
+	#[test_case(spl_token_2022::id(), 0 ; "Token-2022 Program, 0 decimals")]
+	#[test_case(spl_token_2022::id(), 6 ; "Token-2022 Program, 6 decimals")]
+	#[test_case(spl_token_2022::id(), 9 ; "Token-2022 Program, 9 decimals")]
+	#[tokio::test]
+	async fn test_buy_price_is_per_whole_token(token_program_id: Pubkey, decimals: u8) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let treasury = Keypair::new();
+		let mint = Keypair::new();
+		let (whitelist, _) = get_whitelist_address(&mint.pubkey());
+		create_mint(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint,
+			&token_program_id,
+			decimals,
+		)
+		.await;
+		let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
+			&whitelist,
+			&mint.pubkey(),
+			&token_program_id,
+		);
+
+		// 0.25 SOL per whole token whatever the mint's decimals
+		let token_price = 250_000_000;
+		let ix = crate::instructions::init_whitelist(
+			&whitelist,
+			&payer.pubkey(),
+			&vault,
+			&mint.pubkey(),
+			&treasury.pubkey(),
+			token_price,
+			10,
+			5,
+			true,
+			0,
+			0,
+			0,
+			0,
+			0,
+			0,
+			false,
+			false,
+			false,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let one_token = 10u64.pow(decimals as u32);
+		let payer_token_account = mint_tokens(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			10 * one_token,
+			&token_program_id,
+		)
+		.await;
+		let ix = crate::instructions::deposit_tokens(
+			&whitelist,
+			&vault,
+			&payer.pubkey(),
+			&payer_token_account,
+			&mint.pubkey(),
+			10,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let ticket = register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+		let ticket_lamports = banks_client.get_balance(ticket).await.unwrap();
+		let ix = buy_instruction(
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			3,
+			None,
+			&token_program_id,
+		);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		// 3 whole tokens cost 0.75 SOL, not 0.75 SOL per base unit
+		assert_eq!(
+			token_balance(&mut banks_client, payer_token_account).await,
+			3 * one_token
+		);
+		assert_eq!(
+			banks_client.get_balance(ticket).await.unwrap() - ticket_lamports,
+			750_000_000
+		);
+	}
+
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_buy_from_prefunded_ticket(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			15,
+			&token_program_id,
+		)
+		.await;
+		let ticket = register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+		let ticket_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&ticket,
+				&mint.pubkey(),
+				&token_program_id,
+			);
+		let user_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&payer.pubkey(),
+				&mint.pubkey(),
+				&token_program_id,
+			);
+
+		// Moves the ticket's whole allowance of 10 tokens out of the vault ahead of the sale
+		let ix = crate::instructions::transfer_tokens(
+			&whitelist,
+			&payer.pubkey(),
+			&vault,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			&ticket,
+			&ticket_token_account,
+			&token_program_id,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let unit = 10u64.pow(9);
+		assert_eq!(
+			token_balance(&mut banks_client, ticket_token_account).await,
+			10 * unit
+		);
+		assert_eq!(token_balance(&mut banks_client, vault).await, 5 * unit);
+
+		// Each purchase is delivered once, out of the ticket's tokens and not again from the vault
+		for (amount, bought) in [(4, 4), (6, 10)] {
+			let ix = buy_instruction(
+				&whitelist,
+				&vault,
+				&mint.pubkey(),
+				&payer.pubkey(),
+				amount,
+				None,
+				&token_program_id,
+			);
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer], recent_blockhash);
+			banks_client.process_transaction(transaction).await.unwrap();
+
+			assert_eq!(
+				token_balance(&mut banks_client, user_token_account).await,
+				bought * unit
+			);
+			assert_eq!(
+				token_balance(&mut banks_client, ticket_token_account).await,
+				(10 - bought) * unit
+			);
+			assert_eq!(token_balance(&mut banks_client, vault).await, 5 * unit);
+		}
+
+		let ticket_data = banks_client
+			.get_account_data_with_borsh::<Ticket>(ticket)
 			.await
-			.unwrap()
-			.minimum_balance(space);
+			.unwrap();
+		assert_eq!(ticket_data.amount_bought, 10 * unit);
+	}
 
-		let init_mint = spl_token_2022::instruction::initialize_mint(
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_withdraw_sol(token_program_id: Pubkey) {
+		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let (whitelist, vault, mint, treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
 			&token_program_id,
-			&mint_keypair.pubkey(),
+		)
+		.await;
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			10,
+			&token_program_id,
+		)
+		.await;
+		let ticket = register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+
+		let ix = buy_instruction(
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
 			&payer.pubkey(),
+			5,
 			None,
-			decimals,
+			&token_program_id,
 		);
+		// The treasury must already hold its own rent to receive a handful of lamports
+		let fund_ix =
+			system_instruction::transfer(&payer.pubkey(), &treasury.pubkey(), 1_000_000_000);
+		let mut transaction = Transaction::new_with_payer(&[ix, fund_ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
 
-		let instructions = [
-			system_instruction::create_account(
+		// The purchase SOL sits in the ticket on top of its rent
+		let rent = banks_client
+			.get_rent()
+			.await
+			.unwrap()
+			.minimum_balance(Ticket::LEN);
+		let ticket_lamports = banks_client.get_balance(ticket).await.unwrap();
+		let proceeds = ticket_lamports - rent;
+		assert!(proceeds > 0);
+		let treasury_lamports = banks_client.get_balance(treasury.pubkey()).await.unwrap();
+
+		let withdraw = |amount| {
+			let ix = crate::instructions::withdraw_sol(
+				&whitelist,
 				&payer.pubkey(),
-				&mint_keypair.pubkey(),
-				mint_rent,
-				space as u64,
-				&token_program_id,
-			),
-			init_mint.unwrap(),
-		];
+				&treasury.pubkey(),
+				Some(&ticket),
+				amount,
+			)
+			.unwrap();
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer], recent_blockhash);
+			transaction
+		};
 
-		let mut transaction = Transaction::new_with_payer(&instructions, Some(&payer.pubkey()));
-		transaction.sign(&[payer, &mint_keypair], *recent_blockhash);
+		// The ticket keeps its rent
+		assert_whitelist_error(
+			&mut banks_client,
+			withdraw(proceeds + 1),
+			WhitelistError::InsufficientFunds,
+		)
+		.await;
 
-		banks_client.process_transaction(transaction).await.unwrap();
-		let mint_test = banks_client
-			.get_account(mint_keypair.pubkey())
+		banks_client
+			.process_transaction(withdraw(proceeds))
 			.await
-			.expect("get mint account")
-			.expect("mint account is none");
-		assert_eq!(mint_test.data.len(), space);
-
-		println!("Mint created");
+			.unwrap();
+		assert_eq!(banks_client.get_balance(ticket).await.unwrap(), rent);
+		assert_eq!(
+			banks_client.get_balance(treasury.pubkey()).await.unwrap(),
+			treasury_lamports + proceeds
+		);
+		let ticket_account = banks_client.get_account(ticket).await.unwrap().unwrap();
+		assert_eq!(ticket_account.owner, crate::id());
 	}
 
-	async fn create_default_whitelist(
-		banks_client: &mut BanksClient,
-		payer: &Keypair,
-		recent_blockhash: &Hash,
-		token_program_id: &Pubkey,
-	) -> (Pubkey, Pubkey, Keypair, Keypair) {
-		let treasury = Keypair::new();
+	#[test_case(spl_token::id() ; "Token Program")]
+	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+	#[tokio::test]
+	async fn test_treasury_pda(token_program_id: Pubkey) {
+		let mut context = program_test().start_with_context().await;
+		let payer = context.payer.insecure_clone();
+		let recent_blockhash = context.last_blockhash;
+		let now = context
+			.banks_client
+			.get_sysvar::<Clock>()
+			.await
+			.unwrap()
+			.unix_timestamp;
 		let mint_keypair = Keypair::new();
-		let (whitelist, _) = get_whitelist_address(&mint_keypair.pubkey());
+		let mint = mint_keypair.pubkey();
 		create_mint(
-			banks_client,
+			&mut context.banks_client,
 			&payer,
 			&recent_blockhash,
 			&mint_keypair,
-			token_program_id,
+			&token_program_id,
 			9,
 		)
 		.await;
+		let (whitelist, _) = get_whitelist_address(&mint);
+		let (treasury, _) = get_treasury_address(&mint);
 		let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
 			&whitelist,
-			&mint_keypair.pubkey(),
-			token_program_id,
+			&mint,
+			&token_program_id,
 		);
 
-		let token_price = 1;
-		let buy_limit = 10;
-		let whitelist_size = 5;
-		let allow_registration = true;
-		let registration_start_timestamp = 0;
-		let registration_duration = 0;
-		let sale_start_timestamp = 0;
-		let sale_duration = 0;
+		let init = |treasury_is_pda| {
+			let ix = crate::instructions::init_whitelist(
+				&whitelist,
+				&payer.pubkey(),
+				&vault,
+				&mint,
+				&treasury,
+				1_000_000,
+				10,
+				5,
+				true,
+				0,
+				0,
+				now,
+				3600,
+				0,
+				0,
+				false,
+				false,
+				treasury_is_pda,
+				&token_program_id,
+			)
+			.unwrap();
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer], recent_blockhash);
+			transaction
+		};
 
-		let ix = crate::instructions::init_whitelist(
-			&whitelist,
-			&payer.pubkey(),
-			&vault,
-			&mint_keypair.pubkey(),
-			&treasury.pubkey(),
-			token_price,
-			buy_limit,
-			whitelist_size,
-			allow_registration,
-			registration_start_timestamp,
-			registration_duration,
-			sale_start_timestamp,
-			sale_duration,
-			token_program_id,
+		// The PDA as a plain treasury would never be created
+		assert_whitelist_error(
+			&mut context.banks_client,
+			init(false),
+			WhitelistError::IncorrectTreasuryAddress,
 		)
-		.unwrap();
+		.await;
 
+		// Funding the PDA ahead of time cannot block initialisation. The runtime only lets a
+		// new system account be funded to the rent exemption, so that is what gets sent
+		let treasury_rent = context
+			.banks_client
+			.get_rent()
+			.await
+			.unwrap()
+			.minimum_balance(0);
+		let ix = system_instruction::transfer(&payer.pubkey(), &treasury, treasury_rent);
 		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
-		transaction.sign(&[payer], *recent_blockhash);
-		banks_client.process_transaction(transaction).await.unwrap();
-
-		println!("Whitelist initialised");
-		(whitelist, vault, mint_keypair, treasury)
-	}
-
-	#[test_case(spl_token::id() ; "Token Program")]
-	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
-	#[tokio::test]
-	async fn test_init_whitelist(token_program_id: Pubkey) {
-		let treasury_keypair = Keypair::new();
-		let mint_keypair = Keypair::new();
-
-		let (whitelist, _) = get_whitelist_address(&mint_keypair.pubkey());
+		transaction.sign(&[&payer], recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
+			.await
+			.unwrap();
+		context
+			.banks_client
+			.process_transaction(init(true))
+			.await
+			.unwrap();
 
-		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
+		let treasury_account = context
+			.banks_client
+			.get_account(treasury)
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(treasury_account.owner, crate::id());
+		assert_eq!(treasury_account.lamports, treasury_rent);
+		let wl_data = context
+			.banks_client
+			.get_account_data_with_borsh::<Whitelist>(whitelist)
+			.await
+			.unwrap();
+		assert_eq!(wl_data.treasury, treasury);
 
-		create_mint(
-			&mut banks_client,
+		fund_vault(
+			&mut context.banks_client,
 			&payer,
 			&recent_blockhash,
-			&mint_keypair,
+			&whitelist,
+			&vault,
+			&mint,
+			10,
 			&token_program_id,
-			9,
 		)
 		.await;
-
-		let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
+		let ticket = register_user(
+			&mut context.banks_client,
+			&payer,
+			&recent_blockhash,
 			&whitelist,
-			&mint_keypair.pubkey(),
+		)
+		.await;
+		let ix = buy_instruction(
+			&whitelist,
+			&vault,
+			&mint,
+			&payer.pubkey(),
+			5 * 10u64.pow(9),
+			None,
 			&token_program_id,
 		);
-
-		let token_price = 1;
-		let buy_limit = 10;
-		let whitelist_size = 5;
-		let allow_registration = true;
-		let registration_start_timestamp = 0;
-		let registration_duration = 0;
-		let sale_start_timestamp = 0;
-		let sale_duration = 0;
-
-		let ix = crate::instructions::init_whitelist(
+		let withdraw_ix = crate::instructions::withdraw_sol(
 			&whitelist,
 			&payer.pubkey(),
-			&vault,
-			&mint_keypair.pubkey(),
-			&treasury_keypair.pubkey(),
-			token_price,
-			buy_limit,
-			whitelist_size,
-			allow_registration,
-			registration_start_timestamp,
-			registration_duration,
-			sale_start_timestamp,
-			sale_duration,
-			&token_program_id,
+			&treasury,
+			Some(&ticket),
+			5_000_000,
 		)
 		.unwrap();
-		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
-		transaction.sign(&[payer], recent_blockhash);
-		banks_client.process_transaction(transaction).await.unwrap();
-		let whitelist_account = banks_client
-			.get_account(whitelist)
+		let mut transaction =
+			Transaction::new_with_payer(&[ix, withdraw_ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		context
+			.banks_client
+			.process_transaction(transaction)
 			.await
-			.expect("get_account")
-			.expect("whitelist account not none");
-		let rent = banks_client.get_rent().await.unwrap();
-		assert_eq!(whitelist_account.data.len(), Whitelist::LEN);
-		assert_eq!(whitelist_account.owner, crate::id());
+			.unwrap();
 		assert_eq!(
-			whitelist_account.lamports,
-			rent.minimum_balance(Whitelist::LEN)
+			context.banks_client.get_balance(treasury).await.unwrap(),
+			treasury_rent + 5_000_000
 		);
-	}
 
-	#[test_case(spl_token::id() ; "Token Program")]
-	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
-	#[tokio::test]
-	async fn test_add_user(token_program_id: Pubkey) {
-		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
-		let (whitelist, _, mint, _) = create_default_whitelist(
-			&mut banks_client,
+		// The whitelist can only be terminated once the sale is over, and the proceeds would be
+		// stranded once it is closed
+		let recent_blockhash = warp_to_timestamp(&mut context, now + 3600).await;
+		let recipient_token_account = mint_tokens(
+			&mut context.banks_client,
 			&payer,
 			&recent_blockhash,
+			&mint,
+			&payer.pubkey(),
+			1,
 			&token_program_id,
 		)
 		.await;
+		let terminate = || {
+			let mut ix = crate::instructions::terminate_whitelist(
+				&whitelist,
+				&payer.pubkey(),
+				&vault,
+				&mint,
+				&payer.pubkey(),
+				&recipient_token_account,
+				&token_program_id,
+			)
+			.unwrap();
+			ix.accounts.push(AccountMeta::new_readonly(treasury, false));
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer], recent_blockhash);
+			transaction
+		};
+		assert_whitelist_error(
+			&mut context.banks_client,
+			terminate(),
+			WhitelistError::TreasuryNotEmpty,
+		)
+		.await;
 
-		let user_keypair = Keypair::new();
-		let (user_ticket, _) = get_user_ticket_address(&user_keypair.pubkey(), &whitelist);
-		let ix = crate::instructions::add_user(
-			&whitelist,
-			&payer.pubkey(),
-			&mint.pubkey(),
-			&user_keypair.pubkey(),
-			&user_ticket,
+		let recipient = Pubkey::new_unique();
+		let withdraw = |authority: &Keypair, amount| {
+			let ix = crate::instructions::withdraw_treasury(
+				&whitelist,
+				&authority.pubkey(),
+				&treasury,
+				&recipient,
+				amount,
+			)
+			.unwrap();
+			let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+			transaction.sign(&[&payer, authority], recent_blockhash);
+			transaction
+		};
+
+		let stranger = Keypair::new();
+		assert_whitelist_error(
+			&mut context.banks_client,
+			withdraw(&stranger, 5_000_000),
+			WhitelistError::Unauthorised,
 		)
-		.unwrap();
+		.await;
 
-		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
-		transaction.sign(&[payer], recent_blockhash);
-		banks_client.process_transaction(transaction).await.unwrap();
+		// The treasury keeps its rent
+		assert_whitelist_error(
+			&mut context.banks_client,
+			withdraw(&payer, 5_000_001),
+			WhitelistError::InsufficientFunds,
+		)
+		.await;
+
+		context
+			.banks_client
+			.process_transaction(withdraw(&payer, 5_000_000))
+			.await
+			.unwrap();
+		assert_eq!(
+			context.banks_client.get_balance(recipient).await.unwrap(),
+			5_000_000
+		);
+		assert_eq!(
+			context.banks_client.get_balance(treasury).await.unwrap(),
+			treasury_rent
+		);
+
+		context
+			.banks_client
+			.process_transaction(terminate())
+			.await
+			.unwrap();
+		assert!(context
+			.banks_client
+			.get_account(whitelist)
+			.await
+			.unwrap()
+			.is_none());
 	}
 
 	#[test_case(spl_token::id() ; "Token Program")]
 	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
 	#[tokio::test]
-	async fn test_remove_user(token_program_id: Pubkey) {
-		let user_keypair = Keypair::new();
+	async fn test_buy_with_another_users_ticket(token_program_id: Pubkey) {
 		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
-		let (whitelist, _vault, mint, _treasury) = create_default_whitelist(
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
 			&mut banks_client,
 			&payer,
 			&recent_blockhash,
 			&token_program_id,
 		)
 		.await;
-
-		let (user_ticket, _) = get_user_ticket_address(&user_keypair.pubkey(), &whitelist);
-
-		let add_ix = crate::instructions::add_user(
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
 			&whitelist,
-			&payer.pubkey(),
+			&vault,
 			&mint.pubkey(),
-			&user_keypair.pubkey(),
-			&user_ticket,
+			50,
+			&token_program_id,
 		)
-		.unwrap();
-
-		let mut transaction = Transaction::new_with_payer(&[add_ix], Some(&payer.pubkey()));
-		transaction.sign(&[&payer], recent_blockhash);
-		banks_client.process_transaction(transaction).await.unwrap();
-
-		let remove_ix = crate::instructions::remove_user(
+		.await;
+		let ticket = register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
+
+		// An unregistered user presenting the payer's ticket as their own
+		let user = Keypair::new();
+		let ticket_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&ticket,
+				&mint.pubkey(),
+				&token_program_id,
+			);
+		let user_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&user.pubkey(),
+				&mint.pubkey(),
+				&token_program_id,
+			);
+		let ix = crate::instructions::buy_tokens(
 			&whitelist,
-			&payer.pubkey(),
+			&vault,
 			&mint.pubkey(),
-			&user_keypair.pubkey(),
-			&user_ticket,
+			&user.pubkey(),
+			&ticket,
+			&ticket_token_account,
+			&user_token_account,
+			1,
+			None,
+			false,
+			&token_program_id,
 		)
 		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer, &user], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::IncorrectUserAccount,
+		)
+		.await;
 
-		let mut transaction = Transaction::new_with_payer(&[remove_ix], Some(&payer.pubkey()));
-		transaction.sign(&[payer], recent_blockhash);
-		banks_client.process_transaction(transaction).await.unwrap();
+		let ticket_data = banks_client
+			.get_account_data_with_borsh::<Ticket>(ticket)
+			.await
+			.unwrap();
+		assert_eq!(ticket_data.amount_bought, 0);
 	}
 
 	#[test_case(spl_token::id() ; "Token Program")]
 	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
 	#[tokio::test]
-	async fn test_amend_whitelist_size(token_program_id: Pubkey) {
+	async fn test_cleanup_token_account(token_program_id: Pubkey) {
 		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
-		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
 			&mut banks_client,
 			&payer,
 			&recent_blockhash,
 			&token_program_id,
 		)
 		.await;
-
-		let ix =
-			crate::instructions::amend_whitelist_size(&whitelist, &payer.pubkey(), 42).unwrap();
-
-		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
-		transaction.sign(&[payer], recent_blockhash);
-		banks_client.process_transaction(transaction).await.unwrap();
-	}
-
-	#[test_case(spl_token::id() ; "Token Program")]
-	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
-	#[tokio::test]
-	async fn test_amend_times(token_program_id: Pubkey) {
-		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
-		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+		fund_vault(
 			&mut banks_client,
 			&payer,
 			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			50,
 			&token_program_id,
 		)
 		.await;
 
-		let ix = crate::instructions::amend_times(
+		let user = Keypair::new();
+		let ix = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 1_000_000_000);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+		register_user(&mut banks_client, &user, &recent_blockhash, &whitelist).await;
+
+		// The purchase creates the user's token account
+		let ix = buy_instruction(
 			&whitelist,
-			&payer.pubkey(),
-			None,
-			Some(259200000),
+			&vault,
+			&mint.pubkey(),
+			&user.pubkey(),
+			1,
 			None,
-			Some(604800000),
-		)
-		.unwrap();
-
+			&token_program_id,
+		);
 		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
-		transaction.sign(&[payer], recent_blockhash);
+		transaction.sign(&[&payer, &user], recent_blockhash);
 		banks_client.process_transaction(transaction).await.unwrap();
-	}
 
-	#[test_case(spl_token::id() ; "Token Program")]
-	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
-	#[tokio::test]
-	async fn test_allow_registration_false(token_program_id: Pubkey) {
-		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
-		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
-			&mut banks_client,
-			&payer,
-			&recent_blockhash,
+		let user_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&user.pubkey(),
+				&mint.pubkey(),
+				&token_program_id,
+			);
+		let cleanup_ix = crate::instructions::cleanup_token_account(
+			&whitelist,
+			&user.pubkey(),
+			&mint.pubkey(),
+			&user_token_account,
 			&token_program_id,
 		)
+		.unwrap();
+		let mut transaction =
+			Transaction::new_with_payer(&[cleanup_ix.clone()], Some(&payer.pubkey()));
+		transaction.sign(&[&payer, &user], recent_blockhash);
+		assert_whitelist_error(
+			&mut banks_client,
+			transaction,
+			WhitelistError::TokenAccountNotEmpty,
+		)
 		.await;
 
-		let ix_false =
-			crate::instructions::allow_registration(&whitelist, &payer.pubkey(), false).unwrap();
+		// Move the purchased tokens on, leaving the account empty
+		let payer_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&payer.pubkey(),
+				&mint.pubkey(),
+				&token_program_id,
+			);
+		let ix = spl_token_2022::instruction::transfer_checked(
+			&token_program_id,
+			&user_token_account,
+			&mint.pubkey(),
+			&payer_token_account,
+			&user.pubkey(),
+			&[],
+			10u64.pow(9),
+			9,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer, &user], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
 
-		let mut transaction = Transaction::new_with_payer(&[ix_false], Some(&payer.pubkey()));
-		transaction.sign(&[payer], recent_blockhash);
+		let token_account_rent = banks_client
+			.get_account(user_token_account)
+			.await
+			.unwrap()
+			.unwrap()
+			.lamports;
+		let user_lamports = banks_client.get_balance(user.pubkey()).await.unwrap();
+
+		let recent_blockhash = banks_client
+			.get_new_latest_blockhash(&recent_blockhash)
+			.await
+			.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[cleanup_ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer, &user], recent_blockhash);
 		banks_client.process_transaction(transaction).await.unwrap();
 
-		let whitelist_account = banks_client
-			.get_account(whitelist)
+		assert!(banks_client
+			.get_account(user_token_account)
 			.await
-			.expect("get_account")
-			.expect("whitelist account not none");
-		let wl_data = Whitelist::try_from_slice(&whitelist_account.data[..]).unwrap();
-		assert_eq!(wl_data.allow_registration, false);
+			.unwrap()
+			.is_none());
+		assert_eq!(
+			banks_client.get_balance(user.pubkey()).await.unwrap(),
+			user_lamports + token_account_rent
+		);
 	}
 
 	#[test_case(spl_token::id() ; "Token Program")]
 	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
 	#[tokio::test]
-	async fn test_allow_registration_true(token_program_id: Pubkey) {
+	async fn test_buy_zero_amount(token_program_id: Pubkey) {
 		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
-		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
 			&mut banks_client,
 			&payer,
 			&recent_blockhash,
 			&token_program_id,
 		)
 		.await;
+		fund_vault(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			50,
+			&token_program_id,
+		)
+		.await;
+		register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
 
-		let ix_true =
-			crate::instructions::allow_registration(&whitelist, &payer.pubkey(), true).unwrap();
-
-		let mut transaction = Transaction::new_with_payer(&[ix_true], Some(&payer.pubkey()));
-		transaction.sign(&[payer], recent_blockhash);
-		banks_client.process_transaction(transaction).await.unwrap();
+		let ix = buy_instruction(
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			0,
+			None,
+			&token_program_id,
+		);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(&mut banks_client, transaction, WhitelistError::ZeroAmount).await;
 
-		let whitelist_account = banks_client
-			.get_account(whitelist)
+		// The rejected buy must not have opened the buyer's token account
+		let user_token_account =
+			spl_associated_token_account::get_associated_token_address_with_program_id(
+				&payer.pubkey(),
+				&mint.pubkey(),
+				&token_program_id,
+			);
+		assert!(banks_client
+			.get_account(user_token_account)
 			.await
-			.expect("get_account")
-			.expect("whitelist account not none");
-		let wl_data = Whitelist::try_from_slice(&whitelist_account.data[..]).unwrap();
-		assert_eq!(wl_data.allow_registration, true);
+			.unwrap()
+			.is_none());
 	}
 
 	#[test_case(spl_token::id() ; "Token Program")]
 	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
 	#[tokio::test]
-	async fn test_register(token_program_id: Pubkey) {
+	async fn test_pause_sale(token_program_id: Pubkey) {
 		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
-		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
+			&mut banks_client,
+			&payer,
+			&recent_blockhash,
+			&token_program_id,
+		)
+		.await;
+		fund_vault(
 			&mut banks_client,
 			&payer,
 			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			50,
 			&token_program_id,
 		)
 		.await;
 
-		let (ticket, _) = get_user_ticket_address(&payer.pubkey(), &whitelist);
+		let ix =
+			crate::instructions::set_paused(&whitelist, &payer.pubkey(), PauseTarget::Sale, true)
+				.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
 
-		let ix = crate::instructions::register(&whitelist, &payer.pubkey(), &ticket).unwrap();
+		// Registration stays open while buying is paused
+		register_user(&mut banks_client, &payer, &recent_blockhash, &whitelist).await;
 
+		let ix = buy_instruction(
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			1,
+			None,
+			&token_program_id,
+		);
 		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
-		transaction.sign(&[payer], recent_blockhash);
+		transaction.sign(&[&payer], recent_blockhash);
+		assert_whitelist_error(&mut banks_client, transaction, WhitelistError::SalePaused).await;
+
+		let ix =
+			crate::instructions::set_paused(&whitelist, &payer.pubkey(), PauseTarget::Sale, false)
+				.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
 		banks_client.process_transaction(transaction).await.unwrap();
 
-		let ticket_account = banks_client
-			.get_account(ticket)
+		let recent_blockhash = banks_client
+			.get_new_latest_blockhash(&recent_blockhash)
 			.await
-			.expect("get_account")
-			.expect("associated_account not none");
-
-		let rent = banks_client.get_rent().await.unwrap();
-		assert_eq!(ticket_account.data.len(), Ticket::LEN);
-		assert_eq!(ticket_account.owner, crate::id());
-		assert_eq!(ticket_account.lamports, rent.minimum_balance(Ticket::LEN));
+			.unwrap();
+		let ix = buy_instruction(
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			1,
+			None,
+			&token_program_id,
+		);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
 	}
 
 	#[test_case(spl_token::id() ; "Token Program")]
 	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
 	#[tokio::test]
-	async fn test_unregister(token_program_id: Pubkey) {
+	async fn test_pause_registration(token_program_id: Pubkey) {
 		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
-		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+		let (whitelist, vault, mint, _treasury) = create_default_whitelist(
 			&mut banks_client,
 			&payer,
 			&recent_blockhash,
 			&token_program_id,
 		)
 		.await;
-
-		let (ticket, _) = get_user_ticket_address(&payer.pubkey(), &whitelist);
-
-		let ix_true = crate::instructions::register(&whitelist, &payer.pubkey(), &ticket).unwrap();
-
-		let mut transaction = Transaction::new_with_payer(&[ix_true], Some(&payer.pubkey()));
-		transaction.sign(&[payer], recent_blockhash);
-		banks_client.process_transaction(transaction).await.unwrap();
-	}
-
-	#[test_case(spl_token::id() ; "Token Program")]
-	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
-	#[tokio::test]
-	async fn test_start_registration(token_program_id: Pubkey) {
-		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
-		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+		fund_vault(
 			&mut banks_client,
 			&payer,
 			&recent_blockhash,
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			50,
 			&token_program_id,
 		)
 		.await;
 
-		let ix = crate::instructions::start_registration(&whitelist, &payer.pubkey()).unwrap();
+		let ix = crate::instructions::set_paused(
+			&whitelist,
+			&payer.pubkey(),
+			PauseTarget::Registration,
+			true,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
 
+		let user = Keypair::new();
+		let ix = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 1_000_000_000);
 		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
-		transaction.sign(&[payer], recent_blockhash);
+		transaction.sign(&[&payer], recent_blockhash);
 		banks_client.process_transaction(transaction).await.unwrap();
-	}
 
-	#[test_case(spl_token::id() ; "Token Program")]
-	#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
-	#[tokio::test]
-	async fn test_start_token_sale(token_program_id: Pubkey) {
-		let (mut banks_client, payer, recent_blockhash) = setup_test_environment().await;
-		let (whitelist, _vault, _mint, _treasury) = create_default_whitelist(
+		let (user_ticket, _) = get_user_ticket_address(&user.pubkey(), &whitelist);
+		let ix = crate::instructions::register(
+			&whitelist,
+			&user.pubkey(),
+			&user_ticket,
+			&get_allocation_address(&user.pubkey(), &whitelist).0,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&user.pubkey()));
+		transaction.sign(&[&user], recent_blockhash);
+		assert_whitelist_error(
 			&mut banks_client,
-			&payer,
-			&recent_blockhash,
-			&token_program_id,
+			transaction,
+			WhitelistError::RegistrationPaused,
 		)
 		.await;
 
-		let ix = crate::instructions::start_registration(&whitelist, &payer.pubkey()).unwrap();
+		// Existing ticket holders can still buy while registration is paused
+		let (payer_ticket, _) = get_user_ticket_address(&payer.pubkey(), &whitelist);
+		let ix = crate::instructions::add_user(
+			&whitelist,
+			&payer.pubkey(),
+			&mint.pubkey(),
+			&payer.pubkey(),
+			&payer_ticket,
+		)
+		.unwrap();
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
+
+		let ix = buy_instruction(
+			&whitelist,
+			&vault,
+			&mint.pubkey(),
+			&payer.pubkey(),
+			1,
+			None,
+			&token_program_id,
+		);
+		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+		transaction.sign(&[&payer], recent_blockhash);
+		banks_client.process_transaction(transaction).await.unwrap();
 
+		let ix = crate::instructions::set_paused(
+			&whitelist,
+			&payer.pubkey(),
+			PauseTarget::Registration,
+			false,
+		)
+		.unwrap();
 		let mut transaction = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
-		transaction.sign(&[payer], recent_blockhash);
+		transaction.sign(&[&payer], recent_blockhash);
 		banks_client.process_transaction(transaction).await.unwrap();
+
+		let recent_blockhash = banks_client
+			.get_new_latest_blockhash(&recent_blockhash)
+			.await
+			.unwrap();
+		register_user(&mut banks_client, &user, &recent_blockhash, &whitelist).await;
 	}
 }
@@ -1,20 +1,31 @@
 use {
-	crate::error::WhitelistError,
+	crate::{error::WhitelistError, get_user_ticket_address},
 	borsh::{BorshDeserialize, BorshSchema, BorshSerialize},
 	solana_program::{
 		entrypoint::ProgramResult,
+		msg,
+		program_error::ProgramError,
 		pubkey::Pubkey,
 		sysvar::{clock::Clock, Sysvar},
 	},
+	std::mem::size_of,
 };
 
 #[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Whitelist {
+	pub version: u8,
 	pub bump: u8,
+	#[cfg_attr(feature = "serde", serde(with = "pubkey_string"))]
 	pub authority: Pubkey,
+	#[cfg_attr(feature = "serde", serde(with = "pubkey_string"))]
 	pub vault: Pubkey,
+	#[cfg_attr(feature = "serde", serde(with = "pubkey_string"))]
 	pub treasury: Pubkey,
+	#[cfg_attr(feature = "serde", serde(with = "pubkey_string"))]
 	pub mint: Pubkey,
+	/// Price of one whole token in lamports, not of a base unit. Purchases are charged
+	/// `buy_cost`, which scales it by the mint's decimals
 	pub token_price: u64,
 	pub buy_limit: u64,
 	pub deposited: u64,
@@ -24,23 +35,76 @@ pub struct Whitelist {
 	pub registration_duration: i64,
 	pub sale_timestamp: i64,
 	pub sale_duration: i64,
+	/// Number of tickets currently open on the whitelist
+	pub registered: u64,
+	/// Rejects `Register` while set, buying is unaffected
+	pub registration_paused: bool,
+	/// Rejects `Buy` while set, registration is unaffected
+	pub sale_paused: bool,
+	/// Extra allowance, in base units, granted to a referrer's ticket for each user who
+	/// registers with `RegisterWithReferral` naming them, 0 disables the bonus
+	pub referral_bonus: u64,
+	/// Ceiling on tickets open at once that applies even when `whitelist_size` is 0, so an
+	/// unlimited whitelist cannot be flooded with ticket accounts, 0 disables the ceiling
+	pub max_tickets: u64,
+	/// Seconds a ticket must wait after a purchase before buying again, slowing bots that
+	/// split a purchase to snipe the sale open, 0 disables the cooldown
+	pub cooldown_secs: u64,
+	/// Seconds after a ticket's latest purchase during which its owner may `Refund` tokens
+	/// bought, after which sales are final, 0 disables refunds
+	pub refund_window_secs: u64,
+	/// Makes `Register` charge the ticket's rent to the authority, who must co-sign, instead of
+	/// the registering user
+	pub authority_pays_rent: bool,
+	/// Key whose signed voucher a user must present with `RegisterWithSignature` to register,
+	/// the default pubkey when registration is not gated. Stored as a plain pubkey to keep the
+	/// whitelist a fixed size
+	#[cfg_attr(feature = "serde", serde(with = "pubkey_string"))]
+	pub register_authority: Pubkey,
 }
 
 impl Whitelist {
-	pub const LEN: usize = 194;
+	pub const LEN: usize = Self::space();
+	pub const VERSION: u8 = 1;
+
+	/// Size of the serialized account, summed field by field in declaration order so it must be
+	/// extended alongside the struct
+	pub const fn space() -> usize {
+		size_of::<u8>() // version
+			+ size_of::<u8>() // bump
+			+ size_of::<Pubkey>() * 4 // authority, vault, treasury, mint
+			+ size_of::<u64>() * 4 // token_price, buy_limit, deposited, whitelist_size
+			+ size_of::<bool>() // allow_registration
+			+ size_of::<i64>() * 4 // registration and sale timestamps and durations
+			+ size_of::<u64>() // registered
+			+ size_of::<bool>() * 2 // registration_paused, sale_paused
+			+ size_of::<u64>() * 4 // referral_bonus, max_tickets, cooldown_secs, refund_window_secs
+			+ size_of::<bool>() // authority_pays_rent
+			+ size_of::<Pubkey>() // register_authority
+	}
+
+	/// Deserializes a whitelist account, rejecting any layout version this program does not
+	/// understand
+	pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+		match data.first() {
+			Some(&Self::VERSION) => Ok(Self::try_from_slice(data)?),
+			_ => Err(WhitelistError::UnsupportedVersion.into()),
+		}
+	}
 
 	pub fn check_times(&self) -> ProgramResult {
 		let clock = Clock::get()?;
-		// Perform safety checks if a `registration_start_timestamp` is not `None`
+		// Perform safety checks if a `registration_timestamp` is set, `0` means unset. Start times
+		// in the past are accepted by `test-mode` builds
 		if self.registration_timestamp != 0 {
-			if self.registration_timestamp < clock.unix_timestamp {
+			if !crate::TEST_MODE && self.registration_timestamp < clock.unix_timestamp {
 				return Err(WhitelistError::InvalidRegistrationStartTime.into());
 			}
 		}
 
-		// Perform safety checks if a `sale_start_timestamp` is not `None`
+		// Perform safety checks if a `sale_timestamp` is set, `0` means unset
 		if self.sale_timestamp != 0 {
-			if self.sale_timestamp < clock.unix_timestamp {
+			if !crate::TEST_MODE && self.sale_timestamp < clock.unix_timestamp {
 				return Err(WhitelistError::InvalidSaleStartTime.into());
 			}
 			if self.registration_timestamp != 0 && self.registration_timestamp > self.sale_timestamp {
@@ -51,9 +115,86 @@ impl Whitelist {
 		Ok(())
 	}
 
+	/// Whether registration is still scheduled to be open once the sale starts. Registration only
+	/// has an end when both its start and duration are set
+	pub fn registration_overlaps_sale(&self) -> bool {
+		self.registration_timestamp > 0
+			&& self.registration_duration > 0
+			&& self.sale_timestamp > 0
+			&& self
+				.registration_timestamp
+				.saturating_add(self.registration_duration)
+				> self.sale_timestamp
+	}
+
+	/// Counts a newly created ticket, failing if the whitelist is already at capacity. A
+	/// `whitelist_size` of 0 is unbounded, but `max_tickets` still applies
+	pub fn register_ticket(&mut self) -> ProgramResult {
+		if self.whitelist_size > 0 && self.registered >= self.whitelist_size {
+			return Err(WhitelistError::WhitelistFull.into());
+		}
+		if self.max_tickets > 0 && self.registered >= self.max_tickets {
+			return Err(WhitelistError::TicketLimitReached.into());
+		}
+		self.registered = self
+			.registered
+			.checked_add(1)
+			.ok_or(WhitelistError::Overflow)?;
+		Ok(())
+	}
+
+	/// Releases the space held by a closed ticket
+	pub fn unregister_ticket(&mut self) {
+		self.registered = self.registered.saturating_sub(1);
+	}
+
+	/// Whether the sale window closed at or before `unix_timestamp`, a `sale_duration` of 0
+	/// leaves the sale open until the vault is emptied
+	pub fn sale_ended(&self, unix_timestamp: i64) -> bool {
+		self.sale_timestamp > 0
+			&& self.sale_duration > 0
+			&& self.sale_timestamp + self.sale_duration <= unix_timestamp
+	}
+
+	/// When a ticket that last bought at `last_purchase_ts` may buy again, `None` if it already
+	/// may at `unix_timestamp`. A ticket that has never bought is never cooling down
+	pub fn cooldown_until(&self, last_purchase_ts: i64, unix_timestamp: i64) -> Option<i64> {
+		if self.cooldown_secs == 0 || last_purchase_ts == 0 {
+			return None;
+		}
+		let cooldown = i64::try_from(self.cooldown_secs).unwrap_or(i64::MAX);
+		let until = last_purchase_ts.saturating_add(cooldown);
+		(unix_timestamp < until).then_some(until)
+	}
+
+	/// Whether a ticket that last bought at `last_purchase_ts` may still be refunded at
+	/// `unix_timestamp`. A ticket that has never bought has nothing to refund
+	pub fn refund_open(&self, last_purchase_ts: i64, unix_timestamp: i64) -> bool {
+		if self.refund_window_secs == 0 || last_purchase_ts == 0 {
+			return false;
+		}
+		let window = i64::try_from(self.refund_window_secs).unwrap_or(i64::MAX);
+		unix_timestamp < last_purchase_ts.saturating_add(window)
+	}
+
+	/// Returns the key registration vouchers must be signed by, if registration is gated
+	pub fn register_authority(&self) -> Option<Pubkey> {
+		(self.register_authority != Pubkey::default()).then_some(self.register_authority)
+	}
+
+	/// Whether `unix_timestamp` falls outside the sale window, before the sale starts or once it
+	/// has ended. A whitelist without a sale start is on sale from initialisation, as `Buy` treats
+	/// it, and a sale without a duration only ends when the vault is emptied, never by time
+	pub fn sale_window_closed(&self, unix_timestamp: i64) -> bool {
+		(self.sale_timestamp > 0 && unix_timestamp < self.sale_timestamp)
+			|| self.sale_ended(unix_timestamp)
+	}
+
+	/// Fails with `SaleOngoing` while the sale window is open, guarding the vault against
+	/// withdrawals mid-sale
 	pub fn check_sale_time(&self) -> ProgramResult {
 		let clock = Clock::get()?;
-		if self.sale_timestamp != 0 && self.sale_timestamp >= clock.unix_timestamp {
+		if self.sale_window_closed(clock.unix_timestamp) {
 			Ok(())
 		} else {
 			Err(WhitelistError::SaleOngoing.into())
@@ -61,16 +202,311 @@ impl Whitelist {
 	}
 }
 
+/// Layout of a `Whitelist` account created before the `version` field was introduced, only
+/// used to read accounts that are being migrated with `MigrateWhitelist`
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, PartialEq)]
+pub struct WhitelistV0 {
+	pub bump: u8,
+	pub authority: Pubkey,
+	pub vault: Pubkey,
+	pub treasury: Pubkey,
+	pub mint: Pubkey,
+	pub token_price: u64,
+	pub buy_limit: u64,
+	pub deposited: u64,
+	pub whitelist_size: u64,
+	pub allow_registration: bool,
+	pub registration_timestamp: i64,
+	pub registration_duration: i64,
+	pub sale_timestamp: i64,
+	pub sale_duration: i64,
+}
+
+impl WhitelistV0 {
+	pub const LEN: usize = 194;
+}
+
+impl From<WhitelistV0> for Whitelist {
+	fn from(legacy: WhitelistV0) -> Self {
+		Whitelist {
+			version: Whitelist::VERSION,
+			bump: legacy.bump,
+			authority: legacy.authority,
+			vault: legacy.vault,
+			treasury: legacy.treasury,
+			mint: legacy.mint,
+			token_price: legacy.token_price,
+			buy_limit: legacy.buy_limit,
+			deposited: legacy.deposited,
+			whitelist_size: legacy.whitelist_size,
+			allow_registration: legacy.allow_registration,
+			registration_timestamp: legacy.registration_timestamp,
+			registration_duration: legacy.registration_duration,
+			sale_timestamp: legacy.sale_timestamp,
+			sale_duration: legacy.sale_duration,
+			// Legacy whitelists did not count tickets, tickets opened before the migration are
+			// not counted towards the capacity
+			registered: 0,
+			registration_paused: false,
+			sale_paused: false,
+			referral_bonus: 0,
+			max_tickets: 0,
+			cooldown_secs: 0,
+			refund_window_secs: 0,
+			authority_pays_rent: false,
+			register_authority: Pubkey::default(),
+		}
+	}
+}
+
 #[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Ticket {
+	pub version: u8,
 	pub bump: u8,
+	#[cfg_attr(feature = "serde", serde(with = "pubkey_string"))]
 	pub whitelist: Pubkey,
+	#[cfg_attr(feature = "serde", serde(with = "pubkey_string"))]
 	pub owner: Pubkey,
+	#[cfg_attr(feature = "serde", serde(with = "pubkey_string"))]
 	pub payer: Pubkey,
 	pub allowance: u64,
 	pub amount_bought: u64,
+	/// Amount of `amount_bought` that has been released to the owner
+	pub claimed: u64,
+	/// Free-form UTF-8 note set by the authority for bookkeeping, zero padded
+	pub note: [u8; Ticket::NOTE_LEN],
+	/// User who referred the owner, the default pubkey when the owner registered without a
+	/// referral. Stored as a plain pubkey to keep the ticket a fixed size
+	#[cfg_attr(feature = "serde", serde(with = "pubkey_string"))]
+	pub referrer: Pubkey,
+	/// Unix timestamp of the ticket's latest purchase, 0 before the first
+	pub last_purchase_ts: i64,
 }
 
 impl Ticket {
+	pub const LEN: usize = Self::space();
+	pub const VERSION: u8 = 1;
+	pub const NOTE_LEN: usize = 32;
+
+	/// Size of the serialized account, summed field by field in declaration order so it must be
+	/// extended alongside the struct
+	pub const fn space() -> usize {
+		size_of::<u8>() // version
+			+ size_of::<u8>() // bump
+			+ size_of::<Pubkey>() * 3 // whitelist, owner, payer
+			+ size_of::<u64>() * 3 // allowance, amount_bought, claimed
+			+ Self::NOTE_LEN // note
+			+ size_of::<Pubkey>() // referrer
+			+ size_of::<i64>() // last_purchase_ts
+	}
+
+	/// Encodes a note into the fixed-size, zero padded representation stored on the ticket
+	pub fn encode_note(note: &str) -> Result<[u8; Self::NOTE_LEN], ProgramError> {
+		let bytes = note.as_bytes();
+		if bytes.len() > Self::NOTE_LEN {
+			return Err(WhitelistError::NoteTooLong.into());
+		}
+		let mut encoded = [0u8; Self::NOTE_LEN];
+		encoded[..bytes.len()].copy_from_slice(bytes);
+		Ok(encoded)
+	}
+
+	/// Amount bought that has not yet been released to the owner
+	pub fn claimable(&self) -> u64 {
+		self.amount_bought.saturating_sub(self.claimed)
+	}
+
+	/// Records `amount` as released to the owner, `claimed` may never exceed `amount_bought`
+	pub fn record_claim(&mut self, amount: u64) -> ProgramResult {
+		if amount > self.claimable() {
+			return Err(WhitelistError::ClaimExceedsPurchase.into());
+		}
+		self.claimed += amount;
+		Ok(())
+	}
+
+	/// Returns `amount` of the tokens released to the owner, freeing the allowance they used
+	pub fn record_refund(&mut self, amount: u64) -> ProgramResult {
+		if amount > self.claimed {
+			return Err(WhitelistError::RefundExceedsPurchase.into());
+		}
+		self.claimed -= amount;
+		self.amount_bought -= amount;
+		Ok(())
+	}
+
+	/// Returns the referrer recorded at registration, if any
+	pub fn referrer(&self) -> Option<Pubkey> {
+		(self.referrer != Pubkey::default()).then_some(self.referrer)
+	}
+
+	/// Returns the note as a trimmed string, invalid UTF-8 is replaced rather than rejected as
+	/// the note is purely informational
+	pub fn note_string(&self) -> String {
+		String::from_utf8_lossy(&self.note)
+			.trim_end_matches('\0')
+			.trim()
+			.to_string()
+	}
+
+	/// Deserializes a ticket account, rejecting any layout version this program does not
+	/// understand
+	pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+		match data.first() {
+			Some(&Self::VERSION) => Ok(Self::try_from_slice(data)?),
+			_ => Err(WhitelistError::UnsupportedVersion.into()),
+		}
+	}
+
+	/// Checks that `account_key` is the ticket PDA of `user` on `whitelist` and that
+	/// `stored_bump`, the bump recorded on the ticket, is the bump it was derived with
+	pub fn derive_and_verify(
+		user: &Pubkey,
+		whitelist: &Pubkey,
+		account_key: &Pubkey,
+		stored_bump: u8,
+	) -> ProgramResult {
+		let (ticket, bump) = get_user_ticket_address(user, whitelist);
+		if account_key != &ticket || stored_bump != bump {
+			msg!(
+				"Ticket {} (bump {}) does not match derived ticket {} (bump {})",
+				account_key,
+				stored_bump,
+				ticket,
+				bump
+			);
+			return Err(WhitelistError::IncorrectUserAccount.into());
+		}
+		Ok(())
+	}
+}
+
+/// Layout of a `Ticket` account created before the `version` field was introduced, only used to
+/// read accounts that are being migrated with `MigrateTicket`
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, PartialEq)]
+pub struct TicketV0 {
+	pub bump: u8,
+	pub whitelist: Pubkey,
+	pub owner: Pubkey,
+	pub payer: Pubkey,
+	pub allowance: u64,
+	pub amount_bought: u64,
+}
+
+impl TicketV0 {
 	pub const LEN: usize = 113;
 }
+
+impl From<TicketV0> for Ticket {
+	fn from(legacy: TicketV0) -> Self {
+		Ticket {
+			version: Ticket::VERSION,
+			bump: legacy.bump,
+			whitelist: legacy.whitelist,
+			owner: legacy.owner,
+			payer: legacy.payer,
+			allowance: legacy.allowance,
+			amount_bought: legacy.amount_bought,
+			// Legacy purchases were delivered as they were bought
+			claimed: legacy.amount_bought,
+			note: [0; Ticket::NOTE_LEN],
+			referrer: Pubkey::default(),
+			last_purchase_ts: 0,
+		}
+	}
+}
+
+/// Net tokens a third party has deposited into a whitelist's vault with `DepositFor`, the
+/// depositor may reclaim this amount with `WithdrawDeposit` until the sale starts
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Depositor {
+	pub version: u8,
+	pub bump: u8,
+	#[cfg_attr(feature = "serde", serde(with = "pubkey_string"))]
+	pub whitelist: Pubkey,
+	#[cfg_attr(feature = "serde", serde(with = "pubkey_string"))]
+	pub owner: Pubkey,
+	pub amount: u64,
+}
+
+impl Depositor {
+	pub const LEN: usize = Self::space();
+	pub const VERSION: u8 = 1;
+
+	/// Size of the serialized account, see [`Whitelist::space`]
+	pub const fn space() -> usize {
+		size_of::<u8>() // version
+			+ size_of::<u8>() // bump
+			+ size_of::<Pubkey>() * 2 // whitelist, owner
+			+ size_of::<u64>() // amount
+	}
+
+	/// Deserializes a depositor account, rejecting any layout version this program does not
+	/// understand
+	pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+		match data.first() {
+			Some(&Self::VERSION) => Ok(Self::try_from_slice(data)?),
+			_ => Err(WhitelistError::UnsupportedVersion.into()),
+		}
+	}
+}
+
+/// Allowance the authority reserves for a user ahead of registration with `SetAllocation`. A
+/// ticket opened with `Register` takes this allowance instead of the whitelist's `buy_limit` when
+/// the allocation is passed along
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Allocation {
+	pub version: u8,
+	pub bump: u8,
+	#[cfg_attr(feature = "serde", serde(with = "pubkey_string"))]
+	pub whitelist: Pubkey,
+	#[cfg_attr(feature = "serde", serde(with = "pubkey_string"))]
+	pub user: Pubkey,
+	/// Allowance in base units
+	pub allowance: u64,
+}
+
+impl Allocation {
+	pub const LEN: usize = Self::space();
+	pub const VERSION: u8 = 1;
+
+	/// Size of the serialized account, see [`Whitelist::space`]
+	pub const fn space() -> usize {
+		size_of::<u8>() // version
+			+ size_of::<u8>() // bump
+			+ size_of::<Pubkey>() * 2 // whitelist, user
+			+ size_of::<u64>() // allowance
+	}
+
+	/// Deserializes an allocation account, rejecting any layout version this program does not
+	/// understand
+	pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+		match data.first() {
+			Some(&Self::VERSION) => Ok(Self::try_from_slice(data)?),
+			_ => Err(WhitelistError::UnsupportedVersion.into()),
+		}
+	}
+}
+
+/// Serializes public keys as base58 strings so that JSON consumers see the same representation as
+/// explorers and wallets
+#[cfg(feature = "serde")]
+mod pubkey_string {
+	use {
+		serde::{Deserialize, Deserializer, Serializer},
+		solana_program::pubkey::Pubkey,
+		std::str::FromStr,
+	};
+
+	pub fn serialize<S: Serializer>(pubkey: &Pubkey, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_str(&pubkey.to_string())
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pubkey, D::Error> {
+		let s = String::deserialize(deserializer)?;
+		Pubkey::from_str(&s).map_err(serde::de::Error::custom)
+	}
+}
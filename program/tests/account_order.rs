@@ -0,0 +1,807 @@
+//! Pins the account order produced by the instruction builders to the order the processor
+//! reads. Every instruction is sent twice: first with two of its accounts swapped, which must be
+//! rejected, then exactly as built, which must succeed
+
+use {
+	fsp_wl::{
+		account_order, get_allocation_address, get_depositor_address, get_user_ticket_address,
+		get_whitelist_address,
+		instructions::{self, PauseTarget},
+		processor::Processor,
+	},
+	solana_program_test::*,
+	solana_sdk::{
+		instruction::Instruction,
+		pubkey::Pubkey,
+		signature::{Keypair, Signer},
+		system_instruction,
+		sysvar::clock::Clock,
+		transaction::Transaction,
+	},
+	spl_token_2022::{extension::ExtensionType, state::Mint},
+	test_case::test_case,
+};
+
+const DECIMALS: u8 = 9;
+
+struct Sale {
+	banks_client: BanksClient,
+	payer: Keypair,
+	whitelist: Pubkey,
+	vault: Pubkey,
+	mint: Pubkey,
+	treasury: Pubkey,
+}
+
+fn program_test() -> ProgramTest {
+	let mut program_test = ProgramTest::new("fsp_wl", fsp_wl::id(), processor!(Processor::process));
+
+	program_test.add_program(
+		"spl_token_2022",
+		spl_token_2022::id(),
+		processor!(spl_token_2022::processor::Processor::process),
+	);
+	program_test.add_program(
+		"spl_token",
+		spl_token::id(),
+		processor!(spl_token::processor::Processor::process),
+	);
+
+	program_test
+}
+
+async fn process(
+	banks_client: &mut BanksClient,
+	payer: &Keypair,
+	instructions: &[Instruction],
+) -> Result<(), BanksClientError> {
+	let recent_blockhash = banks_client.get_latest_blockhash().await.unwrap();
+	let mut transaction = Transaction::new_with_payer(instructions, Some(&payer.pubkey()));
+	transaction.sign(&[payer], recent_blockhash);
+	banks_client.process_transaction(transaction).await
+}
+
+/// Sends `ix` with the accounts at `a` and `b` swapped, expecting the processor to reject it,
+/// then sends `ix` as built, expecting it to succeed
+async fn assert_account_order(
+	banks_client: &mut BanksClient,
+	payer: &Keypair,
+	ix: Instruction,
+	a: usize,
+	b: usize,
+) {
+	let mut misordered = ix.clone();
+	misordered.accounts.swap(a, b);
+	assert!(
+		process(banks_client, payer, &[misordered]).await.is_err(),
+		"accounts {} and {} swapped were accepted",
+		a,
+		b
+	);
+
+	process(banks_client, payer, &[ix]).await.unwrap();
+}
+
+async fn create_mint(
+	banks_client: &mut BanksClient,
+	payer: &Keypair,
+	mint_keypair: &Keypair,
+	token_program_id: &Pubkey,
+) {
+	let space = ExtensionType::try_calculate_account_len::<Mint>(&[]).unwrap();
+	let mint_rent = banks_client
+		.get_rent()
+		.await
+		.unwrap()
+		.minimum_balance(space);
+
+	let instructions = [
+		system_instruction::create_account(
+			&payer.pubkey(),
+			&mint_keypair.pubkey(),
+			mint_rent,
+			space as u64,
+			token_program_id,
+		),
+		spl_token_2022::instruction::initialize_mint(
+			token_program_id,
+			&mint_keypair.pubkey(),
+			&payer.pubkey(),
+			None,
+			DECIMALS,
+		)
+		.unwrap(),
+	];
+
+	let recent_blockhash = banks_client.get_latest_blockhash().await.unwrap();
+	let mut transaction = Transaction::new_with_payer(&instructions, Some(&payer.pubkey()));
+	transaction.sign(&[payer, mint_keypair], recent_blockhash);
+	banks_client.process_transaction(transaction).await.unwrap();
+}
+
+/// Creates the associated token account of `owner`, minting `amount` whole tokens into it
+async fn create_token_account(
+	banks_client: &mut BanksClient,
+	payer: &Keypair,
+	owner: &Pubkey,
+	mint: &Pubkey,
+	amount: u64,
+	token_program_id: &Pubkey,
+) -> Pubkey {
+	let token_account = spl_associated_token_account::get_associated_token_address_with_program_id(
+		owner,
+		mint,
+		token_program_id,
+	);
+
+	let mut instructions = vec![
+		spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+			&payer.pubkey(),
+			owner,
+			mint,
+			token_program_id,
+		),
+	];
+	if amount > 0 {
+		instructions.push(
+			spl_token_2022::instruction::mint_to(
+				token_program_id,
+				mint,
+				&token_account,
+				&payer.pubkey(),
+				&[],
+				amount * 10u64.pow(DECIMALS as u32),
+			)
+			.unwrap(),
+		);
+	}
+	process(banks_client, payer, &instructions).await.unwrap();
+
+	token_account
+}
+
+/// Builds `InitialiseWhitelist` for a fresh mint with the sale an hour away, so deposits can
+/// still be withdrawn and the whitelist terminated
+async fn init_instruction(
+	banks_client: &mut BanksClient,
+	payer: &Keypair,
+	token_program_id: &Pubkey,
+) -> (Instruction, Pubkey, Pubkey, Pubkey, Pubkey) {
+	let mint_keypair = Keypair::new();
+	let treasury = Pubkey::new_unique();
+	create_mint(banks_client, payer, &mint_keypair, token_program_id).await;
+
+	let mint = mint_keypair.pubkey();
+	let (whitelist, _) = get_whitelist_address(&mint);
+	let vault = spl_associated_token_account::get_associated_token_address_with_program_id(
+		&whitelist,
+		&mint,
+		token_program_id,
+	);
+	let now = banks_client
+		.get_sysvar::<Clock>()
+		.await
+		.unwrap()
+		.unix_timestamp;
+
+	let ix = instructions::init_whitelist(
+		&whitelist,
+		&payer.pubkey(),
+		&vault,
+		&mint,
+		&treasury,
+		1,
+		10,
+		5,
+		true,
+		0,
+		0,
+		now + 3600,
+		3600,
+		0,
+		0,
+		false,
+		false,
+		false,
+		token_program_id,
+	)
+	.unwrap();
+
+	(ix, whitelist, vault, mint, treasury)
+}
+
+async fn setup(token_program_id: Pubkey) -> Sale {
+	let (mut banks_client, payer, _) = program_test().start().await;
+	let (ix, whitelist, vault, mint, treasury) =
+		init_instruction(&mut banks_client, &payer, &token_program_id).await;
+	process(&mut banks_client, &payer, &[ix]).await.unwrap();
+
+	Sale {
+		banks_client,
+		payer,
+		whitelist,
+		vault,
+		mint,
+		treasury,
+	}
+}
+
+#[test_case(spl_token::id() ; "Token Program")]
+#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+#[tokio::test]
+async fn test_init_whitelist_account_order(token_program_id: Pubkey) {
+	let (mut banks_client, payer, _) = program_test().start().await;
+	let (ix, ..) = init_instruction(&mut banks_client, &payer, &token_program_id).await;
+
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::initialise_whitelist::VAULT,
+		account_order::initialise_whitelist::MINT,
+	)
+	.await;
+}
+
+#[test_case(spl_token::id() ; "Token Program")]
+#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+#[tokio::test]
+async fn test_authority_account_order(token_program_id: Pubkey) {
+	let Sale {
+		mut banks_client,
+		payer,
+		whitelist,
+		mint,
+		..
+	} = setup(token_program_id).await;
+	let authority = payer.pubkey();
+
+	let ix = instructions::amend_whitelist_size(&whitelist, &authority, 10).unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::amend_whitelist_size::WHITELIST,
+		account_order::amend_whitelist_size::AUTHORITY,
+	)
+	.await;
+
+	let ix =
+		instructions::amend_times(&whitelist, &authority, None, None, None, None, false).unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::amend_times::WHITELIST,
+		account_order::amend_times::AUTHORITY,
+	)
+	.await;
+
+	let ix = instructions::allow_registration(&whitelist, &authority, true).unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::allow_register::WHITELIST,
+		account_order::allow_register::AUTHORITY,
+	)
+	.await;
+
+	let ix = instructions::start_registration(&whitelist, &authority).unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::start_registration::WHITELIST,
+		account_order::start_registration::AUTHORITY,
+	)
+	.await;
+
+	let ix = instructions::set_paused(&whitelist, &authority, PauseTarget::Sale, true).unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::set_paused::WHITELIST,
+		account_order::set_paused::AUTHORITY,
+	)
+	.await;
+
+	let ix = instructions::set_referral_bonus(&whitelist, &authority, &mint, 1).unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::set_referral_bonus::WHITELIST,
+		account_order::set_referral_bonus::AUTHORITY,
+	)
+	.await;
+
+	let ix = instructions::set_buy_cooldown(&whitelist, &authority, 30).unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::set_buy_cooldown::WHITELIST,
+		account_order::set_buy_cooldown::AUTHORITY,
+	)
+	.await;
+}
+
+#[test_case(spl_token::id() ; "Token Program")]
+#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+#[tokio::test]
+async fn test_ticket_account_order(token_program_id: Pubkey) {
+	let Sale {
+		mut banks_client,
+		payer,
+		whitelist,
+		vault,
+		mint,
+		treasury,
+	} = setup(token_program_id).await;
+	let authority = payer.pubkey();
+	let user = Pubkey::new_unique();
+	let (user_ticket, _) = get_user_ticket_address(&user, &whitelist);
+
+	let ix = instructions::add_user(&whitelist, &authority, &mint, &user, &user_ticket).unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::add_user::USER,
+		account_order::add_user::USER_TICKET,
+	)
+	.await;
+
+	let ix = instructions::set_ticket_note(&whitelist, &authority, &user_ticket, "vip").unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::set_ticket_note::WHITELIST,
+		account_order::set_ticket_note::USER_TICKET,
+	)
+	.await;
+
+	let ix = instructions::remove_user(&whitelist, &authority, &mint, &user, &user_ticket).unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::remove_user::USER,
+		account_order::remove_user::USER_TICKET,
+	)
+	.await;
+
+	let (payer_ticket, _) = get_user_ticket_address(&authority, &whitelist);
+	let (payer_allocation, _) = get_allocation_address(&authority, &whitelist);
+	let ix =
+		instructions::register(&whitelist, &authority, &payer_ticket, &payer_allocation).unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::register::WHITELIST,
+		account_order::register::USER_TICKET,
+	)
+	.await;
+
+	let referrer = Pubkey::new_unique();
+	let (referrer_ticket, _) = get_user_ticket_address(&referrer, &whitelist);
+	let ix =
+		instructions::add_user(&whitelist, &authority, &mint, &referrer, &referrer_ticket).unwrap();
+	process(&mut banks_client, &payer, &[ix]).await.unwrap();
+	let ix = instructions::register_with_referral(
+		&whitelist,
+		&authority,
+		&payer_ticket,
+		&referrer,
+		&referrer_ticket,
+		&payer_allocation,
+	)
+	.unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::register_with_referral::USER_TICKET,
+		account_order::register_with_referral::REFERRER_TICKET,
+	)
+	.await;
+
+	// Unregistering needs a registration window with a duration and the ticket's token account
+	// to exist
+	let ix = instructions::amend_times(&whitelist, &authority, None, Some(3600), None, None, false)
+		.unwrap();
+	process(&mut banks_client, &payer, &[ix]).await.unwrap();
+	let ticket_token_account = create_token_account(
+		&mut banks_client,
+		&payer,
+		&payer_ticket,
+		&mint,
+		0,
+		&token_program_id,
+	)
+	.await;
+
+	let ix = instructions::unregister(
+		&whitelist,
+		&authority,
+		&vault,
+		&mint,
+		&authority,
+		&payer_ticket,
+		&ticket_token_account,
+		&token_program_id,
+	)
+	.unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::unregister::USER_TICKET,
+		account_order::unregister::TICKET_TOKEN,
+	)
+	.await;
+
+	let referrer_ticket_token_account =
+		spl_associated_token_account::get_associated_token_address_with_program_id(
+			&referrer_ticket,
+			&mint,
+			&token_program_id,
+		);
+	let ix = instructions::force_close_ticket(
+		&whitelist,
+		&authority,
+		&mint,
+		&treasury,
+		&referrer_ticket,
+		&referrer_ticket_token_account,
+		&token_program_id,
+	)
+	.unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::force_close_ticket::TICKET,
+		account_order::force_close_ticket::TICKET_TOKEN,
+	)
+	.await;
+}
+
+#[test_case(spl_token::id() ; "Token Program")]
+#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+#[tokio::test]
+async fn test_vault_account_order(token_program_id: Pubkey) {
+	let Sale {
+		mut banks_client,
+		payer,
+		whitelist,
+		vault,
+		mint,
+		..
+	} = setup(token_program_id).await;
+	let authority = payer.pubkey();
+	let payer_token_account = create_token_account(
+		&mut banks_client,
+		&payer,
+		&authority,
+		&mint,
+		100,
+		&token_program_id,
+	)
+	.await;
+
+	let ix = instructions::deposit_tokens(
+		&whitelist,
+		&vault,
+		&authority,
+		&payer_token_account,
+		&mint,
+		20,
+		&token_program_id,
+	)
+	.unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::deposit_tokens::VAULT,
+		account_order::deposit_tokens::DEPOSITOR_TOKEN,
+	)
+	.await;
+
+	let (ledger, _) = get_depositor_address(&authority, &whitelist);
+	let ix = instructions::deposit_for(
+		&whitelist,
+		&vault,
+		&authority,
+		&payer_token_account,
+		&mint,
+		&ledger,
+		20,
+		&token_program_id,
+	)
+	.unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::deposit_for::DEPOSITOR_LEDGER,
+		account_order::deposit_for::SYSTEM_PROGRAM,
+	)
+	.await;
+
+	let ix = instructions::withdraw_deposit(
+		&whitelist,
+		&vault,
+		&authority,
+		&payer_token_account,
+		&mint,
+		&ledger,
+		5,
+		&token_program_id,
+	)
+	.unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::withdraw_deposit::VAULT,
+		account_order::withdraw_deposit::DEPOSITOR_TOKEN,
+	)
+	.await;
+
+	let user = Pubkey::new_unique();
+	let (user_ticket, _) = get_user_ticket_address(&user, &whitelist);
+	let ticket_token_account =
+		spl_associated_token_account::get_associated_token_address_with_program_id(
+			&user_ticket,
+			&mint,
+			&token_program_id,
+		);
+	let ix = instructions::add_user_and_seed(
+		&whitelist,
+		&authority,
+		&mint,
+		&user,
+		&user_ticket,
+		&vault,
+		&ticket_token_account,
+		&token_program_id,
+	)
+	.unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::add_user_and_seed::VAULT,
+		account_order::add_user_and_seed::TICKET_TOKEN,
+	)
+	.await;
+
+	let ix = instructions::withdraw_tokens(
+		&whitelist,
+		&authority,
+		&vault,
+		&mint,
+		&payer_token_account,
+		5,
+		&token_program_id,
+	)
+	.unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::withdraw_tokens::VAULT,
+		account_order::withdraw_tokens::RECIPIENT_TOKEN,
+	)
+	.await;
+
+	let ix = instructions::terminate_whitelist(
+		&whitelist,
+		&authority,
+		&vault,
+		&mint,
+		&authority,
+		&payer_token_account,
+		&token_program_id,
+	)
+	.unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::terminate_whitelist::RECIPIENT,
+		account_order::terminate_whitelist::RECIPIENT_TOKEN,
+	)
+	.await;
+}
+
+#[test_case(spl_token::id() ; "Token Program")]
+#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+#[tokio::test]
+async fn test_sale_account_order(token_program_id: Pubkey) {
+	let Sale {
+		mut banks_client,
+		payer,
+		whitelist,
+		vault,
+		mint,
+		treasury,
+	} = setup(token_program_id).await;
+	let authority = payer.pubkey();
+	let payer_token_account = create_token_account(
+		&mut banks_client,
+		&payer,
+		&authority,
+		&mint,
+		50,
+		&token_program_id,
+	)
+	.await;
+	let ix = instructions::deposit_tokens(
+		&whitelist,
+		&vault,
+		&authority,
+		&payer_token_account,
+		&mint,
+		50,
+		&token_program_id,
+	)
+	.unwrap();
+	process(&mut banks_client, &payer, &[ix]).await.unwrap();
+
+	let ix = instructions::start_token_sale(&whitelist, &authority, &vault, false).unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::start_token_sale::WHITELIST,
+		account_order::start_token_sale::VAULT,
+	)
+	.await;
+
+	let (ticket, _) = get_user_ticket_address(&authority, &whitelist);
+	let ix = instructions::add_user(&whitelist, &authority, &mint, &authority, &ticket).unwrap();
+	process(&mut banks_client, &payer, &[ix]).await.unwrap();
+	let ticket_token_account =
+		spl_associated_token_account::get_associated_token_address_with_program_id(
+			&ticket,
+			&mint,
+			&token_program_id,
+		);
+
+	let ix = instructions::buy_tokens(
+		&whitelist,
+		&vault,
+		&mint,
+		&authority,
+		&ticket,
+		&ticket_token_account,
+		&payer_token_account,
+		1,
+		None,
+		false,
+		&token_program_id,
+	)
+	.unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::buy::USER_TICKET,
+		account_order::buy::TICKET_TOKEN,
+	)
+	.await;
+
+	create_token_account(
+		&mut banks_client,
+		&payer,
+		&ticket,
+		&mint,
+		0,
+		&token_program_id,
+	)
+	.await;
+	let treasury_token_account =
+		spl_associated_token_account::get_associated_token_address_with_program_id(
+			&treasury,
+			&mint,
+			&token_program_id,
+		);
+
+	// `TransferTokens` signs the vault transfer for the authority rather than the whitelist, so
+	// it never succeeds, only the rejection of the swapped order is pinned here
+	let ix = instructions::transfer_tokens(
+		&whitelist,
+		&authority,
+		&vault,
+		&mint,
+		&authority,
+		&ticket,
+		&ticket_token_account,
+		&token_program_id,
+	)
+	.unwrap();
+	let mut misordered = ix.clone();
+	misordered.accounts.swap(
+		account_order::transfer_tokens::TICKET,
+		account_order::transfer_tokens::TICKET_TOKEN,
+	);
+	assert!(process(&mut banks_client, &payer, &[misordered])
+		.await
+		.is_err());
+
+	let ix = instructions::burn_ticket(
+		&whitelist,
+		&authority,
+		&mint,
+		&treasury,
+		&treasury_token_account,
+		&ticket,
+		&ticket_token_account,
+		&token_program_id,
+	)
+	.unwrap();
+	assert_account_order(
+		&mut banks_client,
+		&payer,
+		ix,
+		account_order::burn_ticket::TICKET,
+		account_order::burn_ticket::TICKET_TOKEN,
+	)
+	.await;
+}
+
+#[test_case(spl_token::id() ; "Token Program")]
+#[test_case(spl_token_2022::id() ; "Token-2022 Program")]
+#[tokio::test]
+async fn test_migrate_whitelist_account_order(token_program_id: Pubkey) {
+	let Sale {
+		mut banks_client,
+		payer,
+		whitelist,
+		..
+	} = setup(token_program_id).await;
+
+	// Only legacy accounts can be migrated, so the order is pinned by which check rejects the
+	// instruction: a swapped order fails before the layout is ever inspected
+	let ix = instructions::migrate_whitelist(&whitelist, &payer.pubkey()).unwrap();
+	let mut misordered = ix.clone();
+	misordered.accounts.swap(
+		account_order::migrate_whitelist::WHITELIST,
+		account_order::migrate_whitelist::AUTHORITY,
+	);
+
+	let err = process(&mut banks_client, &payer, &[misordered])
+		.await
+		.unwrap_err()
+		.unwrap();
+	assert_eq!(
+		err,
+		solana_sdk::transaction::TransactionError::InstructionError(
+			0,
+			solana_sdk::instruction::InstructionError::Custom(
+				fsp_wl::error::WhitelistError::WhitelistNotInitialized as u32
+			)
+		)
+	);
+
+	let err = process(&mut banks_client, &payer, &[ix])
+		.await
+		.unwrap_err()
+		.unwrap();
+	assert_eq!(
+		err,
+		solana_sdk::transaction::TransactionError::InstructionError(
+			0,
+			solana_sdk::instruction::InstructionError::Custom(
+				fsp_wl::error::WhitelistError::WhitelistAlreadyMigrated as u32
+			)
+		)
+	);
+}
@@ -1,5 +1,18 @@
-use solana_program_test::{ProgramTest, *};
-use solana_sdk::pubkey::Pubkey;
+use {
+	fsp_wl::state::{Ticket, Whitelist},
+	solana_program_test::{ProgramTest, *},
+	solana_sdk::{program_pack::Pack, pubkey::Pubkey, rent::Rent},
+	spl_token_2022::extension::ExtensionType,
+};
+
+// Lamports the fixtures are loaded with, the rent exemption minimum for each account's size.
+// `test_fixture_lamports_match_account_sizes` fails when a size changes without these
+const WHITELIST_LAMPORTS: u64 = 2_770_080;
+const TICKET_LAMPORTS: u64 = 2_241_120;
+const MINT_LAMPORTS: u64 = 1_461_600;
+const TOKEN_ACCOUNT_LAMPORTS: u64 = 2_039_280;
+/// A Token-2022 account with the `ImmutableOwner` extension, as created by the ATA program
+const TOKEN_2022_ACCOUNT_LAMPORTS: u64 = 2_074_080;
 
 pub struct TestAccounts<'a> {
 	whitelist: Option<&'a Pubkey>,
@@ -19,9 +32,9 @@ pub struct TestAccounts<'a> {
 
 pub fn program_test(accounts: TestAccounts) -> ProgramTest {
 	let mut pc = ProgramTest::new(
-		"stuk_wl",
-		stuk_wl::id(),
-		processor!(stuk_wl::processor::Processor::process),
+		"fsp_wl",
+		fsp_wl::id(),
+		processor!(fsp_wl::processor::Processor::process),
 	);
 
 	pc.prefer_bpf(false);
@@ -44,33 +57,53 @@ pub fn program_test(accounts: TestAccounts) -> ProgramTest {
 	);
 
 	if let Some(whitelist) = accounts.whitelist {
-		pc.add_account_with_file_data(*whitelist, 203928, stuk_wl::id(), "whitelist.bin")
+		pc.add_account_with_file_data(
+			*whitelist,
+			WHITELIST_LAMPORTS,
+			fsp_wl::id(),
+			"whitelist.bin",
+		)
 	}
 
 	if let Some(whitelist_2022) = accounts.whitelist_2022 {
-		pc.add_account_with_file_data(*whitelist_2022, 207408, stuk_wl::id(), "whitelist_2022.bin")
+		pc.add_account_with_file_data(
+			*whitelist_2022,
+			WHITELIST_LAMPORTS,
+			fsp_wl::id(),
+			"whitelist_2022.bin",
+		)
 	}
 
 	if let Some(mint) = accounts.mint {
-		pc.add_account_with_file_data(*mint, 1461600, spl_token::id(), "mint.bin");
+		pc.add_account_with_file_data(*mint, MINT_LAMPORTS, spl_token::id(), "mint.bin");
 	}
 
 	if let Some(mint_2022) = accounts.mint_2022 {
-		pc.add_account_with_file_data(*mint_2022, 1461600, spl_token_2022::id(), "mint_2022.bin")
+		pc.add_account_with_file_data(
+			*mint_2022,
+			MINT_LAMPORTS,
+			spl_token_2022::id(),
+			"mint_2022.bin",
+		)
 	}
 
 	if let Some(vault) = accounts.vault {
-		pc.add_account_with_file_data(*vault, 203928, spl_token::id(), "vault.bin")
+		pc.add_account_with_file_data(*vault, TOKEN_ACCOUNT_LAMPORTS, spl_token::id(), "vault.bin")
 	}
 
 	if let Some(vault_2022) = accounts.vault_2022 {
-		pc.add_account_with_file_data(*vault_2022, 207408, spl_token_2022::id(), "vault_2022.bin")
+		pc.add_account_with_file_data(
+			*vault_2022,
+			TOKEN_2022_ACCOUNT_LAMPORTS,
+			spl_token_2022::id(),
+			"vault_2022.bin",
+		)
 	}
 
 	if let Some(wallet_token_account) = accounts.wallet_token_account {
 		pc.add_account_with_file_data(
 			*wallet_token_account,
-			203928,
+			TOKEN_ACCOUNT_LAMPORTS,
 			spl_token::id(),
 			"wallet_token_account.bin",
 		)
@@ -79,21 +112,21 @@ pub fn program_test(accounts: TestAccounts) -> ProgramTest {
 	if let Some(wallet_token_account_2022) = accounts.wallet_token_account_2022 {
 		pc.add_account_with_file_data(
 			*wallet_token_account_2022,
-			207408,
+			TOKEN_2022_ACCOUNT_LAMPORTS,
 			spl_token_2022::id(),
 			"wallet_token_account_2022.bin",
 		)
 	}
 
 	if let Some(ticket) = accounts.ticket_account {
-		pc.add_account_with_file_data(*ticket, 167736, stuk_wl::id(), "ticket_account.bin")
+		pc.add_account_with_file_data(*ticket, TICKET_LAMPORTS, fsp_wl::id(), "ticket_account.bin")
 	}
 
 	if let Some(ticket_2022) = accounts.ticket_account_2022 {
 		pc.add_account_with_file_data(
 			*ticket_2022,
-			167736,
-			stuk_wl::id(),
+			TICKET_LAMPORTS,
+			fsp_wl::id(),
 			"ticket_account_2022.bin",
 		)
 	}
@@ -101,7 +134,7 @@ pub fn program_test(accounts: TestAccounts) -> ProgramTest {
 	if let Some(ticket_token_account) = accounts.ticket_token_account {
 		pc.add_account_with_file_data(
 			*ticket_token_account,
-			203928,
+			TOKEN_ACCOUNT_LAMPORTS,
 			spl_token::id(),
 			"ticket_token_account.bin",
 		)
@@ -110,7 +143,7 @@ pub fn program_test(accounts: TestAccounts) -> ProgramTest {
 	if let Some(ticket_token_account_2022) = accounts.ticket_token_account_2022 {
 		pc.add_account_with_file_data(
 			*ticket_token_account_2022,
-			207408,
+			TOKEN_2022_ACCOUNT_LAMPORTS,
 			spl_token_2022::id(),
 			"ticket_token_account_2022.bin",
 		)
@@ -118,3 +151,27 @@ pub fn program_test(accounts: TestAccounts) -> ProgramTest {
 
 	pc
 }
+
+#[test]
+fn test_fixture_lamports_match_account_sizes() {
+	let rent = Rent::default();
+	assert_eq!(WHITELIST_LAMPORTS, rent.minimum_balance(Whitelist::LEN));
+	assert_eq!(TICKET_LAMPORTS, rent.minimum_balance(Ticket::LEN));
+	assert_eq!(
+		MINT_LAMPORTS,
+		rent.minimum_balance(spl_token::state::Mint::LEN)
+	);
+	assert_eq!(
+		TOKEN_ACCOUNT_LAMPORTS,
+		rent.minimum_balance(spl_token::state::Account::LEN)
+	);
+	assert_eq!(
+		TOKEN_2022_ACCOUNT_LAMPORTS,
+		rent.minimum_balance(
+			ExtensionType::try_calculate_account_len::<spl_token_2022::state::Account>(&[
+				ExtensionType::ImmutableOwner
+			])
+			.unwrap()
+		)
+	);
+}